@@ -0,0 +1,4 @@
+// The migrations are embedded by `sqlx::migrate!`, so changes to them must rebuild
+fn main() {
+    println!("cargo:rerun-if-changed=../database/migrations");
+}
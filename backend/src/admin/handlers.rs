@@ -0,0 +1,141 @@
+use crate::admin::models::{MetadataPayload, ReconcileQuery};
+use crate::audit::models::AuditQuery;
+use crate::audit::queries::AuditQueries;
+use crate::audit::{Audit, Event, Outcome};
+use crate::crypt::jwt::Claims;
+use crate::error::Result;
+use crate::response::ApiResponse;
+use crate::state::AppState;
+use crate::users::profile;
+use crate::users::queries::UserQueries;
+use crate::Error;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use sqlx::types::Uuid;
+use std::sync::Arc;
+
+pub async fn reconcile_storage(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Query(params): Query<ReconcileQuery>,
+) -> Result<impl IntoResponse> {
+    require_admin(&state, &claims).await?;
+
+    let report = state
+        .storage
+        .reconcile(params.repair.unwrap_or(false))
+        .await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully reconciled storage",
+        report,
+    ))
+}
+
+/// Rewraps object data keys with the current master key after a rotation.
+pub async fn rotate_keys(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse> {
+    require_admin(&state, &claims).await?;
+
+    let rotated = state.storage.rotate_keys().await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully rotated keys",
+        rotated,
+    ))
+}
+
+/// Introspects the data API's schema again after its tables change.
+pub async fn reload_data_api(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse> {
+    require_admin(&state, &claims).await?;
+
+    let schema = state.data_api.reload(&state.storage.postgres.pool).await?;
+    let mut tables: Vec<String> = schema.tables.keys().cloned().collect();
+    tables.sort();
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully reloaded the data API schema",
+        tables,
+    ))
+}
+
+/// Pages through the audit log, newest first, filtered by actor, action and outcome.
+pub async fn list_audit_events(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Query(params): Query<AuditQuery>,
+) -> Result<impl IntoResponse> {
+    require_admin(&state, &claims).await?;
+
+    let actor = params.actor.as_deref().map(Uuid::parse_str).transpose()?;
+    let outcome = params.outcome.as_deref().map(str::parse).transpose()?;
+    let page = AuditQueries::list(
+        actor,
+        params.action.as_deref(),
+        outcome,
+        params.before,
+        params.limit,
+        &state.storage.postgres.pool,
+    )
+    .await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully retrieved audit events",
+        page,
+    ))
+}
+
+/// Merges into a user's metadata; `app_metadata` can only be written here.
+pub async fn update_user_metadata(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+    Path(user_id): Path<String>,
+    Json(payload): Json<MetadataPayload>,
+) -> Result<impl IntoResponse> {
+    require_admin(&state, &claims).await?;
+
+    let user_id = Uuid::parse_str(&user_id)?;
+    let event = Event::new("admin.update_metadata")
+        .actor(Uuid::parse_str(&claims.sub)?)
+        .target(user_id.to_string());
+    let result = profile::update_metadata(
+        &state,
+        user_id,
+        payload.app_metadata.as_ref(),
+        payload.user_metadata.as_ref(),
+    )
+    .await;
+    audit.record(&state, event, Outcome::of(&result)).await;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully updated user metadata",
+        result?,
+    ))
+}
+
+/// Roles live in the database rather than the token, so a demotion applies at once.
+async fn require_admin(state: &AppState, claims: &Claims) -> Result<()> {
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let role = UserQueries::get_role(user_id, &state.storage.postgres.pool).await?;
+
+    if role != "admin" {
+        return Err(Error::HttpError(
+            StatusCode::FORBIDDEN,
+            "Admin access required".into(),
+        ));
+    }
+    Ok(())
+}
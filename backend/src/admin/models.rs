@@ -0,0 +1,14 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Deserialize)]
+pub struct ReconcileQuery {
+    pub repair: Option<bool>,
+}
+
+/// Merged into the user's metadata like a profile update's `user_metadata`.
+#[derive(Debug, Deserialize)]
+pub struct MetadataPayload {
+    pub app_metadata: Option<Map<String, Value>>,
+    pub user_metadata: Option<Map<String, Value>>,
+}
@@ -0,0 +1,18 @@
+use crate::admin::handlers::{
+    list_audit_events, reconcile_storage, reload_data_api, rotate_keys, update_user_metadata,
+};
+use crate::middleware::auth_middleware;
+use crate::state::AppState;
+use axum::routing::{get, patch, post};
+use axum::{middleware, Router};
+use std::sync::Arc;
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/storage/reconcile", post(reconcile_storage))
+        .route("/storage/rotate-keys", post(rotate_keys))
+        .route("/rest/reload", post(reload_data_api))
+        .route("/audit", get(list_audit_events))
+        .route("/users/{id}/metadata", patch(update_user_metadata))
+        .route_layer(middleware::from_fn(auth_middleware))
+}
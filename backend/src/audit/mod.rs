@@ -0,0 +1,169 @@
+//! Append-only record of security-relevant events: sign ins, credential changes, deleted
+//! files and linked integrations. Handlers take an [`Audit`] to learn where the request
+//! came from and record each event with whether it succeeded.
+
+use crate::config::CONFIG;
+use crate::state::AppState;
+use crate::Error;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, StatusCode};
+use queries::AuditQueries;
+use sqlx::types::Uuid;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+pub mod models;
+pub mod queries;
+
+/// Longest user agent kept, as clients control it.
+const MAX_USER_AGENT: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+impl Outcome {
+    pub fn of<T, E>(result: &std::result::Result<T, E>) -> Self {
+        match result {
+            Ok(_) => Outcome::Success,
+            Err(_) => Outcome::Failure,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+        }
+    }
+}
+
+impl FromStr for Outcome {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "success" => Ok(Outcome::Success),
+            "failure" => Ok(Outcome::Failure),
+            _ => Err(Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid outcome '{}', expected success or failure", s),
+            )),
+        }
+    }
+}
+
+/// What happened and to what, such as `auth.login` of an email address.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub action: &'static str,
+    pub actor: Option<Uuid>,
+    pub target: Option<String>,
+}
+
+impl Event {
+    pub fn new(action: &'static str) -> Self {
+        Event {
+            action,
+            actor: None,
+            target: None,
+        }
+    }
+
+    /// The user who acted, when known.
+    pub fn actor(mut self, actor: impl Into<Option<Uuid>>) -> Self {
+        self.actor = actor.into();
+        self
+    }
+
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+}
+
+/// Where a request came from, extracted by handlers that record events.
+#[derive(Debug, Clone, Default)]
+pub struct Audit {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl Audit {
+    /// Records an event. A failure to record is logged rather than failing the request
+    /// that was audited.
+    pub async fn record(&self, state: &AppState, event: Event, outcome: Outcome) {
+        if let Err(e) =
+            AuditQueries::insert(&event, self, outcome, &state.storage.postgres.pool).await
+        {
+            tracing::error!("Failed to record audit event {}: {}", event.action, e);
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Audit {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr);
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.chars().take(MAX_USER_AGENT).collect());
+
+        Ok(Audit {
+            ip: client_ip(&parts.headers, peer, CONFIG.trust_proxy_headers),
+            user_agent,
+        })
+    }
+}
+
+/// The client's address: the first `X-Forwarded-For` hop behind a trusted proxy, else
+/// the peer of the connection.
+fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>, trust_proxy: bool) -> Option<String> {
+    let forwarded = headers
+        .get("X-Forwarded-For")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .and_then(|ip| ip.trim().parse::<std::net::IpAddr>().ok());
+
+    match forwarded {
+        Some(ip) if trust_proxy => Some(ip.to_string()),
+        _ => peer.map(|addr| addr.ip().to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_ip() {
+        let peer = Some(SocketAddr::from(([10, 0, 0, 1], 443)));
+        let mut headers = HeaderMap::new();
+        assert_eq!(client_ip(&headers, peer, true).as_deref(), Some("10.0.0.1"));
+        assert_eq!(client_ip(&headers, None, true), None);
+
+        headers.insert("X-Forwarded-For", "203.0.113.7, 10.0.0.1".parse().unwrap());
+        assert_eq!(
+            client_ip(&headers, peer, true).as_deref(),
+            Some("203.0.113.7")
+        );
+        // Anyone can send the header, so it only counts behind a proxy
+        assert_eq!(
+            client_ip(&headers, peer, false).as_deref(),
+            Some("10.0.0.1")
+        );
+
+        headers.insert("X-Forwarded-For", "not an address".parse().unwrap());
+        assert_eq!(client_ip(&headers, peer, true).as_deref(), Some("10.0.0.1"));
+    }
+}
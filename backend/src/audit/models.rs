@@ -0,0 +1,40 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PAGE_LIMIT: i64 = 50;
+pub const MAX_PAGE_LIMIT: i64 = 500;
+
+#[derive(Debug, sqlx::FromRow, Serialize)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub actor_id: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub outcome: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Events newest first. `next_cursor` is passed as `before` for the following page.
+#[derive(Debug, Serialize)]
+pub struct AuditPage {
+    pub events: Vec<AuditEvent>,
+    pub next_cursor: Option<i64>,
+}
+
+/// Filters of the admin audit log, all optional.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub outcome: Option<String>,
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ActivityQuery {
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
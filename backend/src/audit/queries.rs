@@ -0,0 +1,79 @@
+use super::models::{AuditEvent, AuditPage, DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+use super::{Audit, Event, Outcome};
+use crate::Result;
+use sqlx::types::Uuid;
+use sqlx::PgPool;
+
+pub struct AuditQueries;
+
+impl AuditQueries {
+    pub async fn insert(
+        event: &Event,
+        audit: &Audit,
+        outcome: Outcome,
+        pool: &PgPool,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO audit_events (actor_id, action, target, ip, user_agent, outcome)
+            VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        )
+        .bind(event.actor)
+        .bind(event.action)
+        .bind(&event.target)
+        .bind(&audit.ip)
+        .bind(&audit.user_agent)
+        .bind(outcome.as_str())
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// A page of events matching every filter given, newest first.
+    pub async fn list(
+        actor: Option<Uuid>,
+        action: Option<&str>,
+        outcome: Option<Outcome>,
+        before: Option<i64>,
+        limit: Option<i64>,
+        pool: &PgPool,
+    ) -> Result<AuditPage> {
+        let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT);
+
+        // One extra row tells whether there is a next page
+        let mut events: Vec<AuditEvent> = sqlx::query_as(
+            r#"
+            SELECT id, actor_id::TEXT AS actor_id, action, target, ip, user_agent, outcome, created_at
+            FROM audit_events
+            WHERE ($1::UUID IS NULL OR actor_id = $1)
+            AND ($2::TEXT IS NULL OR action = $2)
+            AND ($3::TEXT IS NULL OR outcome = $3)
+            AND ($4::BIGINT IS NULL OR id < $4)
+            ORDER BY id DESC
+            LIMIT $5
+        "#,
+        )
+        .bind(actor)
+        .bind(action)
+        .bind(outcome.map(|o| o.as_str()))
+        .bind(before)
+        .bind(limit + 1)
+        .fetch_all(pool)
+        .await?;
+
+        let next_cursor = match events.len() as i64 > limit {
+            true => {
+                events.truncate(limit as usize);
+                events.last().map(|event| event.id)
+            }
+            false => None,
+        };
+
+        Ok(AuditPage {
+            events,
+            next_cursor,
+        })
+    }
+}
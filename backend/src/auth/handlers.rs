@@ -1,13 +1,15 @@
 use super::models::{Code, VerifyQuery};
 use super::queries::{AuthQueries, TokenQueries};
+use crate::audit::{Audit, Event, Outcome};
 use crate::auth::models::{LoginUser, NewPassword, RegisterUser};
 use crate::config::CONFIG;
 use crate::crypt::hash::verify_password;
-use crate::crypt::jwt::{encode_jwt, Claims};
+use crate::crypt::jwt::{encode_jwt, Claims, Metadata};
 use crate::crypt::tokens::generate_token;
 use crate::error::Result;
 use crate::response::ApiResponse;
 use crate::state::AppState;
+use crate::users::queries::UserQueries;
 use crate::Error;
 use axum::extract::{Query, State};
 use axum::http::StatusCode;
@@ -22,13 +24,18 @@ use std::sync::Arc;
 
 pub async fn register(
     State(state): State<Arc<AppState>>,
+    audit: Audit,
     Json(user): Json<RegisterUser>,
 ) -> Result<impl IntoResponse> {
     let mut tx = state.storage.postgres.start_transaction().await?;
+    let event = Event::new("auth.register").target(&user.email);
 
     match AuthQueries::register(&user, &mut tx).await {
         Ok(id) => {
             tx.commit().await?;
+            audit
+                .record(&state, event.actor(id), Outcome::Success)
+                .await;
             Ok(ApiResponse::new(
                 StatusCode::CREATED,
                 &format!("Successfully created user with id {}", id),
@@ -37,6 +44,7 @@ pub async fn register(
         }
         Err(e) => {
             tx.rollback().await?;
+            audit.record(&state, event, Outcome::Failure).await;
             Err(e.into())
         }
     }
@@ -44,17 +52,36 @@ pub async fn register(
 
 pub async fn login(
     State(state): State<Arc<AppState>>,
+    audit: Audit,
     Json(user): Json<LoginUser>,
 ) -> Result<impl IntoResponse> {
-    let (id, hash) = AuthQueries::get_password(&user.email, &state.storage.postgres.pool)
-        .await
-        .map_err(|_| Error::CustomError("Invalid email or password".into()))?;
+    let event = Event::new("auth.login").target(&user.email);
+    let (id, hash, delete_after) =
+        match AuthQueries::get_password(&user.email, &state.storage.postgres.pool).await {
+            Ok(found) => found,
+            Err(_) => {
+                audit.record(&state, event, Outcome::Failure).await;
+                return Err(Error::CustomError("Invalid email or password".into()));
+            }
+        };
 
+    // Attempts against an existing account show in its owner's security activity
+    let event = event.actor(id);
     if !verify_password(&user.password, &hash)? {
+        audit.record(&state, event, Outcome::Failure).await;
         return Err(Error::CustomError("Invalid email or password.".into()));
     }
+    // Signing in again doesn't cancel a deletion; the emailed link does
+    if delete_after.is_some() {
+        audit.record(&state, event, Outcome::Failure).await;
+        return Err(Error::HttpError(
+            StatusCode::FORBIDDEN,
+            "Account is scheduled for deletion".into(),
+        ));
+    }
+    audit.record(&state, event, Outcome::Success).await;
 
-    let jwt = encode_jwt(id.to_string(), false)?;
+    let jwt = encode_jwt(id.to_string(), false, Metadata::default())?;
 
     Ok(ApiResponse::new(
         StatusCode::OK,
@@ -65,6 +92,7 @@ pub async fn login(
 
 pub async fn verify_email(
     State(state): State<Arc<AppState>>,
+    audit: Audit,
     Query(query): Query<VerifyQuery>,
 ) -> Result<impl IntoResponse> {
     let user_id = match state
@@ -74,10 +102,16 @@ pub async fn verify_email(
         .await
     {
         Ok(user_id) => user_id,
-        Err(_) => return Err(Error::CustomError("Invalid or expired token".into())),
+        Err(_) => {
+            let event = Event::new("auth.verify_email");
+            audit.record(&state, event, Outcome::Failure).await;
+            return Err(Error::CustomError("Invalid or expired token".into()));
+        }
     };
 
     AuthQueries::verify_user(user_id, &state.storage.postgres.pool).await?;
+    let event = Event::new("auth.verify_email").actor(user_id);
+    audit.record(&state, event, Outcome::Success).await;
 
     Ok(ApiResponse::new(
         StatusCode::CREATED,
@@ -88,6 +122,7 @@ pub async fn verify_email(
 
 pub async fn reset_password(
     State(state): State<Arc<AppState>>,
+    audit: Audit,
     Query(query): Query<VerifyQuery>,
     Json(body): Json<NewPassword>,
 ) -> Result<impl IntoResponse> {
@@ -98,14 +133,20 @@ pub async fn reset_password(
         .await
     {
         Ok(user_id) => user_id,
-        Err(_) => return Err(Error::CustomError("Invalid or expired token".into())),
+        Err(_) => {
+            let event = Event::new("auth.reset_password");
+            audit.record(&state, event, Outcome::Failure).await;
+            return Err(Error::CustomError("Invalid or expired token".into()));
+        }
     };
 
     let mut tx = state.storage.postgres.start_transaction().await?;
+    let event = Event::new("auth.reset_password").actor(user_id);
 
     match AuthQueries::update_password(user_id, &body.password, &mut tx).await {
         Ok(_) => {
             tx.commit().await?;
+            audit.record(&state, event, Outcome::Success).await;
             Ok(ApiResponse::new(
                 StatusCode::OK,
                 "Successfully updated password.",
@@ -114,6 +155,7 @@ pub async fn reset_password(
         }
         Err(e) => {
             tx.rollback().await?;
+            audit.record(&state, event, Outcome::Failure).await;
             Err(e.into())
         }
     }
@@ -122,20 +164,27 @@ pub async fn reset_password(
 pub async fn verify_mfa(
     State(state): State<Arc<AppState>>,
     Extension(claims): Extension<Claims>,
+    audit: Audit,
     jar: CookieJar,
     Json(code): Json<Code>,
 ) -> Result<impl IntoResponse> {
     let user_id = Uuid::parse_str(&claims.sub)?;
+    let event = Event::new("auth.verify_mfa").actor(user_id);
 
     let stored_code = match state.storage.redis.get_mfa_code(user_id).await? {
         Some(code) => code,
-        None => return Err(Error::CustomError("MFA code invalid or expired".into())),
+        None => {
+            audit.record(&state, event, Outcome::Failure).await;
+            return Err(Error::CustomError("MFA code invalid or expired".into()));
+        }
     };
 
     //  Compare codes
     if stored_code != code.code {
+        audit.record(&state, event, Outcome::Failure).await;
         return Err(Error::CustomError("Incorrect MFA code".into()));
     }
+    audit.record(&state, event, Outcome::Success).await;
 
     // Create Refresh token
     let refresh_token = generate_token();
@@ -162,7 +211,8 @@ pub async fn verify_mfa(
     let jar = jar.add(cookie);
 
     // if not return jwt for full jwt
-    let jwt = encode_jwt(claims.sub, true)?;
+    let metadata = UserQueries::get_claim_metadata(user_id, &state.storage.postgres.pool).await?;
+    let jwt = encode_jwt(claims.sub, true, metadata)?;
 
     Ok((
         jar,
@@ -187,7 +237,9 @@ pub async fn refresh(
     match TokenQueries::validate_refresh_token(&token, &state.storage.postgres.pool).await {
         Ok(user_id) => {
             // if not return jwt for full jwt
-            let jwt = encode_jwt(user_id.to_string(), true)?;
+            let pool = &state.storage.postgres.pool;
+            let metadata = UserQueries::get_claim_metadata(user_id, pool).await?;
+            let jwt = encode_jwt(user_id.to_string(), true, metadata)?;
 
             Ok((
                 jar,
@@ -204,6 +256,8 @@ pub async fn refresh(
 
 pub async fn logout(
     State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
     jar: CookieJar,
 ) -> Result<impl IntoResponse> {
     let cookie = jar
@@ -213,6 +267,8 @@ pub async fn logout(
     let token = cookie.value().to_owned();
 
     TokenQueries::delete_refresh_token(&token, &state.storage.postgres.pool).await?;
+    let event = Event::new("auth.logout").actor(Uuid::parse_str(&claims.sub)?);
+    audit.record(&state, event, Outcome::Success).await;
 
     let jar = jar.remove(Cookie::build("refresh_token").path("/").build());
 
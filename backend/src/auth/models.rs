@@ -30,6 +30,14 @@ pub struct VerifyQuery {
     pub token: String,
 }
 
+/// A signed in device, as its refresh token shows it without the token itself.
+#[derive(sqlx::FromRow, Clone, Serialize, Debug)]
+pub struct Session {
+    pub id: i32,
+    pub created_at: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+}
+
 #[derive(sqlx::FromRow, Clone, Serialize, Deserialize, Debug)]
 pub struct RefreshToken {
     pub id: i32,
@@ -1,7 +1,8 @@
+use crate::audit::{Audit, Event, Outcome};
 use crate::auth::oauth::AuthRequest;
 use crate::data::{Flow, Token};
 use crate::state::AppState;
-use crate::{error::Result, response::ApiResponse};
+use crate::{error::Result, response::ApiResponse, Error};
 use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
@@ -33,11 +34,15 @@ pub async fn get_authentication_url(
 
 pub async fn google_callback(
     State(state): State<Arc<AppState>>,
+    audit: Audit,
     Query(params): Query<AuthRequest>,
 ) -> Result<impl IntoResponse> {
+    let event = Event::new("oauth.link").target("google");
+
     // Split `id:csrf_token`
     let parts: Vec<&str> = params.state.split(':').collect();
     if parts.len() != 2 {
+        audit.record(&state, event, Outcome::Failure).await;
         return Ok(ApiResponse::new(
             StatusCode::BAD_REQUEST,
             &format!("Invalid state format."),
@@ -46,34 +51,42 @@ pub async fn google_callback(
     }
 
     let (id, csrf_token) = (parts[0], parts[1]);
-    let stored_verifier: Flow = state.storage.redis.get_flow(id, "google").await?;
+    let event = event.actor(Uuid::parse_str(id).ok());
 
-    if csrf_token != stored_verifier.csrf_state.secret() {
-        return Ok(ApiResponse::new(
-            StatusCode::BAD_REQUEST,
-            &format!("Invalid state."),
-            "",
-        ));
-    }
+    // Failed exchanges are recorded as well, including forged states
+    let result: Result<()> = async {
+        let stored_verifier: Flow = state.storage.redis.get_flow(id, "google").await?;
 
-    let auth = state
-        .oauth
-        .google
-        .exchange_code(params.code, stored_verifier.pkce_verifier.unwrap())
-        .await?;
+        if csrf_token != stored_verifier.csrf_state.secret() {
+            return Err(Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                "Invalid state.".into(),
+            ));
+        }
 
-    // Insert refresh and access token to db
-    let token = Token {
-        access_token: auth.0.secret().to_string(),
-        refresh_token: Some(auth.1.secret().to_string()),
-    };
+        let auth = state
+            .oauth
+            .google
+            .exchange_code(params.code, stored_verifier.pkce_verifier.unwrap())
+            .await?;
 
-    state
-        .storage
-        .redis
-        .store_oauth_tokens(id, "google", &token)
-        .await?;
-    state.storage.redis.delete_flow(id, "google").await?;
+        // Insert refresh and access token to db
+        let token = Token {
+            access_token: auth.0.secret().to_string(),
+            refresh_token: Some(auth.1.secret().to_string()),
+        };
+
+        state
+            .storage
+            .redis
+            .store_oauth_tokens(id, "google", &token)
+            .await?;
+        state.storage.redis.delete_flow(id, "google").await?;
+        Ok(())
+    }
+    .await;
+    audit.record(&state, event, Outcome::of(&result)).await;
+    result?;
 
     Ok(ApiResponse::new(
         StatusCode::CREATED,
@@ -1,9 +1,11 @@
 use crate::{
+    audit::{Audit, Event, Outcome},
     auth::oauth::AuthRequest,
     data::{Flow, Token},
     error::Result,
     response::ApiResponse,
     state::AppState,
+    Error,
 };
 use axum::{
     extract::{Path, Query, State},
@@ -36,11 +38,15 @@ pub async fn get_authentication_url(
 
 pub async fn microsoft_callback(
     State(state): State<Arc<AppState>>,
+    audit: Audit,
     Query(params): Query<AuthRequest>,
 ) -> Result<impl IntoResponse> {
+    let event = Event::new("oauth.link").target("microsoft");
+
     // Split `id:csrf_token`
     let parts: Vec<&str> = params.state.split(':').collect();
     if parts.len() != 2 {
+        audit.record(&state, event, Outcome::Failure).await;
         return Ok(ApiResponse::new(
             StatusCode::BAD_REQUEST,
             &format!("Invalid state format."),
@@ -49,40 +55,45 @@ pub async fn microsoft_callback(
     }
 
     let (id, csrf_token) = (parts[0], parts[1]);
-    let stored_verifier: Flow = state.storage.redis.get_flow(id, "microsoft").await?;
+    let event = event.actor(Uuid::parse_str(id).ok());
 
-    if csrf_token != stored_verifier.csrf_state.secret() {
-        return Ok(ApiResponse::new(
-            StatusCode::BAD_REQUEST,
-            &format!("Invalid state."),
-            "",
-        ));
-    }
+    // Failed exchanges are recorded as well, including forged states
+    let result: Result<()> = async {
+        let stored_verifier: Flow = state.storage.redis.get_flow(id, "microsoft").await?;
 
-    //Get token
-    let auth = state
-        .oauth
-        .microsoft
-        .exchange_code(params.code, stored_verifier.pkce_verifier.unwrap())
-        .await?;
+        if csrf_token != stored_verifier.csrf_state.secret() {
+            return Err(Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                "Invalid state.".into(),
+            ));
+        }
 
-    // Save tokens
-    let refresh_token = match auth.1 {
-        Some(token) => Some(token.secret().to_string()),
-        None => None,
-    };
+        //Get token
+        let auth = state
+            .oauth
+            .microsoft
+            .exchange_code(params.code, stored_verifier.pkce_verifier.unwrap())
+            .await?;
 
-    let token = Token {
-        access_token: auth.0.secret().to_string(),
-        refresh_token,
-    };
+        // Save tokens
+        let refresh_token = auth.1.map(|token| token.secret().to_string());
 
-    state
-        .storage
-        .redis
-        .store_oauth_tokens(id, "microsoft", &token)
-        .await?;
-    state.storage.redis.delete_flow(id, "microsoft").await?;
+        let token = Token {
+            access_token: auth.0.secret().to_string(),
+            refresh_token,
+        };
+
+        state
+            .storage
+            .redis
+            .store_oauth_tokens(id, "microsoft", &token)
+            .await?;
+        state.storage.redis.delete_flow(id, "microsoft").await?;
+        Ok(())
+    }
+    .await;
+    audit.record(&state, event, Outcome::of(&result)).await;
+    result?;
 
     Ok(ApiResponse::new(
         StatusCode::CREATED,
@@ -3,8 +3,13 @@ use crate::{
         google::client::GoogleOauth, microsoft::client::MicrosoftOauth,
         notion::client::NotionOauth, slack::client::SlackOauth,
     },
-    Result,
+    data::Token,
+    Error, Result,
 };
+use oauth2::{AccessToken, RefreshToken, StandardRevocableToken};
+
+/// Providers a user can link through OAuth.
+pub const PROVIDERS: [&str; 4] = ["google", "microsoft", "notion", "slack"];
 
 #[derive(Debug, Clone)]
 pub struct OAuthClient {
@@ -23,4 +28,33 @@ impl OAuthClient {
             notion: NotionOauth::new()?,
         })
     }
+
+    /// Revokes a linked account's grant at the provider. Microsoft and Notion have no
+    /// endpoint to revoke a single grant, so false is returned and the tokens can only
+    /// be forgotten.
+    pub async fn revoke(&self, provider: &str, token: &Token) -> Result<bool> {
+        match provider {
+            "google" => {
+                // Revoking the refresh token ends the whole grant
+                let token = match &token.refresh_token {
+                    Some(refresh_token) => StandardRevocableToken::RefreshToken(RefreshToken::new(
+                        refresh_token.clone(),
+                    )),
+                    None => StandardRevocableToken::AccessToken(AccessToken::new(
+                        token.access_token.clone(),
+                    )),
+                };
+                self.google
+                    .revoke_token(token)
+                    .await
+                    .map_err(|e| Error::from(e.to_string()))?;
+                Ok(true)
+            }
+            "slack" => {
+                self.slack.revoke_token(token.access_token.clone()).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
 }
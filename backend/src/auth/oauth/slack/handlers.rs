@@ -1,9 +1,11 @@
 use crate::{
+    audit::{Audit, Event, Outcome},
     auth::oauth::AuthRequest,
     data::{Flow, Token},
     error::Result,
     response::ApiResponse,
     state::AppState,
+    Error,
 };
 use axum::{
     extract::{Path, Query, State},
@@ -36,11 +38,15 @@ pub async fn get_authentication_url(
 
 pub async fn slack_callback(
     State(state): State<Arc<AppState>>,
+    audit: Audit,
     Query(params): Query<AuthRequest>,
 ) -> Result<impl IntoResponse> {
+    let event = Event::new("oauth.link").target("slack");
+
     // Split `id:csrf_token`
     let parts: Vec<&str> = params.state.split(':').collect();
     if parts.len() != 2 {
+        audit.record(&state, event, Outcome::Failure).await;
         return Ok(ApiResponse::new(
             StatusCode::BAD_REQUEST,
             &format!("Invalid state format."),
@@ -49,30 +55,38 @@ pub async fn slack_callback(
     }
 
     let (id, csrf_token) = (parts[0], parts[1]);
-    let stored_verifier: Flow = state.storage.redis.get_flow(id, "slack").await?;
-    if csrf_token != stored_verifier.csrf_state.secret() {
-        return Ok(ApiResponse::new(
-            StatusCode::BAD_REQUEST,
-            &format!("Invalid state."),
-            "",
-        ));
-    }
+    let event = event.actor(Uuid::parse_str(id).ok());
 
-    let auth = state.oauth.slack.exchange_code(params.code).await?;
+    // Failed exchanges are recorded as well, including forged states
+    let result: Result<()> = async {
+        let stored_verifier: Flow = state.storage.redis.get_flow(id, "slack").await?;
+        if csrf_token != stored_verifier.csrf_state.secret() {
+            return Err(Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                "Invalid state.".into(),
+            ));
+        }
 
-    // Insert refresh and access token to db
-    let token = Token {
-        access_token: auth.0.secret().to_string(),
-        refresh_token: None,
-        // Some(auth.1.secret().to_string()),
-    };
+        let auth = state.oauth.slack.exchange_code(params.code).await?;
 
-    state
-        .storage
-        .redis
-        .store_oauth_tokens(id, "slack", &token)
-        .await?;
-    state.storage.redis.delete_flow(id, "slack").await?;
+        // Insert refresh and access token to db
+        let token = Token {
+            access_token: auth.0.secret().to_string(),
+            refresh_token: None,
+            // Some(auth.1.secret().to_string()),
+        };
+
+        state
+            .storage
+            .redis
+            .store_oauth_tokens(id, "slack", &token)
+            .await?;
+        state.storage.redis.delete_flow(id, "slack").await?;
+        Ok(())
+    }
+    .await;
+    audit.record(&state, event, Outcome::of(&result)).await;
+    result?;
 
     Ok(ApiResponse::new(
         StatusCode::CREATED,
@@ -1,4 +1,4 @@
-use super::models::RefreshToken;
+use super::models::{RefreshToken, Session};
 use crate::auth::models::RegisterUser;
 use crate::crypt::hash::{hash_password, hash_token};
 use crate::{Error, Result};
@@ -38,10 +38,14 @@ impl AuthQueries {
         Ok(user_id)
     }
 
-    pub async fn get_password(email: &str, pool: &PgPool) -> Result<(Uuid, String)> {
-        let result: (Uuid, String) = sqlx::query_as::<_, (Uuid, String)>(
+    /// The user's id and password hash, and when they're due to be deleted if they asked.
+    pub async fn get_password(
+        email: &str,
+        pool: &PgPool,
+    ) -> Result<(Uuid, String, Option<DateTime<Utc>>)> {
+        let result = sqlx::query_as::<_, (Uuid, String, Option<DateTime<Utc>>)>(
             r#"
-            SELECT u.id, a.password_hash 
+            SELECT u.id, a.password_hash, u.delete_after
             FROM auth_providers AS a
             JOIN users AS u 
                 ON u.id = a.user_id 
@@ -169,6 +173,24 @@ impl TokenQueries {
         Ok(())
     }
 
+    /// Signs the user out of every device.
+    pub async fn delete_user_refresh_tokens(
+        user_id: Uuid,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM refresh_tokens
+            WHERE user_id = $1
+        "#,
+        )
+        .bind(user_id)
+        .execute(tx.deref_mut())
+        .await?;
+
+        Ok(())
+    }
+
     pub async fn validate_refresh_token(token: &str, pool: &PgPool) -> Result<Uuid> {
         let result: Uuid = sqlx::query_scalar(
             r#"
@@ -184,4 +206,22 @@ impl TokenQueries {
 
         Ok(result)
     }
+
+    /// The user's unexpired refresh tokens, newest first.
+    pub async fn list_sessions(user_id: Uuid, pool: &PgPool) -> Result<Vec<Session>> {
+        let sessions: Vec<Session> = sqlx::query_as(
+            r#"
+            SELECT id, created_at, expires_at
+            FROM refresh_tokens
+            WHERE user_id = $1
+            AND expires_at > NOW()
+            ORDER BY created_at DESC
+        "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(sessions)
+    }
 }
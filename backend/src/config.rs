@@ -37,6 +37,7 @@ pub struct AppConfig {
     // JWT
     pub jwt_algorithm: String,
     pub jwt_access_secret: String,
+    pub jwt_metadata_claims: String,
     pub access_token_expire_minutes: u8,
     pub refresh_token_expire_days: u8,
     pub temp_login_expire_minutes: u8,
@@ -54,6 +55,7 @@ pub struct AppConfig {
     pub app_url: String,
     pub app_name: String,
     pub debug: bool,
+    pub trust_proxy_headers: bool,
     pub log_file: String,
     pub log_level: String,
 
@@ -71,6 +73,7 @@ pub struct AppConfig {
 
     //Storage
     pub postgres_url: String,
+    pub migrate_on_start: bool,
     pub redis_url: String,
     pub bucket: String,
     pub gcp_path: String,
@@ -78,6 +81,39 @@ pub struct AppConfig {
     pub aws_access: String,
     pub aws_secret: String,
     pub aws_region: String,
+    pub s3_endpoint: String,
+    pub azure_account: String,
+    pub azure_access_key: String,
+    pub azure_endpoint: String,
+    pub azure_use_emulator: bool,
+    pub object_max_versions: i32,
+    pub storage_quota_bytes: i64,
+    pub organization_quota_bytes: i64,
+    pub quota_grace_percent: i64,
+    pub tus_expire_hours: u8,
+    pub tus_sweep_interval_minutes: u64,
+    pub reconcile_interval_minutes: u64,
+    pub reconcile_grace_minutes: i64,
+    pub reconcile_repair: bool,
+    pub malware_scanner: String,
+    pub clamav_url: String,
+    pub object_encryption: bool,
+    pub object_master_keys: String,
+    pub object_dedup: bool,
+    pub object_dedup_secret: String,
+    pub object_archive_url: String,
+    pub lifecycle_interval_minutes: u64,
+    pub scan_retry_interval_minutes: u64,
+    pub storage_rules_path: String,
+    pub data_api_schema: String,
+    pub data_api_role: String,
+    pub graphql_max_depth: usize,
+    pub graphql_max_complexity: usize,
+    pub account_deletion_grace_days: i64,
+    pub account_purge_interval_minutes: u64,
+    pub export_link_expire_hours: i64,
+    pub export_interval_minutes: u64,
+    pub ws_ticket_expire_seconds: u64,
 
     // Google
     pub google_client_id: ClientId,
@@ -111,6 +147,7 @@ impl AppConfig {
             // JWT
             jwt_algorithm: get_env("JWT_ALGORITHM", None)?,
             jwt_access_secret: get_env("JWT_ACCESS_SECRET", None)?,
+            jwt_metadata_claims: get_env("JWT_METADATA_CLAIMS", Some(""))?,
             access_token_expire_minutes: get_env("ACCESS_TOKEN_EXPIRE_MINUTES", Some("30"))?,
             refresh_token_expire_days: get_env("REFRESH_TOKEN_EXPIRE_MINUTES", Some("7"))?,
             temp_login_expire_minutes: get_env("TEMP_LONG_EXPIRE_MINUTES", Some("5"))?,
@@ -131,6 +168,7 @@ impl AppConfig {
             log_level: get_env("LOG_LEVEL", None)?,
             log_file: get_env("LOG_FILE", None)?,
             debug: get_env("DEBUG", Some("false"))?,
+            trust_proxy_headers: get_env("TRUST_PROXY_HEADERS", Some("false"))?,
 
             // Email
             smtp_email: get_env("SMTP_EMAIL", None)?,
@@ -146,13 +184,47 @@ impl AppConfig {
 
             // Storage
             postgres_url: get_env("POSTGRES_URL", None)?,
+            migrate_on_start: get_env("MIGRATE_ON_START", Some("true"))?,
             redis_url: get_env("REDIS_URL", None)?,
             gcp_path: get_env("GCP_SERVICE_ACCOUNT_PATH", Some(""))?,
             object_url: get_env("OBJECT_URL", None)?,
-            bucket: get_env("OBJECT_BUCKET", None)?,
+            bucket: get_env("OBJECT_BUCKET", Some(""))?,
             aws_access: get_env("AWS_ACCESS_KEY_ID", Some(""))?,
             aws_secret: get_env("AWS_SECRET_ACCESS_KEY", Some(""))?,
-            aws_region: get_env("AWS_REGION", None)?,
+            aws_region: get_env("AWS_REGION", Some("us-east-1"))?,
+            s3_endpoint: get_env("S3_ENDPOINT", Some(""))?,
+            azure_account: get_env("AZURE_STORAGE_ACCOUNT", Some(""))?,
+            azure_access_key: get_env("AZURE_STORAGE_ACCESS_KEY", Some(""))?,
+            azure_endpoint: get_env("AZURE_STORAGE_ENDPOINT", Some(""))?,
+            azure_use_emulator: get_env("AZURE_USE_EMULATOR", Some("false"))?,
+            object_max_versions: get_env("OBJECT_MAX_VERSIONS", Some("10"))?,
+            storage_quota_bytes: get_env("STORAGE_QUOTA_BYTES", Some("0"))?,
+            organization_quota_bytes: get_env("ORGANIZATION_QUOTA_BYTES", Some("0"))?,
+            quota_grace_percent: get_env("STORAGE_QUOTA_GRACE_PERCENT", Some("10"))?,
+            tus_expire_hours: get_env("TUS_EXPIRE_HOURS", Some("24"))?,
+            tus_sweep_interval_minutes: get_env("TUS_SWEEP_INTERVAL_MINUTES", Some("60"))?,
+            reconcile_interval_minutes: get_env("RECONCILE_INTERVAL_MINUTES", Some("1440"))?,
+            reconcile_grace_minutes: get_env("RECONCILE_GRACE_MINUTES", Some("60"))?,
+            reconcile_repair: get_env("RECONCILE_REPAIR", Some("false"))?,
+            malware_scanner: get_env("MALWARE_SCANNER", Some("none"))?,
+            clamav_url: get_env("CLAMAV_URL", Some("tcp://localhost:3310"))?,
+            object_encryption: get_env("OBJECT_ENCRYPTION", Some("false"))?,
+            object_master_keys: get_env("OBJECT_MASTER_KEYS", Some(""))?,
+            object_dedup: get_env("OBJECT_DEDUP", Some("false"))?,
+            object_dedup_secret: get_env("OBJECT_DEDUP_SECRET", Some(""))?,
+            object_archive_url: get_env("OBJECT_ARCHIVE_URL", Some(""))?,
+            lifecycle_interval_minutes: get_env("LIFECYCLE_INTERVAL_MINUTES", Some("60"))?,
+            scan_retry_interval_minutes: get_env("SCAN_RETRY_INTERVAL_MINUTES", Some("15"))?,
+            storage_rules_path: get_env("STORAGE_RULES_PATH", Some(""))?,
+            data_api_schema: get_env("DATA_API_SCHEMA", Some("api"))?,
+            data_api_role: get_env("DATA_API_ROLE", Some("authenticated"))?,
+            graphql_max_depth: get_env("GRAPHQL_MAX_DEPTH", Some("10"))?,
+            graphql_max_complexity: get_env("GRAPHQL_MAX_COMPLEXITY", Some("250"))?,
+            account_deletion_grace_days: get_env("ACCOUNT_DELETION_GRACE_DAYS", Some("30"))?,
+            account_purge_interval_minutes: get_env("ACCOUNT_PURGE_INTERVAL_MINUTES", Some("60"))?,
+            export_link_expire_hours: get_env("EXPORT_LINK_EXPIRE_HOURS", Some("72"))?,
+            export_interval_minutes: get_env("EXPORT_INTERVAL_MINUTES", Some("5"))?,
+            ws_ticket_expire_seconds: get_env("WS_TICKET_EXPIRE_SECONDS", Some("30"))?,
 
             //OAuth
             google_client_id: ClientId::new(get_env("GOOGLE_CLIENT_ID", None)?),
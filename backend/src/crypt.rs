@@ -6,6 +6,7 @@ pub mod jwt {
     use chrono::{Duration, Utc};
     use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
     use serde::{Deserialize, Serialize};
+    use serde_json::{Map, Value};
     use std::usize;
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,9 +15,56 @@ pub mod jwt {
         pub mfa_verified: bool,
         pub exp: usize,
         pub iat: usize,
+        #[serde(flatten)]
+        pub metadata: Metadata,
     }
 
-    pub fn encode_jwt(user_id: String, mfa_verified: bool) -> Result<String> {
+    /// The fields of a user's metadata `JWT_METADATA_CLAIMS` exposes, fixed until the
+    /// token is refreshed.
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct Metadata {
+        #[serde(default, skip_serializing_if = "Map::is_empty")]
+        pub app_metadata: Map<String, Value>,
+        #[serde(default, skip_serializing_if = "Map::is_empty")]
+        pub user_metadata: Map<String, Value>,
+    }
+
+    impl Metadata {
+        /// Picks the fields `JWT_METADATA_CLAIMS` lists.
+        pub fn select(
+            app_metadata: &Map<String, Value>,
+            user_metadata: &Map<String, Value>,
+        ) -> Self {
+            Self::select_fields(&CONFIG.jwt_metadata_claims, app_metadata, user_metadata)
+        }
+
+        /// Picks the fields listed as `app_metadata.plan,user_metadata.theme`, skipping
+        /// entries that name neither.
+        fn select_fields(
+            fields: &str,
+            app_metadata: &Map<String, Value>,
+            user_metadata: &Map<String, Value>,
+        ) -> Self {
+            let mut metadata = Metadata::default();
+            for field in fields.split(',').map(str::trim) {
+                let (selected, source, name) = match field.split_once('.') {
+                    Some(("app_metadata", name)) => {
+                        (&mut metadata.app_metadata, app_metadata, name)
+                    }
+                    Some(("user_metadata", name)) => {
+                        (&mut metadata.user_metadata, user_metadata, name)
+                    }
+                    _ => continue,
+                };
+                if let Some(value) = source.get(name) {
+                    selected.insert(name.to_string(), value.clone());
+                }
+            }
+            metadata
+        }
+    }
+
+    pub fn encode_jwt(user_id: String, mfa_verified: bool, metadata: Metadata) -> Result<String> {
         let now = Utc::now();
         let expiry = if mfa_verified {
             now + Duration::minutes(CONFIG.access_token_expire_minutes.into())
@@ -29,6 +77,7 @@ pub mod jwt {
             mfa_verified: mfa_verified,
             exp: expiry.timestamp() as usize,
             iat: now.timestamp() as usize,
+            metadata,
         };
 
         let jwt = encode(
@@ -55,6 +104,40 @@ pub mod jwt {
             Err(_) => false,
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use serde_json::json;
+
+        fn map(value: Value) -> Map<String, Value> {
+            match value {
+                Value::Object(map) => map,
+                _ => unreachable!(),
+            }
+        }
+
+        #[test]
+        fn test_select_metadata() {
+            let app = map(json!({ "plan": "pro", "role": "admin" }));
+            let user = map(json!({ "theme": "dark", "plan": "free" }));
+
+            let metadata = Metadata::select_fields(
+                " app_metadata.plan , user_metadata.theme,user_metadata.missing",
+                &app,
+                &user,
+            );
+            assert_eq!(metadata.app_metadata, map(json!({ "plan": "pro" })));
+            assert_eq!(metadata.user_metadata, map(json!({ "theme": "dark" })));
+
+            // Unknown sources, bare names and an empty list pick nothing
+            for fields in ["", "plan", "other.plan", "app_metadata", ","] {
+                let metadata = Metadata::select_fields(fields, &app, &user);
+                assert!(metadata.app_metadata.is_empty(), "{:?}", fields);
+                assert!(metadata.user_metadata.is_empty(), "{:?}", fields);
+            }
+        }
+    }
 }
 
 pub mod hash {
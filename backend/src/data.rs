@@ -1,11 +1,20 @@
+use crate::crypt::tokens::generate_token;
 use crate::{Error, Result};
+use axum::extract::multipart::Field;
 use axum::extract::ws::Message;
-use axum::{body::Bytes, extract::Multipart};
+use axum::{body::Bytes, extract::Multipart, http::StatusCode};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
 use oauth2::{AuthorizationCode, CsrfToken, PkceCodeVerifier};
 use redis::{FromRedisValue, RedisResult};
 use serde::ser::{SerializeStruct, Serializer};
 use serde::{Deserialize, Serialize};
 use serde_json::{self, Value};
+use sha2::Sha256;
 use sqlx::{prelude::FromRow, types::Uuid};
 use url::Url;
 
@@ -43,6 +52,17 @@ impl From<Event> for Message {
     }
 }
 
+/// A message on a websocket channel, as published through Redis and delivered to the
+/// channel's subscribers.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChannelMessage {
+    pub channel: String,
+    /// The user who published it, absent when the server did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+    pub data: Value,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Token {
     pub access_token: String,
@@ -73,6 +93,9 @@ pub struct Objects {
     pub user_id: Uuid,
     pub key: String,
     pub filename: String,
+    pub version: i32,
+    pub max_versions: Option<i32>,
+    pub expires_at: Option<DateTime<Utc>>,
     // content_type: String,
     // size_bytes: i64,
     // visibility: String,
@@ -83,20 +106,486 @@ impl Serialize for Objects {
     where
         S: Serializer,
     {
-        let mut s = serializer.serialize_struct("id", 3)?;
+        let mut s = serializer.serialize_struct("id", 6)?;
         s.serialize_field("user_id", &self.user_id.to_string())?;
         s.serialize_field("key", &self.key)?;
+        s.serialize_field("path", self.path())?;
         s.serialize_field("filename", &self.filename)?;
+        s.serialize_field("version", &self.version)?;
+        s.serialize_field("expires_at", &self.expires_at)?;
+        s.end()
+    }
+}
+
+/// An immutable upload of an object. `blob_key` is where its bytes live in the store,
+/// independent of the object's logical key so moves never touch the blobs.
+#[derive(Debug, FromRow, Serialize)]
+pub struct ObjectVersion {
+    #[serde(skip)]
+    pub id: i32,
+    #[serde(skip)]
+    pub object_id: i32,
+    pub version: i32,
+    #[serde(skip)]
+    pub blob_key: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub created_at: Option<DateTime<Utc>>,
+    pub scan_status: String,
+    #[serde(skip)]
+    pub key_id: Option<String>,
+    #[serde(skip)]
+    pub wrapped_key: Option<String>,
+    pub storage_class: String,
+}
+
+impl ObjectVersion {
+    /// Generates a fresh blob key under the owner's blob namespace.
+    pub fn new_blob_key(user_id: Uuid) -> String {
+        format!("blobs/{}/{}", user_id, generate_token())
+    }
+
+    /// Key of the owner's shared blob for content hashing to `sha256`. Blobs are only
+    /// shared between one owner's versions, so an upload never reveals what others store.
+    /// The key is an HMAC of the hash under `secret`, so listing the store doesn't give
+    /// away what the files hold.
+    pub fn shared_blob_key(user_id: Uuid, sha256: &str, secret: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC takes keys of any length");
+        mac.update(format!("{}:{}", user_id, sha256).as_bytes());
+        let name = hex::encode(mac.finalize().into_bytes());
+        format!("blobs/{}/shared/{}", user_id, name)
+    }
+
+    /// Whether a blob is content-addressed and reference counted. Ones stored before
+    /// keys were HMACs are named by the plain hash.
+    pub fn is_shared_blob(blob_key: &str) -> bool {
+        matches!(blob_key.split('/').nth(2), Some("shared" | "sha256"))
+    }
+
+    /// Refuses versions that haven't been scanned clean.
+    pub fn ensure_servable(&self) -> Result<()> {
+        match ScanStatus::parse(&self.scan_status) {
+            ScanStatus::Clean => Ok(()),
+            ScanStatus::Pending => Err(Error::HttpError(
+                StatusCode::LOCKED,
+                "File is still being scanned".into(),
+            )),
+            ScanStatus::Infected | ScanStatus::Failed => Err(Error::HttpError(
+                StatusCode::FORBIDDEN,
+                "File is quarantined".into(),
+            )),
+        }
+    }
+}
+
+/// Where a version is in malware scanning. Only clean versions are served; a failed
+/// scan is treated like an infected one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScanStatus {
+    Pending,
+    Clean,
+    Infected,
+    Failed,
+}
+
+impl ScanStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScanStatus::Pending => "pending",
+            ScanStatus::Clean => "clean",
+            ScanStatus::Infected => "infected",
+            ScanStatus::Failed => "failed",
+        }
+    }
+
+    /// Unknown values fail closed.
+    pub fn parse(status: &str) -> Self {
+        match status {
+            "pending" => ScanStatus::Pending,
+            "clean" => ScanStatus::Clean,
+            "infected" => ScanStatus::Infected,
+            _ => ScanStatus::Failed,
+        }
+    }
+}
+
+/// The current version of an object, looked up by key.
+#[derive(Debug, FromRow)]
+pub struct CurrentVersion {
+    pub key: String,
+    pub user_id: Uuid,
+    #[sqlx(flatten)]
+    pub version: ObjectVersion,
+}
+
+/// Which store holds a version's blob. New blobs land in the standard store; lifecycle
+/// rules move old ones to the archive store.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageClass {
+    Standard,
+    Archive,
+}
+
+impl StorageClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageClass::Standard => "standard",
+            StorageClass::Archive => "archive",
+        }
+    }
+
+    pub fn parse(class: &str) -> Result<Self> {
+        match class {
+            "standard" => Ok(StorageClass::Standard),
+            "archive" => Ok(StorageClass::Archive),
+            _ => Err(Error::from(format!("Unknown storage class '{}'", class))),
+        }
+    }
+}
+
+/// Deletes a user's objects under `prefix` and/or moves their versions to the archive
+/// store once old enough. An object's own `expires_at` overrides its expiry.
+#[derive(Debug, FromRow)]
+pub struct LifecycleRule {
+    pub id: i32,
+    pub user_id: Uuid,
+    pub prefix: String,
+    pub expire_after_days: Option<i32>,
+    pub transition_after_days: Option<i32>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl Serialize for LifecycleRule {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let root = Objects::root(self.user_id);
+        let mut s = serializer.serialize_struct("LifecycleRule", 5)?;
+        s.serialize_field("id", &self.id)?;
+        s.serialize_field(
+            "prefix",
+            self.prefix
+                .strip_prefix(root.as_str())
+                .unwrap_or(&self.prefix),
+        )?;
+        s.serialize_field("expire_after_days", &self.expire_after_days)?;
+        s.serialize_field("transition_after_days", &self.transition_after_days)?;
+        s.serialize_field("created_at", &self.created_at)?;
         s.end()
     }
 }
 
+/// What a lifecycle sweep did.
+#[derive(Debug, Default, Serialize)]
+pub struct LifecycleReport {
+    pub expired_objects: u64,
+    pub transitioned_blobs: u64,
+}
+
+/// Bytes stored by an owner against its quota, where no quota means unlimited.
+#[derive(Debug, Serialize)]
+pub struct Usage {
+    pub used_bytes: i64,
+    pub quota_bytes: Option<i64>,
+}
+
+impl Usage {
+    /// Bytes that may still be written, letting usage overshoot the quota by `grace_percent`.
+    pub fn remaining(&self, grace_percent: i64) -> Option<i64> {
+        self.quota_bytes
+            .map(|quota| (quota + quota * grace_percent / 100 - self.used_bytes).max(0))
+    }
+}
+
+/// Storage used by a user and, when they belong to one, by their organization.
+#[derive(Debug, Serialize)]
+pub struct StorageUsage {
+    pub user: Usage,
+    pub organization_id: Option<String>,
+    pub organization: Option<Usage>,
+    pub remaining_bytes: Option<i64>,
+}
+
+/// A page of a user's objects, grouped into common prefixes when listed with a delimiter.
+#[derive(Debug, Serialize)]
+pub struct ObjectList {
+    pub objects: Vec<Objects>,
+    pub prefixes: Vec<String>,
+    pub next_cursor: Option<String>,
+}
+
+/// A version row and the blob it points at, as seen by the reconciler.
+#[derive(Debug, FromRow, Serialize)]
+pub struct VersionBlob {
+    #[serde(skip)]
+    pub id: i32,
+    pub key: String,
+    pub version: i32,
+    pub blob_key: String,
+}
+
+/// Drift found between the store and the `objects` tables. Orphaned blobs have no row
+/// pointing at them; dangling versions point at a blob that is gone.
+#[derive(Debug, Default, Serialize)]
+pub struct ReconcileReport {
+    pub orphaned_blobs: Vec<String>,
+    pub dangling_versions: Vec<VersionBlob>,
+    pub repaired: bool,
+}
+
+/// How an image is fitted into the requested box when both sides are given.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scale to fit inside the box, keeping the aspect ratio.
+    #[default]
+    Contain,
+    /// Scale to cover the box and crop the overflow.
+    Cover,
+    /// Stretch to exactly the box.
+    Fill,
+}
+
+/// Formats transformed images can be encoded to.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl ImageFormat {
+    /// Output format matching a source content type, when it is one we can write.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type {
+            "image/jpeg" | "image/jpg" => Some(ImageFormat::Jpeg),
+            "image/png" => Some(ImageFormat::Png),
+            "image/webp" => Some(ImageFormat::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Png => "image/png",
+            ImageFormat::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+            ImageFormat::Webp => "webp",
+        }
+    }
+}
+
+/// A resize and re-encode of an image object. Rendered copies are cached under the
+/// source blob's key, so a new version of the object never serves a stale copy.
+#[derive(Debug, Clone)]
+pub struct ImageTransform {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+    pub format: Option<ImageFormat>,
+    pub quality: u8,
+}
+
+impl ImageTransform {
+    pub const MAX_DIMENSION: u32 = 4096;
+    pub const DEFAULT_QUALITY: u8 = 80;
+
+    pub fn validate(&self) -> Result<()> {
+        for side in [self.width, self.height].into_iter().flatten() {
+            if side == 0 || side > Self::MAX_DIMENSION {
+                return Err(Error::from(format!(
+                    "Width and height must be between 1 and {}",
+                    Self::MAX_DIMENSION
+                )));
+            }
+        }
+        if !(1..=100).contains(&self.quality) {
+            return Err(Error::from("Quality must be between 1 and 100"));
+        }
+        Ok(())
+    }
+
+    /// Prefix every rendered copy of `blob_key` is stored under.
+    pub fn cache_prefix(blob_key: &str) -> String {
+        format!("transforms/{}/", blob_key)
+    }
+
+    /// Store key of this transform of `blob_key` encoded as `format`.
+    pub fn cache_key(&self, blob_key: &str, format: ImageFormat) -> String {
+        let side = |s: Option<u32>| s.map_or_else(|| "auto".to_string(), |s| s.to_string());
+        format!(
+            "{}{}x{}-{:?}-q{}.{}",
+            Self::cache_prefix(blob_key),
+            side(self.width),
+            side(self.height),
+            self.fit,
+            self.quality,
+            format.extension()
+        )
+        .to_lowercase()
+    }
+
+    /// Decodes `source`, resizes it and encodes it as `format`. CPU bound, so callers
+    /// on the runtime should run it on a blocking thread.
+    pub fn apply(&self, source: &[u8], format: ImageFormat) -> Result<Vec<u8>> {
+        let image = image::load_from_memory(source)?;
+
+        let image = match (self.width, self.height) {
+            (None, None) => image,
+            (Some(width), None) => image.resize(width, u32::MAX, FilterType::Lanczos3),
+            (None, Some(height)) => image.resize(u32::MAX, height, FilterType::Lanczos3),
+            (Some(width), Some(height)) => match self.fit {
+                Fit::Contain => image.resize(width, height, FilterType::Lanczos3),
+                Fit::Cover => image.resize_to_fill(width, height, FilterType::Lanczos3),
+                Fit::Fill => image.resize_exact(width, height, FilterType::Lanczos3),
+            },
+        };
+
+        let mut output = Vec::new();
+        match format {
+            // JPEG has no alpha channel
+            ImageFormat::Jpeg => image
+                .to_rgb8()
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut output, self.quality))?,
+            ImageFormat::Png => image.write_with_encoder(PngEncoder::new(&mut output))?,
+            ImageFormat::Webp => image
+                .to_rgba8()
+                .write_with_encoder(WebPEncoder::new_lossless(&mut output))?,
+        }
+
+        Ok(output)
+    }
+}
+
+/// State of a resumable (tus) upload, kept in Redis until the last byte arrives.
+/// Bytes short of a full part wait in a separate buffer key, still in plaintext.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TusUpload {
+    pub id: String,
+    pub user_id: String,
+    pub key: String,
+    pub filename: String,
+    pub content_type: String,
+    pub blob_key: String,
+    pub multipart_id: String,
+    pub parts: Vec<String>,
+    pub length: i64,
+    pub offset: i64,
+    pub expires_at: i64,
+    pub key_id: Option<String>,
+    pub wrapped_key: Option<String>,
+    /// Hex nonce prefix of the sealed blob, which parts keep sealing under.
+    pub nonce_prefix: Option<String>,
+}
+
+impl TusUpload {
+    /// Rows the upload is recorded under once complete.
+    pub fn records(&self) -> Result<(Objects, ObjectVersion)> {
+        let user_id = Uuid::parse_str(&self.user_id)?;
+
+        let object = Objects {
+            id: 0,
+            user_id,
+            key: self.key.clone(),
+            filename: self.filename.clone(),
+            version: 1,
+            max_versions: None,
+            expires_at: None,
+        };
+
+        let version = ObjectVersion {
+            id: 0,
+            object_id: 0,
+            version: 1,
+            blob_key: self.blob_key.clone(),
+            content_type: self.content_type.clone(),
+            size_bytes: self.length,
+            created_at: None,
+            scan_status: ScanStatus::Pending.as_str().to_string(),
+            key_id: self.key_id.clone(),
+            wrapped_key: self.wrapped_key.clone(),
+            storage_class: StorageClass::Standard.as_str().to_string(),
+        };
+
+        Ok((object, version))
+    }
+}
+
 impl Objects {
-    pub async fn process_upload(
-        mut multipart: Multipart,
+    /// Root every key of a user lives under.
+    pub fn root(user_id: Uuid) -> String {
+        format!("{}/", user_id)
+    }
+
+    /// Builds the store key for a normalized path relative to the user root.
+    pub fn key_for(user_id: Uuid, path: &str) -> String {
+        format!("{}{}", Self::root(user_id), path)
+    }
+
+    /// Path of the object relative to the user root.
+    pub fn path(&self) -> &str {
+        let root = Self::root(self.user_id);
+        self.key.strip_prefix(root.as_str()).unwrap_or(&self.key)
+    }
+
+    /// Builds the rows for a new upload of `filename` into `folder`, with a fresh blob key.
+    pub fn new_upload(
         user_id: Uuid,
-    ) -> Result<(Objects, Bytes)> {
-        // Just get the first/only field
+        folder: &str,
+        filename: String,
+        content_type: String,
+        size_bytes: i64,
+    ) -> Result<(Objects, ObjectVersion)> {
+        if filename.is_empty() || filename.contains('/') || filename == "." || filename == ".." {
+            return Err(Error::from(format!("Invalid filename '{}'", filename)));
+        }
+
+        let path = match folder {
+            "" => filename.clone(),
+            folder => format!("{}/{}", folder, filename),
+        };
+
+        let object = Objects {
+            id: 0,
+            user_id,
+            key: Objects::key_for(user_id, &path),
+            filename,
+            version: 1,
+            max_versions: None,
+            expires_at: None,
+        };
+
+        let version = ObjectVersion {
+            id: 0,
+            object_id: 0,
+            version: 1,
+            blob_key: ObjectVersion::new_blob_key(user_id),
+            content_type,
+            size_bytes,
+            created_at: None,
+            scan_status: ScanStatus::Pending.as_str().to_string(),
+            key_id: None,
+            wrapped_key: None,
+            storage_class: StorageClass::Standard.as_str().to_string(),
+        };
+
+        Ok((object, version))
+    }
+
+    /// Takes the first/only field of a multipart upload with its filename and content
+    /// type, leaving the body unread until the upload is authorized.
+    pub async fn upload_field(multipart: &mut Multipart) -> Result<(Field<'_>, String, String)> {
         let field = multipart
             .next_field()
             .await?
@@ -112,21 +601,103 @@ impl Objects {
             .map(|ct| ct.to_string())
             .unwrap_or_else(|| "application/octet-stream".to_string());
 
-        let file_bytes = field.bytes().await?;
-        let size_bytes = file_bytes.len() as i64;
+        Ok((field, filename, content_type))
+    }
+
+    /// Reads an upload's body, chunk by chunk so it is cut off as soon as it outgrows
+    /// the quota.
+    pub async fn read_upload(mut field: Field<'_>, max_bytes: Option<i64>) -> Result<Bytes> {
+        let mut buffer = Vec::new();
+        while let Some(chunk) = field.chunk().await? {
+            buffer.extend_from_slice(&chunk);
+            if max_bytes.is_some_and(|max| buffer.len() as i64 > max) {
+                return Err(Error::QuotaExceeded(
+                    "Upload exceeds the remaining storage quota".into(),
+                ));
+            }
+        }
+        Ok(Bytes::from(buffer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // let file_bytes = file_bytes.ok_or_else(|| Error::from("missing file field"))?;
-        let object = Objects {
-            id: 0,
-            user_id: user_id,
-            key: format!("{}/{}", user_id, filename), // or however you want to generate key
-            filename,
-            // content_type,
-            // size_bytes,
-            // visibility: "private".to_string(),
-            // created_at: Utc::now(),
+    #[test]
+    fn test_usage_remaining() {
+        let usage = Usage {
+            used_bytes: 900,
+            quota_bytes: Some(1000),
+        };
+        assert_eq!(usage.remaining(0), Some(100));
+        assert_eq!(usage.remaining(10), Some(200));
+
+        let over = Usage {
+            used_bytes: 1500,
+            quota_bytes: Some(1000),
+        };
+        assert_eq!(over.remaining(10), Some(0));
+
+        let unlimited = Usage {
+            used_bytes: 1500,
+            quota_bytes: None,
+        };
+        assert_eq!(unlimited.remaining(10), None);
+    }
+
+    #[test]
+    fn test_shared_blob_key() {
+        let user_id = Uuid::nil();
+        let shared = ObjectVersion::shared_blob_key(user_id, "ab12", "secret");
+
+        assert!(shared.starts_with(&format!("blobs/{}/shared/", user_id)));
+        assert!(!shared.contains("ab12"));
+        assert_eq!(
+            shared,
+            ObjectVersion::shared_blob_key(user_id, "ab12", "secret")
+        );
+        assert_ne!(
+            shared,
+            ObjectVersion::shared_blob_key(user_id, "ab12", "other")
+        );
+        assert_ne!(
+            shared,
+            ObjectVersion::shared_blob_key(Uuid::max(), "ab12", "secret")
+        );
+        assert!(ObjectVersion::is_shared_blob(&shared));
+        assert!(ObjectVersion::is_shared_blob(&format!(
+            "blobs/{}/sha256/ab12",
+            user_id
+        )));
+        assert!(!ObjectVersion::is_shared_blob(
+            &ObjectVersion::new_blob_key(user_id)
+        ));
+    }
+
+    #[test]
+    fn test_image_transform() {
+        let mut source = Vec::new();
+        image::RgbImage::new(40, 20)
+            .write_with_encoder(PngEncoder::new(&mut source))
+            .unwrap();
+
+        let dimensions = |width, height, fit| {
+            let transform = ImageTransform {
+                width,
+                height,
+                fit,
+                format: None,
+                quality: ImageTransform::DEFAULT_QUALITY,
+            };
+            let output = transform.apply(&source, ImageFormat::Png).unwrap();
+            let image = image::load_from_memory(&output).unwrap();
+            (image.width(), image.height())
         };
 
-        Ok((object, file_bytes))
+        assert_eq!(dimensions(Some(20), None, Fit::Contain), (20, 10));
+        assert_eq!(dimensions(Some(10), Some(10), Fit::Contain), (10, 5));
+        assert_eq!(dimensions(Some(10), Some(10), Fit::Cover), (10, 10));
+        assert_eq!(dimensions(Some(10), Some(30), Fit::Fill), (10, 30));
     }
 }
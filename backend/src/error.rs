@@ -11,6 +11,8 @@ use thiserror::Error;
 pub enum Error {
     #[error("Database error: {0}")]
     SqlError(#[from] sqlx::Error),
+    #[error("Migration error: {0}")]
+    MigrateError(#[from] sqlx::migrate::MigrateError),
     #[error("Request error: {0}")]
     TracingError(#[from] tracing::subscriber::SetGlobalDefaultError),
     #[error("Io error: {0}")]
@@ -57,6 +59,12 @@ pub enum Error {
     UuidError(#[from] sqlx::types::uuid::Error),
     #[error("ParseInt: {0}")]
     ParseIntError(#[from] ParseIntError),
+    #[error("Image error: {0}")]
+    ImageError(#[from] image::ImageError),
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+    #[error("{1}")]
+    HttpError(StatusCode, String),
     // #[error("Ngrok connect error: {0}")]
     // ConnectError(#[from] ngrok::session::ConnectError),
     // #[error("Ngrok rpc error: {0}")]
@@ -100,13 +108,20 @@ impl Into<ApiResponse<String>> for Error {
             Error::SqlError(ref err) => {
                 // Check for specific constraint violations
                 if let sqlx::Error::Database(db_err) = err {
-                    if db_err.constraint() == Some("users_email_key") {
-                        (StatusCode::CONFLICT, "Email already registered".to_string())
-                    } else {
-                        (
+                    match db_err.constraint() {
+                        Some("users_email_key") => {
+                            (StatusCode::CONFLICT, "Email already registered".to_string())
+                        }
+                        Some("idx_object_key") => {
+                            (StatusCode::CONFLICT, "Object already exists".to_string())
+                        }
+                        Some("idx_user_folders") => {
+                            (StatusCode::CONFLICT, "Folder already exists".to_string())
+                        }
+                        _ => (
                             StatusCode::INTERNAL_SERVER_ERROR,
                             "Database error".to_string(),
-                        )
+                        ),
                     }
                 } else {
                     (
@@ -115,6 +130,7 @@ impl Into<ApiResponse<String>> for Error {
                     )
                 }
             }
+            Error::MigrateError(ref msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.to_string()),
             Error::TracingError(ref msg) => (StatusCode::BAD_REQUEST, msg.to_string()),
             Error::IoError(ref msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.to_string()),
             Error::EnvVarError(ref msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.to_string()),
@@ -137,6 +153,9 @@ impl Into<ApiResponse<String>> for Error {
             Error::MultipartError(ref msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.to_string()),
             Error::ParseIntError(ref msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.to_string()),
             Error::UuidError(ref msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.to_string()),
+            Error::ImageError(ref msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg.to_string()),
+            Error::QuotaExceeded(ref msg) => (StatusCode::INSUFFICIENT_STORAGE, msg.to_string()),
+            Error::HttpError(status, ref msg) => (status, msg.to_string()),
             Error::RequestError(ref msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.to_string()),
             Error::TokenRequestError(ref msg) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, msg.to_string())
@@ -152,6 +171,19 @@ impl Into<ApiResponse<String>> for Error {
     }
 }
 
+impl Error {
+    /// The message clients see, without the internals of server errors, which are
+    /// logged instead.
+    pub fn client_message(self) -> String {
+        let response: ApiResponse<String> = self.into();
+        if response.code == StatusCode::INTERNAL_SERVER_ERROR.as_u16() {
+            tracing::error!("Request failed: {}", response.message);
+            return "Internal server error".to_string();
+        }
+        response.message
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
         let response: ApiResponse<String> = self.into();
@@ -180,4 +212,13 @@ mod tests {
         // Test
         assert_eq!(error.to_string(), x_error.to_string());
     }
+
+    #[test]
+    fn test_client_message() {
+        let redis = Error::from("Redis error: connection refused (os error 111)");
+        assert_eq!(redis.client_message(), "Internal server error");
+
+        let forbidden = Error::HttpError(StatusCode::FORBIDDEN, "Not allowed".into());
+        assert_eq!(forbidden.client_message(), "Not allowed");
+    }
 }
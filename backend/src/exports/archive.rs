@@ -0,0 +1,553 @@
+//! Writes an export's ZIP to the object store in multipart parts, saving a checkpoint
+//! after each so a run that dies part way is picked up where the last part ended. The
+//! bytes of the entry being written are read again from its start and skipped up to the
+//! checkpoint, so entries' sources have to give the same bytes on every run.
+
+use super::models::{Checkpoint, DataExport, ExportEntry, ExportSource};
+use super::queries::ExportQueries;
+use crate::objects::zip::{Pending, ZipWriter};
+use crate::state::AppState;
+use crate::storage::object::envelope::{BlobEncryptor, DataKey, CHUNK_SIZE, PREFIX_SIZE};
+use crate::storage::object::PART_SIZE;
+use crate::{Error, Result};
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use object_store::path::Path;
+use sqlx::types::Uuid;
+
+/// How long a run holds an export, renewed with every part.
+pub const LEASE_SECONDS: i64 = 300;
+
+/// Where an archive's entries are read from and its parts and checkpoints go.
+#[async_trait]
+pub trait ArchiveTarget: Send + Sync {
+    /// Opens an entry's bytes, or None if it is gone.
+    async fn open(&self, entry: &ExportEntry) -> Result<Option<BoxStream<'static, Result<Bytes>>>>;
+    /// Uploads part `index` (0-based) and returns its content id.
+    async fn put_part(&self, index: usize, data: Bytes) -> Result<String>;
+    /// Saves where the archive got to once a part is written.
+    async fn save(&self, checkpoint: &Checkpoint, central: &[u8], buffer: &[u8]) -> Result<()>;
+    /// Completes the upload from its parts.
+    async fn complete(&self, parts: &[String]) -> Result<()>;
+}
+
+/// An export's archive in the object store, checkpointed in its row.
+pub struct ExportTarget<'a> {
+    state: &'a AppState,
+    id: Uuid,
+    key: Option<DataKey>,
+    blob: Path,
+    multipart_id: String,
+}
+
+pub struct ArchiveWriter<T> {
+    target: T,
+    encryptor: Option<BlobEncryptor>,
+    writer: ZipWriter,
+    checkpoint: Checkpoint,
+    buffer: Vec<u8>,
+}
+
+impl<'a> ArchiveWriter<ExportTarget<'a>> {
+    /// Picks up the export's archive at its last checkpoint, or at the start.
+    pub fn resume(state: &'a AppState, export: &DataExport) -> Result<Self> {
+        let multipart_id = export
+            .multipart_id
+            .clone()
+            .ok_or_else(|| Error::from("Export has no multipart upload"))?;
+        let key = state
+            .storage
+            .object
+            .data_key(export.key_id.as_deref(), export.wrapped_key.as_deref())?;
+        let target = ExportTarget {
+            state,
+            id: export.id,
+            key: key.clone(),
+            blob: Path::from(export.blob_key.as_str()),
+            multipart_id,
+        };
+        Self::with_target(target, export, key.as_ref())
+    }
+}
+
+impl<T: ArchiveTarget> ArchiveWriter<T> {
+    /// Picks up the export's archive at its last checkpoint in `target`.
+    pub fn with_target(target: T, export: &DataExport, key: Option<&DataKey>) -> Result<Self> {
+        let checkpoint = export
+            .checkpoint
+            .as_ref()
+            .map(|checkpoint| checkpoint.0.clone())
+            .unwrap_or_default();
+        let writer = ZipWriter {
+            offset: checkpoint.offset,
+            central: export.central.clone().unwrap_or_default(),
+            entries: checkpoint.entries,
+        };
+
+        // Parts always end on whole chunks, so sealing continues at the next one
+        let encryptor = match (key, export.nonce_prefix.as_deref()) {
+            (Some(key), Some(prefix)) => {
+                let prefix: [u8; PREFIX_SIZE] = hex::decode(prefix)
+                    .ok()
+                    .and_then(|prefix| prefix.try_into().ok())
+                    .ok_or_else(|| Error::EncryptionError("Invalid nonce prefix".into()))?;
+                let counter = checkpoint.sent / CHUNK_SIZE as u64;
+                Some(BlobEncryptor::resume(key, prefix, counter as u32))
+            }
+            _ => None,
+        };
+
+        Ok(ArchiveWriter {
+            target,
+            encryptor,
+            writer,
+            checkpoint,
+            buffer: export.buffer.clone().unwrap_or_default(),
+        })
+    }
+
+    /// Writes the entries from the checkpoint on and completes the upload.
+    pub async fn write(mut self, entries: &[ExportEntry]) -> Result<()> {
+        for (index, entry) in entries.iter().enumerate().skip(self.checkpoint.entry) {
+            let resumed = self.checkpoint.pending.take();
+            let Some(data) = self.target.open(entry).await? else {
+                if resumed.is_some() {
+                    return Err(Error::from(format!(
+                        "'{}' was deleted while being exported",
+                        entry.name
+                    )));
+                }
+                // Deleted since the export was collected, so no longer held
+                continue;
+            };
+
+            let (pending, crc, read) = match resumed {
+                Some(pending) => (pending, self.checkpoint.crc, self.checkpoint.read),
+                None => {
+                    let (header, pending) =
+                        self.writer.begin(entry.name.clone(), entry.modified)?;
+                    self.buffer.extend_from_slice(&header);
+                    (pending, 0, 0)
+                }
+            };
+            let (crc, size) = self.copy(index, &pending, data, crc, read).await?;
+            let descriptor = self.writer.end(pending, crc, size)?;
+            self.buffer.extend_from_slice(&descriptor);
+
+            if self.buffer.len() >= PART_SIZE {
+                self.checkpoint.entry = index + 1;
+                self.checkpoint.crc = 0;
+                self.checkpoint.read = 0;
+                self.flush().await?;
+            }
+        }
+
+        let writer = std::mem::take(&mut self.writer);
+        self.buffer.extend_from_slice(&writer.finish()?);
+        self.complete().await
+    }
+
+    /// Copies an entry's data past the `read` bytes of it already written, returning
+    /// its CRC and size.
+    async fn copy(
+        &mut self,
+        index: usize,
+        pending: &Pending,
+        mut data: BoxStream<'static, Result<Bytes>>,
+        crc: u32,
+        mut read: u64,
+    ) -> Result<(u32, u64)> {
+        let mut hasher = crc32fast::Hasher::new_with_initial(crc);
+        let mut skip = read;
+        while let Some(chunk) = data.next().await {
+            let mut chunk = chunk?;
+            if skip > 0 {
+                let skipped = skip.min(chunk.len() as u64);
+                chunk = chunk.slice(skipped as usize..);
+                skip -= skipped;
+            }
+            if chunk.is_empty() {
+                continue;
+            }
+
+            hasher.update(&chunk);
+            read += chunk.len() as u64;
+            self.buffer.extend_from_slice(&chunk);
+            if self.buffer.len() >= PART_SIZE {
+                self.checkpoint.entry = index;
+                self.checkpoint.pending = Some(pending.clone());
+                self.checkpoint.crc = hasher.clone().finalize();
+                self.checkpoint.read = read;
+                self.flush().await?;
+            }
+        }
+        if skip > 0 {
+            return Err(Error::from("Export entry shrank while being exported"));
+        }
+
+        self.checkpoint.pending = None;
+        Ok((hasher.finalize(), read))
+    }
+
+    /// Sends the whole chunks buffered as the next part and saves the checkpoint the
+    /// caller set up for after it.
+    async fn flush(&mut self) -> Result<()> {
+        let len = self.buffer.len() - self.buffer.len() % CHUNK_SIZE;
+        let data: Vec<u8> = self.buffer.drain(..len).collect();
+        let data = self.seal(&data, false)?;
+        self.put_part(data).await?;
+
+        self.checkpoint.sent += len as u64;
+        self.checkpoint.offset = self.writer.offset;
+        self.checkpoint.entries = self.writer.entries;
+        self.target
+            .save(&self.checkpoint, &self.writer.central, &self.buffer)
+            .await
+    }
+
+    /// Sends what is left as the last part and completes the upload.
+    async fn complete(mut self) -> Result<()> {
+        let rest = std::mem::take(&mut self.buffer);
+        let rest = self.seal(&rest, true)?;
+        if !rest.is_empty() {
+            self.put_part(rest).await?;
+        }
+        self.target.complete(&self.checkpoint.parts).await
+    }
+
+    /// Seals bytes for the next part when the export is encrypted; the first part
+    /// starts with the sealed blob's header.
+    fn seal(&mut self, data: &[u8], last: bool) -> Result<Vec<u8>> {
+        let Some(encryptor) = &mut self.encryptor else {
+            return Ok(data.to_vec());
+        };
+        let mut sealed = match self.checkpoint.parts.is_empty() {
+            true => encryptor.header().to_vec(),
+            false => Vec::new(),
+        };
+        sealed.extend_from_slice(&encryptor.seal(data, last)?);
+        Ok(sealed)
+    }
+
+    /// Uploads the next part. A part sent again after a run died before its checkpoint
+    /// replaces the earlier one.
+    async fn put_part(&mut self, data: Vec<u8>) -> Result<()> {
+        let content_id = self
+            .target
+            .put_part(self.checkpoint.parts.len(), Bytes::from(data))
+            .await?;
+        self.checkpoint.parts.push(content_id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ArchiveTarget for ExportTarget<'_> {
+    async fn open(&self, entry: &ExportEntry) -> Result<Option<BoxStream<'static, Result<Bytes>>>> {
+        let storage = &self.state.storage;
+        match &entry.source {
+            ExportSource::Document { blob_key } => {
+                let blob = Path::from(blob_key.as_str());
+                let data = storage
+                    .object
+                    .get_sealed_stream(&blob, self.key.clone())
+                    .await?;
+                Ok(Some(data))
+            }
+            ExportSource::Object { object_id, version } => {
+                let pool = &storage.postgres.pool;
+                match ExportQueries::version(*object_id, *version, pool).await? {
+                    Some(version) => {
+                        version.ensure_servable()?;
+                        Ok(Some(storage.open_version(&version).await?))
+                    }
+                    None => Ok(None),
+                }
+            }
+        }
+    }
+
+    async fn put_part(&self, index: usize, data: Bytes) -> Result<String> {
+        self.state
+            .storage
+            .object
+            .put_part(&self.blob, &self.multipart_id, index, data)
+            .await
+    }
+
+    async fn save(&self, checkpoint: &Checkpoint, central: &[u8], buffer: &[u8]) -> Result<()> {
+        ExportQueries::save_checkpoint(
+            self.id,
+            checkpoint,
+            central,
+            buffer,
+            LEASE_SECONDS,
+            &self.state.storage.postgres.pool,
+        )
+        .await
+    }
+
+    async fn complete(&self, parts: &[String]) -> Result<()> {
+        self.state
+            .storage
+            .object
+            .complete_multipart(&self.blob, &self.multipart_id, parts)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::object::envelope::decrypt_stream;
+    use chrono::{TimeZone, Utc};
+    use sqlx::types::Json;
+    use std::collections::{BTreeMap, HashMap};
+    use std::ops::RangeInclusive;
+    use std::sync::Mutex;
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[derive(Default)]
+    struct Stored {
+        parts: BTreeMap<usize, Vec<u8>>,
+        uploads: Vec<usize>,
+        saves: usize,
+        saved: Option<(Checkpoint, Vec<u8>, Vec<u8>)>,
+        archive: Option<Vec<u8>>,
+    }
+
+    /// Keeps the archive in memory, failing the run at the `fail_at`th save.
+    struct MemoryTarget {
+        documents: HashMap<String, Vec<u8>>,
+        fail_at: Option<usize>,
+        stored: Mutex<Stored>,
+    }
+
+    #[async_trait]
+    impl ArchiveTarget for &MemoryTarget {
+        async fn open(
+            &self,
+            entry: &ExportEntry,
+        ) -> Result<Option<BoxStream<'static, Result<Bytes>>>> {
+            let ExportSource::Document { blob_key } = &entry.source else {
+                return Ok(None);
+            };
+            // Chunks that don't line up with parts, so resuming skips into one
+            let chunks: Vec<Result<Bytes>> = self.documents[blob_key]
+                .chunks(300_000)
+                .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                .collect();
+            Ok(Some(futures::stream::iter(chunks).boxed()))
+        }
+
+        async fn put_part(&self, index: usize, data: Bytes) -> Result<String> {
+            let mut stored = self.stored.lock().unwrap();
+            stored.parts.insert(index, data.to_vec());
+            stored.uploads.push(index);
+            Ok(index.to_string())
+        }
+
+        async fn save(&self, checkpoint: &Checkpoint, central: &[u8], buffer: &[u8]) -> Result<()> {
+            let mut stored = self.stored.lock().unwrap();
+            stored.saves += 1;
+            if Some(stored.saves) == self.fail_at {
+                return Err(Error::from("Run died"));
+            }
+            stored.saved = Some((checkpoint.clone(), central.to_vec(), buffer.to_vec()));
+            Ok(())
+        }
+
+        async fn complete(&self, parts: &[String]) -> Result<()> {
+            let mut stored = self.stored.lock().unwrap();
+            let archive = parts
+                .iter()
+                .map(|part| stored.parts[&part.parse::<usize>().unwrap()].as_slice())
+                .collect::<Vec<_>>()
+                .concat();
+            stored.archive = Some(archive);
+            Ok(())
+        }
+    }
+
+    fn entries(sizes: &[(&str, usize)]) -> (Vec<ExportEntry>, HashMap<String, Vec<u8>>) {
+        let modified = Utc.with_ymd_and_hms(2024, 5, 6, 7, 8, 10).unwrap();
+        let mut entries = Vec::new();
+        let mut documents = HashMap::new();
+        for (index, &(name, size)) in sizes.iter().enumerate() {
+            let data: Vec<u8> = (0..size).map(|i| (i * 7 + index) as u8).collect();
+            entries.push(ExportEntry {
+                name: name.to_string(),
+                modified,
+                size: size as u64,
+                source: ExportSource::Document {
+                    blob_key: name.to_string(),
+                },
+            });
+            documents.insert(name.to_string(), data);
+        }
+        (entries, documents)
+    }
+
+    fn export(saved: Option<(Checkpoint, Vec<u8>, Vec<u8>)>, prefix: Option<String>) -> DataExport {
+        let (checkpoint, central, buffer) = match saved {
+            Some((checkpoint, central, buffer)) => {
+                (Some(Json(checkpoint)), Some(central), Some(buffer))
+            }
+            None => (None, None, None),
+        };
+        DataExport {
+            id: Uuid::nil(),
+            user_id: Uuid::nil(),
+            status: "running".to_string(),
+            blob_key: "archive.zip".to_string(),
+            key_id: None,
+            wrapped_key: None,
+            nonce_prefix: prefix,
+            multipart_id: Some("upload".to_string()),
+            entries: None,
+            checkpoint,
+            central,
+            buffer,
+            attempts: 0,
+            error: None,
+            leased_until: None,
+            created_at: Utc::now(),
+            completed_at: None,
+            expires_at: None,
+        }
+    }
+
+    /// Runs the writer from the target's last checkpoint, sealing with `key` and
+    /// `prefix` if given.
+    async fn run(
+        target: &MemoryTarget,
+        key: Option<&DataKey>,
+        prefix: Option<&str>,
+        entries: &[ExportEntry],
+    ) -> Result<()> {
+        let saved = target.stored.lock().unwrap().saved.clone();
+        let export = export(saved, prefix.map(str::to_string));
+        ArchiveWriter::with_target(target, &export, key)?
+            .write(entries)
+            .await
+    }
+
+    /// Checks every record of the archive, returning its files.
+    fn read_archive(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let end = archive.len() - 22;
+        assert_eq!(u32_at(archive, end), 0x06054b50);
+
+        let mut at = u32_at(archive, end + 16) as usize;
+        let mut files = Vec::new();
+        for _ in 0..u16_at(archive, end + 10) {
+            assert_eq!(u32_at(archive, at), 0x02014b50);
+            let crc = u32_at(archive, at + 16);
+            let size = u32_at(archive, at + 24) as usize;
+            let name_len = u16_at(archive, at + 28) as usize;
+            let name = String::from_utf8(archive[at + 46..at + 46 + name_len].to_vec()).unwrap();
+
+            let local = u32_at(archive, at + 42) as usize;
+            assert_eq!(u32_at(archive, local), 0x04034b50);
+            assert_eq!(&archive[local + 30..local + 30 + name_len], name.as_bytes());
+            let data = local + 30 + name_len;
+            assert_eq!(crc32fast::hash(&archive[data..data + size]), crc);
+            assert_eq!(u32_at(archive, data + size), 0x08074b50);
+            assert_eq!(u32_at(archive, data + size + 4), crc);
+
+            files.push((name, archive[data..data + size].to_vec()));
+            at += 46 + name_len;
+        }
+        assert_eq!(at, end);
+        files
+    }
+
+    /// Writes the archive in one run, then again with a run that dies at each of the
+    /// `fail_at` saves and one resuming it, checking they all come out the same.
+    /// Returns the archive's files.
+    async fn write_resumed(
+        sizes: &[(&str, usize)],
+        key: Option<&DataKey>,
+        fail_at: RangeInclusive<usize>,
+    ) -> Vec<(String, Vec<u8>)> {
+        let (entries, documents) = entries(sizes);
+        let prefix = key.map(|key| hex::encode(BlobEncryptor::new(key).prefix()));
+        let target = |fail_at| MemoryTarget {
+            documents: documents.clone(),
+            fail_at,
+            stored: Mutex::default(),
+        };
+
+        let whole = target(None);
+        run(&whole, key, prefix.as_deref(), &entries).await.unwrap();
+        let expected = whole.stored.lock().unwrap().archive.clone().unwrap();
+
+        for fail_at in fail_at {
+            let target = target(Some(fail_at));
+            let died = run(&target, key, prefix.as_deref(), &entries).await;
+            assert!(died.is_err());
+            run(&target, key, prefix.as_deref(), &entries)
+                .await
+                .unwrap();
+
+            let stored = target.stored.lock().unwrap();
+            assert!(
+                stored.archive.as_ref() == Some(&expected),
+                "archive differs after failing at save {}",
+                fail_at
+            );
+            // Only the part sent after the last checkpoint is sent again
+            let resent = stored.uploads.iter().filter(|&&index| index == fail_at - 1);
+            assert_eq!(resent.count(), 2);
+            assert_eq!(stored.uploads.len(), stored.parts.len() + 1);
+        }
+
+        let archive = match key {
+            Some(key) => {
+                let sealed = futures::stream::iter([Ok(Bytes::from(expected))]);
+                decrypt_stream(key.clone(), sealed)
+                    .map(|chunk| chunk.unwrap().to_vec())
+                    .concat()
+                    .await
+            }
+            None => expected,
+        };
+        let files = read_archive(&archive);
+        for (name, data) in &files {
+            assert!(&documents[name] == data, "{} differs", name);
+        }
+        files
+    }
+
+    #[tokio::test]
+    async fn test_resume_writes_same_archive() {
+        // The first entry's descriptor fills the first part, so it checkpoints between
+        // entries; the later ones checkpoint part way through their data
+        let sizes = [
+            ("a.bin", PART_SIZE - 40),
+            ("b.json", 100),
+            ("c.bin", 6 * 1024 * 1024),
+            ("d.bin", 5 * 1024 * 1024),
+        ];
+        let files = write_resumed(&sizes, None, 1..=3).await;
+
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a.bin", "b.json", "c.bin", "d.bin"]);
+    }
+
+    #[tokio::test]
+    async fn test_resume_writes_same_sealed_archive() {
+        // Sealing continues from the second checkpoint, part way through the entry
+        let key = DataKey::generate();
+        let files = write_resumed(&[("a.bin", 2 * PART_SIZE + 500_000)], Some(&key), 2..=2).await;
+        assert_eq!(files.len(), 1);
+    }
+}
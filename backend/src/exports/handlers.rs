@@ -0,0 +1,99 @@
+use super::models::DataExport;
+use super::queries::ExportQueries;
+use crate::audit::{Audit, Event, Outcome};
+use crate::auth::models::VerifyQuery;
+use crate::crypt::jwt::Claims;
+use crate::error::Result;
+use crate::response::ApiResponse;
+use crate::state::AppState;
+use crate::Error;
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::Extension;
+use chrono::Utc;
+use object_store::path::Path;
+use sqlx::types::Uuid;
+use std::sync::Arc;
+
+/// Starts exporting the caller's data; they are emailed a link once it is ready.
+pub async fn request_export(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+) -> Result<impl IntoResponse> {
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let event = Event::new("user.request_export").actor(user_id);
+
+    let result = super::request(&state, user_id).await;
+    audit.record(&state, event, Outcome::of(&result)).await;
+
+    Ok(ApiResponse::new(
+        StatusCode::ACCEPTED,
+        "Export started, a download link will be emailed when it is ready.",
+        result?,
+    ))
+}
+
+pub async fn list_exports(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse> {
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let exports = ExportQueries::list(user_id, &state.storage.postgres.pool).await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully retrieved exports",
+        exports,
+    ))
+}
+
+/// Streams a finished export to whoever holds the emailed link.
+pub async fn download_export(
+    State(state): State<Arc<AppState>>,
+    audit: Audit,
+    Query(query): Query<VerifyQuery>,
+) -> Result<impl IntoResponse> {
+    let export_id = match state
+        .storage
+        .redis
+        .get_token(&query.token, "download-export")
+        .await
+    {
+        Ok(export_id) => export_id,
+        Err(_) => return Err(Error::CustomError("Invalid or expired token".into())),
+    };
+
+    let export = ExportQueries::get(export_id, &state.storage.postgres.pool)
+        .await?
+        .filter(|export| export.status == "completed")
+        .filter(|export| export.expires_at.is_some_and(|at| at > Utc::now()))
+        .ok_or_else(|| Error::HttpError(StatusCode::NOT_FOUND, "Export has expired".into()))?;
+
+    let DataExport {
+        user_id,
+        created_at,
+        ..
+    } = export;
+    let object = &state.storage.object;
+    let key = object.data_key(export.key_id.as_deref(), export.wrapped_key.as_deref())?;
+    let result = object
+        .get_sealed_stream(&Path::from(export.blob_key.as_str()), key)
+        .await;
+    let event = Event::new("user.download_export").actor(user_id);
+    audit.record(&state, event, Outcome::of(&result)).await;
+
+    let headers = [
+        (header::CONTENT_TYPE, "application/zip".to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"export-{}.zip\"",
+                created_at.format("%Y-%m-%d")
+            ),
+        ),
+    ];
+    Ok((headers, Body::from_stream(result?)).into_response())
+}
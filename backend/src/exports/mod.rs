@@ -0,0 +1,335 @@
+//! Exports of everything held about a user: their account, sign in methods, sessions,
+//! audit trail, object metadata and the objects themselves, as one ZIP in the object
+//! store. An export is built in the background and resumed from its last checkpoint if
+//! a run dies; once written, the user is emailed a link that works until it expires.
+
+use crate::audit::{Audit, Event, Outcome};
+use crate::config::CONFIG;
+use crate::crypt::tokens::generate_token;
+use crate::data::{CurrentVersion, Objects};
+use crate::error::{Error, Result};
+use crate::objects::zip;
+use crate::smtp::messages::{export_ready_body, Email};
+use crate::state::AppState;
+use crate::storage::object::envelope::BlobEncryptor;
+use crate::users::queries::UserQueries;
+use archive::{ArchiveWriter, LEASE_SECONDS};
+use axum::body::Bytes;
+use chrono::{Duration, Utc};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::Address;
+use models::{DataExport, ExportEntry, ExportSource, ExportStatus};
+use object_store::path::Path;
+use queries::ExportQueries;
+use sqlx::types::Uuid;
+use std::sync::Arc;
+
+pub mod archive;
+pub mod handlers;
+pub mod models;
+pub mod queries;
+pub mod routes;
+
+/// Runs given up on, after which the export is failed.
+const MAX_ATTEMPTS: i32 = 5;
+/// Exports picked up per sweep.
+const RESUME_BATCH: i64 = 20;
+/// Expired exports deleted per query while sweeping.
+const EXPIRE_BATCH: i64 = 100;
+
+/// Starts exporting the user's data in the background, or returns the export already
+/// underway.
+pub async fn request(state: &Arc<AppState>, user_id: Uuid) -> Result<ExportStatus> {
+    let pool = &state.storage.postgres.pool;
+    if let Some(active) = ExportQueries::active(user_id, pool).await? {
+        return Ok(active);
+    }
+
+    let sealed = state.storage.object.new_data_key()?;
+    let nonce_prefix = sealed
+        .as_ref()
+        .map(|(key, _, _)| hex::encode(BlobEncryptor::new(key).prefix()));
+    let created = ExportQueries::create(
+        user_id,
+        &DataExport::prefix(user_id),
+        sealed.as_ref().map(|(_, key_id, _)| key_id.as_str()),
+        sealed.as_ref().map(|(_, _, wrapped)| wrapped.as_str()),
+        nonce_prefix.as_deref(),
+        pool,
+    )
+    .await?;
+    let export = match created {
+        Some(export) => export,
+        // Another request got there first
+        None => ExportQueries::active(user_id, pool)
+            .await?
+            .ok_or_else(|| Error::from("Export finished while being requested"))?,
+    };
+
+    tokio::spawn(run(state.clone(), Uuid::parse_str(&export.id)?));
+    Ok(export)
+}
+
+/// Runs an export unless another run holds it. A failed run leaves the export to be
+/// retried by the next sweep, until it has failed too often. One holding too much
+/// data for an archive fails at once.
+pub async fn run(state: Arc<AppState>, id: Uuid) {
+    let pool = &state.storage.postgres.pool;
+    let export = match ExportQueries::claim(id, LEASE_SECONDS, pool).await {
+        Ok(Some(export)) => export,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::error!("Failed to claim export {}: {}", id, e);
+            return;
+        }
+    };
+    let user_id = export.user_id;
+    let attempts = export.attempts;
+
+    let Err(e) = build(&state, export).await else {
+        return;
+    };
+    tracing::error!("Export {} failed: {}", id, e);
+    let error = e.to_string();
+    // Running again won't make the user's data fit in one archive
+    let hopeless = matches!(e, Error::QuotaExceeded(_));
+    let handled = match hopeless || attempts >= MAX_ATTEMPTS {
+        true => abandon(&state, id, user_id, &error).await,
+        false => ExportQueries::release(id, &error, pool).await,
+    };
+    if let Err(e) = handled {
+        tracing::error!("Failed to record failure of export {}: {}", id, e);
+    }
+}
+
+/// Picks up exports that are waiting or whose run died, returning how many were.
+pub async fn resume_due(state: &Arc<AppState>) -> Result<usize> {
+    let ids = ExportQueries::claimable(RESUME_BATCH, &state.storage.postgres.pool).await?;
+    for id in &ids {
+        run(state.clone(), *id).await;
+    }
+    Ok(ids.len())
+}
+
+/// Deletes exports whose link has expired with their archives, returning how many were.
+pub async fn sweep_expired(state: &AppState) -> Result<u64> {
+    let pool = &state.storage.postgres.pool;
+    let mut swept = 0;
+    loop {
+        let expired = ExportQueries::expired(EXPIRE_BATCH, pool).await?;
+        if expired.is_empty() {
+            break;
+        }
+
+        for (id, user_id) in expired {
+            state
+                .storage
+                .object
+                .delete_prefix(&DataExport::export_prefix(user_id, id))
+                .await?;
+            ExportQueries::delete(id, pool).await?;
+            swept += 1;
+        }
+    }
+    Ok(swept)
+}
+
+/// Collects the export if this is its first run, writes the archive and sends the link.
+async fn build(state: &AppState, export: DataExport) -> Result<()> {
+    let pool = &state.storage.postgres.pool;
+    let export = match export.entries {
+        Some(_) => export,
+        None => {
+            collect(state, &export).await?;
+            ExportQueries::get(export.id, pool)
+                .await?
+                .ok_or_else(|| Error::from("Export was deleted while running"))?
+        }
+    };
+    let entries = match &export.entries {
+        Some(entries) => &entries.0,
+        None => return Err(Error::from("Export has no entries")),
+    };
+
+    // A run that completed the upload but died before recording it has nothing to redo
+    let blob = Path::from(export.blob_key.as_str());
+    if export.checkpoint.is_none() || !state.storage.object.exists(&blob).await? {
+        ArchiveWriter::resume(state, &export)?
+            .write(entries)
+            .await?;
+    }
+
+    finish(state, &export, entries).await
+}
+
+/// Stages the JSON documents next to the archive and fixes the entries it will hold.
+async fn collect(state: &AppState, export: &DataExport) -> Result<()> {
+    let pool = &state.storage.postgres.pool;
+    let object = &state.storage.object;
+    let user_id = export.user_id;
+    let root = Objects::root(user_id);
+    let prefix = DataExport::export_prefix(user_id, export.id);
+    let key = object.data_key(export.key_id.as_deref(), export.wrapped_key.as_deref())?;
+
+    let documents = [
+        ("account.json", ExportQueries::account(user_id, pool).await?),
+        (
+            "auth_providers.json",
+            ExportQueries::auth_providers(user_id, pool).await?,
+        ),
+        (
+            "sessions.json",
+            ExportQueries::sessions(user_id, pool).await?,
+        ),
+        (
+            "audit_events.json",
+            ExportQueries::audit_events(user_id, pool).await?,
+        ),
+        (
+            "objects.json",
+            ExportQueries::objects(user_id, &root, pool).await?,
+        ),
+    ];
+
+    let now = Utc::now();
+    let mut staged = Vec::new();
+    let mut entries = Vec::new();
+    for (name, document) in documents {
+        let blob_key = format!("{}{}", prefix, name);
+        let data = Bytes::from(serde_json::to_vec_pretty(&document)?);
+        entries.push(ExportEntry {
+            name: name.to_string(),
+            modified: now,
+            size: data.len() as u64,
+            source: ExportSource::Document {
+                blob_key: blob_key.clone(),
+            },
+        });
+        staged.push((blob_key, data));
+    }
+
+    let versions = state
+        .storage
+        .postgres
+        .list_current_versions(user_id, &[], Some(&root), zip::MAX_ENTRIES as i64 + 1)
+        .await?;
+    for CurrentVersion { key, version, .. } in versions {
+        // Unscanned and quarantined files are only listed in objects.json
+        if version.ensure_servable().is_err() {
+            continue;
+        }
+        let path = key.strip_prefix(root.as_str()).unwrap_or(&key);
+        entries.push(ExportEntry {
+            name: format!("objects/{}", path),
+            modified: version.created_at.unwrap_or(now),
+            size: version.size_bytes as u64,
+            source: ExportSource::Object {
+                object_id: version.object_id,
+                version: version.version,
+            },
+        });
+    }
+
+    let size = zip::archive_size(entries.iter().map(|e| (e.name.len(), e.size)));
+    if entries.len() > zip::MAX_ENTRIES || size > zip::MAX_ARCHIVE_BYTES {
+        return Err(Error::QuotaExceeded("Too much data for one archive".into()));
+    }
+
+    for (blob_key, data) in staged {
+        object
+            .put_sealed(&Path::from(blob_key.as_str()), &data, key.as_ref())
+            .await?;
+    }
+
+    let multipart_id = object
+        .create_multipart(&Path::from(export.blob_key.as_str()))
+        .await?;
+    ExportQueries::set_entries(export.id, &entries, &multipart_id, LEASE_SECONDS, pool).await
+}
+
+/// Marks the export complete, then emails the download link and drops the staged
+/// documents.
+async fn finish(state: &AppState, export: &DataExport, entries: &[ExportEntry]) -> Result<()> {
+    let pool = &state.storage.postgres.pool;
+    let user = UserQueries::get_user(export.user_id, pool).await?;
+    let expires_at = Utc::now() + Duration::hours(CONFIG.export_link_expire_hours);
+
+    let token = generate_token();
+    let ttl = (CONFIG.export_link_expire_hours * 3600).max(1) as u64;
+    state
+        .storage
+        .redis
+        .store_token(&token, "download-export", export.id, ttl)
+        .await?;
+
+    let url = format!("{}/exports/download?token={}", CONFIG.app_url, token);
+    let email = Email {
+        recipient: Mailbox::new(Some("".to_owned()), user.email.parse::<Address>()?),
+        sender: Mailbox::new(
+            Some("Info".to_owned()),
+            CONFIG.smtp_email.parse::<Address>()?,
+        ),
+        subject: String::from("Your data export is ready"),
+        header: ContentType::TEXT_HTML,
+        body: export_ready_body(&url, &expires_at.format("%B %-d, %Y").to_string())?,
+    };
+
+    // Once complete the export isn't retried, so a failed email is only logged
+    ExportQueries::complete(export.id, expires_at, pool).await?;
+    if let Err(e) = state.smtp.send_email(email) {
+        tracing::warn!("Failed to email the link to export {}: {}", export.id, e);
+    }
+
+    let staged = entries.iter().filter_map(|entry| match &entry.source {
+        ExportSource::Document { blob_key } => Some(blob_key),
+        ExportSource::Object { .. } => None,
+    });
+    for blob_key in staged {
+        let blob = Path::from(blob_key.as_str());
+        if let Err(e) = state.storage.object.delete(&blob).await {
+            tracing::warn!("Failed to delete staged {}: {}", blob_key, e);
+        }
+    }
+
+    let event = Event::new("user.export").actor(export.user_id);
+    Audit::default()
+        .record(state, event, Outcome::Success)
+        .await;
+    Ok(())
+}
+
+/// Fails an export that won't succeed, dropping what was written of it.
+async fn abandon(state: &AppState, id: Uuid, user_id: Uuid, error: &str) -> Result<()> {
+    let pool = &state.storage.postgres.pool;
+    if let Some(DataExport {
+        blob_key,
+        multipart_id: Some(multipart_id),
+        ..
+    }) = ExportQueries::get(id, pool).await?
+    {
+        let blob = Path::from(blob_key.as_str());
+        if let Err(e) = state
+            .storage
+            .object
+            .abort_multipart(&blob, &multipart_id)
+            .await
+        {
+            tracing::warn!("Failed to abort export {}: {}", id, e);
+        }
+    }
+    state
+        .storage
+        .object
+        .delete_prefix(&DataExport::export_prefix(user_id, id))
+        .await?;
+
+    let expires_at = Utc::now() + Duration::hours(CONFIG.export_link_expire_hours);
+    ExportQueries::fail(id, error, expires_at, pool).await?;
+
+    let event = Event::new("user.export").actor(user_id);
+    Audit::default()
+        .record(state, event, Outcome::Failure)
+        .await;
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use crate::objects::zip::Pending;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::{Json, Uuid};
+use sqlx::FromRow;
+
+/// A user's data export and, while it is being built, where it got to.
+#[derive(Debug, FromRow)]
+pub struct DataExport {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub status: String,
+    pub blob_key: String,
+    pub key_id: Option<String>,
+    pub wrapped_key: Option<String>,
+    pub nonce_prefix: Option<String>,
+    pub multipart_id: Option<String>,
+    pub entries: Option<Json<Vec<ExportEntry>>>,
+    pub checkpoint: Option<Json<Checkpoint>>,
+    pub central: Option<Vec<u8>>,
+    pub buffer: Option<Vec<u8>>,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub leased_until: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl DataExport {
+    /// Where a user's exports are kept in the object store.
+    pub fn prefix(user_id: Uuid) -> String {
+        format!("exports/{}/", user_id)
+    }
+
+    /// Where an export keeps its archive and the documents staged for it.
+    pub fn export_prefix(user_id: Uuid, id: Uuid) -> String {
+        format!("{}{}/", Self::prefix(user_id), id)
+    }
+}
+
+/// What the user sees of an export.
+#[derive(Debug, FromRow, Serialize)]
+pub struct ExportStatus {
+    pub id: String,
+    pub status: String,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A file of the archive, fixed when the export is collected so every run writes the
+/// same archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportEntry {
+    pub name: String,
+    pub modified: DateTime<Utc>,
+    pub size: u64,
+    #[serde(flatten)]
+    pub source: ExportSource,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum ExportSource {
+    /// A JSON document staged next to the archive, sealed under its data key.
+    Document { blob_key: String },
+    /// A version of one of the user's objects.
+    Object { object_id: i32, version: i32 },
+}
+
+/// Position in the archive once a part is written: the parts so far, the entry being
+/// written and the bytes of it read. The central directory and the bytes short of a
+/// part are kept in their own columns.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub parts: Vec<String>,
+    /// Archive bytes sent in parts, which sets where sealing picks up.
+    pub sent: u64,
+    pub entry: usize,
+    /// Set while part of the entry's data is written.
+    pub pending: Option<Pending>,
+    pub read: u64,
+    pub crc: u32,
+    pub offset: u64,
+    pub entries: u16,
+}
@@ -0,0 +1,453 @@
+use super::models::{Checkpoint, DataExport, ExportEntry, ExportStatus};
+use crate::data::ObjectVersion;
+use crate::Result;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::types::{Json, Uuid};
+use sqlx::PgPool;
+
+pub struct ExportQueries;
+
+impl ExportQueries {
+    /// Creates a pending export whose archive goes under `prefix`, sealed under the data
+    /// key and nonce prefix when given. None when the user already has one underway.
+    pub async fn create(
+        user_id: Uuid,
+        prefix: &str,
+        key_id: Option<&str>,
+        wrapped_key: Option<&str>,
+        nonce_prefix: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<Option<ExportStatus>> {
+        let export: Option<ExportStatus> = sqlx::query_as(
+            r#"
+            INSERT INTO data_exports (id, user_id, blob_key, key_id, wrapped_key, nonce_prefix)
+            SELECT id, $1, $2 || id || '/archive.zip', $3, $4, $5
+            FROM (SELECT gen_random_uuid() AS id) AS new
+            ON CONFLICT (user_id) WHERE status IN ('pending', 'running') DO NOTHING
+            RETURNING id::TEXT AS id, status, error, created_at, completed_at, expires_at
+        "#,
+        )
+        .bind(user_id)
+        .bind(prefix)
+        .bind(key_id)
+        .bind(wrapped_key)
+        .bind(nonce_prefix)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(export)
+    }
+
+    /// The user's exports, newest first.
+    pub async fn list(user_id: Uuid, pool: &PgPool) -> Result<Vec<ExportStatus>> {
+        let exports: Vec<ExportStatus> = sqlx::query_as(
+            r#"
+            SELECT id::TEXT AS id, status, error, created_at, completed_at, expires_at
+            FROM data_exports
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+        "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(exports)
+    }
+
+    /// The user's export still being built, if any.
+    pub async fn active(user_id: Uuid, pool: &PgPool) -> Result<Option<ExportStatus>> {
+        let export: Option<ExportStatus> = sqlx::query_as(
+            r#"
+            SELECT id::TEXT AS id, status, error, created_at, completed_at, expires_at
+            FROM data_exports
+            WHERE user_id = $1
+            AND status IN ('pending', 'running')
+            LIMIT 1
+        "#,
+        )
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(export)
+    }
+
+    pub async fn get(id: Uuid, pool: &PgPool) -> Result<Option<DataExport>> {
+        let export: Option<DataExport> = sqlx::query_as(
+            r#"
+            SELECT *
+            FROM data_exports
+            WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(export)
+    }
+
+    /// Takes a pending export, or a running one whose lease ran out, for `lease_seconds`.
+    /// None when another run holds it or it is done.
+    pub async fn claim(id: Uuid, lease_seconds: i64, pool: &PgPool) -> Result<Option<DataExport>> {
+        let export: Option<DataExport> = sqlx::query_as(
+            r#"
+            UPDATE data_exports
+            SET status = 'running',
+                leased_until = NOW() + make_interval(secs => $2),
+                attempts = attempts + 1
+            WHERE id = $1
+            AND (status = 'pending' OR (status = 'running' AND leased_until < NOW()))
+            RETURNING *
+        "#,
+        )
+        .bind(id)
+        .bind(lease_seconds as f64)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(export)
+    }
+
+    /// Exports waiting for a run, oldest first.
+    pub async fn claimable(limit: i64, pool: &PgPool) -> Result<Vec<Uuid>> {
+        let ids: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            SELECT id
+            FROM data_exports
+            WHERE status = 'pending'
+            OR (status = 'running' AND leased_until < NOW())
+            ORDER BY created_at
+            LIMIT $1
+        "#,
+        )
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(ids)
+    }
+
+    /// Fixes the archive's entries and the multipart upload it is written to.
+    pub async fn set_entries(
+        id: Uuid,
+        entries: &[ExportEntry],
+        multipart_id: &str,
+        lease_seconds: i64,
+        pool: &PgPool,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE data_exports
+            SET entries = $2,
+                multipart_id = $3,
+                checkpoint = NULL,
+                central = NULL,
+                buffer = NULL,
+                leased_until = NOW() + make_interval(secs => $4)
+            WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .bind(Json(entries))
+        .bind(multipart_id)
+        .bind(lease_seconds as f64)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Saves the position after a part, renewing the lease.
+    pub async fn save_checkpoint(
+        id: Uuid,
+        checkpoint: &Checkpoint,
+        central: &[u8],
+        buffer: &[u8],
+        lease_seconds: i64,
+        pool: &PgPool,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE data_exports
+            SET checkpoint = $2,
+                central = $3,
+                buffer = $4,
+                leased_until = NOW() + make_interval(secs => $5)
+            WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .bind(Json(checkpoint))
+        .bind(central)
+        .bind(buffer)
+        .bind(lease_seconds as f64)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Marks the archive written, dropping the state it was built with.
+    pub async fn complete(id: Uuid, expires_at: DateTime<Utc>, pool: &PgPool) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE data_exports
+            SET status = 'completed',
+                entries = NULL,
+                checkpoint = NULL,
+                central = NULL,
+                buffer = NULL,
+                error = NULL,
+                leased_until = NULL,
+                completed_at = NOW(),
+                expires_at = $2
+            WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .bind(expires_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Hands a run that failed back for a retry, keeping its position.
+    pub async fn release(id: Uuid, error: &str, pool: &PgPool) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE data_exports
+            SET status = 'pending',
+                error = $2,
+                leased_until = NULL
+            WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .bind(error)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Gives up on an export, which is shown to the user until `expires_at`.
+    pub async fn fail(
+        id: Uuid,
+        error: &str,
+        expires_at: DateTime<Utc>,
+        pool: &PgPool,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE data_exports
+            SET status = 'failed',
+                error = $2,
+                entries = NULL,
+                checkpoint = NULL,
+                central = NULL,
+                buffer = NULL,
+                leased_until = NULL,
+                expires_at = $3
+            WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .bind(error)
+        .bind(expires_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Finished exports past their expiry, as (id, user id).
+    pub async fn expired(limit: i64, pool: &PgPool) -> Result<Vec<(Uuid, Uuid)>> {
+        let expired: Vec<(Uuid, Uuid)> = sqlx::query_as(
+            r#"
+            SELECT id, user_id
+            FROM data_exports
+            WHERE status IN ('completed', 'failed')
+            AND expires_at < NOW()
+            ORDER BY expires_at
+            LIMIT $1
+        "#,
+        )
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(expired)
+    }
+
+    pub async fn delete(id: Uuid, pool: &PgPool) -> Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM data_exports
+            WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// A version an entry was collected from, unless it has since been deleted.
+    pub async fn version(
+        object_id: i32,
+        version: i32,
+        pool: &PgPool,
+    ) -> Result<Option<ObjectVersion>> {
+        let version: Option<ObjectVersion> = sqlx::query_as(
+            r#"
+            SELECT *
+            FROM object_versions
+            WHERE object_id = $1
+            AND version = $2
+        "#,
+        )
+        .bind(object_id)
+        .bind(version)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(version)
+    }
+
+    /// The user's row as stored.
+    pub async fn account(user_id: Uuid, pool: &PgPool) -> Result<Value> {
+        let account: Value = sqlx::query_scalar(
+            r#"
+            SELECT to_jsonb(u)
+            FROM users AS u
+            WHERE id = $1
+        "#,
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(account)
+    }
+
+    /// Sign in methods and linked accounts, without passwords or tokens.
+    pub async fn auth_providers(user_id: Uuid, pool: &PgPool) -> Result<Value> {
+        let providers: Value = sqlx::query_scalar(
+            r#"
+            SELECT jsonb_build_object(
+                'auth_providers', COALESCE((
+                    SELECT jsonb_agg(jsonb_build_object(
+                        'provider', provider,
+                        'has_password', password_hash IS NOT NULL,
+                        'created_at', created_at,
+                        'updated_at', updated_at
+                    ) ORDER BY id)
+                    FROM auth_providers
+                    WHERE user_id = $1
+                ), '[]'),
+                'linked_accounts', COALESCE((
+                    SELECT jsonb_agg(jsonb_build_object(
+                        'provider', provider,
+                        'expires_at', expires_at
+                    ) ORDER BY id)
+                    FROM linked_accounts
+                    WHERE user_id = $1
+                ), '[]')
+            )
+        "#,
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(providers)
+    }
+
+    /// Signed in sessions, without their tokens.
+    pub async fn sessions(user_id: Uuid, pool: &PgPool) -> Result<Value> {
+        let sessions: Value = sqlx::query_scalar(
+            r#"
+            SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                'id', id,
+                'created_at', created_at,
+                'expires_at', expires_at
+            ) ORDER BY id), '[]')
+            FROM refresh_tokens
+            WHERE user_id = $1
+        "#,
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    /// Every audit event the user was the actor of, oldest first.
+    pub async fn audit_events(user_id: Uuid, pool: &PgPool) -> Result<Value> {
+        let events: Value = sqlx::query_scalar(
+            r#"
+            SELECT COALESCE(jsonb_agg(to_jsonb(e) - 'actor_id' ORDER BY id), '[]')
+            FROM audit_events AS e
+            WHERE actor_id = $1
+        "#,
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(events)
+    }
+
+    /// Folders and objects with every version, by path under the user's `root`.
+    pub async fn objects(user_id: Uuid, root: &str, pool: &PgPool) -> Result<Value> {
+        let objects: Value = sqlx::query_scalar(
+            r#"
+            SELECT jsonb_build_object(
+                'folders', COALESCE((
+                    SELECT jsonb_agg(jsonb_build_object(
+                        'path', path,
+                        'created_at', created_at
+                    ) ORDER BY path)
+                    FROM folders
+                    WHERE user_id = $1
+                ), '[]'),
+                'objects', COALESCE((
+                    SELECT jsonb_agg(jsonb_build_object(
+                        'path', substr(o.key, length($2) + 1),
+                        'filename', o.filename,
+                        'version', o.version,
+                        'max_versions', o.max_versions,
+                        'expires_at', o.expires_at,
+                        'versions', (
+                            SELECT jsonb_agg(jsonb_build_object(
+                                'version', v.version,
+                                'content_type', v.content_type,
+                                'size_bytes', v.size_bytes,
+                                'created_at', v.created_at,
+                                'scan_status', v.scan_status,
+                                'storage_class', v.storage_class
+                            ) ORDER BY v.version)
+                            FROM object_versions AS v
+                            WHERE v.object_id = o.id
+                        )
+                    ) ORDER BY o.key)
+                    FROM objects AS o
+                    WHERE o.user_id = $1
+                ), '[]')
+            )
+        "#,
+        )
+        .bind(user_id)
+        .bind(root)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(objects)
+    }
+}
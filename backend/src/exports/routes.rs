@@ -0,0 +1,14 @@
+use crate::exports::handlers::{download_export, list_exports, request_export};
+use crate::middleware::auth_middleware;
+use crate::state::AppState;
+use axum::routing::{get, post};
+use axum::{middleware, Router};
+use std::sync::Arc;
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", post(request_export))
+        .route("/", get(list_exports))
+        .route_layer(middleware::from_fn(auth_middleware))
+        .route("/download", get(download_export))
+}
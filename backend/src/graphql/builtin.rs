@@ -0,0 +1,156 @@
+//! The built-in schema: the signed in user with their objects, sessions and linked
+//! integrations. Field names follow the REST API's JSON.
+
+use super::schema::{Field, ObjectType, Resolve, Schema, TypeRef};
+use super::Context;
+use crate::auth::oauth::service::PROVIDERS;
+use crate::auth::queries::TokenQueries;
+use crate::config::CONFIG;
+use crate::error::Result;
+use crate::objects::handlers::{authorize, principal, DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT};
+use crate::objects::rules::Operation;
+use crate::users::queries::UserQueries;
+use serde_json::{json, Value};
+use sqlx::types::Uuid;
+
+pub fn schema() -> Schema<Context> {
+    let mut schema = Schema::new();
+    schema.max_depth = CONFIG.graphql_max_depth;
+    schema.max_complexity = CONFIG.graphql_max_complexity;
+    schema.scalar("DateTime");
+
+    schema.register(
+        ObjectType::new("Query").field(
+            Field::new("me", TypeRef::named("User").non_null())
+                .description("The signed in user.")
+                .resolve(me),
+        ),
+    );
+
+    schema.register(
+        ObjectType::new("User")
+            .field(Field::new("id", TypeRef::named("ID").non_null()))
+            .field(Field::new("email", TypeRef::named("String").non_null()))
+            .field(Field::new("first_name", TypeRef::named("String")))
+            .field(Field::new("last_name", TypeRef::named("String")))
+            .field(Field::new("role", TypeRef::named("String").non_null()))
+            .field(
+                Field::new("objects", TypeRef::named("ObjectPage").non_null())
+                    .description("A page of the user's objects under a prefix.")
+                    .argument_default("prefix", TypeRef::named("String"), json!(""))
+                    .argument("delimiter", TypeRef::named("String"))
+                    .argument("cursor", TypeRef::named("String"))
+                    .argument_default("limit", TypeRef::named("Int"), json!(DEFAULT_LIST_LIMIT))
+                    .cost(10)
+                    .resolve(objects),
+            )
+            .field(
+                Field::new("sessions", list_of("Session"))
+                    .description("Devices signed in with a refresh token.")
+                    .cost(5)
+                    .resolve(sessions),
+            )
+            .field(
+                Field::new("integrations", list_of("Integration"))
+                    .cost(5)
+                    .resolve(integrations),
+            ),
+    );
+
+    schema.register(
+        ObjectType::new("ObjectPage")
+            .field(Field::new("objects", list_of("Object")))
+            .field(Field::new("prefixes", list_of("String")))
+            .field(Field::new("next_cursor", TypeRef::named("String"))),
+    );
+
+    schema.register(
+        ObjectType::new("Object")
+            .field(Field::new("path", TypeRef::named("String").non_null()))
+            .field(Field::new("key", TypeRef::named("String").non_null()))
+            .field(Field::new("filename", TypeRef::named("String").non_null()))
+            .field(Field::new("version", TypeRef::named("Int").non_null()))
+            .field(Field::new("expires_at", TypeRef::named("DateTime"))),
+    );
+
+    schema.register(
+        ObjectType::new("Session")
+            .field(Field::new("id", TypeRef::named("ID").non_null()))
+            .field(Field::new("created_at", TypeRef::named("DateTime")))
+            .field(Field::new(
+                "expires_at",
+                TypeRef::named("DateTime").non_null(),
+            )),
+    );
+
+    schema.register(
+        ObjectType::new("Integration")
+            .field(Field::new("provider", TypeRef::named("String").non_null()))
+            .field(Field::new("linked", TypeRef::named("Boolean").non_null())),
+    );
+
+    schema
+}
+
+fn list_of(name: &str) -> TypeRef {
+    TypeRef::named(name).non_null().list().non_null()
+}
+
+async fn me(resolve: Resolve<Context>) -> Result<Value> {
+    let Context { state, claims } = resolve.context;
+    let pool = &state.storage.postgres.pool;
+    let user_id = Uuid::parse_str(&claims.sub)?;
+
+    let mut user = serde_json::to_value(UserQueries::get_user(user_id, pool).await?)?;
+    user["role"] = json!(UserQueries::get_role(user_id, pool).await?);
+    Ok(user)
+}
+
+async fn objects(resolve: Resolve<Context>) -> Result<Value> {
+    let prefix: String = resolve.arg("prefix")?;
+    let delimiter: Option<String> = resolve.arg("delimiter")?;
+    let cursor: Option<String> = resolve.arg("cursor")?;
+    let limit: i64 = resolve.arg("limit")?;
+    let Context { state, claims } = resolve.context;
+
+    let owner = Uuid::parse_str(&claims.sub)?;
+    let prefix = prefix.trim_start_matches('/');
+    // Listing needs read access to the prefix itself, as over REST
+    let principal = principal(&state, Some(&claims)).await?;
+    authorize(&state, &principal, owner, prefix, Operation::Read, None).await?;
+
+    let list = state
+        .storage
+        .list_files(
+            owner,
+            prefix,
+            delimiter.as_deref(),
+            cursor.as_deref(),
+            limit.clamp(1, MAX_LIST_LIMIT),
+        )
+        .await?;
+    Ok(serde_json::to_value(list)?)
+}
+
+async fn sessions(resolve: Resolve<Context>) -> Result<Value> {
+    let Context { state, claims } = resolve.context;
+    let user_id = Uuid::parse_str(&claims.sub)?;
+
+    let sessions = TokenQueries::list_sessions(user_id, &state.storage.postgres.pool).await?;
+    Ok(serde_json::to_value(sessions)?)
+}
+
+async fn integrations(resolve: Resolve<Context>) -> Result<Value> {
+    let Context { state, claims } = resolve.context;
+
+    let mut integrations = Vec::new();
+    for provider in PROVIDERS {
+        let linked = state
+            .storage
+            .redis
+            .has_oauth_tokens(&claims.sub, provider)
+            .await?;
+        integrations.push(json!({ "provider": provider, "linked": linked }));
+    }
+    Ok(Value::Array(integrations))
+}
@@ -0,0 +1,831 @@
+//! Executes a request against a schema. The operation is checked in full before any
+//! resolver runs: unknown fields and arguments, mistyped values and queries past the
+//! schema's depth or complexity limits are rejected outright. During execution, a
+//! failing resolver nulls its field and adds an error, and a null in a non-null field
+//! nulls the nearest nullable parent, as the spec describes.
+
+use super::models::GraphQLRequest;
+use super::parser::{
+    parse, Directive, Document, FieldSelection, InputValue, Operation, OperationKind, Selection,
+};
+use super::schema::{Field, ObjectType, Resolve, Schema, TypeRef};
+use crate::error::{Error, Result};
+use axum::http::StatusCode;
+use futures::future::BoxFuture;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// A null in a non-null position, to be replaced by nulling a parent.
+struct Bubble;
+
+/// Fields grouped by the key their values are returned under.
+type Grouped<'d> = Vec<(&'d str, Vec<&'d FieldSelection>)>;
+
+pub async fn execute<C>(schema: &Schema<C>, context: C, request: GraphQLRequest) -> Value
+where
+    C: Clone + Send + Sync + 'static,
+{
+    let document = match parse(&request.query) {
+        Ok(document) => document,
+        Err(e) => return json!({ "errors": [{ "message": e.client_message() }] }),
+    };
+
+    let prepared = prepare(
+        schema,
+        &document,
+        request.variables.unwrap_or_default(),
+        request.operation_name.as_deref(),
+    );
+    let (operation, root, variables) = match prepared {
+        Ok(prepared) => prepared,
+        Err(e) => return json!({ "errors": [{ "message": e.client_message() }] }),
+    };
+
+    let executor = Executor {
+        schema,
+        document: &document,
+        variables,
+        context,
+        errors: Mutex::new(Vec::new()),
+    };
+    let selection: Vec<&Selection> = operation.selection.iter().collect();
+    let data = executor
+        .execute_selection(root, json!({}), selection, Vec::new())
+        .await
+        .unwrap_or(Value::Null);
+
+    let errors = executor.errors.into_inner().unwrap_or_default();
+    if errors.is_empty() {
+        json!({ "data": data })
+    } else {
+        json!({ "data": data, "errors": errors })
+    }
+}
+
+/// Picks the operation to run, coerces its variables and checks it against the schema.
+fn prepare<'d, 's, C>(
+    schema: &'s Schema<C>,
+    document: &'d Document,
+    variables: Map<String, Value>,
+    operation_name: Option<&str>,
+) -> Result<(&'d Operation, &'s ObjectType<C>, Map<String, Value>)> {
+    let operation = match operation_name {
+        Some(name) => document
+            .operations
+            .iter()
+            .find(|o| o.name.as_deref() == Some(name))
+            .ok_or_else(|| request_error(format!("Unknown operation '{}'", name)))?,
+        None if document.operations.len() == 1 => &document.operations[0],
+        None => {
+            return Err(request_error(
+                "operationName is required when the document has several operations",
+            ));
+        }
+    };
+
+    let root_name = match operation.kind {
+        OperationKind::Query => "Query",
+        OperationKind::Mutation => "Mutation",
+    };
+    let root = schema
+        .object(root_name)
+        .filter(|root| !root.fields.is_empty())
+        .ok_or_else(|| request_error(format!("The schema has no {} type", root_name)))?;
+
+    let mut coerced = Map::new();
+    for definition in &operation.variables {
+        if !schema.is_scalar(definition.ty.base()) {
+            return Err(request_error(format!(
+                "Variable '${}' must have a scalar type",
+                definition.name
+            )));
+        }
+        let value = match (variables.get(&definition.name), &definition.default) {
+            (Some(value), _) => value.clone(),
+            (None, Some(default)) => constant(default)?,
+            (None, None) => Value::Null,
+        };
+        let value = coerce(schema, &definition.ty, value)
+            .map_err(|e| request_error(format!("Variable '${}' {}", definition.name, e)))?;
+        coerced.insert(definition.name.clone(), value);
+    }
+
+    let checker = Executor {
+        schema,
+        document,
+        variables: coerced,
+        context: (),
+        errors: Mutex::new(Vec::new()),
+    };
+    let selection: Vec<&Selection> = operation.selection.iter().collect();
+    checker.analyze(root, &selection, 1, &mut Analysis::default())?;
+
+    Ok((operation, root, checker.variables))
+}
+
+/// What checking an operation has found so far.
+#[derive(Default)]
+struct Analysis<'a> {
+    /// Summed cost of the fields selected so far.
+    complexity: usize,
+    /// Cost of each fragment already walked, with how many levels it nests below the
+    /// spread.
+    fragments: HashMap<&'a str, (usize, usize)>,
+    /// Fragments being walked, to catch one spreading itself.
+    spreads: Vec<&'a str>,
+}
+
+impl Analysis<'_> {
+    fn spend(&mut self, cost: usize, limit: usize) -> Result<()> {
+        self.complexity += cost;
+        if self.complexity > limit {
+            return Err(request_error(format!(
+                "Query has a complexity over the limit of {}",
+                limit
+            )));
+        }
+        Ok(())
+    }
+}
+
+struct Executor<'a, S, C> {
+    schema: &'a Schema<S>,
+    document: &'a Document,
+    variables: Map<String, Value>,
+    context: C,
+    errors: Mutex<Vec<Value>>,
+}
+
+impl<'a, S, C> Executor<'a, S, C> {
+    /// Checks a selection against `ty`, adding the cost of every field it selects to
+    /// the analysis, and returns the deepest level it reaches. Both limits are checked
+    /// as the walk goes, so a query past them is given up on early.
+    fn analyze(
+        &self,
+        ty: &ObjectType<S>,
+        selection: &[&'a Selection],
+        depth: usize,
+        analysis: &mut Analysis<'a>,
+    ) -> Result<usize> {
+        self.check_depth(depth)?;
+
+        let mut deepest = depth;
+        for selection in selection {
+            match selection {
+                Selection::Field(field) => {
+                    if !self.included(&field.directives)? {
+                        continue;
+                    }
+                    if field.name == "__typename" {
+                        if !field.selection.is_empty() {
+                            return Err(request_error("'__typename' has no subfields"));
+                        }
+                        continue;
+                    }
+
+                    let definition = ty.get(&field.name).ok_or_else(|| {
+                        request_error(format!(
+                            "Unknown field '{}' on type '{}'",
+                            field.name, ty.name
+                        ))
+                    })?;
+                    self.arguments(definition, field)?;
+                    analysis.spend(definition.cost, self.schema.max_complexity)?;
+
+                    let base = definition.ty.base();
+                    let reached = match self.schema.object(base) {
+                        Some(object) if !field.selection.is_empty() => {
+                            let selection: Vec<&Selection> = field.selection.iter().collect();
+                            self.analyze(object, &selection, depth + 1, analysis)?
+                        }
+                        Some(_) => {
+                            return Err(request_error(format!(
+                                "Field '{}' of type '{}' needs a selection of subfields",
+                                field.name, base
+                            )));
+                        }
+                        None if field.selection.is_empty() => depth,
+                        None => {
+                            return Err(request_error(format!(
+                                "Field '{}' of type '{}' has no subfields",
+                                field.name, base
+                            )));
+                        }
+                    };
+                    deepest = deepest.max(reached);
+                }
+                Selection::FragmentSpread { name, directives } => {
+                    if !self.included(directives)? {
+                        continue;
+                    }
+                    let fragment = self
+                        .document
+                        .fragments
+                        .get(name)
+                        .ok_or_else(|| request_error(format!("Unknown fragment '{}'", name)))?;
+                    if analysis.spreads.contains(&name.as_str()) {
+                        return Err(request_error(format!("Fragment '{}' spreads itself", name)));
+                    }
+                    self.check_condition(ty, Some(&fragment.type_condition))?;
+
+                    // A fragment is walked once, however often it is spread
+                    let height = match analysis.fragments.get(name.as_str()).copied() {
+                        Some((cost, height)) => {
+                            analysis.spend(cost, self.schema.max_complexity)?;
+                            height
+                        }
+                        None => {
+                            let before = analysis.complexity;
+                            analysis.spreads.push(name);
+                            let selection: Vec<&Selection> = fragment.selection.iter().collect();
+                            let reached = self.analyze(ty, &selection, depth, analysis)?;
+                            analysis.spreads.pop();
+
+                            let cost = analysis.complexity - before;
+                            analysis.fragments.insert(name, (cost, reached - depth));
+                            reached - depth
+                        }
+                    };
+                    self.check_depth(depth + height)?;
+                    deepest = deepest.max(depth + height);
+                }
+                Selection::InlineFragment {
+                    type_condition,
+                    directives,
+                    selection,
+                } => {
+                    if !self.included(directives)? {
+                        continue;
+                    }
+                    self.check_condition(ty, type_condition.as_deref())?;
+                    // Nested inline fragments recurse too, so they count toward the depth
+                    let selection: Vec<&Selection> = selection.iter().collect();
+                    let reached = self.analyze(ty, &selection, depth + 1, analysis)?;
+                    deepest = deepest.max(reached);
+                }
+            }
+        }
+        Ok(deepest)
+    }
+
+    fn check_depth(&self, depth: usize) -> Result<()> {
+        if depth > self.schema.max_depth {
+            return Err(request_error(format!(
+                "Query is nested deeper than the limit of {}",
+                self.schema.max_depth
+            )));
+        }
+        Ok(())
+    }
+
+    /// Without interfaces or unions, a fragment only ever applies to its own type.
+    fn check_condition(&self, ty: &ObjectType<S>, condition: Option<&str>) -> Result<()> {
+        match condition {
+            Some(condition) if condition != ty.name => Err(request_error(format!(
+                "Fragment on '{}' can't apply to type '{}'",
+                condition, ty.name
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Evaluates `@skip` and `@include`.
+    fn included(&self, directives: &[Directive]) -> Result<bool> {
+        for directive in directives {
+            let condition = match directive.arguments.as_slice() {
+                [(name, value)] if name == "if" => self.input(value)?,
+                _ => Value::Null,
+            };
+            let Value::Bool(condition) = condition else {
+                return Err(request_error(format!(
+                    "Directive '@{}' takes a single Boolean 'if' argument",
+                    directive.name
+                )));
+            };
+            match directive.name.as_str() {
+                "skip" if condition => return Ok(false),
+                "include" if !condition => return Ok(false),
+                "skip" | "include" => {}
+                name => return Err(request_error(format!("Unknown directive '@{}'", name))),
+            }
+        }
+        Ok(true)
+    }
+
+    /// The arguments passed to a field, with defaults filled in and types checked.
+    fn arguments(
+        &self,
+        definition: &Field<S>,
+        field: &FieldSelection,
+    ) -> Result<Map<String, Value>> {
+        for (name, _) in &field.arguments {
+            if !definition.arguments.iter().any(|a| &a.name == name) {
+                return Err(request_error(format!(
+                    "Unknown argument '{}' on field '{}'",
+                    name, field.name
+                )));
+            }
+        }
+
+        let mut arguments = Map::new();
+        for argument in &definition.arguments {
+            let provided = field
+                .arguments
+                .iter()
+                .find(|(name, _)| name == &argument.name)
+                .map(|(_, value)| self.input(value))
+                .transpose()?;
+            let value = match provided {
+                Some(value) => value,
+                None => argument.default.clone().unwrap_or(Value::Null),
+            };
+            let value = coerce(self.schema, &argument.ty, value).map_err(|e| {
+                request_error(format!(
+                    "Argument '{}' of field '{}' {}",
+                    argument.name, field.name, e
+                ))
+            })?;
+            arguments.insert(argument.name.clone(), value);
+        }
+        Ok(arguments)
+    }
+
+    /// A value from the document, with variables substituted.
+    fn input(&self, value: &InputValue) -> Result<Value> {
+        Ok(match value {
+            InputValue::Variable(name) => self
+                .variables
+                .get(name)
+                .cloned()
+                .ok_or_else(|| request_error(format!("Variable '${}' is not defined", name)))?,
+            InputValue::List(items) => Value::Array(
+                items
+                    .iter()
+                    .map(|item| self.input(item))
+                    .collect::<Result<_>>()?,
+            ),
+            InputValue::Object(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), self.input(value)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            value => constant(value)?,
+        })
+    }
+
+    /// Groups the fields of a selection by response key, expanding fragments and
+    /// dropping skipped fields. The selection was analyzed already, so nothing fails.
+    /// A fragment spread more than once is only expanded the first time, as `visited`
+    /// records.
+    fn collect_fields(
+        &self,
+        selection: &[&'a Selection],
+        grouped: &mut Grouped<'a>,
+        visited: &mut Vec<&'a str>,
+    ) {
+        for selection in selection {
+            match selection {
+                Selection::Field(field) => {
+                    if !self.included(&field.directives).unwrap_or(false) {
+                        continue;
+                    }
+                    let key = field.response_key();
+                    match grouped.iter_mut().find(|(k, _)| *k == key) {
+                        Some((_, fields)) => fields.push(field),
+                        None => grouped.push((key, vec![field])),
+                    }
+                }
+                Selection::FragmentSpread { name, directives } => {
+                    if !self.included(directives).unwrap_or(false)
+                        || visited.contains(&name.as_str())
+                    {
+                        continue;
+                    }
+                    visited.push(name);
+                    if let Some(fragment) = self.document.fragments.get(name) {
+                        let selection: Vec<&Selection> = fragment.selection.iter().collect();
+                        self.collect_fields(&selection, grouped, visited);
+                    }
+                }
+                Selection::InlineFragment {
+                    directives,
+                    selection,
+                    ..
+                } => {
+                    if !self.included(directives).unwrap_or(false) {
+                        continue;
+                    }
+                    let selection: Vec<&Selection> = selection.iter().collect();
+                    self.collect_fields(&selection, grouped, visited);
+                }
+            }
+        }
+    }
+
+    fn error(&self, message: String, path: &[Value]) {
+        if let Ok(mut errors) = self.errors.lock() {
+            errors.push(json!({ "message": message, "path": path }));
+        }
+    }
+}
+
+impl<'a, C> Executor<'a, C, C>
+where
+    C: Clone + Send + Sync + 'static,
+{
+    fn execute_selection<'b>(
+        &'b self,
+        ty: &'a ObjectType<C>,
+        parent: Value,
+        selection: Vec<&'a Selection>,
+        path: Vec<Value>,
+    ) -> BoxFuture<'b, std::result::Result<Value, Bubble>> {
+        Box::pin(async move {
+            let mut grouped = Vec::new();
+            self.collect_fields(&selection, &mut grouped, &mut Vec::new());
+
+            let mut object = Map::new();
+            for (key, fields) in grouped {
+                let field = fields[0];
+                let mut path = path.clone();
+                path.push(json!(key));
+
+                if field.name == "__typename" {
+                    object.insert(key.to_string(), json!(ty.name));
+                    continue;
+                }
+                let Some(definition) = ty.get(&field.name) else {
+                    continue;
+                };
+
+                let resolved = match &definition.resolver {
+                    Some(resolver) => match self.arguments(definition, field) {
+                        Ok(args) => {
+                            resolver(Resolve {
+                                context: self.context.clone(),
+                                parent: parent.clone(),
+                                args,
+                            })
+                            .await
+                        }
+                        Err(e) => Err(e),
+                    },
+                    None => Ok(parent.get(&field.name).cloned().unwrap_or(Value::Null)),
+                };
+
+                let value = match resolved {
+                    Ok(value) => {
+                        let selection: Vec<&Selection> =
+                            fields.iter().flat_map(|f| f.selection.iter()).collect();
+                        self.complete(&definition.ty, value, selection, path)
+                            .await?
+                    }
+                    Err(e) => {
+                        self.error(e.client_message(), &path);
+                        if matches!(definition.ty, TypeRef::NonNull(_)) {
+                            return Err(Bubble);
+                        }
+                        Value::Null
+                    }
+                };
+                object.insert(key.to_string(), value);
+            }
+            Ok(Value::Object(object))
+        })
+    }
+
+    /// Shapes a resolved value to its type, turning errors inside a nullable value into
+    /// a null and passing those inside a non-null one up.
+    fn complete<'b>(
+        &'b self,
+        ty: &'a TypeRef,
+        value: Value,
+        selection: Vec<&'a Selection>,
+        path: Vec<Value>,
+    ) -> BoxFuture<'b, std::result::Result<Value, Bubble>> {
+        Box::pin(async move {
+            match ty {
+                TypeRef::NonNull(inner) => {
+                    let value = self
+                        .complete_nullable(inner, value, selection, path.clone())
+                        .await?;
+                    if value.is_null() {
+                        self.error("Cannot return null for a non-null field".into(), &path);
+                        return Err(Bubble);
+                    }
+                    Ok(value)
+                }
+                ty => Ok(self
+                    .complete_nullable(ty, value, selection, path)
+                    .await
+                    .unwrap_or(Value::Null)),
+            }
+        })
+    }
+
+    fn complete_nullable<'b>(
+        &'b self,
+        ty: &'a TypeRef,
+        value: Value,
+        selection: Vec<&'a Selection>,
+        path: Vec<Value>,
+    ) -> BoxFuture<'b, std::result::Result<Value, Bubble>> {
+        Box::pin(async move {
+            if value.is_null() {
+                return Ok(Value::Null);
+            }
+
+            match ty {
+                TypeRef::List(inner) => {
+                    let Value::Array(items) = value else {
+                        self.error("Expected a list".into(), &path);
+                        return Err(Bubble);
+                    };
+                    let mut completed = Vec::with_capacity(items.len());
+                    for (i, item) in items.into_iter().enumerate() {
+                        let mut path = path.clone();
+                        path.push(json!(i));
+                        completed.push(self.complete(inner, item, selection.clone(), path).await?);
+                    }
+                    Ok(Value::Array(completed))
+                }
+                TypeRef::Named(name) => match self.schema.object(name) {
+                    Some(object) => {
+                        if !value.is_object() {
+                            self.error(format!("Expected an object of type '{}'", name), &path);
+                            return Err(Bubble);
+                        }
+                        self.execute_selection(object, value, selection, path).await
+                    }
+                    // IDs are serialized as strings, whatever type the key has
+                    None if name == "ID" && value.is_number() => {
+                        Ok(Value::String(value.to_string()))
+                    }
+                    None => Ok(value),
+                },
+                TypeRef::NonNull(_) => self.complete(ty, value, selection, path).await,
+            }
+        })
+    }
+}
+
+/// Checks an input value against a scalar type, converting where GraphQL allows it.
+fn coerce<S>(schema: &Schema<S>, ty: &TypeRef, value: Value) -> std::result::Result<Value, String> {
+    match ty {
+        TypeRef::NonNull(inner) => {
+            if value.is_null() {
+                return Err(format!("of type '{}' is required", ty));
+            }
+            coerce(schema, inner, value)
+        }
+        _ if value.is_null() => Ok(Value::Null),
+        TypeRef::List(inner) => match value {
+            Value::Array(items) => items
+                .into_iter()
+                .map(|item| coerce(schema, inner, item))
+                .collect::<std::result::Result<_, _>>()
+                .map(Value::Array),
+            // A single value stands for a list of one
+            value => Ok(Value::Array(vec![coerce(schema, inner, value)?])),
+        },
+        TypeRef::Named(name) => {
+            let valid = match name.as_str() {
+                "Int" => value.as_i64().is_some_and(|n| i32::try_from(n).is_ok()),
+                "Float" => value.is_number(),
+                "String" => value.is_string(),
+                "Boolean" => value.is_boolean(),
+                "ID" => {
+                    if let Some(n) = value.as_i64() {
+                        return Ok(Value::String(n.to_string()));
+                    }
+                    value.is_string()
+                }
+                name => schema.is_scalar(name),
+            };
+            if valid {
+                Ok(value)
+            } else {
+                Err(format!("expects type '{}', got {}", name, value))
+            }
+        }
+    }
+}
+
+/// A constant from the document as JSON. Enum values become strings.
+fn constant(value: &InputValue) -> Result<Value> {
+    Ok(match value {
+        InputValue::Variable(name) => {
+            return Err(request_error(format!(
+                "Variable '${}' can't be used here",
+                name
+            )));
+        }
+        InputValue::Null => Value::Null,
+        InputValue::Int(n) => json!(n),
+        InputValue::Float(n) => json!(n),
+        InputValue::String(s) | InputValue::Enum(s) => Value::String(s.clone()),
+        InputValue::Boolean(b) => Value::Bool(*b),
+        InputValue::List(items) => Value::Array(items.iter().map(constant).collect::<Result<_>>()?),
+        InputValue::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), constant(value)?)))
+                .collect::<Result<_>>()?,
+        ),
+    })
+}
+
+fn request_error(message: impl Into<String>) -> Error {
+    Error::HttpError(StatusCode::BAD_REQUEST, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphql::schema::Field;
+
+    fn schema() -> Schema<()> {
+        let mut schema = Schema::new();
+        schema.register(
+            ObjectType::new("Query")
+                .field(
+                    Field::new("user", TypeRef::named("User"))
+                        .argument("id", TypeRef::named("ID").non_null())
+                        .resolve(|r| async move {
+                            Ok(json!({
+                                "id": r.args["id"],
+                                "name": "Ada",
+                                "tags": ["a", "b"],
+                                "friends": [{ "id": "2", "name": null }],
+                            }))
+                        }),
+                )
+                .field(
+                    Field::new("fail", TypeRef::named("Int")).resolve(|_| async move {
+                        Err(Error::HttpError(StatusCode::FORBIDDEN, "No".into()))
+                    }),
+                ),
+        );
+        schema.register(
+            ObjectType::new("User")
+                .field(Field::new("id", TypeRef::named("ID").non_null()))
+                .field(Field::new("name", TypeRef::named("String")))
+                .field(Field::new(
+                    "strict_name",
+                    TypeRef::named("String").non_null(),
+                ))
+                .field(Field::new(
+                    "tags",
+                    TypeRef::named("String").non_null().list(),
+                ))
+                .field(Field::new("friends", TypeRef::named("User").non_null().list()).cost(10)),
+        );
+        schema
+    }
+
+    fn run(query: &str, variables: Value) -> Value {
+        let request = GraphQLRequest {
+            query: query.to_string(),
+            variables: variables.as_object().cloned(),
+            operation_name: None,
+        };
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(execute(&schema(), (), request))
+    }
+
+    #[test]
+    fn test_execute() {
+        let response = run(
+            r#"query ($id: ID!, $skip: Boolean = false) {
+                user(id: $id) {
+                    __typename
+                    id
+                    alias: name
+                    tags
+                    ... on User { friends { id } }
+                    friends { name @skip(if: $skip) }
+                }
+                fail
+            }"#,
+            json!({ "id": 7 }),
+        );
+
+        assert_eq!(
+            response["data"],
+            json!({
+                "user": {
+                    "__typename": "User",
+                    "id": "7",
+                    "alias": "Ada",
+                    "tags": ["a", "b"],
+                    "friends": [{ "id": "2", "name": null }],
+                },
+                "fail": null,
+            })
+        );
+        assert_eq!(
+            response["errors"],
+            json!([{ "message": "No", "path": ["fail"] }])
+        );
+    }
+
+    #[test]
+    fn test_null_bubbles_to_nullable_parent() {
+        let response = run(r#"{ user(id: "1") { strict_name } }"#, json!({}));
+
+        assert_eq!(response["data"], json!({ "user": null }));
+        assert_eq!(
+            response["errors"][0]["path"],
+            json!(["user", "strict_name"])
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_requests() {
+        let rejected = |query: &str| run(query, json!({}))["errors"][0]["message"].clone();
+
+        assert_eq!(
+            rejected(r#"{ user(id: "1") { age } }"#),
+            json!("Unknown field 'age' on type 'User'")
+        );
+        assert_eq!(
+            rejected("{ user { id } }"),
+            json!("Argument 'id' of field 'user' of type 'ID!' is required")
+        );
+        assert_eq!(
+            rejected(r#"{ user(id: "1") }"#),
+            json!("Field 'user' of type 'User' needs a selection of subfields")
+        );
+        assert_eq!(
+            rejected(r#"{ user(id: "1") { ...a } } fragment a on User { ...a }"#),
+            json!("Fragment 'a' spreads itself")
+        );
+        assert!(run("mutation { x }", json!({}))["data"].is_null());
+    }
+
+    #[test]
+    fn test_limits() {
+        let deep = r#"{ user(id: "1") { friends { friends { friends { id } } } } }"#;
+        assert!(run(deep, json!({})).get("data").is_some());
+
+        let mut schema = schema();
+        schema.max_depth = 3;
+        let shallow = r#"{ user(id: "1") { friends { id } } }"#;
+        assert!(prepare(&schema, &parse(shallow).unwrap(), Map::new(), None).is_ok());
+        assert!(prepare(&schema, &parse(deep).unwrap(), Map::new(), None).is_err());
+        let fragment = r#"{ user(id: "1") { ... { id } } }"#;
+        assert!(prepare(&schema, &parse(fragment).unwrap(), Map::new(), None).is_ok());
+        let fragments = r#"{ user(id: "1") { ... { ... { id } } } }"#;
+        assert!(prepare(&schema, &parse(fragments).unwrap(), Map::new(), None).is_err());
+
+        // 1 for user, 10 for each friends and 1 for the id
+        schema.max_depth = 10;
+        schema.max_complexity = 21;
+        assert!(prepare(&schema, &parse(deep).unwrap(), Map::new(), None).is_err());
+        schema.max_complexity = 32;
+        assert!(prepare(&schema, &parse(deep).unwrap(), Map::new(), None).is_ok());
+    }
+
+    #[test]
+    fn test_fragment_limits() {
+        // Each fragment spreads the next twice, doubling the work of expanding them anew
+        let chain = |field: &str| {
+            let mut query = r#"{ user(id: "1") { ...f0 } }"#.to_string();
+            for i in 0..40 {
+                query.push_str(&format!(
+                    " fragment f{} on User {{ {} ...f{} ...f{} }}",
+                    i,
+                    field,
+                    i + 1,
+                    i + 1
+                ));
+            }
+            query.push_str(&format!(" fragment f40 on User {{ {} }}", field));
+            query
+        };
+        let schema = schema();
+        let started = std::time::Instant::now();
+        let costly = parse(&chain("id")).unwrap();
+        assert!(prepare(&schema, &costly, Map::new(), None).is_err());
+        let free = chain("__typename");
+        assert!(prepare(&schema, &parse(&free).unwrap(), Map::new(), None).is_ok());
+        assert_eq!(
+            run(&free, json!({})),
+            json!({ "data": { "user": { "__typename": "User" } } })
+        );
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+
+        // A fragment reused deeper down still counts toward the depth there
+        let mut schema = schema;
+        schema.max_depth = 3;
+        let reused = r#"{ user(id: "1") { ...f friends { ...f } } }
+            fragment f on User { friends { id } }"#;
+        assert!(prepare(&schema, &parse(reused).unwrap(), Map::new(), None).is_err());
+        let once = r#"{ user(id: "1") { ...f } } fragment f on User { friends { id } }"#;
+        assert!(prepare(&schema, &parse(once).unwrap(), Map::new(), None).is_ok());
+    }
+}
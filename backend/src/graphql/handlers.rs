@@ -0,0 +1,29 @@
+use super::executor::execute;
+use super::models::GraphQLRequest;
+use super::Context;
+use crate::crypt::jwt::Claims;
+use crate::state::AppState;
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use std::sync::Arc;
+
+/// Runs a query or mutation. Following GraphQL over HTTP, the response is the bare
+/// `{ data, errors }` body rather than the usual envelope, and errors in the request
+/// come back in `errors` too.
+pub async fn graphql(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(request): Json<GraphQLRequest>,
+) -> impl IntoResponse {
+    let context = Context {
+        state: state.clone(),
+        claims,
+    };
+    Json(execute(&state.graphql, context, request).await)
+}
+
+/// The schema in SDL, for code generators and editors.
+pub async fn graphql_schema(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    state.graphql.sdl()
+}
@@ -0,0 +1,41 @@
+//! GraphQL over the built-in entities, served at `/graphql`. The schema is built in
+//! code and lives on `AppState`, so applications using this crate as a library add
+//! their own types and root fields before building the router:
+//!
+//! ```ignore
+//! let mut state = AppState::new().await?;
+//! state.graphql.register(
+//!     ObjectType::new("Query").field(
+//!         Field::new("projects", TypeRef::named("Project").non_null().list())
+//!             .resolve(|resolve: Resolve<Context>| async move {
+//!                 list_projects(&resolve.context.state, &resolve.context.claims).await
+//!             }),
+//!     ),
+//! );
+//! state.graphql.register(ObjectType::new("Project").field(...));
+//! let app = router(state);
+//! ```
+//!
+//! Resolvers get the caller's claims through the [`Context`]. Introspection queries
+//! aren't supported; `GET /graphql` returns the schema as SDL instead.
+
+pub mod builtin;
+pub mod executor;
+pub mod handlers;
+pub mod models;
+pub mod parser;
+pub mod routes;
+pub mod schema;
+
+pub use schema::{Field, ObjectType, Resolve, Schema, TypeRef};
+
+use crate::crypt::jwt::Claims;
+use crate::state::AppState;
+use std::sync::Arc;
+
+/// What every resolver gets: the application state and the caller's claims.
+#[derive(Clone)]
+pub struct Context {
+    pub state: Arc<AppState>,
+    pub claims: Claims,
+}
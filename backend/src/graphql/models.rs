@@ -0,0 +1,12 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// A request as GraphQL clients send it over HTTP.
+#[derive(Debug, Deserialize)]
+pub struct GraphQLRequest {
+    pub query: String,
+    #[serde(default)]
+    pub variables: Option<Map<String, Value>>,
+    #[serde(default, rename = "operationName")]
+    pub operation_name: Option<String>,
+}
@@ -0,0 +1,730 @@
+//! Parser for GraphQL executable documents: operations, fragments, variables and
+//! directives. Type system definitions aren't accepted, as the schema is built in code.
+
+use super::schema::TypeRef;
+use crate::error::{Error, Result};
+use axum::http::StatusCode;
+use std::collections::HashMap;
+
+/// Deepest selections, list or object values and list types may nest, so a hostile
+/// document can't exhaust the stack. Well above any query the executor accepts.
+const MAX_NESTING: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    Query,
+    Mutation,
+}
+
+#[derive(Debug)]
+pub struct Document {
+    pub operations: Vec<Operation>,
+    pub fragments: HashMap<String, Fragment>,
+}
+
+#[derive(Debug)]
+pub struct Operation {
+    pub kind: OperationKind,
+    pub name: Option<String>,
+    pub variables: Vec<VariableDefinition>,
+    pub selection: Vec<Selection>,
+}
+
+#[derive(Debug)]
+pub struct VariableDefinition {
+    pub name: String,
+    pub ty: TypeRef,
+    pub default: Option<InputValue>,
+}
+
+#[derive(Debug)]
+pub struct Fragment {
+    pub name: String,
+    pub type_condition: String,
+    pub selection: Vec<Selection>,
+}
+
+#[derive(Debug)]
+pub enum Selection {
+    Field(FieldSelection),
+    FragmentSpread {
+        name: String,
+        directives: Vec<Directive>,
+    },
+    InlineFragment {
+        type_condition: Option<String>,
+        directives: Vec<Directive>,
+        selection: Vec<Selection>,
+    },
+}
+
+#[derive(Debug)]
+pub struct FieldSelection {
+    pub alias: Option<String>,
+    pub name: String,
+    pub arguments: Vec<(String, InputValue)>,
+    pub directives: Vec<Directive>,
+    pub selection: Vec<Selection>,
+}
+
+impl FieldSelection {
+    /// The key the field's value is returned under.
+    pub fn response_key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Directive {
+    pub name: String,
+    pub arguments: Vec<(String, InputValue)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputValue {
+    Variable(String),
+    Null,
+    Int(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+    Enum(String),
+    List(Vec<InputValue>),
+    Object(Vec<(String, InputValue)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Punctuator(char),
+    Spread,
+    Name(String),
+    Int(i64),
+    Float(f64),
+    String(String),
+    End,
+}
+
+pub fn parse(source: &str) -> Result<Document> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        position: 0,
+        depth: 0,
+    };
+    parser.document()
+}
+
+fn syntax_error(message: impl Into<String>) -> Error {
+    Error::HttpError(
+        StatusCode::BAD_REQUEST,
+        format!("Syntax error: {}", message.into()),
+    )
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            // Commas are insignificant, like whitespace
+            ' ' | '\t' | '\n' | '\r' | ',' | '\u{feff}' => i += 1,
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '!' | '$' | '(' | ')' | ':' | '=' | '@' | '[' | ']' | '{' | '}' | '|' | '&' => {
+                tokens.push(Token::Punctuator(c));
+                i += 1;
+            }
+            '.' => {
+                if chars.get(i + 1) != Some(&'.') || chars.get(i + 2) != Some(&'.') {
+                    return Err(syntax_error("expected '...'"));
+                }
+                tokens.push(Token::Spread);
+                i += 3;
+            }
+            '"' => {
+                let (value, end) =
+                    if chars.get(i + 1) == Some(&'"') && chars.get(i + 2) == Some(&'"') {
+                        block_string(&chars, i + 3)?
+                    } else {
+                        string(&chars, i + 1)?
+                    };
+                tokens.push(Token::String(value));
+                i = end;
+            }
+            '-' | '0'..='9' => {
+                let start = i;
+                i += 1;
+                let mut float = false;
+                while i < chars.len() {
+                    match chars[i] {
+                        '0'..='9' => {}
+                        '.' | 'e' | 'E' => float = true,
+                        '+' | '-' if matches!(chars[i - 1], 'e' | 'E') => {}
+                        _ => break,
+                    }
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let token = if float {
+                    text.parse().map(Token::Float).ok()
+                } else {
+                    text.parse().map(Token::Int).ok()
+                };
+                tokens
+                    .push(token.ok_or_else(|| syntax_error(format!("invalid number '{}'", text)))?);
+            }
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let start = i;
+                while i < chars.len() && (chars[i] == '_' || chars[i].is_ascii_alphanumeric()) {
+                    i += 1;
+                }
+                tokens.push(Token::Name(chars[start..i].iter().collect()));
+            }
+            c => return Err(syntax_error(format!("unexpected character '{}'", c))),
+        }
+    }
+
+    tokens.push(Token::End);
+    Ok(tokens)
+}
+
+/// Reads a quoted string starting after its opening quote, returning it and the index
+/// past the closing quote.
+fn string(chars: &[char], mut i: usize) -> Result<(String, usize)> {
+    let mut value = String::new();
+    loop {
+        match chars.get(i) {
+            None | Some('\n') | Some('\r') => return Err(syntax_error("unterminated string")),
+            Some('"') => return Ok((value, i + 1)),
+            Some('\\') => {
+                let escaped = match chars.get(i + 1) {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('/') => '/',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => {
+                        let hex: String = chars.get(i + 2..i + 6).unwrap_or(&[]).iter().collect();
+                        let code = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| syntax_error("invalid unicode escape"))?;
+                        value.push(code);
+                        i += 6;
+                        continue;
+                    }
+                    _ => return Err(syntax_error("invalid escape sequence")),
+                };
+                value.push(escaped);
+                i += 2;
+            }
+            Some(&c) => {
+                value.push(c);
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Reads a `"""` block string starting after its opening quotes, stripping the common
+/// indentation and blank first and last lines as the spec describes.
+fn block_string(chars: &[char], mut i: usize) -> Result<(String, usize)> {
+    let mut raw = String::new();
+    loop {
+        if i >= chars.len() {
+            return Err(syntax_error("unterminated block string"));
+        }
+        if chars[i..].starts_with(&['"', '"', '"']) {
+            i += 3;
+            break;
+        }
+        if chars[i..].starts_with(&['\\', '"', '"', '"']) {
+            raw.push_str("\"\"\"");
+            i += 4;
+            continue;
+        }
+        raw.push(chars[i]);
+        i += 1;
+    }
+
+    let lines: Vec<&str> = raw.lines().collect();
+    let indent = lines
+        .iter()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut lines: Vec<&str> = lines
+        .iter()
+        .enumerate()
+        .map(|(n, line)| {
+            if n == 0 {
+                line
+            } else {
+                line.get(indent..).unwrap_or("")
+            }
+        })
+        .collect();
+    while lines.first().is_some_and(|line| line.trim().is_empty()) {
+        lines.remove(0);
+    }
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+
+    Ok((lines.join("\n"), i))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Selection sets, values and types currently open.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn at(&self, c: char) -> bool {
+        *self.peek() == Token::Punctuator(c)
+    }
+
+    /// Consumes the punctuator `c` if it comes next.
+    fn skip(&mut self, c: char) -> bool {
+        let found = self.at(c);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.skip(c) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{}'", c)))
+        }
+    }
+
+    fn name(&mut self) -> Result<String> {
+        match self.next() {
+            Token::Name(name) => Ok(name),
+            _ => {
+                self.position -= 1;
+                Err(self.unexpected("a name"))
+            }
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<()> {
+        match self.peek() {
+            Token::Name(name) if name == keyword => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(self.unexpected(&format!("'{}'", keyword))),
+        }
+    }
+
+    /// Opens a nested selection set, value or type, which the caller leaves again by
+    /// decrementing `depth`.
+    fn nest(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(syntax_error(format!(
+                "document nests deeper than {} levels",
+                MAX_NESTING
+            )));
+        }
+        Ok(())
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        let found = match self.peek() {
+            Token::Punctuator(c) => format!("'{}'", c),
+            Token::Spread => "'...'".to_string(),
+            Token::Name(name) => format!("'{}'", name),
+            Token::Int(value) => value.to_string(),
+            Token::Float(value) => value.to_string(),
+            Token::String(value) => format!("\"{}\"", value),
+            Token::End => "end of document".to_string(),
+        };
+        syntax_error(format!("expected {}, found {}", expected, found))
+    }
+
+    fn document(&mut self) -> Result<Document> {
+        let mut document = Document {
+            operations: Vec::new(),
+            fragments: HashMap::new(),
+        };
+
+        while *self.peek() != Token::End {
+            match self.peek() {
+                Token::Punctuator('{') => document.operations.push(Operation {
+                    kind: OperationKind::Query,
+                    name: None,
+                    variables: Vec::new(),
+                    selection: self.selection_set()?,
+                }),
+                Token::Name(name) if name == "fragment" => {
+                    let fragment = self.fragment()?;
+                    if document.fragments.contains_key(&fragment.name) {
+                        return Err(syntax_error(format!(
+                            "fragment '{}' is defined twice",
+                            fragment.name
+                        )));
+                    }
+                    document.fragments.insert(fragment.name.clone(), fragment);
+                }
+                Token::Name(_) => {
+                    let operation = self.operation()?;
+                    document.operations.push(operation);
+                }
+                _ => return Err(self.unexpected("an operation or fragment")),
+            }
+        }
+
+        if document.operations.is_empty() {
+            return Err(syntax_error("document has no operations"));
+        }
+        Ok(document)
+    }
+
+    fn operation(&mut self) -> Result<Operation> {
+        let kind = match self.name()?.as_str() {
+            "query" => OperationKind::Query,
+            "mutation" => OperationKind::Mutation,
+            other => {
+                return Err(syntax_error(format!(
+                    "unsupported operation type '{}'",
+                    other
+                )));
+            }
+        };
+        let name = match self.peek() {
+            Token::Name(_) => Some(self.name()?),
+            _ => None,
+        };
+
+        let mut variables = Vec::new();
+        if self.skip('(') {
+            while !self.skip(')') {
+                self.expect('$')?;
+                let name = self.name()?;
+                self.expect(':')?;
+                let ty = self.type_ref()?;
+                let default = if self.skip('=') {
+                    Some(self.value(true)?)
+                } else {
+                    None
+                };
+                variables.push(VariableDefinition { name, ty, default });
+            }
+        }
+        self.directives()?;
+
+        Ok(Operation {
+            kind,
+            name,
+            variables,
+            selection: self.selection_set()?,
+        })
+    }
+
+    fn fragment(&mut self) -> Result<Fragment> {
+        self.keyword("fragment")?;
+        let name = self.name()?;
+        if name == "on" {
+            return Err(syntax_error("a fragment can't be named 'on'"));
+        }
+        self.keyword("on")?;
+        let type_condition = self.name()?;
+        self.directives()?;
+
+        Ok(Fragment {
+            name,
+            type_condition,
+            selection: self.selection_set()?,
+        })
+    }
+
+    fn type_ref(&mut self) -> Result<TypeRef> {
+        let ty = if self.skip('[') {
+            self.nest()?;
+            let inner = self.type_ref()?;
+            self.expect(']')?;
+            self.depth -= 1;
+            inner.list()
+        } else {
+            TypeRef::named(&self.name()?)
+        };
+        Ok(if self.skip('!') { ty.non_null() } else { ty })
+    }
+
+    fn selection_set(&mut self) -> Result<Vec<Selection>> {
+        self.expect('{')?;
+        self.nest()?;
+        let mut selection = Vec::new();
+        while !self.skip('}') {
+            selection.push(self.selection()?);
+        }
+        self.depth -= 1;
+        if selection.is_empty() {
+            return Err(syntax_error("empty selection set"));
+        }
+        Ok(selection)
+    }
+
+    fn selection(&mut self) -> Result<Selection> {
+        if *self.peek() == Token::Spread {
+            self.position += 1;
+            return match self.peek() {
+                Token::Name(name) if name != "on" => {
+                    let name = self.name()?;
+                    Ok(Selection::FragmentSpread {
+                        name,
+                        directives: self.directives()?,
+                    })
+                }
+                _ => {
+                    let type_condition = match self.peek() {
+                        Token::Name(_) => {
+                            self.keyword("on")?;
+                            Some(self.name()?)
+                        }
+                        _ => None,
+                    };
+                    Ok(Selection::InlineFragment {
+                        type_condition,
+                        directives: self.directives()?,
+                        selection: self.selection_set()?,
+                    })
+                }
+            };
+        }
+
+        let mut name = self.name()?;
+        let mut alias = None;
+        if self.skip(':') {
+            alias = Some(name);
+            name = self.name()?;
+        }
+        let arguments = self.arguments(false)?;
+        let directives = self.directives()?;
+        let selection = if self.at('{') {
+            self.selection_set()?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Selection::Field(FieldSelection {
+            alias,
+            name,
+            arguments,
+            directives,
+            selection,
+        }))
+    }
+
+    fn arguments(&mut self, constant: bool) -> Result<Vec<(String, InputValue)>> {
+        let mut arguments = Vec::new();
+        if self.skip('(') {
+            while !self.skip(')') {
+                let name = self.name()?;
+                self.expect(':')?;
+                arguments.push((name, self.value(constant)?));
+            }
+        }
+        Ok(arguments)
+    }
+
+    fn directives(&mut self) -> Result<Vec<Directive>> {
+        let mut directives = Vec::new();
+        while self.skip('@') {
+            let name = self.name()?;
+            directives.push(Directive {
+                name,
+                arguments: self.arguments(false)?,
+            });
+        }
+        Ok(directives)
+    }
+
+    /// Parses a value, where `constant` rules out variables, as in defaults.
+    fn value(&mut self, constant: bool) -> Result<InputValue> {
+        match self.next() {
+            Token::Punctuator('$') if !constant => Ok(InputValue::Variable(self.name()?)),
+            Token::Int(value) => Ok(InputValue::Int(value)),
+            Token::Float(value) => Ok(InputValue::Float(value)),
+            Token::String(value) => Ok(InputValue::String(value)),
+            Token::Name(name) => Ok(match name.as_str() {
+                "true" => InputValue::Boolean(true),
+                "false" => InputValue::Boolean(false),
+                "null" => InputValue::Null,
+                _ => InputValue::Enum(name),
+            }),
+            Token::Punctuator('[') => {
+                self.nest()?;
+                let mut items = Vec::new();
+                while !self.skip(']') {
+                    items.push(self.value(constant)?);
+                }
+                self.depth -= 1;
+                Ok(InputValue::List(items))
+            }
+            Token::Punctuator('{') => {
+                self.nest()?;
+                let mut fields = Vec::new();
+                while !self.skip('}') {
+                    let name = self.name()?;
+                    self.expect(':')?;
+                    fields.push((name, self.value(constant)?));
+                }
+                self.depth -= 1;
+                Ok(InputValue::Object(fields))
+            }
+            _ => {
+                self.position -= 1;
+                Err(self.unexpected("a value"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_operation() {
+        let document = parse(
+            r#"
+            query Files($prefix: String = "docs/", $limit: Int!) {
+                me {
+                    id
+                    page: objects(prefix: $prefix, limit: $limit) @include(if: true) {
+                        objects { ...file }
+                    }
+                }
+            }
+            fragment file on Object { path, version }
+        "#,
+        )
+        .unwrap();
+
+        let operation = &document.operations[0];
+        assert_eq!(operation.kind, OperationKind::Query);
+        assert_eq!(operation.name.as_deref(), Some("Files"));
+        assert_eq!(operation.variables.len(), 2);
+        assert_eq!(operation.variables[1].ty.to_string(), "Int!");
+        assert_eq!(
+            operation.variables[0].default,
+            Some(InputValue::String("docs/".to_string()))
+        );
+
+        let Selection::Field(me) = &operation.selection[0] else {
+            panic!("expected a field");
+        };
+        let Selection::Field(page) = &me.selection[1] else {
+            panic!("expected a field");
+        };
+        assert_eq!(page.response_key(), "page");
+        assert_eq!(page.name, "objects");
+        assert_eq!(
+            page.arguments[1],
+            (
+                "limit".to_string(),
+                InputValue::Variable("limit".to_string())
+            )
+        );
+        assert_eq!(page.directives[0].name, "include");
+        assert_eq!(document.fragments["file"].type_condition, "Object");
+    }
+
+    #[test]
+    fn test_parse_values() {
+        let document = parse(
+            r#"{ f(a: -1.5e3, b: [1, "two\nA"], c: {d: null, e: ENUM}, g: """
+                block
+                  text
+            """) }"#,
+        )
+        .unwrap();
+        let Selection::Field(field) = &document.operations[0].selection[0] else {
+            panic!("expected a field");
+        };
+
+        assert_eq!(field.arguments[0].1, InputValue::Float(-1500.0));
+        assert_eq!(
+            field.arguments[1].1,
+            InputValue::List(vec![
+                InputValue::Int(1),
+                InputValue::String("two\nA".to_string())
+            ])
+        );
+        assert_eq!(
+            field.arguments[2].1,
+            InputValue::Object(vec![
+                ("d".to_string(), InputValue::Null),
+                ("e".to_string(), InputValue::Enum("ENUM".to_string())),
+            ])
+        );
+        assert_eq!(
+            field.arguments[3].1,
+            InputValue::String("block\n  text".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("{ me { id }").is_err());
+        assert!(parse("subscription { me }").is_err());
+        assert!(parse("query ($a: Int = $b) { me }").is_err());
+        assert!(parse("fragment f on User { id }").is_err());
+        assert!(parse("{ }").is_err());
+    }
+
+    #[test]
+    fn test_parse_nesting_limit() {
+        let nested = |open: &str, close: &str, levels: usize| {
+            format!("{}{}", open.repeat(levels), close.repeat(levels))
+        };
+
+        let fields = format!("{{ a {} }}", nested("{ a ", "}", MAX_NESTING - 1));
+        assert!(parse(&fields).is_ok());
+        let fields = format!("{{ a {} }}", nested("{ a ", "}", MAX_NESTING));
+        assert!(parse(&fields).is_err());
+
+        let list = format!("{{ f(x: {}) }}", nested("[", "]", MAX_NESTING - 1));
+        assert!(parse(&list).is_ok());
+        let deep = format!("{{ f(x: {}) }}", nested("[", "]", 100_000));
+        assert!(parse(&deep).is_err());
+        let object = format!("{{ f(x: {}) }}", nested("{a: ", "}", 100_000));
+        assert!(parse(&object).is_err());
+        let ty = format!(
+            "query ($x: {}Int{}) {{ f }}",
+            "[".repeat(100_000),
+            "]".repeat(100_000)
+        );
+        assert!(parse(&ty).is_err());
+    }
+}
@@ -0,0 +1,12 @@
+use super::handlers::{graphql, graphql_schema};
+use crate::middleware::auth_middleware;
+use crate::state::AppState;
+use axum::routing::get;
+use axum::{middleware, Router};
+use std::sync::Arc;
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(graphql_schema).post(graphql))
+        .route_layer(middleware::from_fn(auth_middleware))
+}
@@ -0,0 +1,363 @@
+//! The GraphQL schema, built in code from object types whose fields carry their own
+//! resolvers. Fields without a resolver read the key of the same name from the parent
+//! value, so most types are plain JSON objects returned by their parent's resolver.
+
+use crate::error::{Error, Result};
+use axum::http::StatusCode;
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+
+/// Scalars every schema has.
+const BUILTIN_SCALARS: [&str; 5] = ["Int", "Float", "String", "Boolean", "ID"];
+/// Limits used until a schema sets its own.
+const DEFAULT_MAX_DEPTH: usize = 10;
+const DEFAULT_MAX_COMPLEXITY: usize = 250;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeRef {
+    Named(String),
+    List(Box<TypeRef>),
+    NonNull(Box<TypeRef>),
+}
+
+impl TypeRef {
+    pub fn named(name: &str) -> Self {
+        TypeRef::Named(name.to_string())
+    }
+
+    pub fn list(self) -> Self {
+        TypeRef::List(Box::new(self))
+    }
+
+    pub fn non_null(self) -> Self {
+        TypeRef::NonNull(Box::new(self))
+    }
+
+    /// The named type at the bottom of any list and non-null wrappers.
+    pub fn base(&self) -> &str {
+        match self {
+            TypeRef::Named(name) => name,
+            TypeRef::List(inner) | TypeRef::NonNull(inner) => inner.base(),
+        }
+    }
+}
+
+impl fmt::Display for TypeRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeRef::Named(name) => write!(f, "{}", name),
+            TypeRef::List(inner) => write!(f, "[{}]", inner),
+            TypeRef::NonNull(inner) => write!(f, "{}!", inner),
+        }
+    }
+}
+
+/// What a resolver is called with: the request's context, the value of the parent
+/// object, and the field's arguments, already checked against their declared types.
+pub struct Resolve<C> {
+    pub context: C,
+    pub parent: Value,
+    pub args: Map<String, Value>,
+}
+
+impl<C> Resolve<C> {
+    /// Deserializes an argument, which is null when it was left out without a default.
+    pub fn arg<T: DeserializeOwned>(&self, name: &str) -> Result<T> {
+        let value = self.args.get(name).cloned().unwrap_or(Value::Null);
+        serde_json::from_value(value).map_err(|e| {
+            Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid argument '{}': {}", name, e),
+            )
+        })
+    }
+}
+
+pub type Resolver<C> = Arc<dyn Fn(Resolve<C>) -> BoxFuture<'static, Result<Value>> + Send + Sync>;
+
+pub struct Argument {
+    pub name: String,
+    pub ty: TypeRef,
+    pub default: Option<Value>,
+}
+
+pub struct Field<C> {
+    pub name: String,
+    pub ty: TypeRef,
+    pub description: Option<String>,
+    pub arguments: Vec<Argument>,
+    /// What selecting the field adds to a query's complexity.
+    pub cost: usize,
+    pub resolver: Option<Resolver<C>>,
+}
+
+impl<C> Field<C> {
+    pub fn new(name: &str, ty: TypeRef) -> Self {
+        Field {
+            name: name.to_string(),
+            ty,
+            description: None,
+            arguments: Vec::new(),
+            cost: 1,
+            resolver: None,
+        }
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    pub fn argument(mut self, name: &str, ty: TypeRef) -> Self {
+        self.arguments.push(Argument {
+            name: name.to_string(),
+            ty,
+            default: None,
+        });
+        self
+    }
+
+    /// An argument that takes `default` when left out.
+    pub fn argument_default(mut self, name: &str, ty: TypeRef, default: Value) -> Self {
+        self.arguments.push(Argument {
+            name: name.to_string(),
+            ty,
+            default: Some(default),
+        });
+        self
+    }
+
+    /// Sets the field's cost, for fields that are expensive to resolve or return lists.
+    pub fn cost(mut self, cost: usize) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    pub fn resolve<F, Fut>(mut self, resolver: F) -> Self
+    where
+        F: Fn(Resolve<C>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Value>> + Send + 'static,
+    {
+        self.resolver = Some(Arc::new(move |resolve| Box::pin(resolver(resolve))));
+        self
+    }
+}
+
+pub struct ObjectType<C> {
+    pub name: String,
+    pub description: Option<String>,
+    pub fields: Vec<Field<C>>,
+}
+
+impl<C> ObjectType<C> {
+    pub fn new(name: &str) -> Self {
+        ObjectType {
+            name: name.to_string(),
+            description: None,
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    pub fn field(mut self, field: Field<C>) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Field<C>> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+}
+
+/// Object types by name, with `Query` and `Mutation` as the roots. Registering a type
+/// that already exists adds its fields to it, which is how application code extends
+/// the roots and the built-in types.
+pub struct Schema<C> {
+    types: BTreeMap<String, ObjectType<C>>,
+    scalars: BTreeSet<String>,
+    pub max_depth: usize,
+    pub max_complexity: usize,
+}
+
+impl<C> fmt::Debug for Schema<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Schema")
+            .field("types", &self.types.keys().collect::<Vec<_>>())
+            .field("scalars", &self.scalars)
+            .field("max_depth", &self.max_depth)
+            .field("max_complexity", &self.max_complexity)
+            .finish()
+    }
+}
+
+impl<C> Default for Schema<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> Schema<C> {
+    pub fn new() -> Self {
+        Schema {
+            types: BTreeMap::new(),
+            scalars: BUILTIN_SCALARS.iter().map(|s| s.to_string()).collect(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_complexity: DEFAULT_MAX_COMPLEXITY,
+        }
+    }
+
+    /// Adds an object type, or its fields to the type of the same name. A field that
+    /// already exists is replaced.
+    pub fn register(&mut self, ty: ObjectType<C>) -> &mut Self {
+        match self.types.get_mut(&ty.name) {
+            Some(existing) => {
+                for field in ty.fields {
+                    existing.fields.retain(|f| f.name != field.name);
+                    existing.fields.push(field);
+                }
+                if ty.description.is_some() {
+                    existing.description = ty.description;
+                }
+            }
+            None => {
+                self.types.insert(ty.name.clone(), ty);
+            }
+        }
+        self
+    }
+
+    /// Adds a custom scalar, passed through as whatever JSON its resolvers return.
+    pub fn scalar(&mut self, name: &str) -> &mut Self {
+        self.scalars.insert(name.to_string());
+        self
+    }
+
+    pub fn object(&self, name: &str) -> Option<&ObjectType<C>> {
+        self.types.get(name)
+    }
+
+    pub fn is_scalar(&self, name: &str) -> bool {
+        self.scalars.contains(name)
+    }
+
+    /// Checks every field and argument refers to a type the schema has, and that
+    /// arguments take scalars only.
+    pub fn validate(&self) -> Result<()> {
+        for ty in self.types.values() {
+            for field in &ty.fields {
+                let base = field.ty.base();
+                if !self.is_scalar(base) && self.object(base).is_none() {
+                    return Err(Error::from(format!(
+                        "Field '{}.{}' has unknown type '{}'",
+                        ty.name, field.name, base
+                    )));
+                }
+                for argument in &field.arguments {
+                    if !self.is_scalar(argument.ty.base()) {
+                        return Err(Error::from(format!(
+                            "Argument '{}' of '{}.{}' must be a scalar",
+                            argument.name, ty.name, field.name
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The schema in the GraphQL schema definition language, for clients and tooling.
+    pub fn sdl(&self) -> String {
+        let mut sdl = String::new();
+
+        for scalar in &self.scalars {
+            if !BUILTIN_SCALARS.contains(&scalar.as_str()) {
+                sdl.push_str(&format!("scalar {}\n\n", scalar));
+            }
+        }
+
+        for ty in self.types.values().filter(|ty| !ty.fields.is_empty()) {
+            if let Some(description) = &ty.description {
+                sdl.push_str(&format!("\"\"\"{}\"\"\"\n", description));
+            }
+            sdl.push_str(&format!("type {} {{\n", ty.name));
+            for field in &ty.fields {
+                if let Some(description) = &field.description {
+                    sdl.push_str(&format!("  \"\"\"{}\"\"\"\n", description));
+                }
+                sdl.push_str(&format!("  {}", field.name));
+                if !field.arguments.is_empty() {
+                    let arguments: Vec<String> = field
+                        .arguments
+                        .iter()
+                        .map(|a| match &a.default {
+                            Some(default) => format!("{}: {} = {}", a.name, a.ty, default),
+                            None => format!("{}: {}", a.name, a.ty),
+                        })
+                        .collect();
+                    sdl.push_str(&format!("({})", arguments.join(", ")));
+                }
+                sdl.push_str(&format!(": {}\n", field.ty));
+            }
+            sdl.push_str("}\n\n");
+        }
+
+        sdl.truncate(sdl.trim_end().len());
+        sdl.push('\n');
+        sdl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_register_merges_fields() {
+        let mut schema: Schema<()> = Schema::new();
+        schema.register(
+            ObjectType::new("Query")
+                .field(Field::new("a", TypeRef::named("Int")))
+                .field(Field::new("b", TypeRef::named("Int"))),
+        );
+        schema.register(
+            ObjectType::new("Query").field(Field::new("b", TypeRef::named("String").non_null())),
+        );
+
+        let query = schema.object("Query").unwrap();
+        assert_eq!(query.fields.len(), 2);
+        assert_eq!(query.get("b").unwrap().ty.to_string(), "String!");
+    }
+
+    #[test]
+    fn test_sdl() {
+        let mut schema: Schema<()> = Schema::new();
+        schema.scalar("DateTime");
+        schema.register(ObjectType::new("Query").field(
+            Field::new("items", TypeRef::named("DateTime").non_null().list()).argument_default(
+                "limit",
+                TypeRef::named("Int"),
+                json!(10),
+            ),
+        ));
+        schema.register(ObjectType::new("Mutation"));
+
+        assert_eq!(
+            schema.sdl(),
+            "scalar DateTime\n\ntype Query {\n  items(limit: Int = 10): [DateTime!]\n}\n"
+        );
+        assert!(schema.validate().is_ok());
+
+        schema.register(ObjectType::new("Query").field(Field::new("x", TypeRef::named("Nope"))));
+        assert!(schema.validate().is_err());
+    }
+}
@@ -9,6 +9,7 @@ use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::{self, Extension};
 use serde_json::json;
+use sqlx::types::Uuid;
 use std::sync::Arc;
 
 // Gmail
@@ -69,7 +70,7 @@ pub async fn gmail_subscription(
 }
 
 pub async fn gmail_callback(State(state): State<Arc<AppState>>, Path(id): Path<String>) {
-    let key = format!("ws:{}", id);
+    let user_id = Uuid::parse_str(&id).expect("errror");
 
     let event = Event {
         name: "event".to_string(),
@@ -79,7 +80,7 @@ pub async fn gmail_callback(State(state): State<Arc<AppState>>, Path(id): Path<S
     state
         .storage
         .redis
-        .append_event_queue(&key, &event)
+        .notify_user(user_id, &event)
         .await
         .expect("errror");
 }
@@ -1,17 +1,24 @@
+pub mod admin;
+pub mod audit;
 pub mod auth;
 pub mod config;
 pub mod crypt;
 pub mod data;
 pub mod error;
+pub mod exports;
+pub mod graphql;
 pub mod integrations;
 pub mod logger;
 pub mod middleware;
 pub mod objects;
+pub mod realtime;
 pub mod response;
+pub mod rest;
 pub mod router;
 pub mod smtp;
 pub mod state;
 pub mod storage;
+pub mod tasks;
 pub mod users;
 pub mod utils;
 pub mod websocket;
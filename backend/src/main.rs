@@ -2,8 +2,13 @@ use app::config::CONFIG;
 use app::logger::init_global_logger;
 use app::router::router;
 use app::state::AppState;
-use app::Result;
+use app::storage::postgres::init_db;
+use app::storage::postgres::migrations;
+use app::storage::StorageClient;
+use app::tasks::spawn_background_tasks;
+use app::{Error, Result};
 use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::net::TcpListener;
 
 #[tokio::main]
@@ -11,16 +16,76 @@ async fn main() -> Result<()> {
     // Setup Logging
     // init_global_logger()?;
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None | Some("serve") => serve().await,
+        Some("reconcile") => reconcile(args.iter().any(|a| a == "--repair")).await,
+        Some("rotate-keys") => rotate_keys().await,
+        Some("migrate") => migrate().await,
+        Some(command) => Err(Error::from(format!(
+            "Unknown command '{}', expected serve, migrate, reconcile [--repair] or rotate-keys",
+            command
+        ))),
+    }
+}
+
+async fn serve() -> Result<()> {
     // Initialize the Axum routing service
-    let state = AppState::new().await?;
+    let state = Arc::new(AppState::new().await?);
+
+    // Nothing may touch the schema before it matches this build
+    let pool = &state.storage.postgres.pool;
+    if CONFIG.migrate_on_start {
+        migrations::migrate(pool).await?;
+    } else {
+        migrations::verify(pool).await?;
+    }
+    state.graphql.validate()?;
+    spawn_background_tasks(state.clone());
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
     let listener = TcpListener::bind(addr).await?;
     tracing::info!("Listening on {}", &CONFIG.app_url);
 
-    // Run the server
+    // Run the server, keeping each connection's peer address for the audit log
     let app = router(state);
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
+
+    Ok(())
+}
+
+/// One-off reconcile from the command line, printing the report as JSON.
+async fn reconcile(repair: bool) -> Result<()> {
+    let storage = StorageClient::new().await?;
+    let report = storage.reconcile(repair).await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+/// Rewraps object data keys with the current master key after a rotation.
+async fn rotate_keys() -> Result<()> {
+    let storage = StorageClient::new().await?;
+    let rotated = storage.rotate_keys().await?;
+    println!("Rotated {} data keys", rotated);
+    Ok(())
+}
 
+/// Applies pending schema migrations, for deployments that set `MIGRATE_ON_START=false`.
+async fn migrate() -> Result<()> {
+    let pool = init_db(&CONFIG.postgres_url).await?;
+    let applied = migrations::migrate(&pool).await?;
+    for version in &applied {
+        println!("Applied migration {}", version);
+    }
+    println!(
+        "Schema is at migration {} ({} applied)",
+        migrations::latest_version(),
+        applied.len()
+    );
     Ok(())
 }
@@ -29,7 +29,7 @@ pub async fn auth_middleware(
     Ok(response)
 }
 
-fn get_token(headers: &HeaderMap) -> Result<&str> {
+pub(crate) fn get_token(headers: &HeaderMap) -> Result<&str> {
     headers
         .get("Authorization")
         .and_then(|v| v.to_str().ok())
@@ -37,7 +37,7 @@ fn get_token(headers: &HeaderMap) -> Result<&str> {
         .ok_or_else(|| Error::from("Missing or invalid token"))
 }
 
-fn validate_jwt(token: &str, require_mfa: bool) -> Result<Claims> {
+pub(crate) fn validate_jwt(token: &str, require_mfa: bool) -> Result<Claims> {
     let claims = decode_jwt(token)?;
 
     if claims.exp <= Utc::now().timestamp() as usize {
@@ -1,31 +1,99 @@
+use crate::audit::{Audit, Event, Outcome};
 use crate::crypt::jwt::Claims;
 use crate::data::Objects;
 use crate::error::Result;
+use crate::objects::models::{
+    ArchivePayload, EvaluatePayload, ExpiryPayload, FolderPayload, FolderQuery, LifecyclePayload,
+    LifecycleQuery, ListQuery, MoveFolderPayload, PublicFileQuery, RenameFolderPayload,
+    RestoreVersionPayload, RetentionPayload, TransformQuery, UploadQuery, VersionsQuery,
+};
+use crate::objects::rules::{Decision, Operation, Principal, Target, Upload};
+use crate::objects::zip;
 use crate::response::ApiResponse;
 use crate::state::AppState;
+use crate::utils::normalize_path;
 use crate::Error;
+use axum::body::Body;
 use axum::extract::Multipart;
 use axum::extract::Query;
 use axum::extract::State;
 use axum::http::header;
+use axum::http::HeaderMap;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::Extension;
+use axum::Json;
 use sqlx::types::Uuid;
 use std::collections::HashMap;
 use std::sync::Arc;
 
+pub(crate) const DEFAULT_LIST_LIMIT: i64 = 100;
+pub(crate) const MAX_LIST_LIMIT: i64 = 1000;
+
 pub async fn upload_file(
     State(state): State<Arc<AppState>>,
     Extension(claims): Extension<Claims>,
-    multipart: Multipart,
+    Query(params): Query<UploadQuery>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
 ) -> Result<impl IntoResponse> {
-    let user_id = Uuid::parse_str(&claims.sub)?;
+    let owner = owner_id(&claims, params.owner.as_deref())?;
+    let folder = normalize_path(params.folder.as_deref().unwrap_or(""))?;
+    let (field, filename, content_type) = Objects::upload_field(&mut multipart).await?;
+    let (object, mut version) = Objects::new_upload(owner, &folder, filename, content_type, 0)?;
+
+    // Nothing about the owner's storage is looked at, or read, until the write is allowed
+    let principal = principal(&state, Some(&claims)).await?;
+    authorize(
+        &state,
+        &principal,
+        owner,
+        object.path(),
+        Operation::Write,
+        None,
+    )
+    .await?;
+
+    // Reject up front when the declared size can't fit, then enforce while streaming
+    let remaining = state.storage.usage(owner).await?.remaining_bytes;
+    let content_length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<i64>().ok());
+    if let Some((remaining, length)) = remaining.zip(content_length)
+        && length > remaining
+    {
+        // Only the owner learns how much of their quota is left
+        return Err(Error::QuotaExceeded(
+            match owner.to_string() == claims.sub {
+                true => format!(
+                    "Upload of {} bytes exceeds the remaining {} bytes",
+                    length, remaining
+                ),
+                false => "Upload exceeds the remaining storage quota".into(),
+            },
+        ));
+    }
 
-    let (object, file) = Objects::process_upload(multipart, user_id).await?;
-    let path = object_store::path::Path::from(object.key.as_ref());
+    let file = Objects::read_upload(field, remaining).await?;
+    version.size_bytes = file.len() as i64;
 
-    match state.storage.upload_file(&object, &path, &file).await {
+    // The rule's size and type limits apply now the file is known
+    let upload = Upload {
+        size: version.size_bytes,
+        content_type: &version.content_type,
+    };
+    authorize(
+        &state,
+        &principal,
+        owner,
+        object.path(),
+        Operation::Write,
+        Some(&upload),
+    )
+    .await?;
+
+    match state.storage.upload_file(&object, version, &file).await {
         Ok(_) => Ok(ApiResponse::new(
             StatusCode::CREATED,
             "Successfully created file",
@@ -43,13 +111,45 @@ pub async fn get_file(
     let name = params
         .get("name")
         .ok_or_else(|| Error::from("Missing 'name' parameter"))?;
-    let key = format!("{}/{}", &claims.sub, name);
-    let path = object_store::path::Path::from(key.as_str());
+    let owner = params.get("owner").map(String::as_str);
+    let path = authorized_path(&state, &claims, owner, name, Operation::Read).await?;
+    let version = params
+        .get("version")
+        .map(|v| v.parse::<i32>())
+        .transpose()?;
+
+    serve_file(&state, &path, version).await
+}
+
+/// Serves an object the storage rules make readable without signing in.
+pub async fn get_public_file(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<PublicFileQuery>,
+) -> Result<impl IntoResponse> {
+    let owner = Uuid::parse_str(&params.owner)?;
+    let name = normalize_path(&params.name)?;
+    authorize(
+        &state,
+        &Principal::default(),
+        owner,
+        &name,
+        Operation::Read,
+        None,
+    )
+    .await?;
+
+    serve_file(&state, &object_path(owner, &name)?, None).await
+}
 
-    match state.storage.get_file(&path).await {
-        Ok((obj, file)) => {
+async fn serve_file(
+    state: &AppState,
+    path: &object_store::path::Path,
+    version: Option<i32>,
+) -> Result<axum::response::Response> {
+    match state.storage.get_file(path, version).await {
+        Ok((obj, version, file)) => {
             let headers = [
-                (header::CONTENT_TYPE, "application/octet-stream"),
+                (header::CONTENT_TYPE, version.content_type.as_str()),
                 (
                     header::CONTENT_DISPOSITION,
                     &format!("attachment; filename=\"{}\"", obj.filename),
@@ -62,18 +162,102 @@ pub async fn get_file(
     }
 }
 
+/// Streams the named objects and/or everything in a folder as a ZIP archive.
+pub async fn download_archive(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<ArchivePayload>,
+) -> Result<impl IntoResponse> {
+    if payload.names.is_empty() && payload.folder.is_none() {
+        return Err(Error::from("Missing 'names' or 'folder'"));
+    }
+
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let keys = payload
+        .names
+        .iter()
+        .map(|name| Ok(object_path(user_id, name)?.to_string()))
+        .collect::<Result<Vec<String>>>()?;
+    let folder = payload.folder.as_deref().map(normalize_path).transpose()?;
+    let prefix = folder.as_deref().map(|folder| match folder {
+        "" => Objects::root(user_id),
+        folder => Objects::key_for(user_id, &format!("{}/", folder)),
+    });
+
+    let entries = state
+        .storage
+        .archive_entries(user_id, &keys, prefix.as_deref())
+        .await?;
+    // Names are collected first, as the entries' sources can't be shared across awaits
+    let principal = principal(&state, Some(&claims)).await?;
+    let names: Vec<String> = entries.iter().map(|entry| entry.name.clone()).collect();
+    for name in &names {
+        authorize(&state, &principal, user_id, name, Operation::Read, None).await?;
+    }
+
+    let filename = match folder.as_deref().and_then(|f| f.rsplit('/').next()) {
+        Some(name) if !name.is_empty() => format!("{}.zip", name),
+        _ => "download.zip".to_string(),
+    };
+    let headers = [
+        (header::CONTENT_TYPE, "application/zip".to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        ),
+    ];
+
+    Ok((headers, Body::from_stream(zip::stream(entries))).into_response())
+}
+
+pub async fn transform_image(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Query(params): Query<TransformQuery>,
+) -> Result<impl IntoResponse> {
+    let path = authorized_path(
+        &state,
+        &claims,
+        params.owner.as_deref(),
+        &params.name,
+        Operation::Read,
+    )
+    .await?;
+    let transform = params.transform();
+    transform.validate()?;
+
+    let (image, format) = state.storage.transform_image(&path, &transform).await?;
+    let headers = [
+        (header::CONTENT_TYPE, format.content_type()),
+        (header::CACHE_CONTROL, "private, max-age=3600"),
+    ];
+
+    Ok((headers, image).into_response())
+}
+
 pub async fn delete_files(
     State(state): State<Arc<AppState>>,
     Extension(claims): Extension<Claims>,
+    audit: Audit,
     Query(params): Query<HashMap<String, String>>,
 ) -> Result<impl IntoResponse> {
     let name = params
         .get("name")
         .ok_or_else(|| Error::from("Missing 'name' parameter"))?;
-    let key = format!("{}/{}", &claims.sub, name);
-    let path = object_store::path::Path::from(key.as_str());
+    let owner = params.get("owner").map(String::as_str);
+    let event = Event::new("object.delete")
+        .actor(Uuid::parse_str(&claims.sub)?)
+        .target(Objects::key_for(owner_id(&claims, owner)?, name));
 
-    match state.storage.delete_file(&path).await {
+    // Denied deletes are recorded as well
+    let result = async {
+        let path = authorized_path(&state, &claims, owner, name, Operation::Write).await?;
+        state.storage.delete_file(&path).await
+    }
+    .await;
+    audit.record(&state, event, Outcome::of(&result)).await;
+
+    match result {
         Ok(_) => Ok(ApiResponse::new(StatusCode::NO_CONTENT, "", "")),
         Err(e) => Err(e.into()),
     }
@@ -82,15 +266,491 @@ pub async fn delete_files(
 pub async fn list_files(
     State(state): State<Arc<AppState>>,
     Extension(claims): Extension<Claims>,
+    Query(params): Query<ListQuery>,
+) -> Result<impl IntoResponse> {
+    let owner = owner_id(&claims, params.owner.as_deref())?;
+    let prefix = list_prefix(params.prefix.as_deref().unwrap_or(""))?;
+    // Listing needs read access to the prefix itself
+    let principal = principal(&state, Some(&claims)).await?;
+    authorize(&state, &principal, owner, &prefix, Operation::Read, None).await?;
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+
+    let mut list = state
+        .storage
+        .list_files(
+            owner,
+            &prefix,
+            params.delimiter.as_deref(),
+            params.cursor.as_deref(),
+            limit,
+        )
+        .await?;
+
+    // Rules can differ below the prefix, so each entry is only listed if it can be read
+    let owner_organization = owner_organization(&state, &principal, owner).await?;
+    let readable = |path: &str| {
+        let target = Target {
+            owner,
+            owner_organization,
+            path,
+        };
+        state
+            .rules
+            .evaluate(&principal, &target, Operation::Read, None)
+            .allowed
+    };
+    list.objects.retain(|object| readable(object.path()));
+    list.prefixes.retain(|prefix| readable(prefix));
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        &format!("Successfully retrieved list."),
+        list,
+    ))
+}
+
+pub async fn get_usage(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
 ) -> Result<impl IntoResponse> {
     let user_id = Uuid::parse_str(&claims.sub)?;
+    let usage = state.storage.usage(user_id).await?;
 
-    match state.storage.list_files(user_id).await {
-        Ok(objs) => Ok(ApiResponse::new(
-            StatusCode::OK,
-            &format!("Successfully retrieved list."),
-            objs,
-        )),
-        Err(e) => Err(e.into()),
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully retrieved usage.",
+        usage,
+    ))
+}
+
+pub async fn list_versions(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Query(params): Query<VersionsQuery>,
+) -> Result<impl IntoResponse> {
+    let path = authorized_path(
+        &state,
+        &claims,
+        params.owner.as_deref(),
+        &params.name,
+        Operation::Read,
+    )
+    .await?;
+    let versions = state.storage.list_versions(&path).await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully retrieved versions.",
+        versions,
+    ))
+}
+
+pub async fn restore_version(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<RestoreVersionPayload>,
+) -> Result<impl IntoResponse> {
+    let path = authorized_path(&state, &claims, None, &payload.name, Operation::Write).await?;
+    let version = state
+        .storage
+        .restore_version(&path, payload.version)
+        .await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        &format!(
+            "Restored version {} as version {}",
+            payload.version, version
+        ),
+        version,
+    ))
+}
+
+pub async fn set_retention(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<RetentionPayload>,
+) -> Result<impl IntoResponse> {
+    if payload.max_versions.is_some_and(|max| max < 1) {
+        return Err(Error::from("max_versions must be at least 1"));
+    }
+
+    let path = authorized_path(&state, &claims, None, &payload.name, Operation::Write).await?;
+    state
+        .storage
+        .set_max_versions(&path, payload.max_versions)
+        .await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully updated retention",
+        "",
+    ))
+}
+
+/// Sets or clears the time an object is deleted at, overriding lifecycle rules.
+pub async fn set_expiry(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<ExpiryPayload>,
+) -> Result<impl IntoResponse> {
+    let path = authorized_path(&state, &claims, None, &payload.name, Operation::Write).await?;
+    state.storage.set_expiry(&path, payload.expires_at).await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully updated expiry",
+        "",
+    ))
+}
+
+pub async fn list_lifecycle_rules(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse> {
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let rules = state.storage.postgres.list_lifecycle_rules(user_id).await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully retrieved lifecycle rules",
+        rules,
+    ))
+}
+
+/// Creates or replaces the lifecycle rule for a prefix.
+pub async fn put_lifecycle_rule(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<LifecyclePayload>,
+) -> Result<impl IntoResponse> {
+    let days = [payload.expire_after_days, payload.transition_after_days];
+    if days.iter().all(Option::is_none) {
+        return Err(Error::from(
+            "A rule needs expire_after_days or transition_after_days",
+        ));
+    }
+    if days.iter().flatten().any(|days| *days < 1) {
+        return Err(Error::from("Lifecycle days must be at least 1"));
+    }
+    if payload.transition_after_days.is_some() && state.storage.archive.is_none() {
+        return Err(Error::HttpError(
+            StatusCode::BAD_REQUEST,
+            "No archive store is configured to transition to".into(),
+        ));
+    }
+
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let prefix = lifecycle_prefix(user_id, &payload.prefix)?;
+    let rule = state
+        .storage
+        .postgres
+        .upsert_lifecycle_rule(
+            user_id,
+            &prefix,
+            payload.expire_after_days,
+            payload.transition_after_days,
+        )
+        .await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully saved lifecycle rule",
+        rule,
+    ))
+}
+
+pub async fn delete_lifecycle_rule(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Query(params): Query<LifecycleQuery>,
+) -> Result<impl IntoResponse> {
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let prefix = lifecycle_prefix(user_id, &params.prefix)?;
+
+    if !state
+        .storage
+        .postgres
+        .delete_lifecycle_rule(user_id, &prefix)
+        .await?
+    {
+        return Err(Error::HttpError(
+            StatusCode::NOT_FOUND,
+            "No lifecycle rule for this prefix".into(),
+        ));
+    }
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully deleted lifecycle rule",
+        "",
+    ))
+}
+
+pub async fn create_folder(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<FolderPayload>,
+) -> Result<impl IntoResponse> {
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let path = folder_path(&payload.path)?;
+    authorize_folders(&state, &claims, [&path]).await?;
+
+    state.storage.create_folder(user_id, &path).await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::CREATED,
+        "Successfully created folder",
+        path,
+    ))
+}
+
+pub async fn move_folder(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<MoveFolderPayload>,
+) -> Result<impl IntoResponse> {
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let from = folder_path(&payload.from)?;
+    let to = folder_path(&payload.to)?;
+    authorize_folders(&state, &claims, [&from, &to]).await?;
+
+    state.storage.move_folder(user_id, &from, &to).await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully moved folder",
+        to,
+    ))
+}
+
+pub async fn rename_folder(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<RenameFolderPayload>,
+) -> Result<impl IntoResponse> {
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let from = folder_path(&payload.path)?;
+
+    let name = normalize_path(&payload.name)?;
+    if name.is_empty() || name.contains('/') {
+        return Err(Error::from(format!(
+            "Invalid folder name '{}'",
+            payload.name
+        )));
+    }
+
+    let to = match from.rsplit_once('/') {
+        Some((parent, _)) => format!("{}/{}", parent, name),
+        None => name,
+    };
+    authorize_folders(&state, &claims, [&from, &to]).await?;
+
+    state.storage.move_folder(user_id, &from, &to).await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully renamed folder",
+        to,
+    ))
+}
+
+pub async fn delete_folder(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+    Query(params): Query<FolderQuery>,
+) -> Result<impl IntoResponse> {
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let path = folder_path(&params.path)?;
+    let event = Event::new("folder.delete")
+        .actor(user_id)
+        .target(Objects::key_for(user_id, &path));
+
+    let result = async {
+        authorize_folders(&state, &claims, [&path]).await?;
+        state.storage.delete_folder(user_id, &path).await
+    }
+    .await;
+    audit.record(&state, event, Outcome::of(&result)).await;
+    result?;
+
+    Ok(ApiResponse::new(StatusCode::NO_CONTENT, "", ""))
+}
+
+/// Evaluates an access against the storage rules without performing it, to debug them.
+pub async fn evaluate_rules(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<EvaluatePayload>,
+) -> Result<impl IntoResponse> {
+    let owner = owner_id(&claims, payload.owner.as_deref())?;
+    let path = normalize_path(&payload.path)?;
+    let principal = match payload.anonymous {
+        true => Principal::default(),
+        false => principal(&state, Some(&claims)).await?,
+    };
+    let upload = payload.size.map(|size| Upload {
+        size,
+        content_type: payload
+            .content_type
+            .as_deref()
+            .unwrap_or("application/octet-stream"),
+    });
+
+    let decision = evaluate(
+        &state,
+        &principal,
+        owner,
+        &path,
+        payload.operation,
+        upload.as_ref(),
+    )
+    .await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully evaluated storage rules",
+        decision,
+    ))
+}
+
+/// The user whose root an `owner` parameter addresses, the caller's by default.
+fn owner_id(claims: &Claims, owner: Option<&str>) -> Result<Uuid> {
+    Ok(Uuid::parse_str(owner.unwrap_or(&claims.sub))?)
+}
+
+/// The caller as storage rules see them, anonymous without claims.
+pub(crate) async fn principal(state: &AppState, claims: Option<&Claims>) -> Result<Principal> {
+    let Some(claims) = claims else {
+        return Ok(Principal::default());
+    };
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let (role, organization_id) = member(state, user_id).await?;
+
+    Ok(Principal {
+        user_id: Some(user_id),
+        role: Some(role),
+        organization_id,
+    })
+}
+
+async fn member(state: &AppState, user_id: Uuid) -> Result<(String, Option<Uuid>)> {
+    state
+        .storage
+        .postgres
+        .get_member(user_id)
+        .await?
+        .ok_or_else(|| Error::HttpError(StatusCode::NOT_FOUND, "User not found".into()))
+}
+
+async fn evaluate(
+    state: &AppState,
+    principal: &Principal,
+    owner: Uuid,
+    path: &str,
+    operation: Operation,
+    upload: Option<&Upload<'_>>,
+) -> Result<Decision> {
+    let target = Target {
+        owner,
+        owner_organization: owner_organization(state, principal, owner).await?,
+        path,
+    };
+
+    Ok(state.rules.evaluate(principal, &target, operation, upload))
+}
+
+/// The organization of `owner`, looked up unless it's the caller.
+async fn owner_organization(
+    state: &AppState,
+    principal: &Principal,
+    owner: Uuid,
+) -> Result<Option<Uuid>> {
+    match principal.user_id == Some(owner) {
+        true => Ok(principal.organization_id),
+        false => Ok(member(state, owner).await?.1),
+    }
+}
+
+/// Checks an access to `path` under `owner`'s root against the storage rules.
+pub(crate) async fn authorize(
+    state: &AppState,
+    principal: &Principal,
+    owner: Uuid,
+    path: &str,
+    operation: Operation,
+    upload: Option<&Upload<'_>>,
+) -> Result<()> {
+    let decision = evaluate(state, principal, owner, path, operation, upload).await?;
+    if !decision.allowed {
+        return Err(Error::HttpError(StatusCode::FORBIDDEN, decision.reason));
+    }
+    Ok(())
+}
+
+/// Resolves `name` under the root of `owner`, or of the caller, to the object's logical
+/// key once the storage rules allow the access.
+async fn authorized_path(
+    state: &AppState,
+    claims: &Claims,
+    owner: Option<&str>,
+    name: &str,
+    operation: Operation,
+) -> Result<object_store::path::Path> {
+    let owner = owner_id(claims, owner)?;
+    let name = normalize_path(name)?;
+    let principal = principal(state, Some(claims)).await?;
+    authorize(state, &principal, owner, &name, operation, None).await?;
+
+    object_path(owner, &name)
+}
+
+/// Checks that the caller may write inside each of their folders.
+async fn authorize_folders<const N: usize>(
+    state: &AppState,
+    claims: &Claims,
+    folders: [&str; N],
+) -> Result<()> {
+    let owner = Uuid::parse_str(&claims.sub)?;
+    let principal = principal(state, Some(claims)).await?;
+    for folder in folders {
+        let path = format!("{}/", folder);
+        authorize(state, &principal, owner, &path, Operation::Write, None).await?;
+    }
+    Ok(())
+}
+
+/// Resolves a path relative to the owner's root to the object's logical key.
+fn object_path(owner: Uuid, name: &str) -> Result<object_store::path::Path> {
+    let key = Objects::key_for(owner, &normalize_path(name)?);
+    Ok(object_store::path::Path::from(key.as_str()))
+}
+
+/// Key prefix a lifecycle rule matches. A trailing slash is kept so `exports/` only
+/// matches inside the folder, and an empty prefix matches everything.
+fn lifecycle_prefix(user_id: Uuid, prefix: &str) -> Result<String> {
+    Ok(Objects::key_for(user_id, &list_prefix(prefix)?))
+}
+
+/// Normalizes a prefix relative to the owner's root, keeping a trailing slash so
+/// `docs/` stays inside the folder.
+fn list_prefix(prefix: &str) -> Result<String> {
+    let mut path = normalize_path(prefix)?;
+    if !path.is_empty() && prefix.ends_with('/') {
+        path.push('/');
+    }
+    Ok(path)
+}
+
+/// Normalizes a folder path, refusing the user root itself.
+fn folder_path(path: &str) -> Result<String> {
+    let path = normalize_path(path)?;
+    if path.is_empty() {
+        return Err(Error::from("Missing folder path"));
     }
+    Ok(path)
 }
@@ -1,2 +1,6 @@
 pub mod handlers;
+pub mod models;
 pub mod routes;
+pub mod rules;
+pub mod tus;
+pub mod zip;
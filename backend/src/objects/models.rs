@@ -0,0 +1,127 @@
+use crate::data::{Fit, ImageFormat, ImageTransform};
+use crate::objects::rules::Operation;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct UploadQuery {
+    pub folder: Option<String>,
+    pub owner: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    pub owner: Option<String>,
+    pub prefix: Option<String>,
+    pub delimiter: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FolderQuery {
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FolderPayload {
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoveFolderPayload {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameFolderPayload {
+    pub path: String,
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VersionsQuery {
+    pub owner: Option<String>,
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreVersionPayload {
+    pub name: String,
+    pub version: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RetentionPayload {
+    pub name: String,
+    pub max_versions: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExpiryPayload {
+    pub name: String,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LifecyclePayload {
+    pub prefix: String,
+    pub expire_after_days: Option<i32>,
+    pub transition_after_days: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LifecycleQuery {
+    pub prefix: String,
+}
+
+/// Objects to download as one archive: listed by name, under a folder, or both.
+#[derive(Debug, Deserialize)]
+pub struct ArchivePayload {
+    #[serde(default)]
+    pub names: Vec<String>,
+    pub folder: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PublicFileQuery {
+    pub owner: String,
+    pub name: String,
+}
+
+/// An access to check against the storage rules without performing it.
+#[derive(Debug, Deserialize)]
+pub struct EvaluatePayload {
+    pub owner: Option<String>,
+    pub path: String,
+    pub operation: Operation,
+    pub size: Option<i64>,
+    pub content_type: Option<String>,
+    /// Evaluate as a signed-out caller.
+    #[serde(default)]
+    pub anonymous: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransformQuery {
+    pub owner: Option<String>,
+    pub name: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Option<Fit>,
+    pub format: Option<ImageFormat>,
+    pub quality: Option<u8>,
+}
+
+impl TransformQuery {
+    pub fn transform(&self) -> ImageTransform {
+        ImageTransform {
+            width: self.width,
+            height: self.height,
+            fit: self.fit.unwrap_or_default(),
+            format: self.format,
+            quality: self.quality.unwrap_or(ImageTransform::DEFAULT_QUALITY),
+        }
+    }
+}
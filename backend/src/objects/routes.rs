@@ -1,7 +1,13 @@
 use crate::middleware::auth_middleware;
-use crate::objects::handlers::{delete_files, get_file, list_files, upload_file};
+use crate::objects::handlers::{
+    create_folder, delete_files, delete_folder, delete_lifecycle_rule, download_archive,
+    evaluate_rules, get_file, get_public_file, get_usage, list_files, list_lifecycle_rules,
+    list_versions, move_folder, put_lifecycle_rule, rename_folder, restore_version, set_expiry,
+    set_retention, transform_image, upload_file,
+};
+use crate::objects::tus::routes::router as tus_router;
 use crate::state::AppState;
-use axum::routing::{delete, get, post};
+use axum::routing::{delete, get, post, put};
 use axum::{middleware, Router};
 use std::sync::Arc;
 
@@ -10,6 +16,23 @@ pub fn router() -> Router<Arc<AppState>> {
         .route("/", post(upload_file))
         .route("/", get(get_file))
         .route("/", delete(delete_files))
+        .route("/transform", get(transform_image))
+        .route("/zip", post(download_archive))
         .route("/list", get(list_files))
+        .route("/usage", get(get_usage))
+        .route("/versions", get(list_versions))
+        .route("/versions/restore", post(restore_version))
+        .route("/versions/retention", put(set_retention))
+        .route("/expiry", put(set_expiry))
+        .route("/lifecycle", get(list_lifecycle_rules))
+        .route("/lifecycle", put(put_lifecycle_rule))
+        .route("/lifecycle", delete(delete_lifecycle_rule))
+        .route("/folders", post(create_folder))
+        .route("/folders", delete(delete_folder))
+        .route("/folders/move", post(move_folder))
+        .route("/folders/rename", post(rename_folder))
+        .route("/rules/evaluate", post(evaluate_rules))
         .route_layer(middleware::from_fn(auth_middleware))
+        .route("/public", get(get_public_file))
+        .nest("/tus", tus_router())
 }
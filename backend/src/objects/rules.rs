@@ -0,0 +1,399 @@
+//! Declarative access rules for stored objects, loaded from `STORAGE_RULES_PATH`:
+//!
+//! ```json
+//! {
+//!   "rules": [
+//!     { "match": "avatars/*", "read": ["public"], "write": ["owner"],
+//!       "max_size": 1048576, "content_types": ["image/*"] },
+//!     { "match": "shared/**", "read": ["owner", "org_member"], "write": ["owner", "org_member"] },
+//!     { "match": "**", "read": ["owner", "role:admin"], "write": ["owner"] }
+//!   ]
+//! }
+//! ```
+//!
+//! Patterns match paths relative to the owner's root, where `*` matches within a segment
+//! and `**` across segments. The first rule whose pattern matches decides, and a path
+//! no rule matches is denied. Without a rules file, owners alone can read and write.
+
+use crate::error::{Error, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Read,
+    Write,
+}
+
+/// Who a rule lets in.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Access {
+    /// Anyone, signed in or not.
+    Public,
+    /// The user whose root the object is under.
+    Owner,
+    /// A member of the owner's organization.
+    OrgMember,
+    /// A user with the given role.
+    Role(String),
+}
+
+impl TryFrom<String> for Access {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        match value.as_str() {
+            "public" => Ok(Access::Public),
+            "owner" => Ok(Access::Owner),
+            "org_member" => Ok(Access::OrgMember),
+            _ => match value.strip_prefix("role:") {
+                Some(role) if !role.is_empty() => Ok(Access::Role(role.to_string())),
+                _ => Err(format!("Unknown access '{}'", value)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Public => write!(f, "public"),
+            Access::Owner => write!(f, "owner"),
+            Access::OrgMember => write!(f, "org_member"),
+            Access::Role(role) => write!(f, "role:{}", role),
+        }
+    }
+}
+
+/// The caller, anonymous when there is no user.
+#[derive(Debug, Clone, Default)]
+pub struct Principal {
+    pub user_id: Option<Uuid>,
+    pub role: Option<String>,
+    pub organization_id: Option<Uuid>,
+}
+
+/// The object being accessed, by its owner and its path under the owner's root.
+#[derive(Debug)]
+pub struct Target<'a> {
+    pub owner: Uuid,
+    pub owner_organization: Option<Uuid>,
+    pub path: &'a str,
+}
+
+/// The size and type of an upload, checked against the rule's constraints.
+#[derive(Debug)]
+pub struct Upload<'a> {
+    pub size: i64,
+    pub content_type: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Decision {
+    pub allowed: bool,
+    /// Pattern of the rule that decided, if any matched.
+    pub rule: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    rules: Vec<RuleConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    #[serde(rename = "match")]
+    pattern: String,
+    #[serde(default)]
+    read: Vec<Access>,
+    #[serde(default)]
+    write: Vec<Access>,
+    max_size: Option<i64>,
+    #[serde(default)]
+    content_types: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    pattern: String,
+    regex: Regex,
+    read: Vec<Access>,
+    write: Vec<Access>,
+    max_size: Option<i64>,
+    content_types: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct StorageRules {
+    rules: Vec<Rule>,
+}
+
+impl StorageRules {
+    /// Loads the rules file at `path`, or the owner-only default when it is empty.
+    pub fn load(path: &str) -> Result<Self> {
+        if path.is_empty() {
+            return Self::parse(
+                r#"{"rules": [{"match": "**", "read": ["owner"], "write": ["owner"]}]}"#,
+            );
+        }
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| Error::from(format!("Failed to read storage rules {}: {}", path, e)))?;
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let file: RulesFile = serde_json::from_str(contents)
+            .map_err(|e| Error::from(format!("Invalid storage rules: {}", e)))?;
+
+        let rules = file
+            .rules
+            .into_iter()
+            .map(|rule| {
+                Ok(Rule {
+                    regex: glob_regex(&rule.pattern)?,
+                    pattern: rule.pattern,
+                    read: rule.read,
+                    write: rule.write,
+                    max_size: rule.max_size,
+                    content_types: rule
+                        .content_types
+                        .iter()
+                        .map(|ct| ct.to_ascii_lowercase())
+                        .collect(),
+                })
+            })
+            .collect::<Result<Vec<Rule>>>()?;
+
+        Ok(StorageRules { rules })
+    }
+
+    /// Decides whether `principal` may perform `operation` on `target`. Uploads are also
+    /// held to the size and content type limits of the deciding rule.
+    pub fn evaluate(
+        &self,
+        principal: &Principal,
+        target: &Target,
+        operation: Operation,
+        upload: Option<&Upload>,
+    ) -> Decision {
+        let Some(rule) = self.rules.iter().find(|r| r.regex.is_match(target.path)) else {
+            return Decision {
+                allowed: false,
+                rule: None,
+                reason: format!("No rule matches '{}'", target.path),
+            };
+        };
+        let deny = |reason: String| Decision {
+            allowed: false,
+            rule: Some(rule.pattern.clone()),
+            reason,
+        };
+
+        let access = match operation {
+            Operation::Read => &rule.read,
+            Operation::Write => &rule.write,
+        };
+        let Some(granted) = access.iter().find(|a| grants(a, principal, target)) else {
+            return deny(format!(
+                "Rule '{}' does not allow {}",
+                rule.pattern,
+                match operation {
+                    Operation::Read => "reads",
+                    Operation::Write => "writes",
+                }
+            ));
+        };
+
+        if let Some(upload) = upload {
+            if let Some(max) = rule.max_size
+                && upload.size > max
+            {
+                return deny(format!(
+                    "Upload of {} bytes exceeds the {} byte limit for '{}'",
+                    upload.size, max, rule.pattern
+                ));
+            }
+            if !rule.content_types.is_empty()
+                && !rule
+                    .content_types
+                    .iter()
+                    .any(|allowed| content_type_matches(allowed, upload.content_type))
+            {
+                return deny(format!(
+                    "Content type '{}' is not allowed for '{}'",
+                    upload.content_type, rule.pattern
+                ));
+            }
+        }
+
+        Decision {
+            allowed: true,
+            rule: Some(rule.pattern.clone()),
+            reason: format!("Allowed as {}", granted),
+        }
+    }
+}
+
+fn grants(access: &Access, principal: &Principal, target: &Target) -> bool {
+    match access {
+        Access::Public => true,
+        Access::Owner => principal.user_id == Some(target.owner),
+        Access::OrgMember => {
+            principal.organization_id.is_some()
+                && principal.organization_id == target.owner_organization
+        }
+        Access::Role(role) => principal.role.as_deref() == Some(role.as_str()),
+    }
+}
+
+/// Matches `image/png; charset=...` against `image/png` or `image/*`.
+fn content_type_matches(allowed: &str, content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    match allowed.strip_suffix("/*") {
+        Some(kind) => essence.split('/').next() == Some(kind),
+        None => essence == allowed,
+    }
+}
+
+/// Compiles a path pattern, where `*` matches within a segment and `**` across them.
+fn glob_regex(pattern: &str) -> Result<Regex> {
+    let mut regex = String::from("^");
+    let mut rest = pattern.trim_start_matches('/');
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("**/") {
+            // Zero or more whole segments
+            regex.push_str("(?:.*/)?");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("**") {
+            regex.push_str(".*");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('*') {
+            regex.push_str("[^/]*");
+            rest = after;
+        } else {
+            let end = rest.find('*').unwrap_or(rest.len());
+            regex.push_str(&regex::escape(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    regex.push('$');
+
+    Regex::new(&regex).map_err(|e| Error::from(format!("Invalid pattern '{}': {}", pattern, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"{
+        "rules": [
+            {"match": "avatars/*", "read": ["public"], "write": ["owner"],
+             "max_size": 100, "content_types": ["image/*"]},
+            {"match": "shared/**", "read": ["owner", "org_member"], "write": ["owner"]},
+            {"match": "**", "read": ["owner", "role:admin"], "write": ["owner"]}
+        ]
+    }"#;
+
+    fn target(path: &str) -> Target<'_> {
+        Target {
+            owner: Uuid::nil(),
+            owner_organization: Some(Uuid::max()),
+            path,
+        }
+    }
+
+    #[test]
+    fn test_glob_regex() {
+        let regex = glob_regex("avatars/*").unwrap();
+        assert!(regex.is_match("avatars/me.png"));
+        assert!(!regex.is_match("avatars/old/me.png"));
+
+        let regex = glob_regex("**/*.pdf").unwrap();
+        assert!(regex.is_match("a.pdf"));
+        assert!(regex.is_match("docs/2025/a.pdf"));
+        assert!(!regex.is_match("docs/a.pdf.txt"));
+
+        assert!(glob_regex("**").unwrap().is_match(""));
+        assert!(glob_regex("a+b/**").unwrap().is_match("a+b/c"));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let rules = StorageRules::parse(RULES).unwrap();
+        let owner = Principal {
+            user_id: Some(Uuid::nil()),
+            ..Default::default()
+        };
+        let member = Principal {
+            user_id: Some(Uuid::max()),
+            organization_id: Some(Uuid::max()),
+            ..Default::default()
+        };
+        let admin = Principal {
+            user_id: Some(Uuid::max()),
+            role: Some("admin".to_string()),
+            ..Default::default()
+        };
+        let anonymous = Principal::default();
+        let read = |p: &Principal, path| {
+            rules
+                .evaluate(p, &target(path), Operation::Read, None)
+                .allowed
+        };
+
+        assert!(read(&anonymous, "avatars/me.png"));
+        assert!(read(&member, "shared/plan.txt"));
+        assert!(!read(&member, "private/plan.txt"));
+        assert!(read(&admin, "private/plan.txt"));
+        assert!(read(&owner, "private/plan.txt"));
+
+        let write = rules.evaluate(&member, &target("shared/plan.txt"), Operation::Write, None);
+        assert!(!write.allowed);
+        assert_eq!(write.rule.as_deref(), Some("shared/**"));
+    }
+
+    #[test]
+    fn test_upload_constraints() {
+        let rules = StorageRules::parse(RULES).unwrap();
+        let owner = Principal {
+            user_id: Some(Uuid::nil()),
+            ..Default::default()
+        };
+        let upload = |size, content_type| {
+            rules
+                .evaluate(
+                    &owner,
+                    &target("avatars/me.png"),
+                    Operation::Write,
+                    Some(&Upload { size, content_type }),
+                )
+                .allowed
+        };
+
+        assert!(upload(100, "image/png"));
+        assert!(!upload(101, "image/png"));
+        assert!(!upload(10, "text/plain"));
+        assert!(upload(10, "IMAGE/PNG; q=1"));
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_access() {
+        assert!(
+            StorageRules::parse(r#"{"rules": [{"match": "**", "read": ["everyone"]}]}"#).is_err()
+        );
+        assert!(StorageRules::parse(r#"{"rules": [{"match": "**", "read": ["role:"]}]}"#).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+use crate::config::CONFIG;
+use crate::crypt::jwt::Claims;
+use crate::crypt::tokens::generate_token;
+use crate::data::{Objects, TusUpload};
+use crate::error::Result;
+use crate::objects::handlers::{authorize, principal};
+use crate::objects::rules::{Operation, Upload};
+use crate::state::AppState;
+use crate::storage::object::PART_SIZE;
+use crate::utils::{normalize_path, parse_upload_metadata};
+use crate::Error;
+use axum::body::{Body, Bytes};
+use axum::extract::{Path, State};
+use axum::http::header::{self, HeaderName};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use chrono::{DateTime, Duration, Utc};
+use futures::StreamExt;
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_EXPIRES: HeaderName = HeaderName::from_static("upload-expires");
+
+/// How long a PATCH may hold an upload before another request can take it over.
+const LOCK_SECONDS: u64 = 600;
+
+pub async fn options() -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            ("tus-version", TUS_VERSION),
+            ("tus-extension", TUS_EXTENSIONS),
+        ],
+    )
+}
+
+pub async fn create_upload(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    check_version(&headers)?;
+    let user_id = Uuid::parse_str(&claims.sub)?;
+
+    let length = header_i64(&headers, &UPLOAD_LENGTH)?
+        .filter(|length| *length >= 0)
+        .ok_or_else(|| bad_request("Missing or invalid Upload-Length"))?;
+
+    let metadata = match headers.get("upload-metadata") {
+        Some(value) => parse_upload_metadata(
+            value
+                .to_str()
+                .map_err(|_| bad_request("Invalid Upload-Metadata"))?,
+        )?,
+        None => HashMap::new(),
+    };
+    let filename = metadata
+        .get("filename")
+        .cloned()
+        .ok_or_else(|| bad_request("Upload-Metadata must include a filename"))?;
+    let content_type = metadata
+        .get("filetype")
+        .filter(|ct| !ct.is_empty())
+        .cloned()
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    let folder = normalize_path(metadata.get("folder").map(String::as_str).unwrap_or(""))?;
+
+    let remaining = state.storage.usage(user_id).await?.remaining_bytes;
+    if let Some(remaining) = remaining
+        && length > remaining
+    {
+        return Err(Error::QuotaExceeded(format!(
+            "Upload of {} bytes exceeds the remaining {} bytes",
+            length, remaining
+        )));
+    }
+
+    let (object, version) = Objects::new_upload(user_id, &folder, filename, content_type, length)?;
+    let principal = principal(&state, Some(&claims)).await?;
+    let upload = Upload {
+        size: length,
+        content_type: &version.content_type,
+    };
+    authorize(
+        &state,
+        &principal,
+        user_id,
+        object.path(),
+        Operation::Write,
+        Some(&upload),
+    )
+    .await?;
+    let expires_at = Utc::now() + Duration::hours(CONFIG.tus_expire_hours as i64);
+    let mut upload = TusUpload {
+        id: generate_token(),
+        user_id: claims.sub.clone(),
+        key: object.key,
+        filename: object.filename,
+        content_type: version.content_type,
+        blob_key: version.blob_key,
+        multipart_id: String::new(),
+        parts: Vec::new(),
+        length,
+        offset: 0,
+        expires_at: expires_at.timestamp(),
+        key_id: None,
+        wrapped_key: None,
+        nonce_prefix: None,
+    };
+    state.storage.start_upload(&mut upload).await?;
+
+    // An empty file is complete as soon as it exists
+    if length == 0 {
+        state.storage.complete_upload(&upload, Bytes::new()).await?;
+    }
+
+    let mut response = expiry_headers(&upload);
+    response.insert(
+        header::LOCATION,
+        HeaderValue::from_str(&format!("{}/objects/tus/{}", CONFIG.app_url, upload.id))
+            .map_err(|_| Error::from("Invalid upload location"))?,
+    );
+
+    Ok((StatusCode::CREATED, response))
+}
+
+pub async fn get_offset(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    check_version(&headers)?;
+    let upload = find_upload(&state, &claims, &id).await?;
+
+    let mut response = expiry_headers(&upload);
+    response.insert(UPLOAD_OFFSET, upload.offset.into());
+    response.insert(UPLOAD_LENGTH, upload.length.into());
+    response.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+    Ok((StatusCode::OK, response))
+}
+
+pub async fn patch_upload(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse> {
+    check_version(&headers)?;
+
+    if headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        != Some(OFFSET_CONTENT_TYPE)
+    {
+        return Err(Error::HttpError(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Content-Type must be {}", OFFSET_CONTENT_TYPE),
+        ));
+    }
+    let offset = header_i64(&headers, &UPLOAD_OFFSET)?
+        .ok_or_else(|| bad_request("Missing or invalid Upload-Offset"))?;
+
+    let lock = format!("tus:{}", id);
+    if !state
+        .storage
+        .redis
+        .acquire_lock(&lock, LOCK_SECONDS)
+        .await?
+    {
+        return Err(Error::HttpError(
+            StatusCode::LOCKED,
+            "Upload is being written by another request".into(),
+        ));
+    }
+    let result = append_upload(&state, &claims, &id, offset, body).await;
+    state.storage.redis.release_lock(&lock).await?;
+
+    let upload = result?;
+    let mut response = expiry_headers(&upload);
+    response.insert(UPLOAD_OFFSET, upload.offset.into());
+
+    Ok((StatusCode::NO_CONTENT, response))
+}
+
+pub async fn terminate_upload(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    check_version(&headers)?;
+    let upload = find_upload(&state, &claims, &id).await?;
+
+    state.storage.terminate_upload(&upload).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Adds `Tus-Resumable` to every response, errors included, as the protocol requires.
+pub async fn tus_resumable(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    response
+}
+
+/// Streams the request body into the upload, sending parts as they fill up. Progress is
+/// saved even when the body ends early, so the client can resume from what arrived.
+async fn append_upload(
+    state: &AppState,
+    claims: &Claims,
+    id: &str,
+    offset: i64,
+    body: Body,
+) -> Result<TusUpload> {
+    let mut upload = find_upload(state, claims, id).await?;
+    if offset != upload.offset {
+        return Err(Error::HttpError(
+            StatusCode::CONFLICT,
+            format!("Upload-Offset {} does not match {}", offset, upload.offset),
+        ));
+    }
+
+    let mut pending = state.storage.redis.get_tus_buffer(id).await?;
+    let mut stream = body.into_data_stream();
+    let mut failed = None;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                failed = Some(e.into());
+                break;
+            }
+        };
+        if upload.offset + chunk.len() as i64 > upload.length {
+            failed = Some(Error::HttpError(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Body exceeds the declared Upload-Length".into(),
+            ));
+            break;
+        }
+
+        pending.extend_from_slice(&chunk);
+        upload.offset += chunk.len() as i64;
+
+        if pending.len() >= PART_SIZE {
+            while pending.len() >= PART_SIZE {
+                let part: Vec<u8> = pending.drain(..PART_SIZE).collect();
+                state.storage.upload_part(&mut upload, part.into()).await?;
+            }
+            state
+                .storage
+                .redis
+                .store_tus_upload(&upload, &pending)
+                .await?;
+        }
+    }
+
+    if upload.offset == upload.length && failed.is_none() {
+        state
+            .storage
+            .complete_upload(&upload, pending.into())
+            .await?;
+        return Ok(upload);
+    }
+
+    state
+        .storage
+        .redis
+        .store_tus_upload(&upload, &pending)
+        .await?;
+    match failed {
+        Some(e) => Err(e),
+        None => Ok(upload),
+    }
+}
+
+/// Loads an upload owned by the caller, treating other users' uploads as missing.
+async fn find_upload(state: &AppState, claims: &Claims, id: &str) -> Result<TusUpload> {
+    let upload = state
+        .storage
+        .redis
+        .get_tus_upload(id)
+        .await?
+        .filter(|upload| upload.user_id == claims.sub)
+        .ok_or_else(|| Error::HttpError(StatusCode::NOT_FOUND, "Upload not found".into()))?;
+
+    if upload.expires_at <= Utc::now().timestamp() {
+        return Err(Error::HttpError(
+            StatusCode::GONE,
+            "Upload has expired".into(),
+        ));
+    }
+
+    Ok(upload)
+}
+
+fn check_version(headers: &HeaderMap) -> Result<()> {
+    match headers.get(&TUS_RESUMABLE).and_then(|v| v.to_str().ok()) {
+        Some(TUS_VERSION) => Ok(()),
+        _ => Err(Error::HttpError(
+            StatusCode::PRECONDITION_FAILED,
+            format!("Tus-Resumable must be {}", TUS_VERSION),
+        )),
+    }
+}
+
+fn header_i64(headers: &HeaderMap, name: &HeaderName) -> Result<Option<i64>> {
+    headers
+        .get(name)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|v| v.parse::<i64>().ok())
+                .ok_or_else(|| bad_request(&format!("Invalid {} header", name)))
+        })
+        .transpose()
+}
+
+fn expiry_headers(upload: &TusUpload) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(expires) = DateTime::from_timestamp(upload.expires_at, 0)
+        && let Ok(value) = expires
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string()
+            .parse()
+    {
+        headers.insert(UPLOAD_EXPIRES, value);
+    }
+    headers
+}
+
+fn bad_request(message: &str) -> Error {
+    Error::HttpError(StatusCode::BAD_REQUEST, message.to_string())
+}
@@ -0,0 +1,28 @@
+use super::handlers::{
+    create_upload, get_offset, options, patch_upload, terminate_upload, tus_resumable,
+};
+use crate::middleware::auth_middleware;
+use crate::state::AppState;
+use axum::routing::{head, options as options_route, post};
+use axum::{middleware, Router};
+use std::sync::Arc;
+
+pub fn router() -> Router<Arc<AppState>> {
+    // Discovery stays public so clients and CORS preflights can probe it
+    let public_routes = Router::new().route("/", options_route(options));
+
+    let protected_routes = Router::new()
+        .route("/", post(create_upload))
+        .route(
+            "/{id}",
+            head(get_offset)
+                .patch(patch_upload)
+                .delete(terminate_upload),
+        )
+        .route_layer(middleware::from_fn(auth_middleware));
+
+    Router::new()
+        .merge(public_routes)
+        .merge(protected_routes)
+        .layer(middleware::map_response(tus_resumable))
+}
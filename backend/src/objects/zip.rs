@@ -0,0 +1,284 @@
+//! ZIP archives streamed as they are built. Entries are stored uncompressed, with their
+//! CRC and size in a data descriptor after the bytes, so nothing is held beyond the chunk
+//! in flight and the central directory. Only the classic format is written, which caps
+//! an archive at 65,535 entries and 4 GiB.
+
+use crate::error::{Error, Result};
+use axum::body::Bytes;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+pub const MAX_ENTRIES: usize = u16::MAX as usize;
+pub const MAX_ARCHIVE_BYTES: u64 = u32::MAX as u64;
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const LOCAL_HEADER_SIZE: u64 = 30;
+const DATA_DESCRIPTOR_SIZE: u64 = 16;
+const CENTRAL_HEADER_SIZE: u64 = 46;
+const END_OF_CENTRAL_DIRECTORY_SIZE: u64 = 22;
+const VERSION: u16 = 20;
+/// Version made by, on Unix, so the external attributes carry file permissions.
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION;
+/// Sizes follow the data, and names are UTF-8.
+const FLAGS: u16 = 0x0008 | 0x0800;
+/// A regular file readable by everyone.
+const FILE_ATTRIBUTES: u32 = 0o100644 << 16;
+
+/// The bytes of an entry, opened once the archive reaches it.
+pub type EntrySource = BoxFuture<'static, Result<BoxStream<'static, Result<Bytes>>>>;
+
+pub struct ZipEntry {
+    pub name: String,
+    pub modified: DateTime<Utc>,
+    pub source: EntrySource,
+}
+
+/// Size of an archive of entries with the given name lengths and sizes.
+pub fn archive_size(entries: impl IntoIterator<Item = (usize, u64)>) -> u64 {
+    let entries: u64 = entries
+        .into_iter()
+        .map(|(name, size)| {
+            LOCAL_HEADER_SIZE + DATA_DESCRIPTOR_SIZE + CENTRAL_HEADER_SIZE + 2 * name as u64 + size
+        })
+        .sum();
+    entries + END_OF_CENTRAL_DIRECTORY_SIZE
+}
+
+/// Streams `entries` as a ZIP archive. A failure part way through ends the stream with
+/// the error, leaving the client a truncated archive.
+pub fn stream(entries: Vec<ZipEntry>) -> impl Stream<Item = Result<Bytes>> + Send + 'static {
+    let (tx, rx) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        if let Err(e) = write(entries, &tx).await {
+            tracing::warn!("Failed to stream archive: {}", e);
+            let _ = tx.send(Err(e)).await;
+        }
+    });
+
+    futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    })
+}
+
+async fn write(entries: Vec<ZipEntry>, tx: &mpsc::Sender<Result<Bytes>>) -> Result<()> {
+    let mut writer = ZipWriter::default();
+
+    for entry in entries {
+        let (header, pending) = writer.begin(entry.name, entry.modified)?;
+        send(tx, header).await?;
+
+        let mut data = entry.source.await?;
+        let mut crc = crc32fast::Hasher::new();
+        let mut size = 0;
+        while let Some(chunk) = data.next().await {
+            let chunk = chunk?;
+            crc.update(&chunk);
+            size += chunk.len() as u64;
+            send(tx, chunk).await?;
+        }
+
+        send(tx, writer.end(pending, crc.finalize(), size)?).await?;
+    }
+
+    send(tx, writer.finish()?).await
+}
+
+async fn send(tx: &mpsc::Sender<Result<Bytes>>, bytes: impl Into<Bytes>) -> Result<()> {
+    tx.send(Ok(bytes.into()))
+        .await
+        .map_err(|_| Error::from("Archive download was cancelled"))
+}
+
+/// An entry whose data is being written.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pending {
+    name: String,
+    time: u16,
+    date: u16,
+    offset: u32,
+}
+
+/// Lays out the records around each entry's data, tracking offsets for the central
+/// directory. Its fields are public so an archive built across runs can save and
+/// restore them between entries' bytes.
+#[derive(Debug, Default)]
+pub struct ZipWriter {
+    /// Bytes of the archive laid out so far.
+    pub offset: u64,
+    pub central: Vec<u8>,
+    pub entries: u16,
+}
+
+impl ZipWriter {
+    /// Starts an entry, returning its local header.
+    pub fn begin(&mut self, name: String, modified: DateTime<Utc>) -> Result<(Vec<u8>, Pending)> {
+        let (time, date) = dos_datetime(modified);
+        let pending = Pending {
+            name,
+            time,
+            date,
+            offset: self.offset()?,
+        };
+
+        let mut header = Vec::with_capacity(LOCAL_HEADER_SIZE as usize + pending.name.len());
+        header.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&FLAGS.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // stored
+        header.extend_from_slice(&time.to_le_bytes());
+        header.extend_from_slice(&date.to_le_bytes());
+        header.extend_from_slice(&[0; 12]); // CRC and sizes are in the data descriptor
+        header.extend_from_slice(&(pending.name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(pending.name.as_bytes());
+
+        self.offset += header.len() as u64;
+        Ok((header, pending))
+    }
+
+    /// Ends an entry after `size` bytes of data, returning its data descriptor.
+    pub fn end(&mut self, entry: Pending, crc: u32, size: u64) -> Result<Vec<u8>> {
+        self.offset += size;
+        let size = u32::try_from(size).map_err(|_| too_large())?;
+        self.entries = self.entries.checked_add(1).ok_or_else(too_large)?;
+
+        let mut descriptor = Vec::with_capacity(DATA_DESCRIPTOR_SIZE as usize);
+        descriptor.extend_from_slice(&DATA_DESCRIPTOR.to_le_bytes());
+        descriptor.extend_from_slice(&crc.to_le_bytes());
+        descriptor.extend_from_slice(&size.to_le_bytes());
+        descriptor.extend_from_slice(&size.to_le_bytes());
+        self.offset += descriptor.len() as u64;
+
+        let central = &mut self.central;
+        central.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+        central.extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
+        central.extend_from_slice(&VERSION.to_le_bytes());
+        central.extend_from_slice(&FLAGS.to_le_bytes());
+        central.extend_from_slice(&0u16.to_le_bytes());
+        central.extend_from_slice(&entry.time.to_le_bytes());
+        central.extend_from_slice(&entry.date.to_le_bytes());
+        central.extend_from_slice(&crc.to_le_bytes());
+        central.extend_from_slice(&size.to_le_bytes());
+        central.extend_from_slice(&size.to_le_bytes());
+        central.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+        central.extend_from_slice(&[0; 8]); // extra and comment lengths, disk, internal attributes
+        central.extend_from_slice(&FILE_ATTRIBUTES.to_le_bytes());
+        central.extend_from_slice(&entry.offset.to_le_bytes());
+        central.extend_from_slice(entry.name.as_bytes());
+
+        Ok(descriptor)
+    }
+
+    /// Returns the central directory and the end record that close the archive.
+    pub fn finish(self) -> Result<Vec<u8>> {
+        let offset = self.offset()?;
+        let central_size = u32::try_from(self.central.len()).map_err(|_| too_large())?;
+
+        let mut end = self.central;
+        end.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        end.extend_from_slice(&[0; 4]); // this disk, central directory disk
+        end.extend_from_slice(&self.entries.to_le_bytes());
+        end.extend_from_slice(&self.entries.to_le_bytes());
+        end.extend_from_slice(&central_size.to_le_bytes());
+        end.extend_from_slice(&offset.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        Ok(end)
+    }
+
+    fn offset(&self) -> Result<u32> {
+        u32::try_from(self.offset).map_err(|_| too_large())
+    }
+}
+
+fn too_large() -> Error {
+    Error::from("Archive exceeds the ZIP size limits")
+}
+
+/// MS-DOS time and date, which start in 1980 and count seconds in twos.
+fn dos_datetime(at: DateTime<Utc>) -> (u16, u16) {
+    if at.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = (at.hour() << 11) | (at.minute() << 5) | (at.second() / 2);
+    let date = (((at.year() - 1980) as u32) << 9) | (at.month() << 5) | at.day();
+    (time as u16, date as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    fn entry(name: &str, chunks: &[&'static [u8]]) -> ZipEntry {
+        let chunks: Vec<Result<Bytes>> = chunks.iter().map(|c| Ok(Bytes::from(*c))).collect();
+        ZipEntry {
+            name: name.to_string(),
+            modified: Utc::now(),
+            source: Box::pin(async move { Ok(futures::stream::iter(chunks).boxed()) }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_archive() {
+        let entries = vec![
+            entry("docs/a.txt", &[b"hello ", b"world"]),
+            entry("b.bin", &[]),
+        ];
+        let archive: Vec<u8> = stream(entries)
+            .map(|chunk| chunk.unwrap().to_vec())
+            .concat()
+            .await;
+
+        assert_eq!(
+            archive.len() as u64,
+            archive_size([("docs/a.txt".len(), 11), ("b.bin".len(), 0)])
+        );
+        assert_eq!(u32_at(&archive, 0), LOCAL_HEADER);
+
+        let end = archive.len() - END_OF_CENTRAL_DIRECTORY_SIZE as usize;
+        assert_eq!(u32_at(&archive, end), END_OF_CENTRAL_DIRECTORY);
+        assert_eq!(u16_at(&archive, end + 10), 2);
+
+        let central = u32_at(&archive, end + 16) as usize;
+        assert_eq!(u32_at(&archive, central), CENTRAL_HEADER);
+        assert_eq!(
+            u32_at(&archive, central + 16),
+            crc32fast::hash(b"hello world")
+        );
+        assert_eq!(u32_at(&archive, central + 24), 11);
+        assert_eq!(&archive[central + 46..central + 56], b"docs/a.txt");
+
+        // The entry's data sits right after its local header
+        let local = u32_at(&archive, central + 42) as usize;
+        let data = local + LOCAL_HEADER_SIZE as usize + "docs/a.txt".len();
+        assert_eq!(&archive[data..data + 11], b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_stream_ends_with_source_error() {
+        let failing = ZipEntry {
+            name: "broken".to_string(),
+            modified: Utc::now(),
+            source: Box::pin(async { Err(Error::from("gone")) }),
+        };
+        let chunks: Vec<Result<Bytes>> = stream(vec![failing]).collect().await;
+
+        assert!(chunks.first().is_some_and(|c| c.is_ok()));
+        assert!(chunks.last().is_some_and(|c| c.is_err()));
+    }
+}
@@ -0,0 +1,219 @@
+//! Row changes of the data API's tables, announced by the `realtime_notify` trigger and
+//! relayed to websocket subscribers. Notifications carry only the primary key, so each
+//! subscriber re-reads an inserted or updated row under their own claims and sees it only
+//! when row-level security lets them. Deleted rows are gone by then, so delete events
+//! carry just the key, and only reach a socket that was sent the row before.
+
+use crate::crypt::jwt::Claims;
+use crate::data::Event;
+use crate::error::{Error, Result};
+use crate::rest::handlers::execute;
+use crate::rest::query::{Filter, Operator, QueryBuilder, ReadQuery, SelectItem};
+use crate::state::AppState;
+use axum::http::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Channel `realtime_notify` sends changes on.
+pub const CHANNEL: &str = "table_changes";
+/// Changes buffered for each socket before it starts missing them.
+const BUFFER: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Insert => "insert",
+            ChangeKind::Update => "update",
+            ChangeKind::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Change {
+    pub schema: String,
+    pub table: String,
+    #[serde(rename = "type")]
+    pub kind: ChangeKind,
+    /// Primary key of the changed row.
+    pub keys: Map<String, Value>,
+}
+
+#[derive(Debug)]
+pub struct Realtime {
+    sender: broadcast::Sender<Change>,
+}
+
+impl Default for Realtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Realtime {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(BUFFER);
+        Realtime { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Change> {
+        self.sender.subscribe()
+    }
+
+    /// Relays changes to the tables of `schema` until the process exits. The listener
+    /// reconnects on its own, though changes made while it is down are lost.
+    pub async fn listen(&self, pool: &PgPool, schema: &str) {
+        loop {
+            if let Err(e) = self.relay(pool, schema).await {
+                tracing::error!("Realtime listener failed: {}", e);
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    async fn relay(&self, pool: &PgPool, schema: &str) -> Result<()> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(CHANNEL).await?;
+
+        loop {
+            let notification = listener.recv().await?;
+            let change: Change = match serde_json::from_str(notification.payload()) {
+                Ok(change) => change,
+                Err(e) => {
+                    tracing::warn!("Ignoring malformed change notification: {}", e);
+                    continue;
+                }
+            };
+            // Sending only fails when no socket is listening
+            if change.schema == schema {
+                let _ = self.sender.send(change);
+            }
+        }
+    }
+}
+
+/// A client's interest in the changes to a table, narrowed by data API filters such as
+/// `owner_id=eq.42&done=is.false`.
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub table: String,
+    pub filter: String,
+    filters: Vec<Filter>,
+    /// Keys of the rows sent so far, whose deletes the subscriber may learn of.
+    delivered: HashSet<String>,
+}
+
+impl Subscription {
+    /// Parses and checks a subscription against the data API's schema.
+    pub async fn new(state: &AppState, table: &str, filter: &str) -> Result<Self> {
+        let params: Vec<(String, String)> = url::form_urlencoded::parse(filter.as_bytes())
+            .into_owned()
+            .collect();
+        let query = ReadQuery::parse(&params)?;
+        if query.filters.len() != params.len() {
+            return Err(Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                "Subscriptions only take filters".into(),
+            ));
+        }
+
+        let schema = state.data_api.get(&state.storage.postgres.pool).await?;
+        QueryBuilder::new(&schema, table)?.select(&query)?;
+        // Changes are told apart by primary key, which a table without one can't give
+        if schema.table(table)?.primary_key.is_empty() {
+            return Err(Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Changes to '{}' can't be followed without a primary key",
+                    table
+                ),
+            ));
+        }
+
+        Ok(Subscription {
+            table: table.to_string(),
+            filter: filter.to_string(),
+            filters: query.filters,
+            delivered: HashSet::new(),
+        })
+    }
+
+    /// The event announcing `change` to the holder of `claims`, unless it falls outside
+    /// the subscription or they aren't allowed to see the row.
+    pub async fn event(
+        &mut self,
+        state: &AppState,
+        claims: &Claims,
+        change: &Change,
+    ) -> Result<Option<Event>> {
+        if change.table != self.table {
+            return Ok(None);
+        }
+
+        let data = match change.kind {
+            ChangeKind::Delete => {
+                // The row can't be checked anymore, so only one already sent is announced
+                if !self.delivered.remove(&key_id(&change.keys)) {
+                    return Ok(None);
+                }
+                json!({ "table": change.table, "old_record": change.keys })
+            }
+            ChangeKind::Insert | ChangeKind::Update => {
+                let mut filters = self.filters.clone();
+                filters.extend(change.keys.iter().map(|(column, value)| Filter {
+                    column: column.clone(),
+                    operator: Operator::Eq,
+                    value: key_text(value),
+                }));
+                let query = ReadQuery {
+                    select: vec![SelectItem::Star],
+                    filters,
+                    order: Vec::new(),
+                    limit: 1,
+                    offset: 0,
+                };
+
+                let schema = state.data_api.get(&state.storage.postgres.pool).await?;
+                let sql = QueryBuilder::new(&schema, &self.table)?.select(&query)?;
+                match execute(state, claims, sql).await? {
+                    Value::Array(mut rows) if !rows.is_empty() => {
+                        self.delivered.insert(key_id(&change.keys));
+                        json!({ "table": change.table, "record": rows.swap_remove(0) })
+                    }
+                    _ => return Ok(None),
+                }
+            }
+        };
+
+        Ok(Some(Event {
+            name: change.kind.as_str().to_string(),
+            data,
+        }))
+    }
+}
+
+/// Identifies a row by its primary key.
+fn key_id(keys: &Map<String, Value>) -> String {
+    Value::Object(keys.clone()).to_string()
+}
+
+/// A key value as a filter compares it, without the quotes of a JSON string.
+fn key_text(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
@@ -0,0 +1,146 @@
+use crate::crypt::jwt::Claims;
+use crate::error::Result;
+use crate::response::ApiResponse;
+use crate::rest::query::{Param, QueryBuilder, ReadQuery, Sql};
+use crate::rest::rpc;
+use crate::state::AppState;
+use crate::Error;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use serde_json::{Map, Value};
+use std::ops::DerefMut;
+use std::sync::Arc;
+
+pub async fn read_rows(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(table): Path<String>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<impl IntoResponse> {
+    let query = ReadQuery::parse(&params)?;
+    let schema = state.data_api.get(&state.storage.postgres.pool).await?;
+    let sql = QueryBuilder::new(&schema, &table)?.select(&query)?;
+    let rows = execute(&state, &claims, sql).await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully retrieved rows",
+        rows,
+    ))
+}
+
+/// Inserts one row from an object body, or several from an array.
+pub async fn insert_rows(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(table): Path<String>,
+    Query(params): Query<Vec<(String, String)>>,
+    Json(body): Json<Value>,
+) -> Result<impl IntoResponse> {
+    let query = ReadQuery::parse(&params)?;
+    let schema = state.data_api.get(&state.storage.postgres.pool).await?;
+    let sql = QueryBuilder::new(&schema, &table)?.insert(body, &query.select)?;
+    let rows = execute(&state, &claims, sql).await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::CREATED,
+        "Successfully inserted rows",
+        rows,
+    ))
+}
+
+pub async fn update_rows(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(table): Path<String>,
+    Query(params): Query<Vec<(String, String)>>,
+    Json(body): Json<Value>,
+) -> Result<impl IntoResponse> {
+    let query = ReadQuery::parse(&params)?;
+    let schema = state.data_api.get(&state.storage.postgres.pool).await?;
+    let sql = QueryBuilder::new(&schema, &table)?.update(body, &query)?;
+    let rows = execute(&state, &claims, sql).await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully updated rows",
+        rows,
+    ))
+}
+
+pub async fn delete_rows(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(table): Path<String>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<impl IntoResponse> {
+    let query = ReadQuery::parse(&params)?;
+    let schema = state.data_api.get(&state.storage.postgres.pool).await?;
+    let sql = QueryBuilder::new(&schema, &table)?.delete(&query)?;
+    let rows = execute(&state, &claims, sql).await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully deleted rows",
+        rows,
+    ))
+}
+
+/// Calls a function of the data API's schema with the body's fields as its arguments.
+pub async fn call_function(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(function): Path<String>,
+    Json(arguments): Json<Map<String, Value>>,
+) -> Result<impl IntoResponse> {
+    let schema = state.data_api.get(&state.storage.postgres.pool).await?;
+    let sql = rpc::call(&schema, &function, arguments)?;
+    let result = execute(&state, &claims, sql).await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully called function",
+        result,
+    ))
+}
+
+/// Runs a statement as the caller, leaving row-level security to decide which rows it
+/// touches.
+pub(crate) async fn execute(state: &AppState, claims: &Claims, sql: Sql) -> Result<Value> {
+    state
+        .storage
+        .postgres
+        .with_claims_transaction(claims, |tx| {
+            Box::pin(async move {
+                let mut query = sqlx::query_scalar::<_, Value>(&sql.text);
+                for param in sql.params {
+                    query = match param {
+                        Param::Int(value) => query.bind(value),
+                        Param::Text(value) => query.bind(value),
+                        Param::TextArray(values) => query.bind(values),
+                        Param::Json(value) => query.bind(value),
+                    };
+                }
+                Ok(query.fetch_one(tx.deref_mut()).await?)
+            })
+        })
+        .await
+        .map_err(client_error)
+}
+
+/// Surfaces errors the request caused, like a bad value or a constraint it broke, as
+/// client errors rather than 500s.
+fn client_error(error: Error) -> Error {
+    let Error::SqlError(sqlx::Error::Database(ref e)) = error else {
+        return error;
+    };
+    let status = match e.code().as_deref() {
+        Some("42501") => StatusCode::FORBIDDEN,
+        Some(code) if code.starts_with("23") => StatusCode::CONFLICT,
+        Some(code) if code.starts_with("22") => StatusCode::BAD_REQUEST,
+        _ => return error,
+    };
+    Error::HttpError(status, e.message().to_string())
+}
@@ -0,0 +1,5 @@
+pub mod handlers;
+pub mod query;
+pub mod routes;
+pub mod rpc;
+pub mod schema;
@@ -0,0 +1,683 @@
+//! Builds SQL for data API requests. Query parameters follow PostgREST: `select=id,name,
+//! author(name)` picks columns and embeds tables joined by a foreign key, `col=op.value`
+//! filters, `order=col.desc.nullslast` sorts, and `limit`/`offset` paginate. Identifiers
+//! are checked against the introspected schema and values are always bound.
+
+use crate::error::{Error, Result};
+use crate::rest::schema::{Relation, Schema, Table};
+use axum::http::StatusCode;
+use serde_json::Value;
+
+pub const DEFAULT_LIMIT: i64 = 100;
+pub const MAX_LIMIT: i64 = 1000;
+/// Embedded tables nest at most this deep.
+const MAX_EMBED_DEPTH: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectItem {
+    Star,
+    Column(String),
+    Embed {
+        table: String,
+        select: Vec<SelectItem>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Eq,
+    Neq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    Like,
+    Ilike,
+    In,
+    Is,
+}
+
+impl Operator {
+    fn parse(op: &str) -> Option<Self> {
+        Some(match op {
+            "eq" => Operator::Eq,
+            "neq" => Operator::Neq,
+            "lt" => Operator::Lt,
+            "lte" => Operator::Lte,
+            "gt" => Operator::Gt,
+            "gte" => Operator::Gte,
+            "like" => Operator::Like,
+            "ilike" => Operator::Ilike,
+            "in" => Operator::In,
+            "is" => Operator::Is,
+            _ => return None,
+        })
+    }
+
+    fn sql(&self) -> &'static str {
+        match self {
+            Operator::Eq => "=",
+            Operator::Neq => "<>",
+            Operator::Lt => "<",
+            Operator::Lte => "<=",
+            Operator::Gt => ">",
+            Operator::Gte => ">=",
+            Operator::Like => "LIKE",
+            Operator::Ilike => "ILIKE",
+            Operator::In => "= ANY",
+            Operator::Is => "IS",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub column: String,
+    pub operator: Operator,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Order {
+    pub column: String,
+    pub descending: bool,
+    pub nulls_first: Option<bool>,
+}
+
+/// A parsed request: what to return, which rows, in what order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadQuery {
+    pub select: Vec<SelectItem>,
+    pub filters: Vec<Filter>,
+    pub order: Vec<Order>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl ReadQuery {
+    pub fn parse(params: &[(String, String)]) -> Result<Self> {
+        let mut query = ReadQuery {
+            select: vec![SelectItem::Star],
+            filters: Vec::new(),
+            order: Vec::new(),
+            limit: DEFAULT_LIMIT,
+            offset: 0,
+        };
+
+        for (key, value) in params {
+            match key.as_str() {
+                "select" => query.select = parse_select(value)?,
+                "order" => query.order = parse_order(value)?,
+                "limit" => query.limit = parse_number(key, value)?.clamp(1, MAX_LIMIT),
+                "offset" => query.offset = parse_number(key, value)?.max(0),
+                _ => query.filters.push(parse_filter(key, value)?),
+            }
+        }
+
+        Ok(query)
+    }
+}
+
+fn bad_request(message: String) -> Error {
+    Error::HttpError(StatusCode::BAD_REQUEST, message)
+}
+
+fn parse_number(key: &str, value: &str) -> Result<i64> {
+    value
+        .parse()
+        .map_err(|_| bad_request(format!("Invalid {} '{}'", key, value)))
+}
+
+fn parse_filter(column: &str, value: &str) -> Result<Filter> {
+    let (operator, value) = value
+        .split_once('.')
+        .and_then(|(op, value)| Some((Operator::parse(op)?, value)))
+        .ok_or_else(|| bad_request(format!("Invalid filter '{}={}'", column, value)))?;
+
+    Ok(Filter {
+        column: column.to_string(),
+        operator,
+        value: value.to_string(),
+    })
+}
+
+fn parse_order(value: &str) -> Result<Vec<Order>> {
+    value
+        .split(',')
+        .map(|term| {
+            let mut parts = term.split('.');
+            let column = parts.next().unwrap_or_default().to_string();
+            let mut order = Order {
+                column,
+                descending: false,
+                nulls_first: None,
+            };
+            for modifier in parts {
+                match modifier {
+                    "asc" => order.descending = false,
+                    "desc" => order.descending = true,
+                    "nullsfirst" => order.nulls_first = Some(true),
+                    "nullslast" => order.nulls_first = Some(false),
+                    _ => return Err(bad_request(format!("Invalid order '{}'", term))),
+                }
+            }
+            Ok(order)
+        })
+        .collect()
+}
+
+/// Parses `id,name,author(id,name)`, splitting only on top-level commas.
+pub fn parse_select(value: &str) -> Result<Vec<SelectItem>> {
+    let invalid = || bad_request(format!("Invalid select '{}'", value));
+    let mut items = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+
+    for (i, c) in value.char_indices().chain([(value.len(), ',')]) {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.checked_sub(1).ok_or_else(invalid)?,
+            ',' if depth == 0 => {
+                let item = value[start..i].trim();
+                start = i + 1;
+                if item.is_empty() {
+                    return Err(invalid());
+                }
+                items.push(match item.split_once('(') {
+                    Some((table, inner)) => SelectItem::Embed {
+                        table: table.trim().to_string(),
+                        select: parse_select(inner.strip_suffix(')').ok_or_else(invalid)?)?,
+                    },
+                    None if item == "*" => SelectItem::Star,
+                    None => SelectItem::Column(item.to_string()),
+                });
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(invalid());
+    }
+
+    Ok(items)
+}
+
+/// A value bound to a placeholder.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    Int(i64),
+    Text(String),
+    TextArray(Vec<String>),
+    Json(Value),
+}
+
+/// SQL text with its bound values, numbered in order.
+#[derive(Debug, Default)]
+pub struct Sql {
+    pub text: String,
+    pub params: Vec<Param>,
+}
+
+impl Sql {
+    fn bind(&mut self, param: Param) -> String {
+        self.params.push(param);
+        format!("${}", self.params.len())
+    }
+}
+
+pub fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Builds statements for one table of the schema.
+pub struct QueryBuilder<'a> {
+    schema: &'a Schema,
+    table: &'a Table,
+    sql: Sql,
+    aliases: usize,
+}
+
+impl<'a> QueryBuilder<'a> {
+    pub fn new(schema: &'a Schema, table: &str) -> Result<Self> {
+        Ok(QueryBuilder {
+            schema,
+            table: schema.table(table)?,
+            sql: Sql::default(),
+            aliases: 0,
+        })
+    }
+
+    fn relation_name(&self, table: &str) -> String {
+        format!("{}.{}", quote(&self.schema.name), quote(table))
+    }
+
+    fn alias(&mut self) -> String {
+        self.aliases += 1;
+        format!("t{}", self.aliases)
+    }
+
+    /// `SELECT` returning the matching rows as a JSON array.
+    pub fn select(mut self, query: &ReadQuery) -> Result<Sql> {
+        let table = self.table;
+        let alias = self.alias();
+        let columns = self.select_list(table, &alias, &query.select, 0)?;
+        let filters = self.filters(table, &alias, &query.filters)?;
+        let order = self.order(table, &alias, &query.order)?;
+        let limit = self.sql.bind(Param::Int(query.limit));
+        let offset = self.sql.bind(Param::Int(query.offset));
+
+        self.sql.text = format!(
+            "SELECT COALESCE(json_agg(r), '[]'::json) FROM (SELECT {} FROM {} AS {}{}{} LIMIT {} OFFSET {}) AS r",
+            columns,
+            self.relation_name(&self.table.name),
+            alias,
+            filters,
+            order,
+            limit,
+            offset
+        );
+        Ok(self.sql)
+    }
+
+    /// `INSERT` of one object or an array of them, returning the new rows.
+    pub fn insert(mut self, rows: Value, select: &[SelectItem]) -> Result<Sql> {
+        let rows = match rows {
+            Value::Array(rows) => rows,
+            row @ Value::Object(_) => vec![row],
+            _ => return Err(bad_request("Expected an object or an array".into())),
+        };
+        let mut columns: Vec<&str> = Vec::new();
+        for row in &rows {
+            let row = row
+                .as_object()
+                .ok_or_else(|| bad_request("Expected an array of objects".into()))?;
+            for key in row.keys() {
+                if !columns.contains(&key.as_str()) {
+                    self.table.column(key)?;
+                    columns.push(key);
+                }
+            }
+        }
+        if columns.is_empty() {
+            return Err(bad_request("Nothing to insert".into()));
+        }
+
+        let columns = columns
+            .iter()
+            .map(|c| quote(c))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let relation = self.relation_name(&self.table.name);
+        let rows = self.sql.bind(Param::Json(Value::Array(rows)));
+        let statement = format!(
+            "INSERT INTO {relation} ({columns}) SELECT {columns} FROM jsonb_populate_recordset(NULL::{relation}, {rows}) RETURNING *"
+        );
+        self.returning(&statement, select)
+    }
+
+    /// `UPDATE` of the filtered rows with the fields of `changes`.
+    pub fn update(mut self, changes: Value, query: &ReadQuery) -> Result<Sql> {
+        let changes = match changes {
+            Value::Object(changes) if !changes.is_empty() => changes,
+            _ => return Err(bad_request("Expected an object of changes".into())),
+        };
+        let columns = changes
+            .keys()
+            .map(|key| Ok(quote(&self.table.column(key)?.name)))
+            .collect::<Result<Vec<String>>>()?
+            .join(", ");
+
+        let alias = self.alias();
+        let relation = self.relation_name(&self.table.name);
+        let filters = self.required_filters(&alias, &query.filters)?;
+        let changes = self.sql.bind(Param::Json(Value::Object(changes)));
+        let statement = format!(
+            "UPDATE {relation} AS {alias} SET ({columns}) = (SELECT {columns} FROM jsonb_populate_record(NULL::{relation}, {changes})){filters} RETURNING {alias}.*"
+        );
+        self.returning(&statement, &query.select)
+    }
+
+    /// `DELETE` of the filtered rows, returning them.
+    pub fn delete(mut self, query: &ReadQuery) -> Result<Sql> {
+        let alias = self.alias();
+        let relation = self.relation_name(&self.table.name);
+        let filters = self.required_filters(&alias, &query.filters)?;
+        let statement = format!("DELETE FROM {relation} AS {alias}{filters} RETURNING {alias}.*");
+        self.returning(&statement, &query.select)
+    }
+
+    /// Refuses to change every row of a table by leaving out the filters.
+    fn required_filters(&mut self, alias: &str, filters: &[Filter]) -> Result<String> {
+        if filters.is_empty() {
+            return Err(bad_request("Updates and deletes need a filter".into()));
+        }
+        self.filters(self.table, alias, filters)
+    }
+
+    /// Wraps a data-modifying statement to return its rows through the select list.
+    fn returning(mut self, statement: &str, select: &[SelectItem]) -> Result<Sql> {
+        let alias = self.alias();
+        let columns = self.select_list(self.table, &alias, select, 0)?;
+        self.sql.text = format!(
+            "WITH {alias} AS ({statement}) SELECT COALESCE(json_agg(r), '[]'::json) FROM (SELECT {columns} FROM {alias}) AS r"
+        );
+        Ok(self.sql)
+    }
+
+    fn select_list(
+        &mut self,
+        table: &Table,
+        alias: &str,
+        items: &[SelectItem],
+        depth: usize,
+    ) -> Result<String> {
+        let mut columns = Vec::new();
+        for item in items {
+            match item {
+                SelectItem::Star => columns.extend(
+                    table
+                        .columns
+                        .iter()
+                        .map(|c| format!("{}.{}", alias, quote(&c.name))),
+                ),
+                SelectItem::Column(name) => {
+                    let column = table.column(name)?;
+                    columns.push(format!("{}.{}", alias, quote(&column.name)));
+                }
+                SelectItem::Embed {
+                    table: embedded,
+                    select,
+                } => columns.push(self.embed(table, alias, embedded, select, depth + 1)?),
+            }
+        }
+        Ok(columns.join(", "))
+    }
+
+    /// A subquery returning the rows of `embedded` joined to the outer row, as an object
+    /// when the outer row references it and as an array when it references the outer row.
+    fn embed(
+        &mut self,
+        outer: &Table,
+        outer_alias: &str,
+        embedded: &str,
+        select: &[SelectItem],
+        depth: usize,
+    ) -> Result<String> {
+        if depth > MAX_EMBED_DEPTH {
+            return Err(bad_request(format!(
+                "Embedding is limited to {} levels",
+                MAX_EMBED_DEPTH
+            )));
+        }
+        let schema = self.schema;
+        let table = schema.table(embedded)?;
+        let relation = schema.relation(&outer.name, embedded)?;
+        let alias = self.alias();
+
+        let (outer_columns, columns, aggregate) = match relation {
+            Relation::ToOne(fk) => (&fk.columns, &fk.foreign_columns, "row_to_json(e)"),
+            Relation::ToMany(fk) => (
+                &fk.foreign_columns,
+                &fk.columns,
+                "COALESCE(json_agg(e), '[]'::json)",
+            ),
+        };
+        let join = outer_columns
+            .iter()
+            .zip(columns)
+            .map(|(outer, column)| {
+                format!(
+                    "{}.{} = {}.{}",
+                    alias,
+                    quote(column),
+                    outer_alias,
+                    quote(outer)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        let select = self.select_list(table, &alias, select, depth)?;
+
+        Ok(format!(
+            "(SELECT {} FROM (SELECT {} FROM {} AS {} WHERE {}) AS e) AS {}",
+            aggregate,
+            select,
+            self.relation_name(embedded),
+            alias,
+            join,
+            quote(embedded)
+        ))
+    }
+
+    fn filters(&mut self, table: &Table, alias: &str, filters: &[Filter]) -> Result<String> {
+        let mut conditions = Vec::new();
+        for filter in filters {
+            let column = table.column(&filter.column)?;
+            let target = format!("{}.{}", alias, quote(&column.name));
+            let op = filter.operator.sql();
+
+            conditions.push(match filter.operator {
+                Operator::Is => {
+                    let value = match filter.value.as_str() {
+                        "null" => "NULL",
+                        "true" => "TRUE",
+                        "false" => "FALSE",
+                        "unknown" => "UNKNOWN",
+                        value => return Err(bad_request(format!("Invalid is.{}", value))),
+                    };
+                    format!("{} {} {}", target, op, value)
+                }
+                Operator::Like | Operator::Ilike => {
+                    // `*` stands in for `%`, which is awkward in a URL
+                    let pattern = self.sql.bind(Param::Text(filter.value.replace('*', "%")));
+                    format!("{}::TEXT {} {}", target, op, pattern)
+                }
+                Operator::In => {
+                    let values = parse_list(&filter.value)?;
+                    let values = self.sql.bind(Param::TextArray(values));
+                    format!(
+                        "{} {}(CAST({} AS {}[]))",
+                        target, op, values, column.data_type
+                    )
+                }
+                _ => {
+                    let value = self.sql.bind(Param::Text(filter.value.clone()));
+                    format!("{} {} CAST({} AS {})", target, op, value, column.data_type)
+                }
+            });
+        }
+
+        Ok(match conditions.is_empty() {
+            true => String::new(),
+            false => format!(" WHERE {}", conditions.join(" AND ")),
+        })
+    }
+
+    fn order(&mut self, table: &Table, alias: &str, order: &[Order]) -> Result<String> {
+        let terms = order
+            .iter()
+            .map(|order| {
+                let column = table.column(&order.column)?;
+                let nulls = match order.nulls_first {
+                    Some(true) => " NULLS FIRST",
+                    Some(false) => " NULLS LAST",
+                    None => "",
+                };
+                let direction = if order.descending { "DESC" } else { "ASC" };
+                Ok(format!(
+                    "{}.{} {}{}",
+                    alias,
+                    quote(&column.name),
+                    direction,
+                    nulls
+                ))
+            })
+            .collect::<Result<Vec<String>>>()?;
+
+        Ok(match terms.is_empty() {
+            true => String::new(),
+            false => format!(" ORDER BY {}", terms.join(", ")),
+        })
+    }
+}
+
+/// Parses the `(a,b,"c,d")` list of an `in` filter.
+fn parse_list(value: &str) -> Result<Vec<String>> {
+    let inner = value
+        .strip_prefix('(')
+        .and_then(|v| v.strip_suffix(')'))
+        .ok_or_else(|| bad_request(format!("Invalid list '{}'", value)))?;
+
+    let mut values = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in inner.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => values.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    values.push(current);
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rest::schema::{Column, ForeignKey};
+    use std::collections::HashMap;
+
+    fn table(name: &str, columns: &[&str]) -> (String, Table) {
+        let columns = columns
+            .iter()
+            .map(|c| Column {
+                name: c.to_string(),
+                data_type: "text".to_string(),
+            })
+            .collect();
+        (
+            name.to_string(),
+            Table {
+                name: name.to_string(),
+                columns,
+                primary_key: vec!["id".to_string()],
+            },
+        )
+    }
+
+    fn schema() -> Schema {
+        Schema {
+            name: "api".to_string(),
+            tables: HashMap::from([
+                table("posts", &["id", "author_id", "title"]),
+                table("authors", &["id", "name"]),
+            ]),
+            foreign_keys: vec![ForeignKey {
+                name: "posts_author_id_fkey".to_string(),
+                table: "posts".to_string(),
+                columns: vec!["author_id".to_string()],
+                foreign_table: "authors".to_string(),
+                foreign_columns: vec!["id".to_string()],
+            }],
+            functions: Vec::new(),
+        }
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_select() {
+        let select = parse_select("id,author(name,posts(*)),title").unwrap();
+        assert_eq!(
+            select,
+            vec![
+                SelectItem::Column("id".into()),
+                SelectItem::Embed {
+                    table: "author".into(),
+                    select: vec![
+                        SelectItem::Column("name".into()),
+                        SelectItem::Embed {
+                            table: "posts".into(),
+                            select: vec![SelectItem::Star],
+                        },
+                    ],
+                },
+                SelectItem::Column("title".into()),
+            ]
+        );
+        assert!(parse_select("id,(name").is_err());
+        assert!(parse_select("id,,name").is_err());
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let query = ReadQuery::parse(&params(&[
+            ("title", "like.*rust*"),
+            ("id", "in.(1,\"2,3\")"),
+            ("order", "title.desc.nullslast,id"),
+            ("limit", "5000"),
+        ]))
+        .unwrap();
+
+        assert_eq!(query.filters.len(), 2);
+        assert_eq!(query.filters[0].operator, Operator::Like);
+        assert_eq!(parse_list(&query.filters[1].value).unwrap(), ["1", "2,3"]);
+        assert!(query.order[0].descending);
+        assert_eq!(query.order[0].nulls_first, Some(false));
+        assert_eq!(query.limit, MAX_LIMIT);
+        assert!(ReadQuery::parse(&params(&[("id", "near.1")])).is_err());
+    }
+
+    #[test]
+    fn test_select_sql() {
+        let schema = schema();
+        let query = ReadQuery::parse(&params(&[
+            ("select", "title,authors(name)"),
+            ("id", "eq.1"),
+        ]))
+        .unwrap();
+        let sql = QueryBuilder::new(&schema, "posts")
+            .unwrap()
+            .select(&query)
+            .unwrap();
+
+        assert!(sql
+            .text
+            .contains(r#"FROM "api"."posts" AS t1 WHERE t1."id" = CAST($1 AS text)"#));
+        assert!(sql.text.contains(r#"(SELECT row_to_json(e) FROM (SELECT t2."name" FROM "api"."authors" AS t2 WHERE t2."id" = t1."author_id") AS e) AS "authors""#));
+        assert_eq!(sql.params[0], Param::Text("1".into()));
+
+        // The other way round, authors embed an array of their posts
+        let query = ReadQuery::parse(&params(&[("select", "name,posts(title)")])).unwrap();
+        let sql = QueryBuilder::new(&schema, "authors")
+            .unwrap()
+            .select(&query)
+            .unwrap();
+        assert!(sql.text.contains("COALESCE(json_agg(e), '[]'::json)"));
+        assert!(sql.text.contains(r#"t2."author_id" = t1."id""#));
+    }
+
+    #[test]
+    fn test_rejects_unknown_identifiers() {
+        let schema = schema();
+        let query = ReadQuery::parse(&params(&[("password", "eq.x")])).unwrap();
+        assert!(QueryBuilder::new(&schema, "posts")
+            .unwrap()
+            .select(&query)
+            .is_err());
+        assert!(QueryBuilder::new(&schema, "users").is_err());
+
+        let query = ReadQuery::parse(&params(&[])).unwrap();
+        assert!(QueryBuilder::new(&schema, "posts")
+            .unwrap()
+            .delete(&query)
+            .is_err());
+    }
+}
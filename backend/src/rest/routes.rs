@@ -0,0 +1,25 @@
+use crate::middleware::auth_middleware;
+use crate::rest::handlers::{call_function, delete_rows, insert_rows, read_rows, update_rows};
+use crate::state::AppState;
+use axum::routing::{get, post};
+use axum::{middleware, Router};
+use std::sync::Arc;
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route(
+            "/{table}",
+            get(read_rows)
+                .post(insert_rows)
+                .patch(update_rows)
+                .delete(delete_rows),
+        )
+        .route_layer(middleware::from_fn(auth_middleware))
+}
+
+/// Function calls, served at `/rpc` beside the data API.
+pub fn rpc_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/{function}", post(call_function))
+        .route_layer(middleware::from_fn(auth_middleware))
+}
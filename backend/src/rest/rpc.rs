@@ -0,0 +1,209 @@
+//! Calls to the functions of the data API's schema. The JSON body's fields are the
+//! arguments, passed by name and checked against the types `pg_proc` declares before
+//! Postgres casts them.
+
+use crate::error::{Error, Result};
+use crate::rest::query::{quote, Param, Sql};
+use crate::rest::schema::{Function, Schema};
+use axum::http::StatusCode;
+use serde_json::{Map, Value};
+
+/// Builds the call of `name` with `arguments`. Set-returning functions give a JSON array
+/// of their rows, others their single value, and `void` functions null.
+pub fn call(schema: &Schema, name: &str, arguments: Map<String, Value>) -> Result<Sql> {
+    let names: Vec<&str> = arguments.keys().map(String::as_str).collect();
+    let function = schema.function(name, &names)?;
+
+    let mut passed = Vec::new();
+    for argument in &function.arguments {
+        let Some(value) = arguments.get(&argument.name) else {
+            continue;
+        };
+        check(&argument.data_type, value).map_err(|expected| {
+            Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Argument '{}' of '{}' expects {}, got {}",
+                    argument.name, function.name, expected, value
+                ),
+            )
+        })?;
+        passed.push(format!(
+            "{}{} => {}",
+            if argument.variadic { "VARIADIC " } else { "" },
+            quote(&argument.name),
+            extract(&argument.name, &argument.data_type)
+        ));
+    }
+
+    let call = format!(
+        "{}.{}({})",
+        quote(&schema.name),
+        quote(&function.name),
+        passed.join(", ")
+    );
+    let text = result(function, &call);
+
+    Ok(Sql {
+        text,
+        params: vec![Param::Json(Value::Object(arguments))],
+    })
+}
+
+fn result(function: &Function, call: &str) -> String {
+    if function.returns_set {
+        format!("SELECT COALESCE(json_agg(r), '[]') FROM {call} AS r")
+    } else if function.return_type == "void" {
+        format!("SELECT 'null'::json FROM {call}")
+    } else {
+        format!("SELECT COALESCE(to_json(r), 'null') FROM {call} AS r")
+    }
+}
+
+/// The SQL reading argument `name` out of the JSON body bound as `$1`.
+fn extract(name: &str, data_type: &str) -> String {
+    let field = format!("$1::jsonb -> '{}'", name.replace('\'', "''"));
+    if matches!(data_type, "json" | "jsonb") {
+        format!("({field})::{data_type}")
+    } else if data_type.ends_with("[]") {
+        format!(
+            "(CASE WHEN jsonb_typeof({field}) = 'null' THEN NULL ELSE ARRAY(SELECT jsonb_array_elements_text({field})) END)::{data_type}"
+        )
+    } else {
+        format!("({field} #>> '{{}}')::{data_type}")
+    }
+}
+
+/// Checks a value has the JSON type a Postgres type is written as, returning what was
+/// expected if not. Types without a natural JSON form are left for Postgres to parse.
+fn check(data_type: &str, value: &Value) -> std::result::Result<(), String> {
+    if value.is_null() {
+        return Ok(());
+    }
+
+    if let Some(element) = data_type.strip_suffix("[]") {
+        let Value::Array(items) = value else {
+            return Err(format!("an array of {}", element));
+        };
+        return items
+            .iter()
+            .try_for_each(|item| check(element, item))
+            .map_err(|expected| format!("an array of {}", expected));
+    }
+
+    let valid = match data_type {
+        "smallint" | "integer" | "bigint" => value.is_i64() || value.is_u64(),
+        "numeric" | "real" | "double precision" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "json" | "jsonb" => true,
+        "text"
+        | "uuid"
+        | "date"
+        | "time without time zone"
+        | "timestamp without time zone"
+        | "timestamp with time zone"
+        | "interval" => value.is_string(),
+        _ if data_type.starts_with("character") => value.is_string(),
+        _ => !value.is_array() && !value.is_object(),
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(data_type.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rest::schema::FunctionArgument;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn function(name: &str, arguments: &[(&str, &str)], defaults: usize) -> Function {
+        Function {
+            name: name.to_string(),
+            arguments: arguments
+                .iter()
+                .map(|(name, data_type)| FunctionArgument {
+                    name: name.to_string(),
+                    data_type: data_type.to_string(),
+                    variadic: false,
+                })
+                .collect(),
+            defaults,
+            returns_set: false,
+            return_type: "integer".to_string(),
+        }
+    }
+
+    fn schema() -> Schema {
+        let mut tags = function("tags", &[("names", "text[]")], 0);
+        tags.returns_set = true;
+        Schema {
+            name: "api".to_string(),
+            tables: HashMap::new(),
+            foreign_keys: Vec::new(),
+            functions: vec![
+                function("add", &[("a", "integer"), ("b", "integer")], 1),
+                function(
+                    "add",
+                    &[("a", "numeric"), ("b", "numeric"), ("c", "numeric")],
+                    0,
+                ),
+                tags,
+            ],
+        }
+    }
+
+    fn args(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_call() {
+        let sql = call(&schema(), "add", args(json!({ "a": 1 }))).unwrap();
+        assert_eq!(
+            sql.text,
+            r#"SELECT COALESCE(to_json(r), 'null') FROM "api"."add"("a" => ($1::jsonb -> 'a' #>> '{}')::integer) AS r"#
+        );
+
+        let sql = call(&schema(), "tags", args(json!({ "names": ["a"] }))).unwrap();
+        assert!(sql
+            .text
+            .starts_with("SELECT COALESCE(json_agg(r), '[]') FROM"));
+        assert!(sql
+            .text
+            .contains("ARRAY(SELECT jsonb_array_elements_text($1::jsonb -> 'names'))"));
+    }
+
+    #[test]
+    fn test_overloads() {
+        let schema = schema();
+        assert_eq!(
+            schema.function("add", &["a", "b"]).unwrap().arguments.len(),
+            2
+        );
+        assert_eq!(
+            schema
+                .function("add", &["a", "b", "c"])
+                .unwrap()
+                .arguments
+                .len(),
+            3
+        );
+        assert!(schema.function("add", &["b"]).is_err());
+        assert!(schema.function("add", &["a", "d"]).is_err());
+        assert!(schema.function("sub", &[]).is_err());
+    }
+
+    #[test]
+    fn test_type_checks() {
+        assert!(call(&schema(), "add", args(json!({ "a": "1" }))).is_err());
+        assert!(call(&schema(), "add", args(json!({ "a": 1.5 }))).is_err());
+        assert!(call(&schema(), "add", args(json!({ "a": 1, "b": null }))).is_ok());
+        assert!(call(&schema(), "add", args(json!({ "a": 1.5, "b": 2, "c": 3 }))).is_ok());
+        assert!(call(&schema(), "tags", args(json!({ "names": [1] }))).is_err());
+        assert!(call(&schema(), "tags", args(json!({ "names": "a" }))).is_err());
+    }
+}
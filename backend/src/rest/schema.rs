@@ -0,0 +1,374 @@
+use crate::error::{Error, Result};
+use axum::http::StatusCode;
+use sqlx::{FromRow, PgPool};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    /// Type name as Postgres prints it, used to cast filter values.
+    pub data_type: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub name: String,
+    pub columns: Vec<Column>,
+    /// Columns of the primary key, none for views and tables without one.
+    pub primary_key: Vec<String>,
+}
+
+impl Table {
+    pub fn column(&self, name: &str) -> Result<&Column> {
+        self.columns.iter().find(|c| c.name == name).ok_or_else(|| {
+            Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                format!("Unknown column '{}' on '{}'", name, self.name),
+            )
+        })
+    }
+}
+
+/// A foreign key from `table` to `foreign_table`, column by column.
+#[derive(Debug, Clone, FromRow)]
+pub struct ForeignKey {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub foreign_table: String,
+    pub foreign_columns: Vec<String>,
+}
+
+/// How an embedded table relates to the one it is embedded in.
+#[derive(Debug)]
+pub enum Relation<'a> {
+    /// The outer table references one row of the embedded table.
+    ToOne(&'a ForeignKey),
+    /// Rows of the embedded table reference the outer table.
+    ToMany(&'a ForeignKey),
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionArgument {
+    pub name: String,
+    /// Type name as Postgres prints it, used to check and cast the value.
+    pub data_type: String,
+    pub variadic: bool,
+}
+
+/// A function callable through `/rpc`. Only functions whose arguments all have names are
+/// listed, as calls pass them by name.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub arguments: Vec<FunctionArgument>,
+    /// Trailing arguments that have defaults and can be left out.
+    pub defaults: usize,
+    pub returns_set: bool,
+    pub return_type: String,
+}
+
+impl Function {
+    /// Whether a call passing `names` fits this overload.
+    fn accepts(&self, names: &[&str]) -> bool {
+        let required = self.arguments.len() - self.defaults;
+        names
+            .iter()
+            .all(|name| self.arguments.iter().any(|a| a.name == *name))
+            && self.arguments[..required]
+                .iter()
+                .all(|a| names.contains(&a.name.as_str()))
+    }
+}
+
+/// Tables, columns, foreign keys and functions of the schema the data API exposes.
+#[derive(Debug)]
+pub struct Schema {
+    pub name: String,
+    pub tables: HashMap<String, Table>,
+    pub foreign_keys: Vec<ForeignKey>,
+    pub functions: Vec<Function>,
+}
+
+#[derive(Debug, FromRow)]
+struct FunctionRow {
+    name: String,
+    argument_names: Vec<String>,
+    argument_modes: Vec<String>,
+    argument_types: Vec<String>,
+    defaults: i32,
+    returns_set: bool,
+    return_type: String,
+}
+
+#[derive(Debug, FromRow)]
+struct ColumnRow {
+    table_name: String,
+    column_name: String,
+    data_type: String,
+}
+
+impl Schema {
+    /// Introspects the tables and views of `schema` from the catalog.
+    pub async fn load(pool: &PgPool, schema: &str) -> Result<Self> {
+        let rows: Vec<ColumnRow> = sqlx::query_as(
+            r#"
+            SELECT
+                c.relname AS table_name,
+                a.attname AS column_name,
+                a.atttypid::regtype::TEXT AS data_type
+            FROM pg_class AS c
+            JOIN pg_namespace AS n ON n.oid = c.relnamespace
+            JOIN pg_attribute AS a ON a.attrelid = c.oid
+            WHERE n.nspname = $1
+            AND c.relkind IN ('r', 'p', 'v', 'm')
+            AND a.attnum > 0
+            AND NOT a.attisdropped
+            ORDER BY c.relname, a.attnum
+        "#,
+        )
+        .bind(schema)
+        .fetch_all(pool)
+        .await?;
+
+        let mut tables: HashMap<String, Table> = HashMap::new();
+        for row in rows {
+            tables
+                .entry(row.table_name.clone())
+                .or_insert_with(|| Table {
+                    name: row.table_name,
+                    columns: Vec::new(),
+                    primary_key: Vec::new(),
+                })
+                .columns
+                .push(Column {
+                    name: row.column_name,
+                    data_type: row.data_type,
+                });
+        }
+
+        let keys: Vec<(String, String)> = sqlx::query_as(
+            r#"
+            SELECT c.relname::TEXT, a.attname::TEXT
+            FROM pg_index AS i
+            JOIN pg_class AS c ON c.oid = i.indrelid
+            JOIN pg_namespace AS n ON n.oid = c.relnamespace
+            JOIN pg_attribute AS a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
+            WHERE n.nspname = $1
+            AND i.indisprimary
+            ORDER BY c.relname, array_position(i.indkey::SMALLINT[], a.attnum)
+        "#,
+        )
+        .bind(schema)
+        .fetch_all(pool)
+        .await?;
+        for (table, column) in keys {
+            if let Some(table) = tables.get_mut(&table) {
+                table.primary_key.push(column);
+            }
+        }
+
+        // Only keys between tables of the schema, so embedding stays inside it
+        let foreign_keys: Vec<ForeignKey> = sqlx::query_as(
+            r#"
+            SELECT
+                con.conname::TEXT AS name,
+                c.relname::TEXT AS table,
+                ARRAY(
+                    SELECT a.attname::TEXT
+                    FROM UNNEST(con.conkey) WITH ORDINALITY AS k(attnum, ord)
+                    JOIN pg_attribute AS a ON a.attrelid = con.conrelid AND a.attnum = k.attnum
+                    ORDER BY k.ord
+                ) AS columns,
+                fc.relname::TEXT AS foreign_table,
+                ARRAY(
+                    SELECT a.attname::TEXT
+                    FROM UNNEST(con.confkey) WITH ORDINALITY AS k(attnum, ord)
+                    JOIN pg_attribute AS a ON a.attrelid = con.confrelid AND a.attnum = k.attnum
+                    ORDER BY k.ord
+                ) AS foreign_columns
+            FROM pg_constraint AS con
+            JOIN pg_class AS c ON c.oid = con.conrelid
+            JOIN pg_class AS fc ON fc.oid = con.confrelid
+            JOIN pg_namespace AS n ON n.oid = c.relnamespace
+            JOIN pg_namespace AS fn ON fn.oid = fc.relnamespace
+            WHERE con.contype = 'f'
+            AND n.nspname = $1
+            AND fn.nspname = $1
+            ORDER BY con.conname
+        "#,
+        )
+        .bind(schema)
+        .fetch_all(pool)
+        .await?;
+
+        let rows: Vec<FunctionRow> = sqlx::query_as(
+            r#"
+            SELECT
+                p.proname::TEXT AS name,
+                COALESCE(p.proargnames, '{}') AS argument_names,
+                COALESCE(p.proargmodes::TEXT[], '{}') AS argument_modes,
+                ARRAY(
+                    SELECT format_type(t.oid, NULL)
+                    FROM UNNEST(p.proargtypes::OID[]) WITH ORDINALITY AS t(oid, ord)
+                    ORDER BY t.ord
+                ) AS argument_types,
+                p.pronargdefaults::INT AS defaults,
+                p.proretset AS returns_set,
+                format_type(p.prorettype, NULL) AS return_type
+            FROM pg_proc AS p
+            JOIN pg_namespace AS n ON n.oid = p.pronamespace
+            WHERE n.nspname = $1
+            AND p.prokind = 'f'
+            AND p.prorettype NOT IN ('trigger'::regtype, 'event_trigger'::regtype)
+            ORDER BY p.proname
+        "#,
+        )
+        .bind(schema)
+        .fetch_all(pool)
+        .await?;
+        let functions = rows.into_iter().filter_map(Function::from_row).collect();
+
+        Ok(Schema {
+            name: schema.to_string(),
+            tables,
+            foreign_keys,
+            functions,
+        })
+    }
+
+    /// Finds the overload of function `name` that takes exactly the arguments `names`,
+    /// leaving out only those with defaults.
+    pub fn function(&self, name: &str, names: &[&str]) -> Result<&Function> {
+        let mut overloads = self.functions.iter().filter(|f| f.name == name).peekable();
+        if overloads.peek().is_none() {
+            return Err(Error::HttpError(
+                StatusCode::NOT_FOUND,
+                format!("Unknown function '{}'", name),
+            ));
+        }
+
+        let mut matching = overloads.filter(|f| f.accepts(names));
+        match (matching.next(), matching.next()) {
+            (Some(function), None) => Ok(function),
+            (None, _) => Err(Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Function '{}' takes no overload with arguments ({})",
+                    name,
+                    names.join(", ")
+                ),
+            )),
+            (Some(_), Some(_)) => Err(Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                format!("Call to '{}' matches more than one overload", name),
+            )),
+        }
+    }
+
+    pub fn table(&self, name: &str) -> Result<&Table> {
+        self.tables.get(name).ok_or_else(|| {
+            Error::HttpError(StatusCode::NOT_FOUND, format!("Unknown table '{}'", name))
+        })
+    }
+
+    /// Finds the one foreign key joining `embedded` to `table`, in either direction.
+    pub fn relation(&self, table: &str, embedded: &str) -> Result<Relation<'_>> {
+        let mut relations = self.foreign_keys.iter().filter_map(|fk| {
+            if fk.table == table && fk.foreign_table == embedded {
+                Some(Relation::ToOne(fk))
+            } else if fk.table == embedded && fk.foreign_table == table {
+                Some(Relation::ToMany(fk))
+            } else {
+                None
+            }
+        });
+
+        match (relations.next(), relations.next()) {
+            (Some(relation), None) => Ok(relation),
+            (None, _) => Err(Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                format!("No foreign key between '{}' and '{}'", table, embedded),
+            )),
+            (Some(_), Some(_)) => Err(Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "More than one foreign key between '{}' and '{}'",
+                    table, embedded
+                ),
+            )),
+        }
+    }
+}
+
+impl Function {
+    /// Keeps the input arguments of a catalog row, or drops the function when one of
+    /// them has no name.
+    fn from_row(row: FunctionRow) -> Option<Self> {
+        // Without modes, every argument is an input
+        let modes = if row.argument_modes.is_empty() {
+            vec!["i".to_string(); row.argument_types.len()]
+        } else {
+            row.argument_modes
+        };
+        let names = modes
+            .iter()
+            .zip(
+                row.argument_names
+                    .iter()
+                    .map(Some)
+                    .chain(std::iter::repeat(None)),
+            )
+            .filter(|(mode, _)| matches!(mode.as_str(), "i" | "b" | "v"));
+
+        let mut arguments = Vec::new();
+        for ((mode, name), data_type) in names.zip(row.argument_types) {
+            let name = name.filter(|name| !name.is_empty())?;
+            arguments.push(FunctionArgument {
+                name: name.clone(),
+                data_type,
+                variadic: mode == "v",
+            });
+        }
+
+        Some(Function {
+            name: row.name,
+            arguments,
+            defaults: row.defaults as usize,
+            returns_set: row.returns_set,
+            return_type: row.return_type,
+        })
+    }
+}
+
+/// The introspected schema, loaded on first use and again when reloaded.
+#[derive(Debug)]
+pub struct SchemaCache {
+    schema: String,
+    cached: RwLock<Option<Arc<Schema>>>,
+}
+
+impl SchemaCache {
+    pub fn new(schema: &str) -> Self {
+        SchemaCache {
+            schema: schema.to_string(),
+            cached: RwLock::new(None),
+        }
+    }
+
+    pub async fn get(&self, pool: &PgPool) -> Result<Arc<Schema>> {
+        if let Some(schema) = self.cached.read().await.as_ref() {
+            return Ok(schema.clone());
+        }
+        self.reload(pool).await
+    }
+
+    /// Introspects the schema again, picking up tables changed since it was loaded.
+    pub async fn reload(&self, pool: &PgPool) -> Result<Arc<Schema>> {
+        let schema = Arc::new(Schema::load(pool, &self.schema).await?);
+        *self.cached.write().await = Some(schema.clone());
+        Ok(schema)
+    }
+}
@@ -1,7 +1,11 @@
+use crate::admin::routes::router as admin_router;
 use crate::auth::routes::router as auth_router;
+use crate::exports::routes::router as exports_router;
+use crate::graphql::routes::router as graphql_router;
 use crate::integrations::routes::router as integrations_router;
 use crate::objects::routes::router as objects_router;
 use crate::response::ApiResponse;
+use crate::rest::routes::{router as rest_router, rpc_router};
 use crate::smtp::routes::router as email_router;
 use crate::state::AppState;
 use crate::users::routes::router as user_router;
@@ -12,15 +16,20 @@ use axum::routing::get;
 use axum::Router;
 use std::sync::Arc;
 
-pub fn router(state: AppState) -> Router {
-    let arc_state = Arc::new(state);
+pub fn router(state: impl Into<Arc<AppState>>) -> Router {
+    let arc_state = state.into();
 
     Router::new()
         .nest("/users", user_router())
         .nest("/auth", auth_router())
         .nest("/email", email_router())
         .nest("/objects", objects_router())
+        .nest("/exports", exports_router())
         .nest("/integrations", integrations_router())
+        .nest("/admin", admin_router())
+        .nest("/rest", rest_router())
+        .nest("/rpc", rpc_router())
+        .nest("/graphql", graphql_router())
         .nest("/ws", ws_router())
         .route("/health", get(health_check))
         .with_state(arc_state)
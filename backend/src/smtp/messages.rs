@@ -46,3 +46,21 @@ pub fn mfa_code_body(mfa_code: &str) -> Result<String> {
     let body = contents.replace("{{VERIFICATION_CODE}}", mfa_code);
     Ok(body)
 }
+
+pub fn cancel_deletion_body(cancel_url: &str, delete_after: &str) -> Result<String> {
+    let path = env::current_dir()?.join("src/smtp/templates/cancel-deletion.html");
+    let contents = std::fs::read_to_string(path)?;
+    let body = contents
+        .replace("{{CANCEL_URL}}", cancel_url)
+        .replace("{{DELETE_AFTER}}", delete_after);
+    Ok(body)
+}
+
+pub fn export_ready_body(download_url: &str, expires_at: &str) -> Result<String> {
+    let path = env::current_dir()?.join("src/smtp/templates/export-ready.html");
+    let contents = std::fs::read_to_string(path)?;
+    let body = contents
+        .replace("{{DOWNLOAD_URL}}", download_url)
+        .replace("{{EXPIRES_AT}}", expires_at);
+    Ok(body)
+}
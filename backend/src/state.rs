@@ -1,7 +1,11 @@
 use crate::auth::oauth::service::OAuthClient;
 use crate::config::CONFIG;
 use crate::error::Result;
+use crate::graphql::{builtin, Context, Schema};
 use crate::integrations::service::IntegrationClient;
+use crate::objects::rules::StorageRules;
+use crate::realtime::Realtime;
+use crate::rest::schema::SchemaCache;
 use crate::smtp::service::EmailService;
 use crate::storage::StorageClient;
 
@@ -11,6 +15,10 @@ pub struct AppState {
     pub smtp: EmailService,
     pub integration: IntegrationClient,
     pub oauth: OAuthClient,
+    pub rules: StorageRules,
+    pub data_api: SchemaCache,
+    pub realtime: Realtime,
+    pub graphql: Schema<Context>,
 }
 
 impl AppState {
@@ -26,6 +34,10 @@ impl AppState {
             )?,
             integration: IntegrationClient::new().await?,
             oauth: OAuthClient::new().await?,
+            rules: StorageRules::load(&config.storage_rules_path)?,
+            data_api: SchemaCache::new(&config.data_api_schema),
+            realtime: Realtime::new(),
+            graphql: builtin::schema(),
         })
     }
 }
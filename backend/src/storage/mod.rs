@@ -1,64 +1,1030 @@
 pub mod object;
 pub mod postgres;
 pub mod redis;
+pub mod scanner;
 
 use axum::body::Bytes;
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::BoxStream;
+use futures::StreamExt;
 use object_store::path::Path;
+use sha2::{Digest, Sha256};
 use sqlx::types::Uuid;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
 
 use crate::{
-    data::Objects,
-    storage::{object::ObjectClient, postgres::PostgresClient, redis::RedisClient},
-    Result,
+    config::CONFIG,
+    data::{
+        CurrentVersion, ImageFormat, ImageTransform, LifecycleReport, ObjectList, ObjectVersion,
+        Objects, ReconcileReport, ScanStatus, StorageClass, StorageUsage, TusUpload,
+    },
+    exports::models::DataExport,
+    objects::zip::{self, ZipEntry},
+    storage::{
+        object::{
+            envelope::{BlobEncryptor, DataKey, CHUNK_SIZE, PREFIX_SIZE},
+            ObjectClient, PART_SIZE,
+        },
+        postgres::PostgresClient,
+        redis::RedisClient,
+        scanner::{get_scanner, ScanVerdict, Scanner},
+    },
+    users::queries::UserQueries,
+    Error, Result,
 };
 
+/// How long an upload may hold a shared blob while storing and recording it.
+const BLOB_LOCK_SECONDS: u64 = 600;
+const BLOB_LOCK_ATTEMPTS: u32 = 50;
+
+/// Scans tried on a blob before it stays quarantined for good.
+const MAX_SCAN_ATTEMPTS: i32 = 5;
+/// How long a scan may stay pending before it counts as lost and is run again.
+const SCAN_STALE_MINUTES: i64 = 30;
+/// Blobs scanned again per sweep.
+const SCAN_BATCH: i64 = 100;
+
+/// Where the bytes of a deduplicated upload come from.
+#[derive(Clone, Copy)]
+enum UploadSource<'a> {
+    Bytes(&'a Bytes),
+    /// Already written to the version's own blob, which is dropped or becomes the shared one.
+    Blob,
+}
+
 #[derive(Debug)]
 pub struct StorageClient {
     pub postgres: PostgresClient,
     pub redis: RedisClient,
     pub object: ObjectClient,
+    /// Where lifecycle rules move old blobs, when configured.
+    pub archive: Option<ObjectClient>,
+    pub scanner: Arc<dyn Scanner>,
 }
 
 impl StorageClient {
     pub async fn new() -> Result<Self> {
+        if CONFIG.object_dedup && CONFIG.object_dedup_secret.is_empty() {
+            return Err(Error::from("OBJECT_DEDUP requires OBJECT_DEDUP_SECRET"));
+        }
         Ok(StorageClient {
             postgres: PostgresClient::new().await?,
             redis: RedisClient::new().await?,
             object: ObjectClient::new().await?,
+            archive: match CONFIG.object_archive_url.as_str() {
+                "" => None,
+                url => Some(ObjectClient::with_url(url).await?),
+            },
+            scanner: get_scanner(&CONFIG.malware_scanner)?,
+        })
+    }
+
+    pub async fn upload_file(
+        &self,
+        object: &Objects,
+        mut version: ObjectVersion,
+        file: &Bytes,
+    ) -> Result<()> {
+        if CONFIG.object_dedup {
+            let sha256 = hex::encode(Sha256::digest(file));
+            return self
+                .upload_shared(object, version, &sha256, UploadSource::Bytes(file))
+                .await;
+        }
+
+        let key = self.assign_data_key(&mut version)?;
+        let blob = Path::from(version.blob_key.as_str());
+        self.object.put_sealed(&blob, file, key.as_ref()).await?;
+
+        if let Err(e) = self.record_upload(object, &version).await {
+            // Nothing points at the blob without its row
+            self.delete_blobs(vec![version.blob_key.clone()]).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Gives `version` a fresh data key when objects are encrypted, returning it.
+    fn assign_data_key(&self, version: &mut ObjectVersion) -> Result<Option<DataKey>> {
+        Ok(match self.object.new_data_key()? {
+            Some((key, key_id, wrapped_key)) => {
+                version.key_id = Some(key_id);
+                version.wrapped_key = Some(wrapped_key);
+                Some(key)
+            }
+            None => None,
         })
     }
 
-    pub async fn upload_file(&self, object: &Objects, path: &Path, file: &Bytes) -> Result<()> {
-        match self.object.upsert(&path, &file).await {
-            Ok(()) => match self.postgres.upload_object(object).await {
-                Ok(_) => Ok(()),
-                Err(e) => Err(e.into()),
+    /// Records an upload against the owner's shared blob for its content, storing the
+    /// bytes only when no version holds them yet. New versions of existing content take
+    /// over its data key and scan verdict.
+    async fn upload_shared(
+        &self,
+        object: &Objects,
+        mut version: ObjectVersion,
+        sha256: &str,
+        source: UploadSource<'_>,
+    ) -> Result<()> {
+        let shared =
+            ObjectVersion::shared_blob_key(object.user_id, sha256, &CONFIG.object_dedup_secret);
+        let own = std::mem::replace(&mut version.blob_key, shared.clone());
+        let path = Path::from(shared.as_str());
+        let mut stored = false;
+
+        let result = async {
+            self.lock_blob(&shared).await?;
+            let recorded = async {
+                match self.postgres.get_shared_blob_version(&shared).await? {
+                    Some(existing) => {
+                        version.key_id = existing.key_id;
+                        version.wrapped_key = existing.wrapped_key;
+                        version.scan_status = existing.scan_status;
+                        version.storage_class = existing.storage_class;
+                    }
+                    None => {
+                        match source {
+                            UploadSource::Bytes(file) => {
+                                let key = self.assign_data_key(&mut version)?;
+                                self.object.put_sealed(&path, file, key.as_ref()).await?;
+                            }
+                            UploadSource::Blob => {
+                                self.object.rename(&Path::from(own.as_str()), &path).await?
+                            }
+                        }
+                        stored = true;
+                    }
+                }
+                self.record_upload(object, &version).await
+            }
+            .await;
+
+            if recorded.is_err() && stored {
+                // Still locked and referenced by nothing, so it can go right away
+                if let Err(e) = self.object.delete(&path).await {
+                    tracing::warn!("Failed to delete blob {}: {}", shared, e);
+                }
+            }
+            self.unlock_blob(&shared).await;
+            recorded
+        }
+        .await;
+
+        if matches!(source, UploadSource::Blob) && !stored {
+            self.delete_blobs(vec![own]).await;
+        }
+        result
+    }
+
+    /// Serializes taking a reference to a shared blob with deleting it once unreferenced.
+    async fn lock_blob(&self, blob_key: &str) -> Result<()> {
+        let lock = format!("blob:{}", blob_key);
+        for _ in 0..BLOB_LOCK_ATTEMPTS {
+            if self.redis.acquire_lock(&lock, BLOB_LOCK_SECONDS).await? {
+                return Ok(());
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        Err(Error::HttpError(
+            StatusCode::LOCKED,
+            "The same content is being uploaded, try again".into(),
+        ))
+    }
+
+    async fn unlock_blob(&self, blob_key: &str) {
+        if let Err(e) = self.redis.release_lock(&format!("blob:{}", blob_key)).await {
+            tracing::warn!("Failed to unlock blob {}: {}", blob_key, e);
+        }
+    }
+
+    /// Deletes a shared blob once its last reference is gone. Skipped while an upload
+    /// holds it, as that upload references it again or leaves it to the reconciler.
+    async fn release_shared_blob(&self, blob_key: &str) -> Result<()> {
+        if !self
+            .redis
+            .acquire_lock(&format!("blob:{}", blob_key), BLOB_LOCK_SECONDS)
+            .await?
+        {
+            return Ok(());
+        }
+
+        let result = async {
+            if self
+                .postgres
+                .get_blob_ref_count(blob_key)
+                .await?
+                .unwrap_or(0)
+                > 0
+            {
+                return Ok(());
+            }
+            self.delete_stored(blob_key).await?;
+            self.invalidate_transforms(blob_key).await;
+            self.postgres.delete_unreferenced_blob(blob_key).await
+        }
+        .await;
+        self.unlock_blob(blob_key).await;
+        result
+    }
+
+    /// SHA-256 of a stored blob's plaintext, read back as a stream.
+    async fn hash_blob(&self, version: &ObjectVersion) -> Result<String> {
+        let key = self.version_key(version)?;
+        let mut data = self
+            .store(&version.storage_class)?
+            .get_sealed_stream(&Path::from(version.blob_key.as_str()), key)
+            .await?;
+
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = data.next().await {
+            hasher.update(&chunk?);
+        }
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    /// Records a blob that is already in the store as the object's new current version.
+    /// Only fails when nothing was recorded, so callers can discard the blob.
+    async fn record_upload(&self, object: &Objects, version: &ObjectVersion) -> Result<()> {
+        let previous = self.postgres.get_current_version(&object.key).await?;
+        let pruned = self
+            .postgres
+            .upload_object(object, version, CONFIG.object_max_versions)
+            .await?;
+
+        // Shared content that was already scanned keeps its verdict
+        if ScanStatus::parse(&version.scan_status) == ScanStatus::Pending {
+            self.scan_in_background(version);
+        }
+        if let Some(previous) = previous {
+            self.invalidate_transforms(&previous.blob_key).await;
+        }
+        self.delete_blobs(pruned).await;
+        Ok(())
+    }
+
+    /// Scans a newly recorded blob and stores the verdict on its version, which stays
+    /// pending (and undownloadable) until then.
+    fn scan_in_background(&self, version: &ObjectVersion) {
+        tokio::spawn(self.scan(version));
+    }
+
+    /// Scans a blob and stores the verdict on its versions. An error is recorded as a
+    /// failed scan, which keeps the versions quarantined until a retry clears them.
+    fn scan(&self, version: &ObjectVersion) -> impl Future<Output = ()> + Send + 'static {
+        let object = self.store(&version.storage_class).cloned();
+        let postgres = self.postgres.clone();
+        let scanner = self.scanner.clone();
+        let blob_key = version.blob_key.clone();
+        let key_id = version.key_id.clone();
+        let wrapped_key = version.wrapped_key.clone();
+
+        async move {
+            let verdict = async {
+                let object = object?;
+                let key = object.data_key(key_id.as_deref(), wrapped_key.as_deref())?;
+                let data = object
+                    .get_sealed_stream(&Path::from(blob_key.as_str()), key)
+                    .await?;
+                scanner.scan(data).await
+            };
+
+            let status = match verdict.await {
+                Ok(ScanVerdict::Clean) => ScanStatus::Clean,
+                Ok(ScanVerdict::Infected(signature)) => {
+                    tracing::warn!("Quarantined blob {}: {}", blob_key, signature);
+                    ScanStatus::Infected
+                }
+                Err(e) => {
+                    tracing::error!("Failed to scan blob {}: {}", blob_key, e);
+                    ScanStatus::Failed
+                }
+            };
+
+            if let Err(e) = postgres.set_scan_status(&blob_key, status).await {
+                tracing::error!("Failed to record scan of blob {}: {}", blob_key, e);
+            }
+        }
+    }
+
+    /// Scans again the blobs whose scan failed or never finished, returning how many were.
+    pub async fn retry_scans(&self) -> Result<usize> {
+        let due = self
+            .postgres
+            .scans_due(MAX_SCAN_ATTEMPTS, SCAN_STALE_MINUTES, SCAN_BATCH)
+            .await?;
+        for version in &due {
+            self.scan(version).await;
+        }
+        Ok(due.len())
+    }
+
+    /// Opens a resumable upload: starts the multipart upload its parts go to and
+    /// saves its state, with the data key and nonce prefix its parts are sealed under.
+    pub async fn start_upload(&self, upload: &mut TusUpload) -> Result<()> {
+        if let Some((key, key_id, wrapped_key)) = self.object.new_data_key()? {
+            upload.key_id = Some(key_id);
+            upload.wrapped_key = Some(wrapped_key);
+            upload.nonce_prefix = Some(hex::encode(BlobEncryptor::new(&key).prefix()));
+        }
+        let blob = Path::from(upload.blob_key.as_str());
+        upload.multipart_id = self.object.create_multipart(&blob).await?;
+        self.redis.store_tus_upload(upload, &[]).await
+    }
+
+    /// Sends the next part of a resumable upload. The caller saves the state once the
+    /// bytes it covers are accounted for.
+    pub async fn upload_part(&self, upload: &mut TusUpload, data: Bytes) -> Result<()> {
+        let data = match self.upload_encryptor(upload)? {
+            Some(mut encryptor) => {
+                let mut sealed = match upload.parts.is_empty() {
+                    true => encryptor.header().to_vec(),
+                    false => Vec::new(),
+                };
+                sealed.extend_from_slice(&encryptor.seal(&data, false)?);
+                Bytes::from(sealed)
+            }
+            None => data,
+        };
+        let blob = Path::from(upload.blob_key.as_str());
+        let content_id = self
+            .object
+            .put_part(&blob, &upload.multipart_id, upload.parts.len(), data)
+            .await?;
+        upload.parts.push(content_id);
+        Ok(())
+    }
+
+    /// Completes a resumable upload with its remaining bytes and records it like any
+    /// other upload.
+    pub async fn complete_upload(&self, upload: &TusUpload, rest: Bytes) -> Result<()> {
+        let blob = Path::from(upload.blob_key.as_str());
+        let encryptor = self.upload_encryptor(upload)?;
+
+        if upload.parts.is_empty() {
+            // Never reached a full part, so a plain put is cheaper and handles empty files
+            self.object
+                .abort_multipart(&blob, &upload.multipart_id)
+                .await?;
+            let key = self
+                .object
+                .data_key(upload.key_id.as_deref(), upload.wrapped_key.as_deref())?;
+            self.object.put_sealed(&blob, &rest, key.as_ref()).await?;
+        } else {
+            let mut parts = upload.parts.clone();
+            // A sealed blob always ends with a final chunk, even an empty one
+            let rest = match encryptor {
+                Some(mut encryptor) => Bytes::from(encryptor.seal(&rest, true)?),
+                None => rest,
+            };
+            if !rest.is_empty() {
+                let content_id = self
+                    .object
+                    .put_part(&blob, &upload.multipart_id, parts.len(), rest)
+                    .await?;
+                parts.push(content_id);
+            }
+            self.object
+                .complete_multipart(&blob, &upload.multipart_id, &parts)
+                .await?;
+        }
+
+        let (object, version) = upload.records()?;
+        // Whatever fails, nothing is left of the blob
+        let recorded = match CONFIG.object_dedup {
+            true => match self.hash_blob(&version).await {
+                Ok(sha256) => {
+                    self.upload_shared(&object, version, &sha256, UploadSource::Blob)
+                        .await
+                }
+                Err(e) => {
+                    self.delete_blobs(vec![upload.blob_key.clone()]).await;
+                    Err(e)
+                }
             },
-            Err(e) => Err(e.into()),
+            false => {
+                let recorded = self.record_upload(&object, &version).await;
+                if recorded.is_err() {
+                    self.delete_blobs(vec![upload.blob_key.clone()]).await;
+                }
+                recorded
+            }
+        };
+        if let Err(e) = recorded {
+            // The multipart upload is spent, so the client has to start over
+            self.redis.delete_tus_upload(&upload.id).await?;
+            return Err(e);
+        }
+        self.redis.delete_tus_upload(&upload.id).await
+    }
+
+    /// Picks up sealing a resumable upload after the parts sent so far, each a whole
+    /// number of chunks.
+    fn upload_encryptor(&self, upload: &TusUpload) -> Result<Option<BlobEncryptor>> {
+        let key = self
+            .object
+            .data_key(upload.key_id.as_deref(), upload.wrapped_key.as_deref())?;
+        let (Some(key), Some(prefix)) = (key, upload.nonce_prefix.as_deref()) else {
+            return Ok(None);
+        };
+        let prefix: [u8; PREFIX_SIZE] = hex::decode(prefix)
+            .ok()
+            .and_then(|prefix| prefix.try_into().ok())
+            .ok_or_else(|| Error::EncryptionError("Invalid nonce prefix".into()))?;
+        let counter = upload.parts.len() * (PART_SIZE / CHUNK_SIZE);
+        Ok(Some(BlobEncryptor::resume(&key, prefix, counter as u32)))
+    }
+
+    /// Drops a resumable upload and the parts sent so far.
+    pub async fn terminate_upload(&self, upload: &TusUpload) -> Result<()> {
+        let blob = Path::from(upload.blob_key.as_str());
+        if let Err(e) = self
+            .object
+            .abort_multipart(&blob, &upload.multipart_id)
+            .await
+        {
+            // Already gone from the store; the state still has to go
+            tracing::warn!("Failed to abort upload {}: {}", upload.id, e);
+        }
+        self.redis.delete_tus_upload(&upload.id).await
+    }
+
+    /// Terminates resumable uploads that expired before completing, returning how many
+    /// were.
+    pub async fn sweep_expired_uploads(&self) -> Result<usize> {
+        let expired = self
+            .redis
+            .expired_tus_uploads(Utc::now().timestamp())
+            .await?;
+        for id in &expired {
+            match self.redis.get_tus_upload(id).await? {
+                Some(upload) => self.terminate_upload(&upload).await?,
+                None => self.redis.delete_tus_upload(id).await?,
+            }
         }
+        Ok(expired.len())
     }
 
-    pub async fn get_file(&self, path: &Path) -> Result<(Objects, Bytes)> {
+    /// Fetches the current version of the object at `path`, or a specific `version` of it.
+    pub async fn get_file(
+        &self,
+        path: &Path,
+        version: Option<i32>,
+    ) -> Result<(Objects, ObjectVersion, Bytes)> {
         let metadata = match self.postgres.get_object(path).await {
             Ok(obj) => obj,
             Err(e) => return Err(format!("Error getting metadata: {}", e).into()),
         };
-        let file = self.object.get(path).await?;
+        let version = self
+            .postgres
+            .get_object_version(metadata.id, version.unwrap_or(metadata.version))
+            .await?;
+        version.ensure_servable()?;
+        let key = self.version_key(&version)?;
+        let file = self
+            .store(&version.storage_class)?
+            .get_sealed(&Path::from(version.blob_key.as_str()), key.as_ref())
+            .await?;
 
-        return Ok((metadata, file));
+        Ok((metadata, version, file))
     }
 
     pub async fn delete_file(&self, path: &Path) -> Result<()> {
-        match self.postgres.delete_object(&path.to_string()).await {
-            Ok(()) => match self.object.delete(path).await {
-                Ok(_) => Ok(()),
-                Err(e) => Err(e.into()),
-            },
-            Err(e) => Err(e.into()),
+        let blob_keys = self.postgres.delete_object(path.as_ref()).await?;
+        self.delete_blobs(blob_keys).await;
+        Ok(())
+    }
+
+    pub async fn list_versions(&self, path: &Path) -> Result<Vec<ObjectVersion>> {
+        let object = self.postgres.get_object(path).await?;
+        self.postgres.list_object_versions(object.id).await
+    }
+
+    /// Restores an old version by copying its blob into a new current version, so every
+    /// version stays immutable and can be pruned on its own. Returns the new version.
+    pub async fn restore_version(&self, path: &Path, version: i32) -> Result<i32> {
+        let object = self.postgres.get_object(path).await?;
+        let source = self.postgres.get_object_version(object.id, version).await?;
+
+        let current = self
+            .postgres
+            .get_object_version(object.id, object.version)
+            .await?;
+
+        let (restored, pruned) = if ObjectVersion::is_shared_blob(&source.blob_key) {
+            // Shared content is referenced once more rather than copied
+            self.lock_blob(&source.blob_key).await?;
+            let restored = self
+                .postgres
+                .restore_object_version(
+                    object.id,
+                    &source,
+                    &source.blob_key,
+                    CONFIG.object_max_versions,
+                )
+                .await;
+            self.unlock_blob(&source.blob_key).await;
+            restored?
+        } else {
+            let blob_key = ObjectVersion::new_blob_key(object.user_id);
+            self.store(&source.storage_class)?
+                .copy(
+                    &Path::from(source.blob_key.as_str()),
+                    &Path::from(blob_key.as_str()),
+                )
+                .await?;
+
+            match self
+                .postgres
+                .restore_object_version(object.id, &source, &blob_key, CONFIG.object_max_versions)
+                .await
+            {
+                Ok(result) => result,
+                Err(e) => {
+                    self.delete_blobs(vec![blob_key]).await;
+                    return Err(e);
+                }
+            }
+        };
+        self.invalidate_transforms(&current.blob_key).await;
+        self.delete_blobs(pruned).await;
+
+        Ok(restored)
+    }
+
+    /// Resolves a user's objects at `keys` and under `prefix` into archive entries named
+    /// by their path, each opened only once the archive reaches it. Every object has to
+    /// be the user's and servable, and the archive has to fit the ZIP limits.
+    pub async fn archive_entries(
+        &self,
+        user_id: Uuid,
+        keys: &[String],
+        prefix: Option<&str>,
+    ) -> Result<Vec<ZipEntry>> {
+        let too_large =
+            |message: &str| Error::HttpError(StatusCode::PAYLOAD_TOO_LARGE, message.into());
+
+        let found = self
+            .postgres
+            .list_current_versions(user_id, keys, prefix, zip::MAX_ENTRIES as i64 + 1)
+            .await?;
+        if found.len() > zip::MAX_ENTRIES {
+            return Err(too_large("Too many files for one archive"));
+        }
+        if let Some(missing) = keys
+            .iter()
+            .find(|key| !found.iter().any(|f| &f.key == *key))
+        {
+            let root = Objects::root(user_id);
+            return Err(Error::HttpError(
+                StatusCode::NOT_FOUND,
+                format!(
+                    "'{}' not found",
+                    missing.strip_prefix(root.as_str()).unwrap_or(missing)
+                ),
+            ));
         }
+
+        let root = Objects::root(user_id);
+        let mut entries = Vec::with_capacity(found.len());
+        for CurrentVersion {
+            key,
+            user_id: owner,
+            version,
+        } in found
+        {
+            let name = key.strip_prefix(root.as_str()).unwrap_or(&key).to_string();
+            if owner != user_id {
+                return Err(Error::HttpError(
+                    StatusCode::FORBIDDEN,
+                    format!("'{}' is not yours", name),
+                ));
+            }
+            if let Err(Error::HttpError(status, message)) = version.ensure_servable() {
+                return Err(Error::HttpError(status, format!("'{}': {}", name, message)));
+            }
+
+            let store = self.store(&version.storage_class)?.clone();
+            let data_key = self.version_key(&version)?;
+            let blob = Path::from(version.blob_key.as_str());
+            entries.push((
+                version.size_bytes as u64,
+                ZipEntry {
+                    name,
+                    modified: version.created_at.unwrap_or_else(Utc::now),
+                    source: Box::pin(async move { store.get_sealed_stream(&blob, data_key).await }),
+                },
+            ));
+        }
+
+        let size = zip::archive_size(entries.iter().map(|(size, e)| (e.name.len(), *size)));
+        if size > zip::MAX_ARCHIVE_BYTES {
+            return Err(too_large("Files are too large for one archive"));
+        }
+        Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    pub async fn set_expiry(&self, path: &Path, expires_at: Option<DateTime<Utc>>) -> Result<()> {
+        let object = self.postgres.get_object(path).await?;
+        self.postgres.set_object_expiry(object.id, expires_at).await
+    }
+
+    pub async fn set_max_versions(&self, path: &Path, max_versions: Option<i32>) -> Result<()> {
+        let object = self.postgres.get_object(path).await?;
+        let pruned = self
+            .postgres
+            .set_object_max_versions(object.id, max_versions, CONFIG.object_max_versions)
+            .await?;
+        self.delete_blobs(pruned).await;
+        Ok(())
+    }
+
+    /// Current usage for the user and their organization, with the bytes still allowed
+    /// under the tighter of the two quotas (grace margin included).
+    pub async fn usage(&self, user_id: Uuid) -> Result<StorageUsage> {
+        let configured = |bytes: i64| (bytes > 0).then_some(bytes);
+
+        let mut usage = self
+            .postgres
+            .get_storage_usage(
+                user_id,
+                configured(CONFIG.storage_quota_bytes),
+                configured(CONFIG.organization_quota_bytes),
+            )
+            .await?;
+
+        let grace = CONFIG.quota_grace_percent;
+        usage.remaining_bytes = [
+            usage.user.remaining(grace),
+            usage
+                .organization
+                .as_ref()
+                .and_then(|org| org.remaining(grace)),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+
+        Ok(usage)
+    }
+
+    /// Serves a transformed copy of the current version of an image object, rendering
+    /// and caching it in the store on first request.
+    pub async fn transform_image(
+        &self,
+        path: &Path,
+        transform: &ImageTransform,
+    ) -> Result<(Bytes, ImageFormat)> {
+        let object = self.postgres.get_object(path).await?;
+        let version = self
+            .postgres
+            .get_object_version(object.id, object.version)
+            .await?;
+        version.ensure_servable()?;
+        if !version.content_type.starts_with("image/") {
+            return Err(Error::HttpError(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("'{}' is not an image", object.filename),
+            ));
+        }
+
+        let format = transform
+            .format
+            .or_else(|| ImageFormat::from_content_type(&version.content_type))
+            .unwrap_or(ImageFormat::Png);
+
+        // Renders are sealed under the source's data key, each with a fresh nonce prefix
+        let key = self.version_key(&version)?;
+        let cached = Path::from(transform.cache_key(&version.blob_key, format));
+        match self.object.get_sealed(&cached, key.as_ref()).await {
+            Ok(bytes) => return Ok((bytes, format)),
+            Err(Error::ObjectError(object_store::Error::NotFound { .. })) => {}
+            Err(e) => return Err(e),
+        }
+
+        let source = self
+            .store(&version.storage_class)?
+            .get_sealed(&Path::from(version.blob_key.as_str()), key.as_ref())
+            .await?;
+        let rendered = {
+            let transform = transform.clone();
+            tokio::task::spawn_blocking(move || transform.apply(&source, format))
+                .await
+                .map_err(|e| Error::from(format!("Image transform failed: {}", e)))??
+        };
+        let rendered = Bytes::from(rendered);
+        self.object
+            .put_sealed(&cached, &rendered, key.as_ref())
+            .await?;
+
+        Ok((rendered, format))
+    }
+
+    fn version_key(&self, version: &ObjectVersion) -> Result<Option<DataKey>> {
+        self.object
+            .data_key(version.key_id.as_deref(), version.wrapped_key.as_deref())
+    }
+
+    /// Streams a version's bytes from whichever store holds its blob.
+    pub async fn open_version(
+        &self,
+        version: &ObjectVersion,
+    ) -> Result<BoxStream<'static, Result<Bytes>>> {
+        let key = self.version_key(version)?;
+        self.store(&version.storage_class)?
+            .get_sealed_stream(&Path::from(version.blob_key.as_str()), key)
+            .await
+    }
+
+    /// Rewraps every stored data key still wrapped by an older master key with the
+    /// current one, returning how many were rewrapped. Blobs are left as they are.
+    /// Resumable uploads in flight keep their old wrapping until recorded, so retired
+    /// keys should stay configured until those expire and a later rotation runs.
+    pub async fn rotate_keys(&self) -> Result<u64> {
+        const BATCH: i64 = 500;
+
+        let current = self.object.current_key_id()?.to_string();
+        let mut rotated = 0;
+        loop {
+            let stale = self
+                .postgres
+                .list_stale_wrapped_keys(&current, BATCH)
+                .await?;
+            if stale.is_empty() {
+                return Ok(rotated);
+            }
+            for (id, key_id, wrapped_key) in stale {
+                if let Some((key_id, wrapped_key)) = self.object.rewrap(&key_id, &wrapped_key)? {
+                    self.postgres
+                        .set_wrapped_key(id, &key_id, &wrapped_key)
+                        .await?;
+                    rotated += 1;
+                }
+            }
+        }
+    }
+
+    /// Drops the cached transforms of a blob once it is no longer current.
+    async fn invalidate_transforms(&self, blob_key: &str) {
+        let prefix = ImageTransform::cache_prefix(blob_key);
+        if let Err(e) = self.object.delete_prefix(&prefix).await {
+            tracing::warn!("Failed to delete transforms under {}: {}", prefix, e);
+        }
+    }
+
+    /// Deletes blobs no row points at anymore. Failures are logged and left for the
+    /// reconciler, as the rows are already gone and can't be put back.
+    async fn delete_blobs(&self, mut blob_keys: Vec<String>) {
+        // Versions of the same content share a blob, which is released once
+        blob_keys.sort();
+        blob_keys.dedup();
+        for key in blob_keys {
+            if ObjectVersion::is_shared_blob(&key) {
+                if let Err(e) = self.release_shared_blob(&key).await {
+                    tracing::warn!("Failed to release blob {}: {}", key, e);
+                }
+                continue;
+            }
+            if let Err(e) = self.delete_stored(&key).await {
+                tracing::warn!("Failed to delete blob {}: {}", key, e);
+            }
+            self.invalidate_transforms(&key).await;
+        }
+    }
+
+    /// Deletes a blob from whichever store holds it.
+    async fn delete_stored(&self, blob_key: &str) -> Result<()> {
+        let path = Path::from(blob_key);
+        for store in std::iter::once(&self.object).chain(&self.archive) {
+            match store.delete(&path).await {
+                Ok(()) | Err(Error::ObjectError(object_store::Error::NotFound { .. })) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
     }
 
-    pub async fn list_files(&self, user_id: Uuid) -> Result<Vec<Objects>> {
-        self.postgres.list_user_objects(user_id).await
+    /// The store holding blobs of a storage class.
+    fn store(&self, class: &str) -> Result<&ObjectClient> {
+        match StorageClass::parse(class)? {
+            StorageClass::Standard => Ok(&self.object),
+            StorageClass::Archive => self.archive(),
+        }
+    }
+
+    fn archive(&self) -> Result<&ObjectClient> {
+        self.archive
+            .as_ref()
+            .ok_or_else(|| Error::from("No archive store is configured"))
+    }
+
+    /// Applies expiry and lifecycle rules: deletes expired objects, then moves a batch of
+    /// blobs past their transition age to the archive store. Failed transitions are
+    /// retried on the next sweep.
+    pub async fn sweep_lifecycle(&self) -> Result<LifecycleReport> {
+        const BATCH: i64 = 500;
+
+        let mut report = LifecycleReport::default();
+        loop {
+            let deleted = self.postgres.delete_expired_objects(BATCH).await?;
+            let (mut object_ids, blob_keys): (Vec<i32>, Vec<String>) = deleted.into_iter().unzip();
+            object_ids.sort();
+            object_ids.dedup();
+            report.expired_objects += object_ids.len() as u64;
+            self.delete_blobs(blob_keys).await;
+
+            if (object_ids.len() as i64) < BATCH {
+                break;
+            }
+        }
+
+        if self.archive.is_some() {
+            for blob_key in self.postgres.list_transition_candidates(BATCH).await? {
+                match self.transition_blob(&blob_key).await {
+                    Ok(true) => report.transitioned_blobs += 1,
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!("Failed to archive blob {}: {}", blob_key, e),
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Copies a blob to the archive store, points its versions there and drops the
+    /// standard copy. Returns false when its versions were deleted in the meantime.
+    async fn transition_blob(&self, blob_key: &str) -> Result<bool> {
+        let shared = ObjectVersion::is_shared_blob(blob_key);
+        if shared {
+            self.lock_blob(blob_key).await?;
+        }
+
+        let result = async {
+            let archive = self.archive()?;
+            let path = Path::from(blob_key);
+            self.object.transfer(&path, archive).await?;
+
+            let moved = self
+                .postgres
+                .set_storage_class(blob_key, StorageClass::Standard, StorageClass::Archive)
+                .await?;
+            match moved {
+                0 => archive.delete(&path).await?,
+                _ => self.object.delete(&path).await?,
+            }
+            Ok(moved > 0)
+        }
+        .await;
+
+        if shared {
+            self.unlock_blob(blob_key).await;
+        }
+        result
+    }
+
+    /// Compares the store with the version rows pointing into it, reporting blobs no
+    /// row points at and rows whose blob is gone, and with `repair` deleting both.
+    /// Only the standard store is checked; archived blobs are left alone.
+    pub async fn reconcile(&self, repair: bool) -> Result<ReconcileReport> {
+        // Rows are read before listing, so an upload landing in between can only look
+        // like an orphan, which the grace period rules out
+        let versions = self.postgres.list_version_blobs().await?;
+        let referenced: HashSet<String> = versions.iter().map(|v| v.blob_key.clone()).collect();
+        let cutoff = Utc::now() - Duration::minutes(CONFIG.reconcile_grace_minutes);
+
+        let mut report = ReconcileReport::default();
+        let mut stored = HashSet::new();
+        let mut listing = self.object.client.list(None);
+        while let Some(meta) = listing.next().await {
+            let meta = meta?;
+            let key = meta.location.to_string();
+
+            // Only blobs and their transforms are ours to delete
+            let source = match key.strip_prefix("transforms/") {
+                Some(rest) => rest.rsplit_once('/').map(|(blob, _)| blob.to_string()),
+                None => key.starts_with("blobs/").then(|| key.clone()),
+            };
+            if let Some(source) = source
+                && !referenced.contains(&source)
+                && meta.last_modified < cutoff
+            {
+                report.orphaned_blobs.push(key.clone());
+            }
+            stored.insert(key);
+        }
+
+        report.dangling_versions = versions
+            .into_iter()
+            .filter(|v| !stored.contains(&v.blob_key))
+            .collect();
+
+        if repair {
+            for key in &report.orphaned_blobs {
+                // An upload may be taking a reference to a shared blob right now
+                match ObjectVersion::is_shared_blob(key) {
+                    true => self.release_shared_blob(key).await?,
+                    false => self.object.delete(&Path::from(key.as_str())).await?,
+                }
+            }
+            let ids: Vec<i32> = report.dangling_versions.iter().map(|v| v.id).collect();
+            if !ids.is_empty() {
+                self.postgres.delete_dangling_versions(&ids).await?;
+            }
+            report.repaired = true;
+        }
+
+        Ok(report)
+    }
+
+    pub async fn list_files(
+        &self,
+        user_id: Uuid,
+        prefix: &str,
+        delimiter: Option<&str>,
+        cursor: Option<&str>,
+        limit: i64,
+    ) -> Result<ObjectList> {
+        let prefix = Objects::key_for(user_id, prefix);
+        self.postgres
+            .list_objects(user_id, &prefix, delimiter, cursor, limit)
+            .await
+    }
+
+    pub async fn create_folder(&self, user_id: Uuid, path: &str) -> Result<()> {
+        self.postgres.create_folder(user_id, path).await
+    }
+
+    /// Moves a folder with everything in it. Blobs are addressed by version rather than
+    /// by logical key, so this only re-keys the rows.
+    pub async fn move_folder(&self, user_id: Uuid, from: &str, to: &str) -> Result<()> {
+        if to == from || to.starts_with(&format!("{}/", from)) {
+            return Err(Error::from("Cannot move a folder into itself"));
+        }
+
+        let from_prefix = Objects::key_for(user_id, &format!("{}/", from));
+        let to_prefix = Objects::key_for(user_id, &format!("{}/", to));
+
+        let mut tx = self.postgres.start_transaction().await?;
+        let folders = self
+            .postgres
+            .move_folders(&mut tx, user_id, from, to)
+            .await?;
+        let moved = self
+            .postgres
+            .move_objects(&mut tx, user_id, &from_prefix, &to_prefix)
+            .await?;
+
+        if folders == 0 && moved == 0 {
+            tx.rollback().await?;
+            return Err(Error::from(format!("Folder '{}' not found", from)));
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Deletes a folder with everything in it. Rows go first, so a failing store
+    /// leaves orphaned blobs rather than rows pointing at missing files.
+    pub async fn delete_folder(&self, user_id: Uuid, path: &str) -> Result<()> {
+        let prefix = Objects::key_for(user_id, &format!("{}/", path));
+
+        let mut tx = self.postgres.start_transaction().await?;
+        self.postgres.delete_folders(&mut tx, user_id, path).await?;
+        let blob_keys = self
+            .postgres
+            .delete_objects_with_prefix(&mut tx, user_id, &prefix)
+            .await?;
+        tx.commit().await?;
+
+        self.delete_blobs(blob_keys).await;
+        Ok(())
+    }
+
+    /// Deletes a user with everything they stored, their exports, their uploads in progress
+    /// and the keys they left in Redis. As with folders, rows go first and a failing store
+    /// leaves orphaned blobs behind.
+    pub async fn delete_user(&self, user_id: Uuid) -> Result<()> {
+        let mut tx = self.postgres.start_transaction().await?;
+        let blob_keys = self
+            .postgres
+            .delete_objects_with_prefix(&mut tx, user_id, &Objects::root(user_id))
+            .await?;
+        UserQueries::delete(&mut tx, user_id).await?;
+        tx.commit().await?;
+
+        self.delete_blobs(blob_keys).await;
+        if let Err(e) = self
+            .object
+            .delete_prefix(&DataExport::prefix(user_id))
+            .await
+        {
+            tracing::warn!("Failed to delete exports of user {}: {}", user_id, e);
+        }
+        for upload in self.redis.user_tus_uploads(user_id).await? {
+            self.terminate_upload(&upload).await?;
+        }
+        self.redis.delete_user_keys(user_id).await
     }
 }
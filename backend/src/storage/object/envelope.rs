@@ -0,0 +1,352 @@
+//! Envelope encryption for blobs. Every object gets a random data key, which is stored
+//! wrapped by a master key so rotating the master key only rewrites the wrapped keys.
+//!
+//! A sealed blob is a header (format byte and a random 7-byte nonce prefix) followed
+//! by 64 KiB chunks, each sealed with ChaCha20-Poly1305 under a nonce of the prefix,
+//! the chunk counter and a flag marking the final chunk, so chunks can't be reordered
+//! or the blob truncated without decryption failing.
+
+use crate::error::{Error, Result};
+use axum::body::Bytes;
+use base64::{engine::general_purpose, Engine as _};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use futures::{Stream, StreamExt};
+use rand::RngCore;
+use std::collections::HashMap;
+
+/// Plaintext bytes per sealed chunk.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+pub const PREFIX_SIZE: usize = 7;
+pub const HEADER_SIZE: usize = 1 + PREFIX_SIZE;
+const FORMAT_VERSION: u8 = 1;
+
+/// A per-object key. Never stored as is, only wrapped by a master key.
+#[derive(Clone)]
+pub struct DataKey(Key);
+
+impl DataKey {
+    pub fn generate() -> Self {
+        DataKey(ChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+}
+
+/// Master keys from config by id; new data keys are wrapped with the current one.
+#[derive(Clone)]
+pub struct MasterKeys {
+    current: String,
+    keys: HashMap<String, Key>,
+}
+
+impl std::fmt::Debug for MasterKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MasterKeys")
+            .field("current", &self.current)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MasterKeys {
+    /// Parses `id:base64key` pairs separated by commas, the first being current.
+    /// Returns None when no keys are configured.
+    pub fn parse(spec: &str) -> Result<Option<Self>> {
+        let mut current = None;
+        let mut keys = HashMap::new();
+
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (id, encoded) = entry
+                .split_once(':')
+                .ok_or_else(|| Error::from("Master keys must be given as id:base64key"))?;
+            let key = general_purpose::STANDARD.decode(encoded)?;
+            if key.len() != 32 {
+                return Err(Error::from(format!("Master key '{}' must be 32 bytes", id)));
+            }
+
+            current.get_or_insert_with(|| id.to_string());
+            keys.insert(id.to_string(), *Key::from_slice(&key));
+        }
+
+        Ok(current.map(|current| MasterKeys { current, keys }))
+    }
+
+    pub fn current_id(&self) -> &str {
+        &self.current
+    }
+
+    /// Wraps `key` with the current master key, returning its id and the wrapped key.
+    pub fn wrap(&self, key: &DataKey) -> Result<(String, String)> {
+        let cipher = ChaCha20Poly1305::new(&self.keys[&self.current]);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        // The key id is authenticated so a wrapped key can't be passed off under another id
+        let mut wrapped = cipher.encrypt(
+            &nonce,
+            Payload {
+                msg: key.0.as_slice(),
+                aad: self.current.as_bytes(),
+            },
+        )?;
+        wrapped.splice(..0, nonce.iter().copied());
+
+        Ok((
+            self.current.clone(),
+            general_purpose::STANDARD.encode(wrapped),
+        ))
+    }
+
+    pub fn unwrap(&self, key_id: &str, wrapped: &str) -> Result<DataKey> {
+        let master = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| Error::EncryptionError(format!("Unknown master key '{}'", key_id)))?;
+        let cipher = ChaCha20Poly1305::new(master);
+
+        let wrapped = general_purpose::STANDARD.decode(wrapped)?;
+        if wrapped.len() < 12 {
+            return Err(Error::EncryptionError("Wrapped key is too short".into()));
+        }
+        let (nonce, sealed) = wrapped.split_at(12);
+        let key = cipher.decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: sealed,
+                aad: key_id.as_bytes(),
+            },
+        )?;
+
+        Ok(DataKey(*Key::from_slice(&key)))
+    }
+}
+
+/// Seals a blob chunk by chunk. Position is explicit so a blob written in parts across
+/// requests (resumable uploads) can pick up where the last part ended.
+pub struct BlobEncryptor {
+    cipher: ChaCha20Poly1305,
+    prefix: [u8; PREFIX_SIZE],
+    counter: u32,
+}
+
+impl BlobEncryptor {
+    pub fn new(key: &DataKey) -> Self {
+        let mut prefix = [0u8; PREFIX_SIZE];
+        rand::rng().fill_bytes(&mut prefix);
+        Self::resume(key, prefix, 0)
+    }
+
+    pub fn resume(key: &DataKey, prefix: [u8; PREFIX_SIZE], counter: u32) -> Self {
+        BlobEncryptor {
+            cipher: ChaCha20Poly1305::new(&key.0),
+            prefix,
+            counter,
+        }
+    }
+
+    pub fn prefix(&self) -> [u8; PREFIX_SIZE] {
+        self.prefix
+    }
+
+    /// Bytes the sealed blob starts with.
+    pub fn header(&self) -> [u8; HEADER_SIZE] {
+        let mut header = [0u8; HEADER_SIZE];
+        header[0] = FORMAT_VERSION;
+        header[1..].copy_from_slice(&self.prefix);
+        header
+    }
+
+    /// Seals `plaintext` as consecutive chunks. Unless `last`, it must be a whole number
+    /// of chunks; with `last` the final (possibly short or empty) chunk ends the blob.
+    pub fn seal(&mut self, plaintext: &[u8], last: bool) -> Result<Vec<u8>> {
+        if !last && !plaintext.len().is_multiple_of(CHUNK_SIZE) {
+            return Err(Error::EncryptionError(
+                "Only the last chunk may be partial".into(),
+            ));
+        }
+
+        let mut chunks: Vec<&[u8]> = plaintext.chunks(CHUNK_SIZE).collect();
+        if last && chunks.is_empty() {
+            chunks.push(&[]);
+        }
+
+        let count = chunks.len();
+        let mut sealed = Vec::with_capacity(plaintext.len() + count * TAG_SIZE);
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let nonce = chunk_nonce(&self.prefix, self.counter, last && i + 1 == count);
+            sealed.extend_from_slice(&self.cipher.encrypt(Nonce::from_slice(&nonce), chunk)?);
+            self.counter += 1;
+        }
+
+        Ok(sealed)
+    }
+}
+
+fn chunk_nonce(prefix: &[u8; PREFIX_SIZE], counter: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[PREFIX_SIZE..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+/// Seals a whole blob held in memory.
+pub fn encrypt(key: &DataKey, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut encryptor = BlobEncryptor::new(key);
+    let mut sealed = encryptor.header().to_vec();
+    sealed.extend_from_slice(&encryptor.seal(plaintext, true)?);
+    Ok(sealed)
+}
+
+/// Opens a sealed blob as it streams in, holding one chunk at a time.
+pub fn decrypt_stream<S>(key: DataKey, stream: S) -> impl Stream<Item = Result<Bytes>> + Send
+where
+    S: Stream<Item = Result<Bytes>> + Send + Unpin + 'static,
+{
+    struct State<S> {
+        inner: S,
+        buffer: Vec<u8>,
+        cipher: ChaCha20Poly1305,
+        prefix: Option<[u8; PREFIX_SIZE]>,
+        counter: u32,
+        eof: bool,
+        done: bool,
+    }
+
+    let state = State {
+        inner: stream,
+        buffer: Vec::new(),
+        cipher: ChaCha20Poly1305::new(&key.0),
+        prefix: None,
+        counter: 0,
+        eof: false,
+        done: false,
+    };
+
+    futures::stream::try_unfold(state, |mut state| async move {
+        if state.done {
+            return Ok(None);
+        }
+
+        // A chunk is only known not to be the last once a byte past it has arrived
+        let wanted = match state.prefix {
+            None => HEADER_SIZE,
+            Some(_) => CHUNK_SIZE + TAG_SIZE + 1,
+        };
+        while state.buffer.len() < wanted && !state.eof {
+            match state.inner.next().await {
+                Some(chunk) => state.buffer.extend_from_slice(&chunk?),
+                None => state.eof = true,
+            }
+        }
+
+        let prefix = match state.prefix {
+            Some(prefix) => prefix,
+            None => {
+                if state.buffer.len() < HEADER_SIZE || state.buffer[0] != FORMAT_VERSION {
+                    return Err(Error::EncryptionError(
+                        "Invalid encrypted blob header".into(),
+                    ));
+                }
+                let prefix: [u8; PREFIX_SIZE] = state.buffer[1..HEADER_SIZE].try_into().unwrap();
+                state.buffer.drain(..HEADER_SIZE);
+                state.prefix = Some(prefix);
+                return Ok(Some((Bytes::new(), state)));
+            }
+        };
+
+        let last = state.eof && state.buffer.len() <= CHUNK_SIZE + TAG_SIZE;
+        let take = if last {
+            state.buffer.len()
+        } else {
+            CHUNK_SIZE + TAG_SIZE
+        };
+        let sealed: Vec<u8> = state.buffer.drain(..take).collect();
+
+        let nonce = chunk_nonce(&prefix, state.counter, last);
+        let plaintext = state
+            .cipher
+            .decrypt(Nonce::from_slice(&nonce), sealed.as_slice())?;
+        state.counter += 1;
+        state.done = last;
+
+        Ok(Some((Bytes::from(plaintext), state)))
+    })
+    .filter(|chunk| futures::future::ready(!matches!(chunk, Ok(c) if c.is_empty())))
+}
+
+/// Opens a whole sealed blob held in memory.
+pub async fn decrypt(key: &DataKey, sealed: Bytes) -> Result<Bytes> {
+    let stream = futures::stream::iter([Ok(sealed)]);
+    let mut plaintext = Vec::new();
+
+    let mut chunks = Box::pin(decrypt_stream(key.clone(), stream));
+    while let Some(chunk) = chunks.next().await {
+        plaintext.extend_from_slice(&chunk?);
+    }
+
+    Ok(Bytes::from(plaintext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn master_keys() -> MasterKeys {
+        let old = general_purpose::STANDARD.encode([1u8; 32]);
+        let new = general_purpose::STANDARD.encode([2u8; 32]);
+        MasterKeys::parse(&format!("new:{},old:{}", new, old))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let key = DataKey::generate();
+
+        for size in [0, 1, CHUNK_SIZE, CHUNK_SIZE + 1, 3 * CHUNK_SIZE] {
+            let plaintext: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let sealed = encrypt(&key, &plaintext).unwrap();
+            let opened = decrypt(&key, Bytes::from(sealed)).await.unwrap();
+            assert_eq!(opened.as_ref(), plaintext.as_slice(), "size {}", size);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sealed_in_parts() {
+        let key = DataKey::generate();
+        let plaintext: Vec<u8> = (0..2 * CHUNK_SIZE + 10).map(|i| i as u8).collect();
+
+        let mut first = BlobEncryptor::new(&key);
+        let mut sealed = first.header().to_vec();
+        sealed.extend(first.seal(&plaintext[..CHUNK_SIZE], false).unwrap());
+
+        let mut resumed = BlobEncryptor::resume(&key, first.prefix(), 1);
+        sealed.extend(resumed.seal(&plaintext[CHUNK_SIZE..], true).unwrap());
+
+        let opened = decrypt(&key, Bytes::from(sealed)).await.unwrap();
+        assert_eq!(opened.as_ref(), plaintext.as_slice());
+    }
+
+    #[tokio::test]
+    async fn test_truncation_detected() {
+        let key = DataKey::generate();
+        let plaintext = vec![7u8; 2 * CHUNK_SIZE + 10];
+        let sealed = encrypt(&key, &plaintext).unwrap();
+
+        // Dropping the final chunk leaves a full chunk that was never marked last
+        let truncated = Bytes::from(sealed[..HEADER_SIZE + 2 * (CHUNK_SIZE + TAG_SIZE)].to_vec());
+        assert!(decrypt(&key, truncated).await.is_err());
+    }
+
+    #[test]
+    fn test_rewrap() {
+        let keys = master_keys();
+        let key = DataKey::generate();
+
+        let (id, wrapped) = keys.wrap(&key).unwrap();
+        assert_eq!(id, "new");
+        assert_eq!(keys.unwrap(&id, &wrapped).unwrap().0, key.0);
+
+        // Bound to its key id
+        assert!(keys.unwrap("old", &wrapped).is_err());
+    }
+}
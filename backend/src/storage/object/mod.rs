@@ -2,19 +2,56 @@ use crate::config::CONFIG;
 use crate::error::Error;
 use crate::error::Result;
 use axum::body::Bytes;
+use futures::stream::BoxStream;
 use futures::Stream;
 use futures::StreamExt;
 use object_store::aws::AmazonS3Builder;
+use object_store::azure::MicrosoftAzureBuilder;
 use object_store::gcp::GoogleCloudStorageBuilder;
 use object_store::local::LocalFileSystem;
+use object_store::memory::InMemory;
+use object_store::multipart::{MultipartStore, PartId};
 use object_store::path::Path;
 use object_store::ObjectStore;
 use object_store::WriteMultipart;
+use std::fmt;
 use std::sync::Arc;
 use url::Url;
 
+pub mod envelope;
+
+use envelope::{DataKey, MasterKeys};
+
+/// Resumable upload parts are sent once this many bytes are buffered, the minimum most
+/// stores accept for every part but the last. Sealed parts must end on a chunk boundary.
+pub const PART_SIZE: usize = 5 * 1024 * 1024;
+const _: () = assert!(PART_SIZE.is_multiple_of(envelope::CHUNK_SIZE));
+
+/// Parts in flight at once when streaming a blob between stores.
+const MAX_TRANSFER_PARTS: usize = 8;
+
 pub fn get_object_store(url: &str) -> Result<Arc<dyn ObjectStore>> {
+    Ok(build_stores(url)?.0)
+}
+
+/// A store with its multipart API, when it has one.
+type Stores = (Arc<dyn ObjectStore>, Option<Arc<dyn MultipartStore>>);
+
+/// Builds the store for `url`, along with its low-level multipart API when it has one.
+/// The local filesystem has none, so resumable uploads need a cloud, S3-compatible or
+/// in-memory store. The bucket or container comes from the URL (`s3://host/bucket`,
+/// `gcs://bucket`, `az://container`) when it names one, so an archive store can use
+/// another bucket, and from the config otherwise. Credentials come from the config.
+fn build_stores(url: &str) -> Result<Stores> {
     let parsed = Url::parse(url)?;
+    let bucket = |name: Option<&str>| match name.filter(|n| !n.is_empty()) {
+        Some(name) => Ok(name.to_string()),
+        None if !CONFIG.bucket.is_empty() => Ok(CONFIG.bucket.clone()),
+        None => Err(Error::from(format!(
+            "No bucket in {} or OBJECT_BUCKET",
+            url
+        ))),
+    };
 
     match parsed.scheme() {
         // Local filesystem: file:///path/to/data
@@ -23,69 +60,214 @@ pub fn get_object_store(url: &str) -> Result<Arc<dyn ObjectStore>> {
                 .to_file_path()
                 .map_err(|_| Error::from("Invalid file path"))?;
             let store = LocalFileSystem::new_with_prefix(path)?;
-            Ok(Arc::new(store))
+            Ok((Arc::new(store), None))
         }
 
-        // AWS S3 or S3-compatible (including MinIO)
-        "s3" => {
-            let endpoint = match parsed.host_str() {
-                Some(host) => {
-                    if host.contains("amazonaws.com") {
-                        // AWS S3 → HTTPS
-                        Some(format!("https://{}", host))
-                    } else {
-                        // MinIO or custom → HTTP
-                        Some(format!("http://{}:{}", host, parsed.port().unwrap_or(9000)))
-                    }
-                }
-                None => None,
-            };
+        // Process memory, lost on restart: memory://
+        "memory" => {
+            let store = Arc::new(InMemory::new());
+            Ok((store.clone(), Some(store)))
+        }
 
+        // AWS S3 or S3-compatible (including MinIO): s3://host[:port]/bucket
+        "s3" => {
+            let endpoint = s3_endpoint(&parsed, &CONFIG.s3_endpoint);
             let mut builder = AmazonS3Builder::new()
-                .with_bucket_name(&CONFIG.bucket)
+                .with_bucket_name(bucket(Some(parsed.path().trim_matches('/')))?)
                 .with_region(&CONFIG.aws_region)
                 .with_access_key_id(&CONFIG.aws_access)
                 .with_secret_access_key(&CONFIG.aws_secret);
 
-            if let Some(ep) = endpoint {
-                builder = builder.with_endpoint(&ep);
-                if !ep.starts_with("https://") {
-                    builder = builder.with_allow_http(true); // MinIO / local dev
-                }
+            if let Some(endpoint) = endpoint {
+                builder = builder
+                    .with_allow_http(endpoint.starts_with("http://"))
+                    .with_endpoint(endpoint);
+            }
+
+            let store = Arc::new(builder.build()?);
+            Ok((store.clone(), Some(store)))
+        }
+
+        // Azure Blob Storage: az://container, or Azurite with AZURE_USE_EMULATOR
+        "az" => {
+            let mut builder = MicrosoftAzureBuilder::new()
+                .with_container_name(bucket(parsed.host_str())?)
+                .with_use_emulator(CONFIG.azure_use_emulator);
+
+            // The emulator has well-known defaults for both
+            if !CONFIG.azure_account.is_empty() {
+                builder = builder.with_account(&CONFIG.azure_account);
+            }
+            if !CONFIG.azure_access_key.is_empty() {
+                builder = builder.with_access_key(&CONFIG.azure_access_key);
+            }
+            if !CONFIG.azure_endpoint.is_empty() {
+                builder = builder
+                    .with_endpoint(CONFIG.azure_endpoint.clone())
+                    .with_allow_http(CONFIG.azure_endpoint.starts_with("http://"));
             }
 
-            let store = builder.build()?;
-            Ok(Arc::new(store))
+            let store = Arc::new(builder.build()?);
+            Ok((store.clone(), Some(store)))
         }
 
         // Google Cloud Storage: gcs://bucket
         "gcs" => {
             // Provide service account path from env/config
-            let store = GoogleCloudStorageBuilder::new()
-                .with_bucket_name(&CONFIG.bucket)
-                .with_service_account_path(&CONFIG.gcp_path)
-                .build()?;
-            Ok(Arc::new(store))
+            let store = Arc::new(
+                GoogleCloudStorageBuilder::new()
+                    .with_bucket_name(bucket(parsed.host_str())?)
+                    .with_service_account_path(&CONFIG.gcp_path)
+                    .build()?,
+            );
+            Ok((store.clone(), Some(store)))
         }
 
         scheme => Err(Error::from(format!("Unsupported scheme: {}", scheme))),
     }
 }
 
-#[derive(Debug, Clone)]
+/// The endpoint an `s3://` URL points at: `configured` (S3_ENDPOINT) when set, else the
+/// URL's host, over HTTPS for AWS and HTTP for MinIO and the like. Without either the
+/// store talks to AWS in AWS_REGION.
+fn s3_endpoint(url: &Url, configured: &str) -> Option<String> {
+    match url.host_str().filter(|host| !host.is_empty()) {
+        _ if !configured.is_empty() => Some(configured.to_string()),
+        Some(host) if host.contains("amazonaws.com") => Some(format!("https://{}", host)),
+        Some(host) => Some(format!("http://{}:{}", host, url.port().unwrap_or(9000))),
+        None => None,
+    }
+}
+
+#[derive(Clone)]
 pub struct ObjectClient {
     pub client: Arc<dyn ObjectStore>,
+    multipart: Option<Arc<dyn MultipartStore>>,
+    keys: Option<MasterKeys>,
+    encrypt: bool,
+}
+
+impl fmt::Debug for ObjectClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ObjectClient")
+            .field("client", &self.client)
+            .field("multipart", &self.multipart.is_some())
+            .field("keys", &self.keys)
+            .field("encrypt", &self.encrypt)
+            .finish()
+    }
 }
 
 impl ObjectClient {
     pub async fn new() -> Result<Self> {
+        Self::with_url(&CONFIG.object_url).await
+    }
+
+    pub async fn with_url(url: &str) -> Result<Self> {
         let config = &*CONFIG;
+        let (client, multipart) = build_stores(url)?;
+        let keys = MasterKeys::parse(&config.object_master_keys)?;
+        if config.object_encryption && keys.is_none() {
+            return Err(Error::from("OBJECT_ENCRYPTION requires OBJECT_MASTER_KEYS"));
+        }
 
         Ok(ObjectClient {
-            client: get_object_store(&config.object_url)?,
+            client,
+            multipart,
+            keys,
+            encrypt: config.object_encryption,
         })
     }
 
+    fn keys(&self) -> Result<&MasterKeys> {
+        self.keys
+            .as_ref()
+            .ok_or_else(|| Error::EncryptionError("No master keys configured".into()))
+    }
+
+    /// A fresh data key with its wrapped form (key id, wrapped key) when new objects
+    /// are to be encrypted.
+    pub fn new_data_key(&self) -> Result<Option<(DataKey, String, String)>> {
+        if !self.encrypt {
+            return Ok(None);
+        }
+        let key = DataKey::generate();
+        let (key_id, wrapped) = self.keys()?.wrap(&key)?;
+        Ok(Some((key, key_id, wrapped)))
+    }
+
+    /// Unwraps a stored data key. Objects written without encryption have none.
+    pub fn data_key(&self, key_id: Option<&str>, wrapped: Option<&str>) -> Result<Option<DataKey>> {
+        match (key_id, wrapped) {
+            (Some(key_id), Some(wrapped)) => Ok(Some(self.keys()?.unwrap(key_id, wrapped)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Rewraps a data key with the current master key, or None if it already is.
+    pub fn rewrap(&self, key_id: &str, wrapped: &str) -> Result<Option<(String, String)>> {
+        let keys = self.keys()?;
+        if key_id == keys.current_id() {
+            return Ok(None);
+        }
+        Ok(Some(keys.wrap(&keys.unwrap(key_id, wrapped)?)?))
+    }
+
+    pub fn current_key_id(&self) -> Result<&str> {
+        Ok(self.keys()?.current_id())
+    }
+
+    fn multipart(&self) -> Result<&Arc<dyn MultipartStore>> {
+        self.multipart
+            .as_ref()
+            .ok_or_else(|| Error::from("Object store does not support multipart uploads"))
+    }
+
+    /// Starts a multipart upload that outlives a single request, returning its id.
+    pub async fn create_multipart(&self, location: &Path) -> Result<String> {
+        Ok(self.multipart()?.create_multipart(location).await?)
+    }
+
+    /// Uploads part `index` (0-based) and returns its content id for completion.
+    pub async fn put_part(
+        &self,
+        location: &Path,
+        id: &str,
+        index: usize,
+        data: Bytes,
+    ) -> Result<String> {
+        let part = self
+            .multipart()?
+            .put_part(location, &id.to_string(), index, data.into())
+            .await?;
+        Ok(part.content_id)
+    }
+
+    pub async fn complete_multipart(
+        &self,
+        location: &Path,
+        id: &str,
+        parts: &[String],
+    ) -> Result<()> {
+        let parts = parts
+            .iter()
+            .map(|content_id| PartId {
+                content_id: content_id.clone(),
+            })
+            .collect();
+        self.multipart()?
+            .complete_multipart(location, &id.to_string(), parts)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn abort_multipart(&self, location: &Path, id: &str) -> Result<()> {
+        self.multipart()?
+            .abort_multipart(location, &id.to_string())
+            .await?;
+        Ok(())
+    }
+
     pub async fn upsert(&self, location: &Path, file: &Bytes) -> Result<()> {
         let upload = self.client.put_multipart(location).await?;
         let mut write = WriteMultipart::new(upload);
@@ -95,17 +277,84 @@ impl ObjectClient {
         Ok(())
     }
 
+    /// Writes `file`, sealed under `key` when given.
+    pub async fn put_sealed(
+        &self,
+        location: &Path,
+        file: &Bytes,
+        key: Option<&DataKey>,
+    ) -> Result<()> {
+        match key {
+            Some(key) => {
+                self.upsert(location, &envelope::encrypt(key, file)?.into())
+                    .await
+            }
+            None => self.upsert(location, file).await,
+        }
+    }
+
+    /// Reads a blob written by `put_sealed`, opening it when `key` is given.
+    pub async fn get_sealed(&self, location: &Path, key: Option<&DataKey>) -> Result<Bytes> {
+        let bytes = self.get(location).await?;
+        match key {
+            Some(key) => envelope::decrypt(key, bytes).await,
+            None => Ok(bytes),
+        }
+    }
+
+    /// Streams a blob written by `put_sealed`, opening it when `key` is given.
+    pub async fn get_sealed_stream(
+        &self,
+        location: &Path,
+        key: Option<DataKey>,
+    ) -> Result<BoxStream<'static, Result<Bytes>>> {
+        let stream = self.get_stream(location).await?;
+        Ok(match key {
+            Some(key) => envelope::decrypt_stream(key, stream).boxed(),
+            None => stream.boxed(),
+        })
+    }
+
     pub async fn get(&self, location: &Path) -> Result<Bytes> {
         let result = self.client.get(location).await?;
         let bytes = result.bytes().await?;
         Ok(bytes)
     }
 
-    pub async fn get_stream(&self, location: &Path) -> Result<impl Stream<Item = Result<Bytes>>> {
+    pub async fn get_stream(
+        &self,
+        location: &Path,
+    ) -> Result<impl Stream<Item = Result<Bytes>> + Send + use<>> {
         let result = self.client.get(location).await?;
         Ok(result.into_stream().map(|r| r.map_err(|e| e.into())))
     }
 
+    /// Copies a blob as stored into another store, streaming it through.
+    pub async fn transfer(&self, location: &Path, to: &ObjectClient) -> Result<()> {
+        let mut data = self.get_stream(location).await?;
+        let mut write = WriteMultipart::new(to.client.put_multipart(location).await?);
+
+        let written = async {
+            while let Some(chunk) = data.next().await {
+                write.wait_for_capacity(MAX_TRANSFER_PARTS).await?;
+                write.write(&chunk?);
+            }
+            Ok::<_, Error>(())
+        }
+        .await;
+
+        match written {
+            Ok(()) => {
+                write.finish().await?;
+                Ok(())
+            }
+            Err(e) => {
+                write.abort().await?;
+                Err(e)
+            }
+        }
+    }
+
     pub async fn exists(&self, location: &Path) -> Result<bool> {
         match self.client.head(location).await {
             Ok(_) => Ok(true),
@@ -114,8 +363,69 @@ impl ObjectClient {
         }
     }
 
+    pub async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.client.copy(from, to).await?;
+        Ok(())
+    }
+
+    pub async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.client.rename(from, to).await?;
+        Ok(())
+    }
+
     pub async fn delete(&self, location: &Path) -> Result<()> {
         self.client.delete(location).await?;
         Ok(())
     }
+
+    /// Deletes everything stored under `prefix`.
+    pub async fn delete_prefix(&self, prefix: &str) -> Result<()> {
+        let prefix = Path::from(prefix);
+        let mut listing = self.client.list(Some(&prefix));
+        while let Some(meta) = listing.next().await {
+            self.client.delete(&meta?.location).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let (store, multipart) = build_stores("memory://").unwrap();
+        let path = Path::from("users/1/a.txt");
+        store.put(&path, Bytes::from("hello").into()).await.unwrap();
+
+        let data = store.get(&path).await.unwrap().bytes().await.unwrap();
+        assert_eq!(data, Bytes::from("hello"));
+        assert!(multipart.is_some());
+        assert!(build_stores("ftp://host").is_err());
+    }
+
+    #[test]
+    fn test_s3_endpoint() {
+        let endpoint =
+            |url: &str, configured: &str| s3_endpoint(&Url::parse(url).unwrap(), configured);
+
+        assert_eq!(
+            endpoint("s3://minio:9000/files", "").as_deref(),
+            Some("http://minio:9000")
+        );
+        assert_eq!(
+            endpoint("s3://minio", "").as_deref(),
+            Some("http://minio:9000")
+        );
+        assert_eq!(
+            endpoint("s3://s3.eu-west-1.amazonaws.com/files", "").as_deref(),
+            Some("https://s3.eu-west-1.amazonaws.com")
+        );
+        assert_eq!(
+            endpoint("s3://minio:9000/files", "https://storage.example.com").as_deref(),
+            Some("https://storage.example.com")
+        );
+        assert_eq!(endpoint("s3:///files", ""), None);
+    }
 }
@@ -0,0 +1,166 @@
+//! Schema migrations from `database/migrations`, embedded into the binary at build time.
+//! They are applied on start, or with `backend migrate` when `MIGRATE_ON_START` is off,
+//! under an advisory lock so replicas starting together apply each one once.
+
+use crate::error::{Error, Result};
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::{PgConnection, PgPool};
+use std::collections::HashSet;
+
+static MIGRATOR: Migrator = sqlx::migrate!("../database/migrations");
+
+/// Version of the newest migration this build knows.
+pub fn latest_version() -> i64 {
+    MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0)
+}
+
+/// Applies pending migrations, returning the versions applied.
+pub async fn migrate(pool: &PgPool) -> Result<Vec<i64>> {
+    let mut conn = pool.acquire().await?;
+
+    // Locks are per session, so the lock the migrator takes again inside is reentrant
+    conn.lock().await?;
+    let result = apply(&mut conn).await;
+    conn.unlock().await?;
+
+    result
+}
+
+async fn apply(conn: &mut PgConnection) -> Result<Vec<i64>> {
+    conn.ensure_migrations_table().await?;
+    baseline(conn).await?;
+
+    let applied = applied_versions(conn).await?;
+    check_not_ahead(&applied)?;
+    MIGRATOR.run_direct(conn).await?;
+
+    Ok(MIGRATOR
+        .iter()
+        .map(|m| m.version)
+        .filter(|version| !applied.contains(version))
+        .collect())
+}
+
+/// Checks the database is exactly at this build's schema, for when something else applies
+/// the migrations.
+pub async fn verify(pool: &PgPool) -> Result<()> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+
+    let applied = applied_versions(&mut conn).await?;
+    check_not_ahead(&applied)?;
+
+    let pending = MIGRATOR
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .count();
+    if pending > 0 {
+        return Err(Error::from(format!(
+            "Database schema is missing {} migration(s), run `backend migrate`",
+            pending
+        )));
+    }
+    Ok(())
+}
+
+async fn applied_versions(conn: &mut PgConnection) -> Result<HashSet<i64>> {
+    Ok(conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| m.version)
+        .collect())
+}
+
+/// An older build must not run against a schema a newer one already changed.
+fn check_not_ahead(applied: &HashSet<i64>) -> Result<()> {
+    let latest = latest_version();
+    if let Some(&newest) = applied.iter().max()
+        && newest > latest
+    {
+        return Err(Error::from(format!(
+            "Database schema is at migration {}, newer than this build's latest {}",
+            newest, latest
+        )));
+    }
+    Ok(())
+}
+
+/// Databases migrated by Atlas before the backend applied its own migrations record them
+/// in `atlas_schema_revisions`. Those are marked applied rather than run a second time.
+async fn baseline(conn: &mut PgConnection) -> Result<()> {
+    if !conn.list_applied_migrations().await?.is_empty() {
+        return Ok(());
+    }
+
+    // Atlas keeps its own schema, or the connected one when the URL names a search_path
+    let table: Option<String> = sqlx::query_scalar(
+        r#"
+        SELECT COALESCE(
+            to_regclass('atlas_schema_revisions.atlas_schema_revisions'),
+            to_regclass('public.atlas_schema_revisions')
+        )::TEXT
+    "#,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    let Some(table) = table else {
+        return Ok(());
+    };
+
+    let versions: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT version FROM {} WHERE applied = total",
+        table
+    ))
+    .fetch_all(&mut *conn)
+    .await?;
+    let versions: HashSet<i64> = versions.iter().filter_map(|v| v.parse().ok()).collect();
+
+    let mut baselined = 0;
+    for migration in MIGRATOR.iter().filter(|m| versions.contains(&m.version)) {
+        sqlx::query(
+            r#"
+            INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES ($1, $2, TRUE, $3, 0)
+        "#,
+        )
+        .bind(migration.version)
+        .bind(&*migration.description)
+        .bind(&*migration.checksum)
+        .execute(&mut *conn)
+        .await?;
+        baselined += 1;
+    }
+
+    if baselined > 0 {
+        tracing::info!("Baselined {} migrations applied by Atlas", baselined);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embeds_every_migration() {
+        let files = std::fs::read_dir(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../database/migrations"
+        ))
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .ends_with(".sql")
+        })
+        .count();
+
+        assert_eq!(MIGRATOR.iter().count(), files);
+        assert!(MIGRATOR.iter().all(|m| !m.no_tx));
+        assert_eq!(latest_version(), MIGRATOR.iter().last().unwrap().version);
+    }
+}
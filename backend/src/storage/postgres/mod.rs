@@ -1,11 +1,18 @@
+pub mod migrations;
+
 use crate::config::CONFIG;
-use crate::data::Objects;
+use crate::crypt::jwt::Claims;
+use crate::data::{
+    CurrentVersion, LifecycleRule, ObjectList, ObjectVersion, Objects, ScanStatus, StorageClass,
+    StorageUsage, Usage, VersionBlob,
+};
 use crate::error::{Error, Result};
+use chrono::{DateTime, Utc};
 use object_store::path::Path;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::postgres::{PgConnectOptions, PgPool};
 use sqlx::types::Uuid;
-use sqlx::ConnectOptions;
+use sqlx::{ConnectOptions, FromRow};
 use sqlx::{Postgres, Transaction};
 use std::ops::DerefMut;
 use std::pin::Pin;
@@ -45,6 +52,44 @@ impl PostgresClient {
         }
     }
 
+    /// Like `with_transaction`, but as the caller: the transaction switches to the
+    /// configured role and exposes their claims as `request.jwt.claims`, so row-level
+    /// security policies decide what the operation can see and change.
+    pub async fn with_claims_transaction<T, F>(&self, claims: &Claims, operation: F) -> Result<T>
+    where
+        T: Send,
+        F: for<'a> FnOnce(
+            &'a mut sqlx::Transaction<'_, sqlx::Postgres>,
+        ) -> Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>,
+    {
+        let claims = serde_json::to_string(claims)?;
+        let role = format!("\"{}\"", CONFIG.data_api_role.replace('"', "\"\""));
+
+        let mut tx = self.pool.begin().await?;
+        let result = async {
+            sqlx::query(&format!("SET LOCAL ROLE {}", role))
+                .execute(tx.deref_mut())
+                .await?;
+            sqlx::query("SELECT set_config('request.jwt.claims', $1, true)")
+                .bind(claims)
+                .execute(tx.deref_mut())
+                .await?;
+            operation(&mut tx).await
+        }
+        .await;
+
+        match result {
+            Ok(result) => {
+                tx.commit().await?;
+                Ok(result)
+            }
+            Err(error) => {
+                let _ = tx.rollback().await;
+                Err(error)
+            }
+        }
+    }
+
     pub async fn with_pool<T, F>(&self, operation: F) -> Result<T>
     where
         T: Send,
@@ -65,33 +110,145 @@ impl PostgresClient {
     }
 
     // Object metadate
-    pub async fn upload_object(&self, object: &Objects) -> Result<()> {
+    /// Records `version` as the new current version of the object, creating the object on
+    /// its first upload. Returns the blob keys of versions pruned by the retention limit.
+    pub async fn upload_object(
+        &self,
+        object: &Objects,
+        version: &ObjectVersion,
+        default_max_versions: i32,
+    ) -> Result<Vec<String>> {
         let mut tx = self.start_transaction().await?;
-        let query = r#"
-            INSERT INTO objects (user_id, key, filename)
-            VALUES ($1, $2, $3)
-            ON CONFLICT (user_id, filename) DO UPDATE 
-            SET key = EXCLUDED.key
-        "#;
 
-        match sqlx::query(query)
-            .bind(&object.user_id)
+        let pruned = async {
+            let (object_id, number): (i32, i32) = sqlx::query_as(
+                r#"
+                INSERT INTO objects (user_id, key, filename)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (key) DO UPDATE 
+                SET filename = EXCLUDED.filename,
+                    version = objects.version + 1
+                RETURNING id, version
+            "#,
+            )
+            .bind(object.user_id)
             .bind(&object.key)
             .bind(&object.filename)
+            .fetch_one(tx.deref_mut())
+            .await?;
+
+            sqlx::query(
+                r#"
+                INSERT INTO object_versions (
+                    object_id, version, blob_key, content_type, size_bytes, scan_status,
+                    key_id, wrapped_key, storage_class
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+            )
+            .bind(object_id)
+            .bind(number)
+            .bind(&version.blob_key)
+            .bind(&version.content_type)
+            .bind(version.size_bytes)
+            .bind(&version.scan_status)
+            .bind(&version.key_id)
+            .bind(&version.wrapped_key)
+            .bind(&version.storage_class)
             .execute(tx.deref_mut())
-            .await
-        {
-            Ok(_) => {
+            .await?;
+            Self::retain_blob(&mut tx, &version.blob_key).await?;
+
+            Self::prune_versions(&mut tx, object_id, default_max_versions).await
+        }
+        .await;
+
+        match pruned {
+            Ok(pruned) => {
                 tx.commit().await?;
-                Ok(())
+                Ok(pruned)
             }
             Err(e) => {
                 tx.rollback().await?;
-                Err(e.into())
+                Err(e)
             }
         }
     }
 
+    /// Deletes the oldest versions past the object's retention limit. The current
+    /// version is always the newest, so it is never pruned.
+    async fn prune_versions(
+        tx: &mut Transaction<'_, Postgres>,
+        object_id: i32,
+        default_max_versions: i32,
+    ) -> Result<Vec<String>> {
+        let pruned: Vec<String> = sqlx::query_scalar(
+            r#"
+            DELETE FROM object_versions AS v
+            USING objects AS o
+            WHERE o.id = v.object_id
+            AND v.object_id = $1
+            AND v.version <= o.version - GREATEST(COALESCE(o.max_versions, $2), 1)
+            RETURNING v.blob_key
+        "#,
+        )
+        .bind(object_id)
+        .bind(default_max_versions)
+        .fetch_all(tx.deref_mut())
+        .await?;
+
+        Self::release_blobs(tx, &pruned).await?;
+        Ok(pruned)
+    }
+
+    /// Counts a new reference to a shared blob; other blobs belong to a single version.
+    async fn retain_blob(tx: &mut Transaction<'_, Postgres>, blob_key: &str) -> Result<()> {
+        if !ObjectVersion::is_shared_blob(blob_key) {
+            return Ok(());
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO blobs (blob_key, ref_count)
+            VALUES ($1, 1)
+            ON CONFLICT (blob_key) DO UPDATE
+            SET ref_count = blobs.ref_count + 1
+        "#,
+        )
+        .bind(blob_key)
+        .execute(tx.deref_mut())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Drops a reference per deleted version from the shared blobs among `blob_keys`.
+    /// Blobs left unreferenced keep their row until the blob itself is deleted.
+    async fn release_blobs(tx: &mut Transaction<'_, Postgres>, blob_keys: &[String]) -> Result<()> {
+        if !blob_keys
+            .iter()
+            .any(|key| ObjectVersion::is_shared_blob(key))
+        {
+            return Ok(());
+        }
+        sqlx::query(
+            r#"
+            UPDATE blobs AS b
+            SET ref_count = b.ref_count - d.refs
+            FROM (
+                SELECT blob_key, COUNT(*)::INT AS refs
+                FROM UNNEST($1::VARCHAR[]) AS blob_key
+                GROUP BY blob_key
+            ) AS d
+            WHERE b.blob_key = d.blob_key
+        "#,
+        )
+        .bind(blob_keys)
+        .execute(tx.deref_mut())
+        .await?;
+
+        Ok(())
+    }
+
     pub async fn get_object(&self, path: &Path) -> Result<Objects> {
         let object: Objects = sqlx::query_as(
             r#"
@@ -108,39 +265,925 @@ impl PostgresClient {
         Ok(object)
     }
 
-    pub async fn delete_object(&self, key: &str) -> Result<()> {
+    /// Current version of the object at `key`, if there is one.
+    pub async fn get_current_version(&self, key: &str) -> Result<Option<ObjectVersion>> {
+        let version: Option<ObjectVersion> = sqlx::query_as(
+            r#"
+            SELECT v.*
+            FROM objects o
+            JOIN object_versions v ON v.object_id = o.id AND v.version = o.version
+            WHERE o.key=$1
+        "#,
+        )
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(version)
+    }
+
+    pub async fn get_object_version(&self, object_id: i32, version: i32) -> Result<ObjectVersion> {
+        let version: ObjectVersion = sqlx::query_as(
+            r#"
+            SELECT *
+            FROM object_versions
+            WHERE object_id=$1
+            AND version=$2
+        "#,
+        )
+        .bind(object_id)
+        .bind(version)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| Error::from(format!("Version {} not found", version)))?;
+
+        Ok(version)
+    }
+
+    pub async fn list_object_versions(&self, object_id: i32) -> Result<Vec<ObjectVersion>> {
+        let versions: Vec<ObjectVersion> = sqlx::query_as(
+            r#"
+            SELECT *
+            FROM object_versions
+            WHERE object_id=$1
+            ORDER BY version DESC
+        "#,
+        )
+        .bind(object_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(versions)
+    }
+
+    /// Makes a copy of `source` (already written to `blob_key`) the new current version.
+    /// The content is unchanged, so the copy keeps the source's scan status and data key,
+    /// and sits in the same store.
+    /// Returns the new version number and the blob keys of pruned versions.
+    pub async fn restore_object_version(
+        &self,
+        object_id: i32,
+        source: &ObjectVersion,
+        blob_key: &str,
+        default_max_versions: i32,
+    ) -> Result<(i32, Vec<String>)> {
+        let mut tx = self.start_transaction().await?;
+
+        let restored = async {
+            let number: i32 = sqlx::query_scalar(
+                r#"
+                UPDATE objects
+                SET version = version + 1
+                WHERE id = $1
+                RETURNING version
+            "#,
+            )
+            .bind(object_id)
+            .fetch_one(tx.deref_mut())
+            .await?;
+
+            sqlx::query(
+                r#"
+                INSERT INTO object_versions (
+                    object_id, version, blob_key, content_type, size_bytes, scan_status,
+                    key_id, wrapped_key, storage_class
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+            )
+            .bind(object_id)
+            .bind(number)
+            .bind(blob_key)
+            .bind(&source.content_type)
+            .bind(source.size_bytes)
+            .bind(&source.scan_status)
+            .bind(&source.key_id)
+            .bind(&source.wrapped_key)
+            .bind(&source.storage_class)
+            .execute(tx.deref_mut())
+            .await?;
+            Self::retain_blob(&mut tx, blob_key).await?;
+
+            let pruned = Self::prune_versions(&mut tx, object_id, default_max_versions).await?;
+            Ok::<_, Error>((number, pruned))
+        }
+        .await;
+
+        match restored {
+            Ok(restored) => {
+                tx.commit().await?;
+                Ok(restored)
+            }
+            Err(e) => {
+                tx.rollback().await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Sets the object's retention limit (None falls back to the default) and prunes
+    /// versions past it, returning their blob keys.
+    pub async fn set_object_max_versions(
+        &self,
+        object_id: i32,
+        max_versions: Option<i32>,
+        default_max_versions: i32,
+    ) -> Result<Vec<String>> {
+        let mut tx = self.start_transaction().await?;
+
+        let pruned = async {
+            sqlx::query(
+                r#"
+                UPDATE objects
+                SET max_versions = $2
+                WHERE id = $1
+            "#,
+            )
+            .bind(object_id)
+            .bind(max_versions)
+            .execute(tx.deref_mut())
+            .await?;
+
+            Self::prune_versions(&mut tx, object_id, default_max_versions).await
+        }
+        .await;
+
+        match pruned {
+            Ok(pruned) => {
+                tx.commit().await?;
+                Ok(pruned)
+            }
+            Err(e) => {
+                tx.rollback().await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Deletes the object with all of its versions, returning their blob keys.
+    pub async fn delete_object(&self, key: &str) -> Result<Vec<String>> {
         let mut tx = self.start_transaction().await?;
         let query = r#"
-            DELETE FROM objects 
-            WHERE key=$1 
+            WITH deleted AS (
+                DELETE FROM objects 
+                WHERE key=$1 
+                RETURNING id
+            )
+            SELECT v.blob_key
+            FROM object_versions AS v
+            JOIN deleted AS d ON d.id = v.object_id
         "#;
 
-        match sqlx::query(query).bind(key).execute(tx.deref_mut()).await {
-            Ok(_) => {
+        let blob_keys = async {
+            let blob_keys: Vec<String> = sqlx::query_scalar(query)
+                .bind(key)
+                .fetch_all(tx.deref_mut())
+                .await?;
+            Self::release_blobs(&mut tx, &blob_keys).await?;
+            Ok::<_, Error>(blob_keys)
+        }
+        .await;
+
+        match blob_keys {
+            Ok(blob_keys) => {
                 tx.commit().await?;
-                Ok(())
+                Ok(blob_keys)
             }
             Err(e) => {
                 tx.rollback().await?;
-                Err(e.into())
+                Err(e)
             }
         }
     }
 
-    pub async fn list_user_objects(&self, user_id: Uuid) -> Result<Vec<Objects>> {
-        let objects: Vec<Objects> = sqlx::query_as(
+    /// The current versions of a user's objects at `keys` or under `prefix`, by key.
+    pub async fn list_current_versions(
+        &self,
+        user_id: Uuid,
+        keys: &[String],
+        prefix: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<CurrentVersion>> {
+        let versions: Vec<CurrentVersion> = sqlx::query_as(
             r#"
-            SELECT * 
-            FROM objects 
+            SELECT o.key, o.user_id, v.*
+            FROM objects AS o
+            JOIN object_versions AS v ON v.object_id = o.id AND v.version = o.version
+            WHERE o.user_id = $1
+            AND (o.key = ANY($2) OR starts_with(o.key, $3))
+            ORDER BY o.key
+            LIMIT $4
+        "#,
+        )
+        .bind(user_id)
+        .bind(keys)
+        .bind(prefix)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(versions)
+    }
+
+    pub async fn set_object_expiry(
+        &self,
+        object_id: i32,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE objects
+            SET expires_at = $2
+            WHERE id = $1
+        "#,
+        )
+        .bind(object_id)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Deletes a batch of objects past their `expires_at`, or past the expiry of a
+    /// lifecycle rule when they have none, returning (object id, blob key) per version.
+    pub async fn delete_expired_objects(&self, limit: i64) -> Result<Vec<(i32, String)>> {
+        let mut tx = self.start_transaction().await?;
+
+        let deleted = async {
+            let deleted: Vec<(i32, String)> = sqlx::query_as(
+                r#"
+                WITH deleted AS (
+                    DELETE FROM objects
+                    WHERE id IN (
+                        SELECT o.id
+                        FROM objects AS o
+                        JOIN object_versions AS v ON v.object_id = o.id AND v.version = o.version
+                        WHERE o.expires_at <= NOW()
+                        OR (o.expires_at IS NULL AND EXISTS (
+                            SELECT 1 FROM lifecycle_rules AS r
+                            WHERE r.user_id = o.user_id
+                            AND starts_with(o.key, r.prefix)
+                            AND v.created_at <= NOW() - make_interval(days => r.expire_after_days)
+                        ))
+                        LIMIT $1
+                    )
+                    RETURNING id
+                )
+                SELECT v.object_id, v.blob_key
+                FROM object_versions AS v
+                JOIN deleted AS d ON d.id = v.object_id
+            "#,
+            )
+            .bind(limit)
+            .fetch_all(tx.deref_mut())
+            .await?;
+
+            let blob_keys: Vec<String> = deleted.iter().map(|(_, key)| key.clone()).collect();
+            Self::release_blobs(&mut tx, &blob_keys).await?;
+            Ok::<_, Error>(deleted)
+        }
+        .await;
+
+        match deleted {
+            Ok(deleted) => {
+                tx.commit().await?;
+                Ok(deleted)
+            }
+            Err(e) => {
+                tx.rollback().await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// A batch of standard blobs whose versions are past a lifecycle rule's transition age.
+    pub async fn list_transition_candidates(&self, limit: i64) -> Result<Vec<String>> {
+        let blob_keys: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT DISTINCT v.blob_key
+            FROM object_versions AS v
+            JOIN objects AS o ON o.id = v.object_id
+            JOIN lifecycle_rules AS r ON r.user_id = o.user_id AND starts_with(o.key, r.prefix)
+            WHERE v.storage_class = 'standard'
+            AND v.created_at <= NOW() - make_interval(days => r.transition_after_days)
+            LIMIT $1
+        "#,
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(blob_keys)
+    }
+
+    /// Moves every version of a blob from one store to another, returning how many moved.
+    pub async fn set_storage_class(
+        &self,
+        blob_key: &str,
+        from: StorageClass,
+        to: StorageClass,
+    ) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE object_versions
+            SET storage_class = $3
+            WHERE blob_key = $1
+            AND storage_class = $2
+        "#,
+        )
+        .bind(blob_key)
+        .bind(from.as_str())
+        .bind(to.as_str())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn list_lifecycle_rules(&self, user_id: Uuid) -> Result<Vec<LifecycleRule>> {
+        let rules: Vec<LifecycleRule> = sqlx::query_as(
+            r#"
+            SELECT *
+            FROM lifecycle_rules
             WHERE user_id = $1
+            ORDER BY prefix
         "#,
         )
-        .bind(&user_id)
+        .bind(user_id)
         .fetch_all(&self.pool)
         .await?;
 
-        Ok(objects)
+        Ok(rules)
+    }
+
+    /// Creates the rule for `prefix`, replacing any existing one.
+    pub async fn upsert_lifecycle_rule(
+        &self,
+        user_id: Uuid,
+        prefix: &str,
+        expire_after_days: Option<i32>,
+        transition_after_days: Option<i32>,
+    ) -> Result<LifecycleRule> {
+        let rule: LifecycleRule = sqlx::query_as(
+            r#"
+            INSERT INTO lifecycle_rules (user_id, prefix, expire_after_days, transition_after_days)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, prefix) DO UPDATE
+            SET expire_after_days = EXCLUDED.expire_after_days,
+                transition_after_days = EXCLUDED.transition_after_days
+            RETURNING *
+        "#,
+        )
+        .bind(user_id)
+        .bind(prefix)
+        .bind(expire_after_days)
+        .bind(transition_after_days)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(rule)
     }
+
+    /// Returns whether there was a rule to delete.
+    pub async fn delete_lifecycle_rule(&self, user_id: Uuid, prefix: &str) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM lifecycle_rules
+            WHERE user_id = $1
+            AND prefix = $2
+        "#,
+        )
+        .bind(user_id)
+        .bind(prefix)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Records a scan's verdict on every version of the blob, counting the attempt.
+    pub async fn set_scan_status(&self, blob_key: &str, status: ScanStatus) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE object_versions
+            SET scan_status=$2,
+                scan_attempts = scan_attempts + 1
+            WHERE blob_key=$1
+        "#,
+        )
+        .bind(blob_key)
+        .bind(status.as_str())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// A version of each blob due a scan: one whose scan failed fewer than `max_attempts`
+    /// times, or that is still pending after `stale_minutes`, its scan lost to a restart.
+    pub async fn scans_due(
+        &self,
+        max_attempts: i32,
+        stale_minutes: i64,
+        limit: i64,
+    ) -> Result<Vec<ObjectVersion>> {
+        let versions: Vec<ObjectVersion> = sqlx::query_as(
+            r#"
+            SELECT DISTINCT ON (blob_key) *
+            FROM object_versions
+            WHERE (scan_status = 'failed' AND scan_attempts < $1)
+            OR (scan_status = 'pending' AND created_at < NOW() - make_interval(mins => $2::INT))
+            ORDER BY blob_key
+            LIMIT $3
+        "#,
+        )
+        .bind(max_attempts)
+        .bind(stale_minutes)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(versions)
+    }
+
+    /// A batch of versions whose data keys are wrapped by a master key other than
+    /// `current`, as (id, key_id, wrapped_key).
+    pub async fn list_stale_wrapped_keys(
+        &self,
+        current: &str,
+        limit: i64,
+    ) -> Result<Vec<(i32, String, String)>> {
+        let keys: Vec<(i32, String, String)> = sqlx::query_as(
+            r#"
+            SELECT id, key_id, wrapped_key
+            FROM object_versions
+            WHERE key_id IS NOT NULL
+            AND key_id <> $1
+            ORDER BY id
+            LIMIT $2
+        "#,
+        )
+        .bind(current)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(keys)
+    }
+
+    pub async fn set_wrapped_key(&self, id: i32, key_id: &str, wrapped_key: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE object_versions
+            SET key_id=$2, wrapped_key=$3
+            WHERE id=$1
+        "#,
+        )
+        .bind(id)
+        .bind(key_id)
+        .bind(wrapped_key)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// A version holding the shared blob at `blob_key`, whose data key and scan status
+    /// carry over to new versions of the same content.
+    pub async fn get_shared_blob_version(&self, blob_key: &str) -> Result<Option<ObjectVersion>> {
+        let version: Option<ObjectVersion> = sqlx::query_as(
+            r#"
+            SELECT *
+            FROM object_versions
+            WHERE blob_key=$1
+            ORDER BY id
+            LIMIT 1
+        "#,
+        )
+        .bind(blob_key)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(version)
+    }
+
+    pub async fn get_blob_ref_count(&self, blob_key: &str) -> Result<Option<i32>> {
+        let ref_count: Option<i32> = sqlx::query_scalar(
+            r#"
+            SELECT ref_count
+            FROM blobs
+            WHERE blob_key=$1
+        "#,
+        )
+        .bind(blob_key)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(ref_count)
+    }
+
+    /// Forgets a shared blob once nothing references it.
+    pub async fn delete_unreferenced_blob(&self, blob_key: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM blobs
+            WHERE blob_key=$1
+            AND ref_count <= 0
+        "#,
+        )
+        .bind(blob_key)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Every version row with its blob in the standard store, with the blob it points at.
+    pub async fn list_version_blobs(&self) -> Result<Vec<VersionBlob>> {
+        let versions: Vec<VersionBlob> = sqlx::query_as(
+            r#"
+            SELECT v.id, o.key, v.version, v.blob_key
+            FROM object_versions v
+            JOIN objects o ON o.id = v.object_id
+            WHERE v.storage_class = 'standard'
+        "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(versions)
+    }
+
+    /// Deletes version rows whose blobs are gone. Objects left without versions are
+    /// deleted, and objects that lost their current version fall back to the latest
+    /// one remaining.
+    pub async fn delete_dangling_versions(&self, version_ids: &[i32]) -> Result<()> {
+        let mut tx = self.start_transaction().await?;
+
+        let result = async {
+            let deleted: Vec<(i32, String)> = sqlx::query_as(
+                r#"
+                DELETE FROM object_versions
+                WHERE id = ANY($1)
+                AND storage_class = 'standard'
+                RETURNING object_id, blob_key
+            "#,
+            )
+            .bind(version_ids)
+            .fetch_all(tx.deref_mut())
+            .await?;
+            let (object_ids, blob_keys): (Vec<i32>, Vec<String>) = deleted.into_iter().unzip();
+
+            // The blobs are already gone, so unreferenced rows can go with the versions
+            Self::release_blobs(&mut tx, &blob_keys).await?;
+            sqlx::query(
+                r#"
+                DELETE FROM blobs
+                WHERE blob_key = ANY($1)
+                AND ref_count <= 0
+            "#,
+            )
+            .bind(&blob_keys)
+            .execute(tx.deref_mut())
+            .await?;
+
+            sqlx::query(
+                r#"
+                DELETE FROM objects o
+                WHERE o.id = ANY($1)
+                AND NOT EXISTS (SELECT 1 FROM object_versions v WHERE v.object_id = o.id)
+            "#,
+            )
+            .bind(&object_ids)
+            .execute(tx.deref_mut())
+            .await?;
+
+            sqlx::query(
+                r#"
+                UPDATE objects o
+                SET version = (SELECT MAX(v.version) FROM object_versions v WHERE v.object_id = o.id)
+                WHERE o.id = ANY($1)
+                AND NOT EXISTS (
+                    SELECT 1 FROM object_versions v
+                    WHERE v.object_id = o.id AND v.version = o.version
+                )
+            "#,
+            )
+            .bind(&object_ids)
+            .execute(tx.deref_mut())
+            .await?;
+
+            Ok::<_, Error>(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                tx.commit().await?;
+                Ok(())
+            }
+            Err(e) => {
+                tx.rollback().await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Lists the objects under `prefix` (a key prefix including the user root) ordered by key.
+    /// With a delimiter, keys containing it past the prefix collapse into common prefixes,
+    /// and folders created without any objects show up as prefixes as well.
+    pub async fn list_objects(
+        &self,
+        user_id: Uuid,
+        prefix: &str,
+        delimiter: Option<&str>,
+        cursor: Option<&str>,
+        limit: i64,
+    ) -> Result<ObjectList> {
+        let root = Objects::root(user_id);
+        let rows: Vec<ListingRow> = sqlx::query_as(
+            r#"
+            WITH entries AS (
+                SELECT o.id, o.user_id, o.key, o.filename, o.version, o.max_versions, o.expires_at,
+                    CASE WHEN $3 <> '' AND strpos(substr(o.key, length($2) + 1), $3) > 0
+                        THEN substr(o.key, 1, length($2) + strpos(substr(o.key, length($2) + 1), $3) + length($3) - 1)
+                    END AS prefix
+                FROM objects AS o
+                WHERE o.user_id = $1
+                AND starts_with(o.key, $2)
+                UNION ALL
+                SELECT NULL, NULL, f.key, NULL, NULL, NULL, NULL,
+                    CASE WHEN strpos(substr(f.key, length($2) + 1), $3) > 0
+                        THEN substr(f.key, 1, length($2) + strpos(substr(f.key, length($2) + 1), $3) + length($3) - 1)
+                    END
+                FROM (SELECT $6 || path || '/' AS key FROM folders WHERE user_id = $1) AS f
+                WHERE $3 <> ''
+                AND starts_with(f.key, $2)
+                AND f.key <> $2
+            )
+            SELECT DISTINCT ON (COALESCE(prefix, key) COLLATE "C")
+                COALESCE(prefix, key) AS entry, prefix IS NOT NULL AS is_prefix,
+                id, user_id, key, filename, version, max_versions, expires_at
+            FROM entries
+            WHERE (prefix IS NOT NULL OR id IS NOT NULL)
+            AND ($4::text IS NULL OR COALESCE(prefix, key) COLLATE "C" > $4)
+            ORDER BY COALESCE(prefix, key) COLLATE "C"
+            LIMIT $5
+        "#,
+        )
+        .bind(user_id)
+        .bind(prefix)
+        .bind(delimiter.unwrap_or(""))
+        .bind(cursor.map(|c| format!("{}{}", root, c)))
+        .bind(limit + 1)
+        .bind(&root)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let has_more = rows.len() as i64 > limit;
+        let mut list = ObjectList {
+            objects: Vec::new(),
+            prefixes: Vec::new(),
+            next_cursor: None,
+        };
+
+        for row in rows.into_iter().take(limit as usize) {
+            let entry = row.entry.strip_prefix(root.as_str()).unwrap_or(&row.entry);
+            list.next_cursor = Some(entry.to_string());
+
+            match (row.is_prefix, row.id, row.user_id, row.filename) {
+                (false, Some(id), Some(user_id), Some(filename)) => list.objects.push(Objects {
+                    id,
+                    user_id,
+                    key: row.key,
+                    filename,
+                    version: row.version.unwrap_or(1),
+                    max_versions: row.max_versions,
+                    expires_at: row.expires_at,
+                }),
+                _ => list.prefixes.push(entry.to_string()),
+            }
+        }
+
+        if !has_more {
+            list.next_cursor = None;
+        }
+
+        Ok(list)
+    }
+
+    /// Deletes every object whose key starts with `prefix`, returning the blob keys of
+    /// all their versions.
+    pub async fn delete_objects_with_prefix(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        prefix: &str,
+    ) -> Result<Vec<String>> {
+        let blob_keys: Vec<String> = sqlx::query_scalar(
+            r#"
+            WITH deleted AS (
+                DELETE FROM objects
+                WHERE user_id = $1
+                AND starts_with(key, $2)
+                RETURNING id
+            )
+            SELECT v.blob_key
+            FROM object_versions AS v
+            JOIN deleted AS d ON d.id = v.object_id
+        "#,
+        )
+        .bind(user_id)
+        .bind(prefix)
+        .fetch_all(tx.deref_mut())
+        .await?;
+
+        Self::release_blobs(tx, &blob_keys).await?;
+        Ok(blob_keys)
+    }
+
+    /// Re-keys every object under `from` to live under `to`. Blobs are addressed by
+    /// version, so only the rows change.
+    pub async fn move_objects(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        from: &str,
+        to: &str,
+    ) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE objects
+            SET key = $3 || substr(key, length($2) + 1)
+            WHERE user_id = $1
+            AND starts_with(key, $2)
+        "#,
+        )
+        .bind(user_id)
+        .bind(from)
+        .bind(to)
+        .execute(tx.deref_mut())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Sums the bytes of every stored version for the user and their organization,
+    /// resolving quotas from overrides first and the given defaults otherwise.
+    pub async fn get_storage_usage(
+        &self,
+        user_id: Uuid,
+        default_user_quota: Option<i64>,
+        default_organization_quota: Option<i64>,
+    ) -> Result<StorageUsage> {
+        let row: UsageRow = sqlx::query_as(
+            r#"
+            SELECT
+                (
+                    SELECT COALESCE(SUM(v.size_bytes), 0)::BIGINT
+                    FROM object_versions AS v
+                    JOIN objects AS o ON o.id = v.object_id
+                    WHERE o.user_id = u.id
+                ) AS user_bytes,
+                (SELECT max_bytes FROM storage_quotas WHERE user_id = u.id) AS user_quota,
+                m.organization_id,
+                (
+                    SELECT COALESCE(SUM(v.size_bytes), 0)::BIGINT
+                    FROM object_versions AS v
+                    JOIN objects AS o ON o.id = v.object_id
+                    JOIN organization_members AS om ON om.user_id = o.user_id
+                    WHERE om.organization_id = m.organization_id
+                ) AS organization_bytes,
+                (
+                    SELECT max_bytes
+                    FROM storage_quotas
+                    WHERE organization_id = m.organization_id
+                ) AS organization_quota
+            FROM (SELECT $1::UUID AS id) AS u
+            LEFT JOIN organization_members AS m ON m.user_id = u.id
+        "#,
+        )
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(StorageUsage {
+            user: Usage {
+                used_bytes: row.user_bytes,
+                quota_bytes: row.user_quota.or(default_user_quota),
+            },
+            organization_id: row.organization_id.map(|id| id.to_string()),
+            organization: row.organization_id.map(|_| Usage {
+                used_bytes: row.organization_bytes,
+                quota_bytes: row.organization_quota.or(default_organization_quota),
+            }),
+            remaining_bytes: None,
+        })
+    }
+
+    /// A user's role and organization, as storage rules see them.
+    pub async fn get_member(&self, user_id: Uuid) -> Result<Option<(String, Option<Uuid>)>> {
+        let member = sqlx::query_as(
+            r#"
+            SELECT u.role, m.organization_id
+            FROM users AS u
+            LEFT JOIN organization_members AS m ON m.user_id = u.id
+            WHERE u.id = $1
+        "#,
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(member)
+    }
+
+    // Folders
+    pub async fn create_folder(&self, user_id: Uuid, path: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO folders (user_id, path)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id, path) DO NOTHING
+        "#,
+        )
+        .bind(user_id)
+        .bind(path)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Moves the folder at `from` and every folder nested in it under `to`.
+    pub async fn move_folders(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        from: &str,
+        to: &str,
+    ) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE folders
+            SET path = $3 || substr(path, length($2) + 1)
+            WHERE user_id = $1
+            AND (path = $2 OR starts_with(path, $2 || '/'))
+        "#,
+        )
+        .bind(user_id)
+        .bind(from)
+        .bind(to)
+        .execute(tx.deref_mut())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Deletes the folder at `path` and every folder nested in it.
+    pub async fn delete_folders(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        path: &str,
+    ) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM folders
+            WHERE user_id = $1
+            AND (path = $2 OR starts_with(path, $2 || '/'))
+        "#,
+        )
+        .bind(user_id)
+        .bind(path)
+        .execute(tx.deref_mut())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[derive(FromRow)]
+struct UsageRow {
+    user_bytes: i64,
+    user_quota: Option<i64>,
+    organization_id: Option<Uuid>,
+    organization_bytes: i64,
+    organization_quota: Option<i64>,
+}
+
+#[derive(FromRow)]
+struct ListingRow {
+    entry: String,
+    is_prefix: bool,
+    id: Option<i32>,
+    user_id: Option<Uuid>,
+    key: String,
+    filename: Option<String>,
+    version: Option<i32>,
+    max_versions: Option<i32>,
+    expires_at: Option<DateTime<Utc>>,
 }
 
 // Improved init function
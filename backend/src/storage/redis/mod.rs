@@ -1,9 +1,11 @@
 use crate::{
     config::CONFIG,
-    data::{Event, Flow, Token},
+    crypt::jwt::Claims,
+    data::{ChannelMessage, Event, Flow, Token, TusUpload},
     error::Result,
 };
-use redis::{aio::MultiplexedConnection, AsyncCommands};
+use redis::aio::{MultiplexedConnection, PubSub};
+use redis::AsyncCommands;
 use serde_json;
 use sqlx::types::Uuid;
 
@@ -24,9 +26,88 @@ impl RedisClient {
         self.conn.clone()
     }
 
-    pub async fn append_event_queue(&self, key: &str, event: &Event) -> Result<()> {
-        let value = serde_json::to_vec(event).unwrap();
-        let _: () = self.conn.clone().rpush(key, value).await?;
+    /// A connection of its own, for blocking commands that would hold up every caller
+    /// of the shared one.
+    pub async fn dedicated_connection(&self) -> Result<MultiplexedConnection> {
+        Ok(self.client.get_multiplexed_tokio_connection().await?)
+    }
+
+    /// A connection for subscribing to websocket channels.
+    pub async fn pubsub(&self) -> Result<PubSub> {
+        Ok(self.client.get_async_pubsub().await?)
+    }
+
+    /// Publishes to a websocket channel, returning how many sockets received it.
+    pub async fn publish(&self, message: &ChannelMessage) -> Result<usize> {
+        let value = serde_json::to_string(message)?;
+        let receivers: usize = self
+            .conn
+            .clone()
+            .publish(channel_key(&message.channel), value)
+            .await?;
+        Ok(receivers)
+    }
+
+    /// Sends an event to the user's channel, keeping it in their queue for when they
+    /// next subscribe if no socket of theirs is listening.
+    pub async fn notify_user(&self, user_id: Uuid, event: &Event) -> Result<()> {
+        let message = ChannelMessage {
+            channel: user_channel(user_id),
+            sender: None,
+            data: serde_json::to_value(event)?,
+        };
+        if self.publish(&message).await? == 0 {
+            let value = serde_json::to_vec(event)?;
+            let _: () = self
+                .conn
+                .clone()
+                .rpush(format!("ws:{}", user_id), value)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Takes the events queued for the user while no socket of theirs was listening.
+    pub async fn take_user_events(&self, user_id: Uuid) -> Result<Vec<ChannelMessage>> {
+        let key = format!("ws:{}", user_id);
+        let (values, _): (Vec<Vec<u8>>, ()) = redis::pipe()
+            .atomic()
+            .lrange(&key, 0, -1)
+            .del(&key)
+            .query_async(&mut self.conn.clone())
+            .await?;
+
+        let mut messages = Vec::with_capacity(values.len());
+        for value in values {
+            match serde_json::from_slice::<Event>(&value) {
+                Ok(event) => messages.push(ChannelMessage {
+                    channel: user_channel(user_id),
+                    sender: None,
+                    data: serde_json::to_value(event)?,
+                }),
+                Err(e) => tracing::warn!("Dropping malformed event for {}: {}", user_id, e),
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Takes the named lock for `ttl` seconds, returning false when someone else holds it.
+    pub async fn acquire_lock(&self, name: &str, ttl: u64) -> Result<bool> {
+        let key = format!("lock:{}", name);
+        let locked: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl)
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(locked.is_some())
+    }
+
+    pub async fn release_lock(&self, name: &str) -> Result<()> {
+        let key = format!("lock:{}", name);
+        let _: () = self.conn.clone().del(key).await?;
         Ok(())
     }
 
@@ -62,6 +143,21 @@ impl RedisClient {
         Ok(Uuid::parse_str(&user_id)?)
     }
 
+    /// Stores the claims a websocket ticket stands for until it is used or expires.
+    pub async fn store_ws_ticket(&self, ticket: &str, claims: &Claims, ttl: u64) -> Result<()> {
+        let key = format!("ws-ticket:{}", ticket);
+        let value = serde_json::to_string(claims)?;
+        let _: () = self.conn.clone().set_ex(key, value, ttl).await?;
+        Ok(())
+    }
+
+    /// Redeems a websocket ticket, which works once.
+    pub async fn take_ws_ticket(&self, ticket: &str) -> Result<Option<Claims>> {
+        let key = format!("ws-ticket:{}", ticket);
+        let value: Option<String> = self.conn.clone().get_del(key).await?;
+        Ok(value.map(|v| serde_json::from_str(&v)).transpose()?)
+    }
+
     pub async fn store_flow(&self, user_id: Uuid, platform: &str, flow: &Flow) -> Result<()> {
         let key = format!("oauth:{}:{}:flow", user_id, platform);
         let value = serde_json::to_string(flow)?;
@@ -103,9 +199,111 @@ impl RedisClient {
         Ok(token)
     }
 
+    pub async fn has_oauth_tokens(&self, user_id: &str, platform: &str) -> Result<bool> {
+        let key = format!("oauth:{}:{}", user_id, platform);
+        let exists: bool = self.conn.clone().exists(key).await?;
+        Ok(exists)
+    }
+
     pub async fn delete_oauth_tokens(&self, user_id: &str, platform: &str) -> Result<()> {
         let key = format!("oauth:{}:{}", user_id, platform);
         let _: () = self.conn.clone().del(key).await?;
         Ok(())
     }
+
+    /// Deletes the user's MFA code, queued events and OAuth flows and tokens. One-time
+    /// tokens are keyed by the token rather than the user, and expire on their own.
+    pub async fn delete_user_keys(&self, user_id: Uuid) -> Result<()> {
+        let mut conn = self.conn.clone();
+        let mut keys = vec![format!("mfa:{}", user_id), format!("ws:{}", user_id)];
+        {
+            let mut scan = conn
+                .scan_match::<_, String>(format!("oauth:{}:*", user_id))
+                .await?;
+            while let Some(key) = scan.next_item().await {
+                keys.push(key);
+            }
+        }
+
+        let _: () = self.conn.clone().del(keys).await?;
+        Ok(())
+    }
+
+    /// Saves an upload's state together with its unflushed bytes, atomically so the
+    /// stored offset always matches what was received. Keys outlive the upload's
+    /// expiry by a day so the sweeper can still abort the multipart upload.
+    pub async fn store_tus_upload(&self, upload: &TusUpload, buffer: &[u8]) -> Result<()> {
+        let key = format!("tus:{}", upload.id);
+        let buffer_key = format!("tus:{}:buffer", upload.id);
+        let value = serde_json::to_string(upload)?;
+        let expire_at = upload.expires_at + 86400;
+
+        let _: () = redis::pipe()
+            .atomic()
+            .set(&key, value)
+            .expire_at(&key, expire_at)
+            .set(&buffer_key, buffer)
+            .expire_at(&buffer_key, expire_at)
+            .zadd("tus:expiry", &upload.id, upload.expires_at)
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_tus_upload(&self, id: &str) -> Result<Option<TusUpload>> {
+        let key = format!("tus:{}", id);
+        let value: Option<String> = self.conn.clone().get(key).await?;
+        Ok(value.map(|v| serde_json::from_str(&v)).transpose()?)
+    }
+
+    pub async fn get_tus_buffer(&self, id: &str) -> Result<Vec<u8>> {
+        let key = format!("tus:{}:buffer", id);
+        let buffer: Option<Vec<u8>> = self.conn.clone().get(key).await?;
+        Ok(buffer.unwrap_or_default())
+    }
+
+    pub async fn delete_tus_upload(&self, id: &str) -> Result<()> {
+        let _: () = redis::pipe()
+            .atomic()
+            .del(&[format!("tus:{}", id), format!("tus:{}:buffer", id)])
+            .zrem("tus:expiry", id)
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(())
+    }
+
+    /// Resumable uploads in progress for the user.
+    pub async fn user_tus_uploads(&self, user_id: Uuid) -> Result<Vec<TusUpload>> {
+        let ids: Vec<String> = self.conn.clone().zrange("tus:expiry", 0, -1).await?;
+        let user_id = user_id.to_string();
+        let mut uploads = Vec::new();
+        for id in ids {
+            if let Some(upload) = self.get_tus_upload(&id).await?
+                && upload.user_id == user_id
+            {
+                uploads.push(upload);
+            }
+        }
+        Ok(uploads)
+    }
+
+    /// Ids of uploads whose expiry passed before `now` (unix seconds).
+    pub async fn expired_tus_uploads(&self, now: i64) -> Result<Vec<String>> {
+        let ids: Vec<String> = self
+            .conn
+            .clone()
+            .zrangebyscore("tus:expiry", "-inf", now)
+            .await?;
+        Ok(ids)
+    }
+}
+
+/// The Redis channel a websocket channel is published on.
+pub fn channel_key(channel: &str) -> String {
+    format!("channel:{}", channel)
+}
+
+/// The channel only the user may join, which server events for them are sent on.
+pub fn user_channel(user_id: Uuid) -> String {
+    format!("user:{}", user_id)
 }
@@ -0,0 +1,220 @@
+use crate::config::CONFIG;
+use crate::error::{Error, Result};
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+use url::Url;
+
+/// Bytes to scan, streamed from the object store.
+pub type ScanStream = BoxStream<'static, Result<Bytes>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanVerdict {
+    Clean,
+    Infected(String),
+}
+
+/// Checks uploaded content for malware. Runs after the upload is recorded, so an error
+/// leaves the version quarantined rather than failing the upload.
+#[async_trait]
+pub trait Scanner: Send + Sync + Debug {
+    async fn scan(&self, data: ScanStream) -> Result<ScanVerdict>;
+}
+
+/// Builds the scanner named by `MALWARE_SCANNER`.
+pub fn get_scanner(name: &str) -> Result<Arc<dyn Scanner>> {
+    match name {
+        "none" => Ok(Arc::new(NoopScanner)),
+        "clamav" => Ok(Arc::new(ClamAvScanner::new(&CONFIG.clamav_url)?)),
+        other => Err(Error::from(format!(
+            "Unsupported malware scanner: {}",
+            other
+        ))),
+    }
+}
+
+/// Passes everything; for development without a clamd.
+#[derive(Debug)]
+pub struct NoopScanner;
+
+#[async_trait]
+impl Scanner for NoopScanner {
+    async fn scan(&self, _data: ScanStream) -> Result<ScanVerdict> {
+        Ok(ScanVerdict::Clean)
+    }
+}
+
+#[derive(Debug)]
+enum ClamdAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+/// Scans through a clamd daemon with the INSTREAM command, over TCP
+/// (`tcp://host:3310`) or a unix socket (`unix:///run/clamav/clamd.ctl`).
+#[derive(Debug)]
+pub struct ClamAvScanner {
+    address: ClamdAddress,
+}
+
+impl ClamAvScanner {
+    /// clamd rejects chunks over its StreamMaxLength, so stay well under any setting.
+    const CHUNK_SIZE: usize = 64 * 1024;
+    const TIMEOUT: Duration = Duration::from_secs(300);
+
+    pub fn new(url: &str) -> Result<Self> {
+        let parsed = Url::parse(url)?;
+
+        let address = match parsed.scheme() {
+            "tcp" => {
+                let host = parsed
+                    .host_str()
+                    .ok_or_else(|| Error::from("Missing clamd host"))?;
+                ClamdAddress::Tcp(format!("{}:{}", host, parsed.port().unwrap_or(3310)))
+            }
+            "unix" => ClamdAddress::Unix(PathBuf::from(parsed.path())),
+            scheme => return Err(Error::from(format!("Unsupported clamd scheme: {}", scheme))),
+        };
+
+        Ok(ClamAvScanner { address })
+    }
+
+    async fn instream<S>(mut conn: S, mut data: ScanStream) -> Result<ScanVerdict>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        conn.write_all(b"zINSTREAM\0").await?;
+        while let Some(chunk) = data.next().await {
+            for piece in chunk?.chunks(Self::CHUNK_SIZE) {
+                conn.write_all(&(piece.len() as u32).to_be_bytes()).await?;
+                conn.write_all(piece).await?;
+            }
+        }
+        conn.write_all(&0u32.to_be_bytes()).await?;
+        conn.flush().await?;
+
+        // Replies are NUL terminated, and clamd closes the connection after one
+        let mut reply = Vec::new();
+        let mut byte = [0u8; 1];
+        while conn.read(&mut byte).await? == 1 && byte[0] != 0 {
+            reply.push(byte[0]);
+        }
+
+        Self::parse_reply(std::str::from_utf8(&reply)?)
+    }
+
+    /// `stream: OK`, `stream: <signature> FOUND`, or `<message> ERROR`.
+    fn parse_reply(reply: &str) -> Result<ScanVerdict> {
+        let reply = reply.trim();
+        let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+
+        if result == "OK" {
+            Ok(ScanVerdict::Clean)
+        } else if let Some(signature) = result.strip_suffix(" FOUND") {
+            Ok(ScanVerdict::Infected(signature.to_string()))
+        } else {
+            Err(Error::from(format!("clamd error: {}", reply)))
+        }
+    }
+}
+
+#[async_trait]
+impl Scanner for ClamAvScanner {
+    async fn scan(&self, data: ScanStream) -> Result<ScanVerdict> {
+        let scan = async {
+            match &self.address {
+                ClamdAddress::Tcp(addr) => {
+                    Self::instream(TcpStream::connect(addr).await?, data).await
+                }
+                ClamdAddress::Unix(path) => {
+                    Self::instream(UnixStream::connect(path).await?, data).await
+                }
+            }
+        };
+
+        tokio::time::timeout(Self::TIMEOUT, scan)
+            .await
+            .map_err(|_| Error::from("clamd scan timed out"))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Accepts one INSTREAM session, collecting the streamed bytes, and answers with `reply`.
+    async fn fake_clamd(reply: &'static str) -> (String, tokio::task::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+
+            let mut command = [0u8; 10];
+            conn.read_exact(&mut command).await.unwrap();
+            assert_eq!(&command, b"zINSTREAM\0");
+
+            let mut received = Vec::new();
+            loop {
+                let length = conn.read_u32().await.unwrap() as usize;
+                if length == 0 {
+                    break;
+                }
+                let mut chunk = vec![0u8; length];
+                conn.read_exact(&mut chunk).await.unwrap();
+                received.extend_from_slice(&chunk);
+            }
+
+            conn.write_all(reply.as_bytes()).await.unwrap();
+            conn.write_all(b"\0").await.unwrap();
+            received
+        });
+
+        (format!("tcp://{}", addr), handle)
+    }
+
+    fn stream(parts: &[&'static [u8]]) -> ScanStream {
+        let parts: Vec<Result<Bytes>> = parts.iter().map(|p| Ok(Bytes::from_static(p))).collect();
+        futures::stream::iter(parts).boxed()
+    }
+
+    #[tokio::test]
+    async fn test_clamav_clean() {
+        let (url, server) = fake_clamd("stream: OK").await;
+        let scanner = ClamAvScanner::new(&url).unwrap();
+
+        let verdict = scanner.scan(stream(&[b"hello ", b"world"])).await.unwrap();
+        assert_eq!(verdict, ScanVerdict::Clean);
+        assert_eq!(server.await.unwrap(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_clamav_infected() {
+        let (url, server) = fake_clamd("stream: Eicar-Test-Signature FOUND").await;
+        let scanner = ClamAvScanner::new(&url).unwrap();
+
+        let verdict = scanner.scan(stream(&[b"X5O!P%@AP"])).await.unwrap();
+        assert_eq!(
+            verdict,
+            ScanVerdict::Infected("Eicar-Test-Signature".to_string())
+        );
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clamav_error() {
+        let (url, server) = fake_clamd("INSTREAM size limit exceeded. ERROR").await;
+        let scanner = ClamAvScanner::new(&url).unwrap();
+
+        assert!(scanner.scan(stream(&[b"too big"])).await.is_err());
+        server.await.unwrap();
+    }
+}
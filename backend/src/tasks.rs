@@ -0,0 +1,166 @@
+use crate::config::CONFIG;
+use crate::exports;
+use crate::state::AppState;
+use crate::users::deletion;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// Starts the background jobs enabled in the config.
+pub fn spawn_background_tasks(state: Arc<AppState>) {
+    // Every replica relays changes to its own sockets, so this one runs everywhere
+    let realtime = state.clone();
+    tokio::spawn(async move {
+        realtime
+            .realtime
+            .listen(&realtime.storage.postgres.pool, &CONFIG.data_api_schema)
+            .await
+    });
+
+    if CONFIG.reconcile_interval_minutes > 0 {
+        tokio::spawn(run_periodically(
+            state.clone(),
+            "reconcile",
+            CONFIG.reconcile_interval_minutes,
+            reconcile_storage,
+        ));
+    }
+    if CONFIG.lifecycle_interval_minutes > 0 {
+        tokio::spawn(run_periodically(
+            state.clone(),
+            "lifecycle",
+            CONFIG.lifecycle_interval_minutes,
+            sweep_lifecycle,
+        ));
+    }
+    if CONFIG.tus_sweep_interval_minutes > 0 {
+        tokio::spawn(run_periodically(
+            state.clone(),
+            "tus-sweep",
+            CONFIG.tus_sweep_interval_minutes,
+            sweep_expired_uploads,
+        ));
+    }
+    if CONFIG.scan_retry_interval_minutes > 0 {
+        tokio::spawn(run_periodically(
+            state.clone(),
+            "scan-retry",
+            CONFIG.scan_retry_interval_minutes,
+            retry_scans,
+        ));
+    }
+    if CONFIG.export_interval_minutes > 0 {
+        tokio::spawn(run_periodically(
+            state.clone(),
+            "exports",
+            CONFIG.export_interval_minutes,
+            process_exports,
+        ));
+    }
+    if CONFIG.account_purge_interval_minutes > 0 {
+        tokio::spawn(run_periodically(
+            state,
+            "account-purge",
+            CONFIG.account_purge_interval_minutes,
+            purge_deleted_accounts,
+        ));
+    }
+}
+
+/// Runs `job` every `minutes`. The lock keeps replicas from running it at the same time.
+async fn run_periodically<F, Fut>(state: Arc<AppState>, name: &str, minutes: u64, job: F)
+where
+    F: Fn(Arc<AppState>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let period = Duration::from_secs(minutes * 60);
+    // Held a little short of the period so the next tick finds it free
+    let lock_seconds = period.as_secs().saturating_sub(60).max(60);
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    // The first tick is immediate; leave startup alone
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        match state.storage.redis.acquire_lock(name, lock_seconds).await {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                tracing::error!("Failed to take the {} lock: {}", name, e);
+                continue;
+            }
+        }
+
+        job(state.clone()).await;
+    }
+}
+
+/// Reconciles the object store with the `objects` tables, repairing only when
+/// configured to.
+async fn reconcile_storage(state: Arc<AppState>) {
+    match state.storage.reconcile(CONFIG.reconcile_repair).await {
+        Ok(report) => tracing::info!(
+            "Reconciled storage: {} orphaned blobs, {} dangling versions, repaired: {}",
+            report.orphaned_blobs.len(),
+            report.dangling_versions.len(),
+            report.repaired
+        ),
+        Err(e) => tracing::error!("Failed to reconcile storage: {}", e),
+    }
+}
+
+/// Deletes expired objects and archives old blobs per lifecycle rules.
+async fn sweep_lifecycle(state: Arc<AppState>) {
+    match state.storage.sweep_lifecycle().await {
+        Ok(report) => tracing::info!(
+            "Swept lifecycle: {} expired objects, {} archived blobs",
+            report.expired_objects,
+            report.transitioned_blobs
+        ),
+        Err(e) => tracing::error!("Failed to sweep lifecycle: {}", e),
+    }
+}
+
+/// Terminates resumable uploads abandoned before they completed.
+async fn sweep_expired_uploads(state: Arc<AppState>) {
+    match state.storage.sweep_expired_uploads().await {
+        Ok(0) => {}
+        Ok(swept) => tracing::info!("Terminated {} expired uploads", swept),
+        Err(e) => tracing::error!("Failed to sweep expired uploads: {}", e),
+    }
+}
+
+/// Scans again the uploads whose malware scan failed or was lost.
+async fn retry_scans(state: Arc<AppState>) {
+    match state.storage.retry_scans().await {
+        Ok(0) => {}
+        Ok(scanned) => tracing::info!("Scanned {} blobs again", scanned),
+        Err(e) => tracing::error!("Failed to retry scans: {}", e),
+    }
+}
+
+/// Permanently deletes accounts whose deletion grace period has run out.
+async fn purge_deleted_accounts(state: Arc<AppState>) {
+    match deletion::purge_due(&state).await {
+        Ok(0) => {}
+        Ok(purged) => tracing::info!("Purged {} deleted accounts", purged),
+        Err(e) => tracing::error!("Failed to purge deleted accounts: {}", e),
+    }
+}
+
+/// Resumes data exports that are waiting or whose run died, and deletes expired ones.
+async fn process_exports(state: Arc<AppState>) {
+    match exports::resume_due(&state).await {
+        Ok(0) => {}
+        Ok(resumed) => tracing::info!("Resumed {} data exports", resumed),
+        Err(e) => tracing::error!("Failed to resume data exports: {}", e),
+    }
+    match exports::sweep_expired(&state).await {
+        Ok(0) => {}
+        Ok(swept) => tracing::info!("Deleted {} expired data exports", swept),
+        Err(e) => tracing::error!("Failed to delete expired data exports: {}", e),
+    }
+}
@@ -0,0 +1,110 @@
+//! Account deletion. A request signs the user out, disables sign in and emails a link to
+//! cancel; once the grace period runs out the account is purged with everything it stored
+//! and linked.
+
+use super::queries::UserQueries;
+use crate::audit::{Audit, Event, Outcome};
+use crate::auth::oauth::service::PROVIDERS;
+use crate::auth::queries::TokenQueries;
+use crate::config::CONFIG;
+use crate::crypt::tokens::generate_token;
+use crate::error::Result;
+use crate::smtp::messages::{cancel_deletion_body, Email};
+use crate::state::AppState;
+use chrono::{DateTime, Duration, Utc};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::Address;
+use sqlx::types::Uuid;
+
+/// Users purged per query while sweeping.
+const PURGE_BATCH: i64 = 100;
+
+/// Schedules the user's deletion and emails them a link to cancel it, returning when
+/// the deletion happens.
+pub async fn request(state: &AppState, user_id: Uuid) -> Result<DateTime<Utc>> {
+    let pool = &state.storage.postgres.pool;
+    let user = UserQueries::get_user(user_id, pool).await?;
+    let grace = Duration::days(CONFIG.account_deletion_grace_days);
+
+    let mut tx = state.storage.postgres.start_transaction().await?;
+    let delete_after = UserQueries::request_deletion(&mut tx, user_id, Utc::now() + grace).await?;
+    TokenQueries::delete_user_refresh_tokens(user_id, &mut tx).await?;
+
+    // The link works for as long as there is something to cancel
+    let token = generate_token();
+    let ttl = (delete_after - Utc::now()).num_seconds().max(1) as u64;
+    state
+        .storage
+        .redis
+        .store_token(&token, "cancel-deletion", user_id, ttl)
+        .await?;
+
+    let url = format!("{}/users/cancel-deletion?token={}", CONFIG.app_url, token);
+    let email = Email {
+        recipient: Mailbox::new(Some("".to_owned()), user.email.parse::<Address>()?),
+        sender: Mailbox::new(
+            Some("Info".to_owned()),
+            CONFIG.smtp_email.parse::<Address>()?,
+        ),
+        subject: String::from("Your account will be deleted"),
+        header: ContentType::TEXT_HTML,
+        body: cancel_deletion_body(&url, &delete_after.format("%B %-d, %Y").to_string())?,
+    };
+    state.smtp.send_email(email)?;
+
+    tx.commit().await?;
+    Ok(delete_after)
+}
+
+/// Purges every user whose grace period has run out, returning how many were. A user
+/// that fails is logged and retried on the next sweep.
+pub async fn purge_due(state: &AppState) -> Result<u64> {
+    let mut purged = 0;
+    let mut failed = Vec::new();
+    loop {
+        // Users that failed are skipped, so they can't hold up the rest
+        let pool = &state.storage.postgres.pool;
+        let due = UserQueries::due_for_deletion(PURGE_BATCH, &failed, pool).await?;
+        if due.is_empty() {
+            break;
+        }
+
+        for user_id in due {
+            match purge(state, user_id).await {
+                Ok(()) => purged += 1,
+                Err(e) => {
+                    tracing::error!("Failed to purge user {}: {}", user_id, e);
+                    failed.push(user_id);
+                }
+            }
+        }
+    }
+    Ok(purged)
+}
+
+/// Revokes the user's linked accounts at their providers, then deletes the user with
+/// their objects, blobs and Redis keys.
+async fn purge(state: &AppState, user_id: Uuid) -> Result<()> {
+    let redis = &state.storage.redis;
+    let id = user_id.to_string();
+
+    for provider in PROVIDERS {
+        if !redis.has_oauth_tokens(&id, provider).await? {
+            continue;
+        }
+        // A grant the user already revoked themselves mustn't keep the account around
+        let token = redis.get_oauth_tokens(&id, provider).await?;
+        if let Err(e) = state.oauth.revoke(provider, &token).await {
+            tracing::warn!("Failed to revoke {} tokens of user {}: {}", provider, id, e);
+        }
+    }
+
+    state.storage.delete_user(user_id).await?;
+
+    let event = Event::new("user.purge").actor(user_id);
+    Audit::default()
+        .record(state, event, Outcome::Success)
+        .await;
+    Ok(())
+}
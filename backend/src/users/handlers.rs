@@ -1,4 +1,10 @@
+use super::deletion;
+use super::profile;
 use super::queries::UserQueries;
+use crate::audit::models::ActivityQuery;
+use crate::audit::queries::AuditQueries;
+use crate::audit::{Audit, Event, Outcome};
+use crate::auth::models::VerifyQuery;
 use crate::crypt::hash::hash_password;
 use crate::crypt::jwt::Claims;
 use crate::error::Result;
@@ -6,11 +12,14 @@ use crate::response::ApiResponse;
 use crate::state::AppState;
 use crate::users::models::UpdateEmailPayload;
 use crate::users::models::UpdatePasswordPayload;
-use axum::extract::State;
+use crate::users::models::UpdateProfilePayload;
+use crate::Error;
+use axum::extract::{Query, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::Extension;
 use axum::Json;
+use serde_json::json;
 use sqlx::types::Uuid;
 use std::sync::Arc;
 
@@ -30,14 +39,19 @@ pub async fn get_user(
 pub async fn update_user_email(
     State(state): State<Arc<AppState>>,
     Extension(claims): Extension<Claims>,
+    audit: Audit,
     Json(payload): Json<UpdateEmailPayload>,
 ) -> Result<impl IntoResponse> {
     let user_id = Uuid::parse_str(&claims.sub)?;
     let mut tx = state.storage.postgres.start_transaction().await?;
+    let event = Event::new("user.update_email")
+        .actor(user_id)
+        .target(&payload.email);
 
     match UserQueries::update_email(&mut tx, user_id, &payload.email).await {
         Ok(()) => {
             tx.commit().await?;
+            audit.record(&state, event, Outcome::Success).await;
 
             Ok(ApiResponse::new(
                 StatusCode::OK,
@@ -47,6 +61,7 @@ pub async fn update_user_email(
         }
         Err(e) => {
             tx.rollback().await?;
+            audit.record(&state, event, Outcome::Failure).await;
             Err(e.into())
         }
     }
@@ -55,9 +70,11 @@ pub async fn update_user_email(
 pub async fn update_user_password(
     State(state): State<Arc<AppState>>,
     Extension(claims): Extension<Claims>,
+    audit: Audit,
     Json(payload): Json<UpdatePasswordPayload>,
 ) -> Result<impl IntoResponse> {
     let user_id = Uuid::parse_str(&claims.sub)?;
+    let event = Event::new("user.update_password").actor(user_id);
 
     let mut tx = state.storage.postgres.start_transaction().await?;
     let hash = &hash_password(&payload.password)?;
@@ -65,6 +82,7 @@ pub async fn update_user_password(
     match UserQueries::update_password(&mut tx, user_id, hash).await {
         Ok(()) => {
             tx.commit().await?;
+            audit.record(&state, event, Outcome::Success).await;
 
             Ok(ApiResponse::new(
                 StatusCode::OK,
@@ -74,32 +92,130 @@ pub async fn update_user_password(
         }
         Err(e) => {
             tx.rollback().await?;
+            audit.record(&state, event, Outcome::Failure).await;
             Err(e.into())
         }
     }
 }
 
+/// Schedules the caller's account for deletion after the grace period, which the link
+/// emailed to them cancels.
 pub async fn delete_user(
     State(state): State<Arc<AppState>>,
     Extension(claims): Extension<Claims>,
+    audit: Audit,
 ) -> Result<impl IntoResponse> {
     let user_id = Uuid::parse_str(&claims.sub)?;
+    let event = Event::new("user.request_deletion").actor(user_id);
 
-    let mut tx = state.storage.postgres.start_transaction().await?;
+    let result = deletion::request(&state, user_id).await;
+    audit.record(&state, event, Outcome::of(&result)).await;
+    let delete_after = result?;
 
-    match UserQueries::delete(&mut tx, user_id).await {
-        Ok(()) => {
-            tx.commit().await?;
+    Ok(ApiResponse::new(
+        StatusCode::ACCEPTED,
+        &format!("User id {} will be deleted on {}.", user_id, delete_after),
+        json!({ "delete_after": delete_after }),
+    ))
+}
 
-            Ok(ApiResponse::new(
-                StatusCode::NO_CONTENT,
-                &format!("Deleted user id {} successfully.", user_id),
-                "".to_string(),
-            ))
-        }
-        Err(e) => {
-            tx.rollback().await?;
-            Err(e.into())
-        }
+pub async fn cancel_deletion(
+    State(state): State<Arc<AppState>>,
+    audit: Audit,
+    Query(query): Query<VerifyQuery>,
+) -> Result<impl IntoResponse> {
+    let user_id = match state
+        .storage
+        .redis
+        .get_token(&query.token, "cancel-deletion")
+        .await
+    {
+        Ok(user_id) => user_id,
+        Err(_) => return Err(Error::CustomError("Invalid or expired token".into())),
+    };
+
+    let result = UserQueries::cancel_deletion(user_id, &state.storage.postgres.pool).await;
+    let event = Event::new("user.cancel_deletion").actor(user_id);
+    audit.record(&state, event, Outcome::of(&result)).await;
+    if !result? {
+        return Err(Error::HttpError(
+            StatusCode::NOT_FOUND,
+            "No deletion is pending".into(),
+        ));
     }
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Account deletion cancelled.",
+        "",
+    ))
+}
+
+/// The caller's own audit trail, so they can spot sign ins and changes they didn't make.
+pub async fn get_security_activity(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Query(params): Query<ActivityQuery>,
+) -> Result<impl IntoResponse> {
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let page = AuditQueries::list(
+        Some(user_id),
+        None,
+        None,
+        params.before,
+        params.limit,
+        &state.storage.postgres.pool,
+    )
+    .await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully retrieved security activity",
+        page,
+    ))
+}
+
+pub async fn get_profile(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse> {
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let profile = profile::get(&state, user_id).await?;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Successfully retrieved profile",
+        profile,
+    ))
+}
+
+/// Updates the caller's profile. Metadata they set reaches their token's claims once it
+/// is refreshed.
+pub async fn update_profile(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    audit: Audit,
+    Json(payload): Json<UpdateProfilePayload>,
+) -> Result<impl IntoResponse> {
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    let event = Event::new("user.update_profile").actor(user_id);
+
+    let result = profile::update(&state, user_id, &payload).await;
+    audit.record(&state, event, Outcome::of(&result)).await;
+
+    Ok(ApiResponse::new(
+        StatusCode::OK,
+        "Profile updated successfully",
+        result?,
+    ))
+}
+
+pub async fn delete_avatar(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse> {
+    let user_id = Uuid::parse_str(&claims.sub)?;
+    profile::clear_avatar(&state, user_id).await?;
+
+    Ok(ApiResponse::new(StatusCode::OK, "Avatar removed", ""))
 }
@@ -1,4 +1,6 @@
+pub mod deletion;
 pub mod handlers;
 pub mod models;
+pub mod profile;
 pub mod queries;
 pub mod routes;
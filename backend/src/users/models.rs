@@ -1,5 +1,7 @@
 use serde::ser::{SerializeStruct, Serializer};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::types::Json;
 use sqlx::{prelude::FromRow, types::Uuid};
 
 #[derive(Debug, Deserialize)]
@@ -33,3 +35,32 @@ impl Serialize for User {
         s.end()
     }
 }
+
+/// The user as they present themselves, with the metadata kept about them.
+#[derive(Debug, FromRow, Serialize)]
+pub struct Profile {
+    pub id: String,
+    pub email: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub display_name: Option<String>,
+    /// Path of the avatar among the user's objects.
+    pub avatar: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    pub app_metadata: Json<Map<String, Value>>,
+    pub user_metadata: Json<Map<String, Value>>,
+}
+
+/// Fields left out are kept and an empty string clears one. `user_metadata` is merged
+/// into what is stored, where a key set to null is removed.
+#[derive(Debug, Deserialize)]
+pub struct UpdateProfilePayload {
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    pub user_metadata: Option<Map<String, Value>>,
+}
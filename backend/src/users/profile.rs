@@ -0,0 +1,181 @@
+//! Profiles: the names, avatar, locale and time zone a user presents, with two bags of
+//! free-form metadata. `user_metadata` is the user's own to write; `app_metadata` holds
+//! what the application decides about them, like a plan, and only admins write it.
+
+use super::models::{Profile, UpdateProfilePayload};
+use super::queries::UserQueries;
+use crate::data::Objects;
+use crate::error::{Error, Result};
+use crate::state::AppState;
+use crate::utils::normalize_path;
+use axum::http::StatusCode;
+use serde_json::{Map, Value};
+use sqlx::types::Uuid;
+
+const MAX_NAME_CHARS: usize = 50;
+const MAX_DISPLAY_NAME_CHARS: usize = 100;
+/// Largest a user's `app_metadata` or `user_metadata` may grow, as JSON.
+const MAX_METADATA_BYTES: i32 = 16 * 1024;
+
+pub async fn get(state: &AppState, user_id: Uuid) -> Result<Profile> {
+    UserQueries::get_profile(user_id, &state.storage.postgres.pool)
+        .await?
+        .ok_or_else(|| Error::HttpError(StatusCode::NOT_FOUND, "User not found".into()))
+}
+
+/// Applies the user's changes to their profile, returning it as it now stands.
+pub async fn update(
+    state: &AppState,
+    user_id: Uuid,
+    payload: &UpdateProfilePayload,
+) -> Result<Profile> {
+    validate(state, payload).await?;
+    let avatar = match payload.avatar.as_deref() {
+        None => None,
+        Some("") => Some(None),
+        Some(name) => Some(Some(avatar_object(state, user_id, name).await?)),
+    };
+
+    let mut tx = state.storage.postgres.start_transaction().await?;
+    UserQueries::update_profile(&mut tx, user_id, payload).await?;
+    if let Some(object_id) = avatar {
+        UserQueries::set_avatar(&mut tx, user_id, object_id).await?;
+    }
+    if let Some(user_metadata) = &payload.user_metadata {
+        let size = UserQueries::merge_metadata(&mut tx, user_id, None, Some(user_metadata)).await?;
+        check_metadata_size(size)?;
+    }
+    tx.commit().await?;
+
+    get(state, user_id).await
+}
+
+/// Merges an admin's changes into a user's metadata, returning their profile.
+pub async fn update_metadata(
+    state: &AppState,
+    user_id: Uuid,
+    app_metadata: Option<&Map<String, Value>>,
+    user_metadata: Option<&Map<String, Value>>,
+) -> Result<Profile> {
+    let mut tx = state.storage.postgres.start_transaction().await?;
+    let size = UserQueries::merge_metadata(&mut tx, user_id, app_metadata, user_metadata)
+        .await?
+        .ok_or_else(|| Error::HttpError(StatusCode::NOT_FOUND, "User not found".into()))?;
+    check_metadata_size(Some(size))?;
+    tx.commit().await?;
+
+    get(state, user_id).await
+}
+
+pub async fn clear_avatar(state: &AppState, user_id: Uuid) -> Result<()> {
+    let mut tx = state.storage.postgres.start_transaction().await?;
+    UserQueries::set_avatar(&mut tx, user_id, None).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn validate(state: &AppState, payload: &UpdateProfilePayload) -> Result<()> {
+    let names = [
+        ("first_name", &payload.first_name, MAX_NAME_CHARS),
+        ("last_name", &payload.last_name, MAX_NAME_CHARS),
+        (
+            "display_name",
+            &payload.display_name,
+            MAX_DISPLAY_NAME_CHARS,
+        ),
+    ];
+    for (field, value, max) in names {
+        if value.as_ref().is_some_and(|v| v.chars().count() > max) {
+            return Err(invalid(&format!(
+                "{} is longer than {} characters",
+                field, max
+            )));
+        }
+    }
+
+    if let Some(locale) = payload.locale.as_deref()
+        && !locale.is_empty()
+        && !is_language_tag(locale)
+    {
+        return Err(invalid("locale is not a language tag like 'en-CA'"));
+    }
+    if let Some(timezone) = payload.timezone.as_deref() {
+        let pool = &state.storage.postgres.pool;
+        if !timezone.is_empty() && !UserQueries::timezone_exists(timezone, pool).await? {
+            return Err(invalid(
+                "timezone is not a time zone like 'America/Toronto'",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks an avatar is one of the user's images that can be served, returning its
+/// object.
+async fn avatar_object(state: &AppState, user_id: Uuid, name: &str) -> Result<i32> {
+    let key = Objects::key_for(user_id, &normalize_path(name)?);
+    let version = state
+        .storage
+        .postgres
+        .get_current_version(&key)
+        .await?
+        .ok_or_else(|| Error::HttpError(StatusCode::NOT_FOUND, "Avatar not found".into()))?;
+    if !version.content_type.starts_with("image/") {
+        return Err(invalid("avatar is not an image"));
+    }
+    version.ensure_servable()?;
+    Ok(version.object_id)
+}
+
+fn check_metadata_size(size: Option<i32>) -> Result<()> {
+    match size {
+        Some(size) if size > MAX_METADATA_BYTES => Err(Error::HttpError(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Metadata is larger than {} bytes", MAX_METADATA_BYTES),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// A BCP 47 tag in its common shape: a 2 or 3 letter language followed by subtags of
+/// letters and digits, like `en`, `pt-BR` or `zh-Hant-TW`.
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let language = subtags.next().unwrap_or_default();
+    tag.len() <= 35
+        && (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags
+            .all(|s| (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+fn invalid(message: &str) -> Error {
+    Error::HttpError(StatusCode::BAD_REQUEST, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_language_tag() {
+        for tag in ["en", "fil", "pt-BR", "zh-Hant-TW", "es-419", "de-CH-1996"] {
+            assert!(is_language_tag(tag), "{} is a tag", tag);
+        }
+        for tag in [
+            "",
+            "e",
+            "engl",
+            "e1",
+            "en-",
+            "en--US",
+            "en_US",
+            "en-toolongsubtag",
+            "en US",
+            "ñe",
+            "en-abcdefgh-abcdefgh-abcdefgh-abcdef",
+        ] {
+            assert!(!is_language_tag(tag), "{:?} isn't a tag", tag);
+        }
+    }
+}
@@ -1,5 +1,9 @@
-use crate::{users::models::User, Result};
-use sqlx::types::Uuid;
+use crate::crypt::jwt::Metadata;
+use crate::users::models::{Profile, UpdateProfilePayload, User};
+use crate::Result;
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use sqlx::types::{Json, Uuid};
 use sqlx::{PgPool, Postgres, Transaction};
 
 pub struct UserQueries;
@@ -20,6 +24,142 @@ impl UserQueries {
 
         Ok(user)
     }
+    pub async fn get_profile(id: Uuid, pool: &PgPool) -> Result<Option<Profile>> {
+        let profile: Option<Profile> = sqlx::query_as(
+            r#"
+            SELECT u.id::TEXT AS id, u.email, u.first_name, u.last_name, u.display_name,
+                substring(o.key FROM length(u.id::TEXT) + 2) AS avatar,
+                u.locale, u.timezone, u.app_metadata, u.user_metadata
+            FROM users u
+            LEFT JOIN objects o ON o.id = u.avatar_object_id AND o.user_id = u.id
+            WHERE u.id = $1
+        "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(profile)
+    }
+
+    /// Sets the profile fields given, clearing those given as an empty string.
+    pub async fn update_profile(
+        tx: &mut Transaction<'_, Postgres>,
+        id: Uuid,
+        payload: &UpdateProfilePayload,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET first_name = CASE WHEN $2::TEXT IS NULL THEN first_name ELSE NULLIF($2, '') END,
+                last_name = CASE WHEN $3::TEXT IS NULL THEN last_name ELSE NULLIF($3, '') END,
+                display_name = CASE WHEN $4::TEXT IS NULL THEN display_name ELSE NULLIF($4, '') END,
+                locale = CASE WHEN $5::TEXT IS NULL THEN locale ELSE NULLIF($5, '') END,
+                timezone = CASE WHEN $6::TEXT IS NULL THEN timezone ELSE NULLIF($6, '') END
+            WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .bind(payload.first_name.as_deref())
+        .bind(payload.last_name.as_deref())
+        .bind(payload.display_name.as_deref())
+        .bind(payload.locale.as_deref())
+        .bind(payload.timezone.as_deref())
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_avatar(
+        tx: &mut Transaction<'_, Postgres>,
+        id: Uuid,
+        object_id: Option<i32>,
+    ) -> Result<()> {
+        sqlx::query(r#"UPDATE users SET avatar_object_id = $2 WHERE id = $1"#)
+            .bind(id)
+            .bind(object_id)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+
+    /// Merges into the user's metadata, removing keys set to null. Returns the size in
+    /// bytes of the larger of the two afterwards, or None if there is no such user.
+    pub async fn merge_metadata(
+        tx: &mut Transaction<'_, Postgres>,
+        id: Uuid,
+        app_metadata: Option<&Map<String, Value>>,
+        user_metadata: Option<&Map<String, Value>>,
+    ) -> Result<Option<i32>> {
+        let size: Option<i32> = sqlx::query_scalar(
+            r#"
+            UPDATE users
+            SET app_metadata = CASE WHEN $2::JSONB IS NULL THEN app_metadata
+                    ELSE (app_metadata || $2)
+                        - ARRAY(SELECT key FROM jsonb_each($2) WHERE value = 'null'::JSONB)
+                END,
+                user_metadata = CASE WHEN $3::JSONB IS NULL THEN user_metadata
+                    ELSE (user_metadata || $3)
+                        - ARRAY(SELECT key FROM jsonb_each($3) WHERE value = 'null'::JSONB)
+                END
+            WHERE id = $1
+            RETURNING GREATEST(octet_length(app_metadata::TEXT), octet_length(user_metadata::TEXT))
+        "#,
+        )
+        .bind(id)
+        .bind(app_metadata.map(Json))
+        .bind(user_metadata.map(Json))
+        .fetch_optional(&mut **tx)
+        .await?;
+
+        Ok(size)
+    }
+
+    /// The metadata `JWT_METADATA_CLAIMS` puts in the user's tokens.
+    pub async fn get_claim_metadata(id: Uuid, pool: &PgPool) -> Result<Metadata> {
+        let metadata: Json<Metadata> = sqlx::query_scalar(
+            r#"
+            SELECT jsonb_build_object('app_metadata', app_metadata, 'user_metadata', user_metadata)
+            FROM users
+            WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(Metadata::select(
+            &metadata.app_metadata,
+            &metadata.user_metadata,
+        ))
+    }
+
+    pub async fn timezone_exists(name: &str, pool: &PgPool) -> Result<bool> {
+        let exists: bool =
+            sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM pg_timezone_names WHERE name = $1)"#)
+                .bind(name)
+                .fetch_one(pool)
+                .await?;
+        Ok(exists)
+    }
+
+    pub async fn get_role(id: Uuid, pool: &PgPool) -> Result<String> {
+        let role: String = sqlx::query_scalar(
+            r#"
+            SELECT role
+            FROM users
+            WHERE id=$1
+            LIMIT 1
+        "#,
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(role)
+    }
+
     pub async fn get_user_id(email: &str, pool: &PgPool) -> Result<Uuid> {
         let id: Uuid = sqlx::query_scalar(
             r#"
@@ -83,6 +223,70 @@ impl UserQueries {
         Ok(())
     }
 
+    /// Schedules the user's deletion, returning when it happens. Asking again keeps the
+    /// first schedule.
+    pub async fn request_deletion(
+        tx: &mut Transaction<'_, Postgres>,
+        id: Uuid,
+        delete_after: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>> {
+        let delete_after: DateTime<Utc> = sqlx::query_scalar(
+            r#"
+            UPDATE users
+            SET delete_after = COALESCE(delete_after, $2)
+            WHERE id = $1
+            RETURNING delete_after
+        "#,
+        )
+        .bind(id)
+        .bind(delete_after)
+        .fetch_one(&mut **tx)
+        .await?;
+
+        Ok(delete_after)
+    }
+
+    /// Returns false when no deletion was pending.
+    pub async fn cancel_deletion(id: Uuid, pool: &PgPool) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE users
+            SET delete_after = NULL
+            WHERE id = $1
+            AND delete_after IS NOT NULL
+        "#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Users whose grace period has run out, leaving out those `excluded`.
+    pub async fn due_for_deletion(
+        limit: i64,
+        excluded: &[Uuid],
+        pool: &PgPool,
+    ) -> Result<Vec<Uuid>> {
+        let ids: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            SELECT id
+            FROM users
+            WHERE delete_after <= NOW()
+            AND id <> ALL($2)
+            ORDER BY delete_after
+            LIMIT $1
+        "#,
+        )
+        .bind(limit)
+        .bind(excluded)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(ids)
+    }
+
     pub async fn get_password(email: &str, pool: &PgPool) -> Result<String> {
         let hash: String = sqlx::query_scalar(
             r#"
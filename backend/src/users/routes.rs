@@ -1,7 +1,10 @@
 use crate::middleware::auth_middleware;
 use crate::state::AppState;
-use crate::users::handlers::{delete_user, get_user, update_user_email, update_user_password};
-use axum::routing::{delete, get, put};
+use crate::users::handlers::{
+    cancel_deletion, delete_avatar, delete_user, get_profile, get_security_activity, get_user,
+    update_profile, update_user_email, update_user_password,
+};
+use axum::routing::{delete, get, patch, post, put};
 use axum::{middleware, Router};
 use std::sync::Arc;
 
@@ -11,6 +14,11 @@ pub fn router() -> Router<Arc<AppState>> {
         .route("/", delete(delete_user))
         .route("/email", put(update_user_email))
         .route("/password", put(update_user_password))
+        .route("/security-activity", get(get_security_activity))
+        .route("/profile", get(get_profile))
+        .route("/profile", patch(update_profile))
+        .route("/profile/avatar", delete(delete_avatar))
         // .route("/", put(delete_user))
         .route_layer(middleware::from_fn(auth_middleware))
+        .route("/cancel-deletion", post(cancel_deletion))
 }
@@ -1,8 +1,9 @@
 use crate::{Error, Result};
 use anyhow::Result as anyResult;
+use base64::{engine::general_purpose, Engine as _};
 use sqlx::Column;
 use sqlx::Row;
-use std::{env, fmt::Display, str::FromStr};
+use std::{collections::HashMap, env, fmt::Display, str::FromStr};
 
 /// Used for debugging purposes. ADJUST FOR TESTING
 #[allow(dead_code)]
@@ -44,6 +45,37 @@ where
         .map_err(|e| Error::from(format!("Error parsing '{}': {}", name, e)))
 }
 
+/// Normalizes a user supplied object path (`reports//2025/` -> `reports/2025`),
+/// rejecting relative segments so keys can't escape the user's root.
+pub fn normalize_path(path: &str) -> Result<String> {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    if segments.iter().any(|s| *s == "." || *s == "..") {
+        return Err(Error::from(format!("Invalid path '{}'", path)));
+    }
+
+    Ok(segments.join("/"))
+}
+
+/// Parses a tus `Upload-Metadata` header (`filename d29ybGQ=,is_confidential`) into
+/// decoded key/value pairs; keys without a value map to an empty string.
+pub fn parse_upload_metadata(header: &str) -> Result<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+
+    for pair in header.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => {
+                let decoded = general_purpose::STANDARD.decode(value.trim())?;
+                (key, String::from_utf8(decoded).map_err(|e| e.utf8_error())?)
+            }
+            None => (pair, String::new()),
+        };
+        metadata.insert(key.to_string(), value);
+    }
+
+    Ok(metadata)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -53,4 +85,20 @@ mod tests {
         let x = get_env::<String>("APP_PORT", Some("false")).unwrap();
         println!("{:?}", x);
     }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/reports//2025/").unwrap(), "reports/2025");
+        assert_eq!(normalize_path("").unwrap(), "");
+        assert!(normalize_path("reports/../other").is_err());
+        assert!(normalize_path("./reports").is_err());
+    }
+
+    #[test]
+    fn test_parse_upload_metadata() {
+        let metadata = parse_upload_metadata("filename cmVwb3J0LnBkZg==, is_confidential").unwrap();
+        assert_eq!(metadata["filename"], "report.pdf");
+        assert_eq!(metadata["is_confidential"], "");
+        assert!(parse_upload_metadata("filename not-base64!").is_err());
+    }
 }
@@ -0,0 +1,88 @@
+//! Authenticates websocket upgrades. Browsers can't set headers on a websocket, so
+//! besides `Authorization: Bearer` the token can be offered as the subprotocols
+//! `bearer, <jwt>`, or swapped beforehand for a short-lived ticket passed as `?ticket=`.
+//! A socket lasts only as long as the token it was opened with.
+
+use crate::crypt::jwt::Claims;
+use crate::middleware::{get_token, validate_jwt};
+use crate::state::AppState;
+use crate::{Error, Result};
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use axum::extract::{FromRequestParts, Query};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use chrono::Utc;
+use futures_util::stream::SplitSink;
+use futures_util::SinkExt;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The subprotocol a client offers next to its token, and which the server selects.
+pub const BEARER_PROTOCOL: &str = "bearer";
+
+#[derive(Debug, Deserialize)]
+struct TicketQuery {
+    ticket: Option<String>,
+}
+
+/// The claims of the token a websocket was opened with.
+pub struct SocketClaims(pub Claims);
+
+impl FromRequestParts<Arc<AppState>> for SocketClaims {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self> {
+        let token = get_token(&parts.headers)
+            .ok()
+            .or_else(|| protocol_token(parts));
+        if let Some(token) = token {
+            let claims =
+                validate_jwt(token, true).map_err(|_| unauthorized("Invalid or expired token"))?;
+            return Ok(SocketClaims(claims));
+        }
+
+        let Query(query) = Query::<TicketQuery>::try_from_uri(&parts.uri)
+            .map_err(|e| Error::HttpError(StatusCode::BAD_REQUEST, e.body_text()))?;
+        let Some(ticket) = query.ticket else {
+            return Err(unauthorized("Missing or invalid token"));
+        };
+        match state.storage.redis.take_ws_ticket(&ticket).await? {
+            Some(claims) if claims.exp > Utc::now().timestamp() as usize => {
+                Ok(SocketClaims(claims))
+            }
+            _ => Err(unauthorized("Invalid or expired ticket")),
+        }
+    }
+}
+
+/// The token offered after the `bearer` subprotocol.
+fn protocol_token(parts: &Parts) -> Option<&str> {
+    let protocols = parts
+        .headers
+        .get(header::SEC_WEBSOCKET_PROTOCOL)?
+        .to_str()
+        .ok()?;
+    let mut protocols = protocols.split(',').map(str::trim);
+    protocols.find(|p| *p == BEARER_PROTOCOL)?;
+    protocols.next().filter(|token| !token.is_empty())
+}
+
+fn unauthorized(message: &str) -> Error {
+    Error::HttpError(StatusCode::UNAUTHORIZED, message.into())
+}
+
+/// Time left until the claims expire.
+pub fn expires_in(claims: &Claims) -> Duration {
+    let left = claims.exp as i64 - Utc::now().timestamp();
+    Duration::from_secs(left.max(0) as u64)
+}
+
+/// Closes the socket as its token has expired. Clients reconnect with a fresh one.
+pub async fn close_expired(sender: &mut SplitSink<WebSocket, Message>) {
+    let frame = CloseFrame {
+        code: close_code::POLICY,
+        reason: "Token expired".into(),
+    };
+    let _ = sender.send(Message::Close(Some(frame))).await;
+}
@@ -0,0 +1,147 @@
+//! Named channels clients join over one socket. A channel's kind, the part of its name
+//! before the colon, decides who may subscribe and publish to it:
+//!
+//! - `public:<name>` anyone signed in
+//! - `user:<id>` only that user, who also receives server events for them on it
+//! - `org:<id>` members of that organization
+//! - `role:<role>` users with that role
+
+use crate::objects::rules::Principal;
+use crate::{Error, Result};
+use axum::http::StatusCode;
+use sqlx::types::Uuid;
+use std::fmt;
+use std::str::FromStr;
+
+const MAX_NAME_LEN: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Channel {
+    Public(String),
+    User(Uuid),
+    Organization(Uuid),
+    Role(String),
+}
+
+impl FromStr for Channel {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let invalid = || {
+            Error::HttpError(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid channel '{}'", value),
+            )
+        };
+        let (kind, name) = value.split_once(':').ok_or_else(invalid)?;
+        let valid_name = !name.is_empty()
+            && name.len() <= MAX_NAME_LEN
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid_name {
+            return Err(invalid());
+        }
+
+        match kind {
+            "public" => Ok(Channel::Public(name.to_string())),
+            "user" => Ok(Channel::User(Uuid::parse_str(name).map_err(|_| invalid())?)),
+            "org" => Ok(Channel::Organization(
+                Uuid::parse_str(name).map_err(|_| invalid())?,
+            )),
+            "role" => Ok(Channel::Role(name.to_string())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Channel::Public(name) => write!(f, "public:{}", name),
+            Channel::User(id) => write!(f, "user:{}", id),
+            Channel::Organization(id) => write!(f, "org:{}", id),
+            Channel::Role(role) => write!(f, "role:{}", role),
+        }
+    }
+}
+
+impl Channel {
+    /// Whether the caller may subscribe and publish to the channel.
+    pub fn allows(&self, principal: &Principal) -> bool {
+        match self {
+            Channel::Public(_) => principal.user_id.is_some(),
+            Channel::User(id) => principal.user_id == Some(*id),
+            Channel::Organization(id) => principal.organization_id == Some(*id),
+            Channel::Role(role) => principal.role.as_deref() == Some(role.as_str()),
+        }
+    }
+
+    /// Checks the caller may use the channel.
+    pub fn authorize(&self, principal: &Principal) -> Result<()> {
+        match self.allows(principal) {
+            true => Ok(()),
+            false => Err(Error::HttpError(
+                StatusCode::FORBIDDEN,
+                format!("Not allowed on channel '{}'", self),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(user_id: Uuid, role: &str, organization_id: Option<Uuid>) -> Principal {
+        Principal {
+            user_id: Some(user_id),
+            role: Some(role.to_string()),
+            organization_id,
+        }
+    }
+
+    #[test]
+    fn test_parse_channel_names() {
+        let id = Uuid::max();
+        assert_eq!(
+            "public:lobby".parse::<Channel>().unwrap(),
+            Channel::Public("lobby".into())
+        );
+        assert_eq!(
+            format!("user:{}", id).parse::<Channel>().unwrap(),
+            Channel::User(id)
+        );
+        assert_eq!(
+            "role:admin".parse::<Channel>().unwrap().to_string(),
+            "role:admin"
+        );
+
+        for name in [
+            "lobby",
+            "public:",
+            "user:42",
+            "team:a",
+            "public:a b",
+            "public:a:b",
+        ] {
+            assert!(name.parse::<Channel>().is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_channel_authorization() {
+        let user = Uuid::max();
+        let org = Uuid::nil();
+        let member = principal(user, "user", Some(org));
+
+        assert!(Channel::Public("lobby".into()).allows(&member));
+        assert!(!Channel::Public("lobby".into()).allows(&Principal::default()));
+        assert!(Channel::User(user).allows(&member));
+        assert!(!Channel::User(org).allows(&member));
+        assert!(Channel::Organization(org).allows(&member));
+        assert!(!Channel::Organization(org).allows(&principal(user, "user", None)));
+        assert!(Channel::Role("user".into()).allows(&member));
+        assert!(!Channel::Role("admin".into()).allows(&member));
+    }
+}
@@ -1,63 +1,328 @@
 use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
-use axum::extract::Path;
+use axum::extract::State;
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::{extract::State, response::Response};
-use futures_util::{
-    sink::SinkExt,
-    stream::{SplitSink, SplitStream, StreamExt},
-};
-use redis::AsyncCommands;
-use serde_json::json;
-use sqlx::types::Uuid;
+use futures_util::{sink::SinkExt, stream::StreamExt};
+use redis::aio::PubSubSink;
+use serde_json::{json, Value};
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::data::Event;
+use crate::config::CONFIG;
+use crate::crypt::jwt::Claims;
+use crate::crypt::tokens::generate_token;
+use crate::data::{ChannelMessage, Event};
+use crate::objects::handlers::principal;
+use crate::objects::rules::Principal;
+use crate::realtime::Subscription;
+use crate::response::ApiResponse;
 use crate::state::AppState;
-use crate::Result;
+use crate::storage::redis::channel_key;
+use crate::websocket::auth::{close_expired, expires_in, SocketClaims, BEARER_PROTOCOL};
+use crate::websocket::channels::Channel;
+use crate::websocket::models::{
+    ClientMessage, ServerMessage, SubscribePayload, UnsubscribePayload,
+};
+use crate::{Error, Result};
+use axum::Extension;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Channels one socket can be in at once.
+const MAX_CHANNELS: usize = 100;
+/// Largest `data` a client can publish, as JSON.
+const MAX_PUBLISH_BYTES: usize = 64 * 1024;
+
+/// Swaps the caller's token for a ticket that opens one websocket, for clients that
+/// can't send the token with the upgrade.
+pub async fn create_ticket(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse> {
+    let ticket = generate_token();
+    let ttl = CONFIG.ws_ticket_expire_seconds;
+    state
+        .storage
+        .redis
+        .store_ws_ticket(&ticket, &claims, ttl)
+        .await?;
 
+    Ok(ApiResponse::new(
+        StatusCode::CREATED,
+        "Successfully created ticket",
+        json!({ "ticket": ticket, "expires_in": ttl }),
+    ))
+}
+
+/// Carries the caller's channels. Clients send `subscribe`, `unsubscribe` and `publish`
+/// requests, each answered by an `ack` or `error` with the request's id, and receive a
+/// `message` for everything published to the channels they are in.
 pub async fn handler(
     ws: WebSocketUpgrade,
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
+    SocketClaims(claims): SocketClaims,
 ) -> Result<impl IntoResponse> {
-    let user_id = Uuid::parse_str(&id)?;
-    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state, user_id)))
+    let principal = principal(&state, Some(&claims)).await?;
+    Ok(ws
+        .protocols([BEARER_PROTOCOL])
+        .on_upgrade(move |socket| handle_socket(socket, state, principal, expires_in(&claims))))
 }
 
-async fn handle_socket(socket: WebSocket, state: Arc<AppState>, user_id: Uuid) {
-    let (sender, receiver) = socket.split();
+async fn handle_socket(
+    socket: WebSocket,
+    state: Arc<AppState>,
+    principal: Principal,
+    expiry: Duration,
+) {
+    let (mut sender, mut receiver) = socket.split();
+    let pubsub = match state.storage.redis.pubsub().await {
+        Ok(pubsub) => pubsub,
+        Err(e) => {
+            tracing::error!("Failed to open channels: {}", e);
+            return;
+        }
+    };
+    let (mut sink, mut messages) = pubsub.split();
+    let mut channels: HashSet<String> = HashSet::new();
+    let expiry = tokio::time::sleep(expiry);
+    tokio::pin!(expiry);
 
-    tokio::spawn(write(sender, Arc::clone(&state), user_id));
-    tokio::spawn(read(receiver, Arc::clone(&state), user_id));
+    loop {
+        let replies = tokio::select! {
+            _ = &mut expiry => {
+                close_expired(&mut sender).await;
+                return;
+            }
+            msg = receiver.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    request(&state, &principal, &mut sink, &mut channels, &text).await
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            },
+            msg = messages.next() => match msg {
+                Some(msg) => {
+                    let message = msg
+                        .get_payload::<String>()
+                        .map_err(Error::from)
+                        .and_then(|payload| Ok(serde_json::from_str(&payload)?));
+                    match message {
+                        Ok(message) => vec![ServerMessage::Message(message)],
+                        Err(e) => {
+                            tracing::warn!("Ignoring malformed channel message: {}", e);
+                            continue;
+                        }
+                    }
+                }
+                None => {
+                    tracing::error!("Lost the channels connection");
+                    return;
+                }
+            },
+        };
+
+        for reply in replies {
+            if sender.send(reply.into()).await.is_err() {
+                return;
+            }
+        }
+    }
 }
 
-async fn read(mut receiver: SplitStream<WebSocket>, _state: Arc<AppState>, user_id: Uuid) {
-    while let Some(msg) = receiver.next().await {
-        match msg {
-            Ok(msg) => {
-                println!("Received from client: {:?}", msg);
-                // Optionally handle the message here
+/// Handles a request, returning its `ack` followed by anything it delivers, or its
+/// `error`.
+async fn request(
+    state: &AppState,
+    principal: &Principal,
+    sink: &mut PubSubSink,
+    channels: &mut HashSet<String>,
+    text: &str,
+) -> Vec<ServerMessage> {
+    let value = serde_json::from_str::<Value>(text);
+    let id = value
+        .as_ref()
+        .ok()
+        .and_then(|value| value.get("id"))
+        .and_then(Value::as_str)
+        .map(str::to_string);
+
+    let result = match value.and_then(serde_json::from_value) {
+        Ok(message) => apply(state, principal, sink, channels, message).await,
+        Err(e) => Err(Error::HttpError(StatusCode::BAD_REQUEST, e.to_string())),
+    };
+    match (result, id) {
+        (Ok(delivered), Some(id)) => {
+            let mut replies = vec![ServerMessage::Ack { id }];
+            replies.extend(delivered.into_iter().map(ServerMessage::Message));
+            replies
+        }
+        (Ok(_), None) => Vec::new(),
+        (Err(e), id) => vec![ServerMessage::Error {
+            id,
+            message: e.client_message(),
+        }],
+    }
+}
+
+/// Applies a request, returning the messages it delivers: the events queued for the
+/// caller when they join their own channel.
+async fn apply(
+    state: &AppState,
+    principal: &Principal,
+    sink: &mut PubSubSink,
+    channels: &mut HashSet<String>,
+    message: ClientMessage,
+) -> Result<Vec<ChannelMessage>> {
+    match message {
+        ClientMessage::Subscribe { channel, .. } => {
+            let channel: Channel = channel.parse()?;
+            channel.authorize(principal)?;
+            let name = channel.to_string();
+            if channels.contains(&name) {
+                return Ok(Vec::new());
             }
-            Err(_) => {
-                println!("Client disconnected: read");
-                return;
+            if channels.len() >= MAX_CHANNELS {
+                return Err(Error::HttpError(
+                    StatusCode::BAD_REQUEST,
+                    format!("A socket joins at most {} channels", MAX_CHANNELS),
+                ));
+            }
+
+            sink.subscribe(channel_key(&name)).await?;
+            channels.insert(name);
+            // Queued after subscribing, so an event sent in between isn't missed
+            match channel {
+                Channel::User(user_id) => state.storage.redis.take_user_events(user_id).await,
+                _ => Ok(Vec::new()),
+            }
+        }
+        ClientMessage::Unsubscribe { channel, .. } => {
+            let name = channel.parse::<Channel>()?.to_string();
+            if channels.remove(&name) {
+                sink.unsubscribe(channel_key(&name)).await?;
             }
+            Ok(Vec::new())
+        }
+        ClientMessage::Publish { channel, data, .. } => {
+            let channel: Channel = channel.parse()?;
+            channel.authorize(principal)?;
+            if data.to_string().len() > MAX_PUBLISH_BYTES {
+                return Err(Error::HttpError(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("Messages are at most {} bytes", MAX_PUBLISH_BYTES),
+                ));
+            }
+
+            let message = ChannelMessage {
+                channel: channel.to_string(),
+                sender: principal.user_id.map(|id| id.to_string()),
+                data,
+            };
+            state.storage.redis.publish(&message).await?;
+            Ok(Vec::new())
         }
     }
 }
 
-async fn write(mut sender: SplitSink<WebSocket, Message>, state: Arc<AppState>, user_id: Uuid) {
-    let mut conn = state.storage.redis.connection().await;
-    let key = format!("ws:{}", user_id);
+/// Streams changes to the data API's tables. Clients send `subscribe` events naming a
+/// table and optional filters, and `unsubscribe` to stop, and receive `insert`, `update`
+/// and `delete` events for rows they are allowed to see.
+pub async fn realtime_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    SocketClaims(claims): SocketClaims,
+) -> impl IntoResponse {
+    ws.protocols([BEARER_PROTOCOL])
+        .on_upgrade(move |socket| handle_realtime(socket, state, claims))
+}
+
+async fn handle_realtime(socket: WebSocket, state: Arc<AppState>, claims: Claims) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut changes = state.realtime.subscribe();
+    let mut subscriptions: Vec<Subscription> = Vec::new();
+    let expiry = tokio::time::sleep(expires_in(&claims));
+    tokio::pin!(expiry);
 
     loop {
-        if let Ok(msg) = conn.blpop::<&str, Event>(&key, 0.0).await {
-            if let Err(_) = sender.send(msg.into()).await {
-                println!("Client disconnected: write");
+        let event = tokio::select! {
+            _ = &mut expiry => {
+                close_expired(&mut sender).await;
                 return;
             }
+            msg = receiver.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    match command(&state, &mut subscriptions, &text).await {
+                        Ok(event) => event,
+                        Err(e) => Event {
+                            name: "error".to_string(),
+                            data: json!({ "message": e.to_string() }),
+                        },
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            },
+            change = changes.recv() => match change {
+                Ok(change) => {
+                    // Overlapping subscriptions still get each change once
+                    let mut event = None;
+                    for subscription in &mut subscriptions {
+                        match subscription.event(&state, &claims, &change).await {
+                            Ok(Some(found)) => {
+                                event = Some(found);
+                                break;
+                            }
+                            Ok(None) => {}
+                            Err(e) => tracing::warn!("Failed to check change visibility: {}", e),
+                        }
+                    }
+                    match event {
+                        Some(event) => event,
+                        None => continue,
+                    }
+                }
+                // The client missed changes and should refetch what it shows
+                Err(RecvError::Lagged(missed)) => Event {
+                    name: "lagged".to_string(),
+                    data: json!({ "missed": missed }),
+                },
+                Err(RecvError::Closed) => return,
+            },
+        };
+
+        if sender.send(event.into()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Applies a `subscribe` or `unsubscribe` event, returning its acknowledgement.
+async fn command(
+    state: &AppState,
+    subscriptions: &mut Vec<Subscription>,
+    text: &str,
+) -> Result<Event> {
+    let event: Event = serde_json::from_str(text)?;
+
+    match event.name.as_str() {
+        "subscribe" => {
+            let payload: SubscribePayload = serde_json::from_value(event.data)?;
+            let subscription = Subscription::new(state, &payload.table, &payload.filter).await?;
+            let data = json!({ "table": subscription.table, "filter": subscription.filter });
+            subscriptions.push(subscription);
+            Ok(Event {
+                name: "subscribed".to_string(),
+                data,
+            })
+        }
+        "unsubscribe" => {
+            let payload: UnsubscribePayload = serde_json::from_value(event.data)?;
+            subscriptions.retain(|s| s.table != payload.table);
+            Ok(Event {
+                name: "unsubscribed".to_string(),
+                data: json!({ "table": payload.table }),
+            })
         }
-        tokio::time::sleep(Duration::from_secs(2)).await;
+        name => Err(Error::from(format!("Unknown event '{}'", name))),
     }
 }
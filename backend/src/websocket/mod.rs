@@ -1,2 +1,5 @@
+pub mod auth;
+pub mod channels;
 pub mod handlers;
+pub mod models;
 pub mod routes;
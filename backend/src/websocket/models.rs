@@ -0,0 +1,58 @@
+use crate::data::ChannelMessage;
+use axum::extract::ws::Message;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Deserialize)]
+pub struct SubscribePayload {
+    pub table: String,
+    /// Data API filters, like `owner_id=eq.42&done=is.false`.
+    #[serde(default)]
+    pub filter: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnsubscribePayload {
+    pub table: String,
+}
+
+/// A request on a channel socket. Each carries an id the client chooses, which the
+/// `ack` or `error` answering it repeats.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe {
+        id: String,
+        channel: String,
+    },
+    Unsubscribe {
+        id: String,
+        channel: String,
+    },
+    Publish {
+        id: String,
+        channel: String,
+        data: Value,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Ack {
+        id: String,
+    },
+    /// Without an id when the request couldn't be read.
+    Error {
+        id: Option<String>,
+        message: String,
+    },
+    Message(ChannelMessage),
+}
+
+impl From<ServerMessage> for Message {
+    fn from(message: ServerMessage) -> Self {
+        let txt = serde_json::to_string(&message).unwrap_or_else(|_| "{}".to_string());
+        Message::text(txt)
+    }
+}
@@ -1,11 +1,15 @@
-use super::handlers::handler;
+use super::handlers::{create_ticket, handler, realtime_handler};
 use crate::middleware::auth_middleware;
 use crate::state::AppState;
-use axum::routing::any;
+use axum::routing::{any, post};
 use axum::{middleware, Router};
 use std::sync::Arc;
 
 pub fn router() -> Router<Arc<AppState>> {
-    Router::new().route("/initialize/{id}", any(handler))
-    // .route_layer(middleware::from_fn(auth_middleware))
+    Router::new()
+        .route("/ticket", post(create_ticket))
+        .route_layer(middleware::from_fn(auth_middleware))
+        // Sockets authenticate themselves, as browsers can't send the header
+        .route("/realtime", any(realtime_handler))
+        .route("/initialize", any(handler))
 }
@@ -38,9 +38,17 @@ mod test_integration {
     use std::time::Duration;
 
     use super::*;
+    use app::crypt::jwt::{encode_jwt, Metadata};
+    use app::crypt::tokens::generate_token;
+    use app::data::Event;
+    use app::users::deletion;
+    use app::users::queries::UserQueries;
+    use chrono::Utc;
     use ctor::ctor;
     use futures::StreamExt;
     use futures_util::sink::SinkExt;
+    use serde_json::json;
+    use sqlx::types::Uuid;
     use tokio_tungstenite::connect_async;
     use tungstenite::{client::IntoClientRequest, Message};
 
@@ -50,6 +58,85 @@ mod test_integration {
         println!("✅ .env loaded automatically for tests");
     }
 
+    /// A verified user with a fresh email, scheduled for deletion at `delete_after`.
+    async fn create_user(state: &AppState, delete_after: Option<&str>) -> Result<Uuid> {
+        let user_id = sqlx::query_scalar(
+            r#"
+            INSERT INTO users (email, first_name, is_verified, delete_after)
+            VALUES ($1, 'Test', true, NOW() + $2::INTERVAL)
+            RETURNING id
+        "#,
+        )
+        .bind(format!("{}@example.com", generate_token().to_lowercase()))
+        .bind(delete_after)
+        .fetch_one(&state.storage.postgres.pool)
+        .await?;
+        Ok(user_id)
+    }
+
+    async fn user_exists(state: &AppState, user_id: Uuid) -> Result<bool> {
+        let exists = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE id = $1)")
+            .bind(user_id)
+            .fetch_one(&state.storage.postgres.pool)
+            .await?;
+        Ok(exists)
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_request_and_cancel_deletion() -> Result<()> {
+        let state = AppState::new().await?;
+        let pool = &state.storage.postgres.pool;
+        let user_id = create_user(&state, None).await?;
+
+        let delete_after = deletion::request(&state, user_id).await?;
+        assert!(delete_after > Utc::now());
+        // Asking again keeps the first schedule
+        assert_eq!(deletion::request(&state, user_id).await?, delete_after);
+
+        assert!(UserQueries::cancel_deletion(user_id, pool).await?);
+        assert!(!UserQueries::cancel_deletion(user_id, pool).await?);
+        let due = UserQueries::due_for_deletion(i64::MAX, &[], pool).await?;
+        assert!(!due.contains(&user_id));
+
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_purge_due() -> Result<()> {
+        let state = AppState::new().await?;
+        let pool = &state.storage.postgres.pool;
+        let due = create_user(&state, Some("-1 day")).await?;
+        let pending = create_user(&state, Some("1 day")).await?;
+        let event = Event {
+            name: "test".to_string(),
+            data: json!({}),
+        };
+        state.storage.redis.notify_user(due, &event).await?;
+
+        // Users that keep failing are skipped rather than returned again
+        let ids = UserQueries::due_for_deletion(i64::MAX, &[], pool).await?;
+        assert!(ids.contains(&due) && !ids.contains(&pending));
+        let ids = UserQueries::due_for_deletion(i64::MAX, &[due], pool).await?;
+        assert!(!ids.contains(&due));
+
+        assert!(deletion::purge_due(&state).await? >= 1);
+        assert!(!user_exists(&state, due).await?);
+        assert!(user_exists(&state, pending).await?);
+        assert!(state.storage.redis.take_user_events(due).await?.is_empty());
+
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(pending)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
     // #[tokio::test]
     // #[serial]
     // async fn test_health_status() -> Result<()> {
@@ -75,9 +162,19 @@ mod test_integration {
     #[tokio::test]
     #[serial]
     async fn test_websocket_connection() {
-        let request = "ws://localhost:8080/ws/initialize/8de3e519-4a87-4e9b-9ac4-8bdade32e628"
+        let token = encode_jwt(
+            "8de3e519-4a87-4e9b-9ac4-8bdade32e628".to_string(),
+            true,
+            Metadata::default(),
+        )
+        .unwrap();
+        let mut request = "ws://localhost:8080/ws/initialize"
             .into_client_request()
             .unwrap();
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            format!("bearer, {}", token).parse().unwrap(),
+        );
 
         let (ws_stream, _) = connect_async(request).await.expect("Failed to connect");
         let (mut write, mut read) = ws_stream.split();
@@ -0,0 +1,17 @@
+-- Modify "objects" table
+ALTER TABLE "public"."objects" ALTER COLUMN "key" TYPE character varying(1024);
+-- Drop index "idx_user_files" from table: "objects"
+DROP INDEX "public"."idx_user_files";
+-- Create index "idx_object_key" to table: "objects"
+CREATE UNIQUE INDEX "idx_object_key" ON "public"."objects" ("key");
+-- Create "folders" table
+CREATE TABLE "public"."folders" (
+  "id" serial NOT NULL,
+  "user_id" uuid NOT NULL,
+  "path" character varying(1024) NOT NULL,
+  "created_at" timestamptz NULL DEFAULT now(),
+  PRIMARY KEY ("id"),
+  CONSTRAINT "folders_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "public"."users" ("id") ON UPDATE NO ACTION ON DELETE CASCADE
+);
+-- Create index "idx_user_folders" to table: "folders"
+CREATE UNIQUE INDEX "idx_user_folders" ON "public"."folders" ("user_id", "path");
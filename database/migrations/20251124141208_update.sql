@@ -0,0 +1,18 @@
+-- Modify "objects" table
+ALTER TABLE "public"."objects" ADD COLUMN "version" integer NOT NULL DEFAULT 1, ADD COLUMN "max_versions" integer NULL;
+-- Create "object_versions" table
+CREATE TABLE "public"."object_versions" (
+  "id" serial NOT NULL,
+  "object_id" integer NOT NULL,
+  "version" integer NOT NULL,
+  "blob_key" character varying(1024) NOT NULL,
+  "content_type" character varying(255) NOT NULL DEFAULT 'application/octet-stream',
+  "size_bytes" bigint NOT NULL DEFAULT 0,
+  "created_at" timestamptz NULL DEFAULT now(),
+  PRIMARY KEY ("id"),
+  CONSTRAINT "object_versions_object_id_fkey" FOREIGN KEY ("object_id") REFERENCES "public"."objects" ("id") ON UPDATE NO ACTION ON DELETE CASCADE
+);
+-- Create index "idx_object_versions" to table: "object_versions"
+CREATE UNIQUE INDEX "idx_object_versions" ON "public"."object_versions" ("object_id", "version");
+-- Existing objects become version 1, with the blob left where it was uploaded
+INSERT INTO "public"."object_versions" ("object_id", "version", "blob_key") SELECT "id", 1, "key" FROM "public"."objects";
@@ -0,0 +1,35 @@
+-- Create index "idx_objects_user" to table: "objects"
+CREATE INDEX "idx_objects_user" ON "public"."objects" ("user_id");
+-- Create "organizations" table
+CREATE TABLE "public"."organizations" (
+  "id" uuid NOT NULL DEFAULT gen_random_uuid(),
+  "name" character varying(255) NOT NULL,
+  "created_at" timestamptz NULL DEFAULT now(),
+  PRIMARY KEY ("id")
+);
+-- Create "organization_members" table
+CREATE TABLE "public"."organization_members" (
+  "id" serial NOT NULL,
+  "organization_id" uuid NOT NULL,
+  "user_id" uuid NOT NULL,
+  "role" character varying(50) NOT NULL DEFAULT 'member',
+  "created_at" timestamptz NULL DEFAULT now(),
+  PRIMARY KEY ("id"),
+  CONSTRAINT "organization_members_organization_id_fkey" FOREIGN KEY ("organization_id") REFERENCES "public"."organizations" ("id") ON UPDATE NO ACTION ON DELETE CASCADE,
+  CONSTRAINT "organization_members_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "public"."users" ("id") ON UPDATE NO ACTION ON DELETE CASCADE
+);
+-- Create index "idx_organization_members" to table: "organization_members"
+CREATE UNIQUE INDEX "idx_organization_members" ON "public"."organization_members" ("user_id");
+-- Create "storage_quotas" table
+CREATE TABLE "public"."storage_quotas" (
+  "id" serial NOT NULL,
+  "user_id" uuid NULL,
+  "organization_id" uuid NULL,
+  "max_bytes" bigint NOT NULL,
+  PRIMARY KEY ("id"),
+  CONSTRAINT "storage_quotas_organization_id_key" UNIQUE ("organization_id"),
+  CONSTRAINT "storage_quotas_user_id_key" UNIQUE ("user_id"),
+  CONSTRAINT "storage_quotas_organization_id_fkey" FOREIGN KEY ("organization_id") REFERENCES "public"."organizations" ("id") ON UPDATE NO ACTION ON DELETE CASCADE,
+  CONSTRAINT "storage_quotas_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "public"."users" ("id") ON UPDATE NO ACTION ON DELETE CASCADE,
+  CONSTRAINT "storage_quotas_check" CHECK ((user_id IS NULL) <> (organization_id IS NULL))
+);
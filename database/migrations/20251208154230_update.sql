@@ -0,0 +1,2 @@
+-- Modify "users" table
+ALTER TABLE "public"."users" ADD COLUMN "role" character varying(50) NOT NULL DEFAULT 'user';
@@ -0,0 +1,4 @@
+-- Modify "object_versions" table
+ALTER TABLE "public"."object_versions" ADD COLUMN "scan_status" character varying(20) NOT NULL DEFAULT 'clean';
+-- Versions stored before scanning existed count as clean; new ones wait for a verdict
+ALTER TABLE "public"."object_versions" ALTER COLUMN "scan_status" SET DEFAULT 'pending';
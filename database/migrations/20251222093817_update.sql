@@ -0,0 +1,2 @@
+-- Modify "object_versions" table
+ALTER TABLE "public"."object_versions" ADD COLUMN "key_id" character varying(64) NULL, ADD COLUMN "wrapped_key" text NULL;
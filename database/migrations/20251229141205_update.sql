@@ -0,0 +1,7 @@
+-- Create "blobs" table
+CREATE TABLE "public"."blobs" (
+  "blob_key" character varying(1024) NOT NULL,
+  "ref_count" integer NOT NULL DEFAULT 0,
+  "created_at" timestamptz NULL DEFAULT now(),
+  PRIMARY KEY ("blob_key")
+);
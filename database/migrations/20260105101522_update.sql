@@ -0,0 +1,19 @@
+-- Modify "objects" table
+ALTER TABLE "public"."objects" ADD COLUMN "expires_at" timestamptz NULL;
+-- Create index "idx_objects_expires_at" to table: "objects"
+CREATE INDEX "idx_objects_expires_at" ON "public"."objects" ("expires_at") WHERE (expires_at IS NOT NULL);
+-- Modify "object_versions" table
+ALTER TABLE "public"."object_versions" ADD COLUMN "storage_class" character varying(20) NOT NULL DEFAULT 'standard';
+-- Create "lifecycle_rules" table
+CREATE TABLE "public"."lifecycle_rules" (
+  "id" serial NOT NULL,
+  "user_id" uuid NOT NULL,
+  "prefix" character varying(1024) NOT NULL,
+  "expire_after_days" integer NULL,
+  "transition_after_days" integer NULL,
+  "created_at" timestamptz NULL DEFAULT now(),
+  PRIMARY KEY ("id"),
+  CONSTRAINT "lifecycle_rules_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "public"."users" ("id") ON UPDATE NO ACTION ON DELETE CASCADE
+);
+-- Create index "idx_lifecycle_rules" to table: "lifecycle_rules"
+CREATE UNIQUE INDEX "idx_lifecycle_rules" ON "public"."lifecycle_rules" ("user_id", "prefix");
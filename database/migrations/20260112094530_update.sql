@@ -0,0 +1,18 @@
+-- Create role "authenticated"
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = 'authenticated') THEN
+        CREATE ROLE authenticated NOLOGIN;
+    END IF;
+END
+$$;
+GRANT authenticated TO CURRENT_USER;
+-- Add new schema named "api"
+CREATE SCHEMA IF NOT EXISTS "api";
+GRANT USAGE ON SCHEMA "api" TO authenticated;
+ALTER DEFAULT PRIVILEGES IN SCHEMA "api" GRANT SELECT, INSERT, UPDATE, DELETE ON TABLES TO authenticated;
+ALTER DEFAULT PRIVILEGES IN SCHEMA "api" GRANT USAGE, SELECT ON SEQUENCES TO authenticated;
+-- Create "request_user_id" function
+CREATE OR REPLACE FUNCTION "public"."request_user_id" () RETURNS uuid LANGUAGE sql STABLE AS $$
+    SELECT (NULLIF(current_setting('request.jwt.claims', true), '')::JSON ->> 'sub')::UUID
+$$;
@@ -0,0 +1,22 @@
+-- Create "realtime_notify" function
+CREATE OR REPLACE FUNCTION "public"."realtime_notify" () RETURNS trigger LANGUAGE plpgsql AS $$
+DECLARE
+    row JSONB := to_jsonb(CASE WHEN TG_OP = 'DELETE' THEN OLD ELSE NEW END);
+    keys JSONB;
+BEGIN
+    SELECT jsonb_object_agg(a.attname, row -> a.attname::TEXT)
+    INTO keys
+    FROM pg_index AS i
+    JOIN pg_attribute AS a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
+    WHERE i.indrelid = TG_RELID
+    AND i.indisprimary;
+
+    PERFORM pg_notify('table_changes', jsonb_build_object(
+        'schema', TG_TABLE_SCHEMA,
+        'table', TG_TABLE_NAME,
+        'type', TG_OP,
+        'keys', COALESCE(keys, '{}'::JSONB)
+    )::TEXT);
+    RETURN NULL;
+END
+$$;
@@ -0,0 +1,25 @@
+-- Create "audit_events" table
+CREATE TABLE "public"."audit_events" (
+  "id" bigserial NOT NULL,
+  "actor_id" uuid NULL,
+  "action" character varying(100) NOT NULL,
+  "target" text NULL,
+  "ip" character varying(45) NULL,
+  "user_agent" text NULL,
+  "outcome" character varying(10) NOT NULL,
+  "created_at" timestamptz NOT NULL DEFAULT now(),
+  PRIMARY KEY ("id"),
+  CONSTRAINT "audit_events_outcome_check" CHECK (outcome IN ('success', 'failure'))
+);
+-- Create index "idx_audit_events_actor" to table: "audit_events"
+CREATE INDEX "idx_audit_events_actor" ON "public"."audit_events" ("actor_id", "id");
+-- Create "audit_events_append_only" function
+CREATE OR REPLACE FUNCTION "public"."audit_events_append_only" () RETURNS trigger LANGUAGE plpgsql AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END
+$$;
+-- Create trigger "audit_events_append_only"
+CREATE TRIGGER "audit_events_append_only" BEFORE DELETE OR UPDATE ON "public"."audit_events" FOR EACH ROW EXECUTE FUNCTION "public"."audit_events_append_only"();
+-- Create trigger "audit_events_no_truncate"
+CREATE TRIGGER "audit_events_no_truncate" BEFORE TRUNCATE ON "public"."audit_events" FOR EACH STATEMENT EXECUTE FUNCTION "public"."audit_events_append_only"();
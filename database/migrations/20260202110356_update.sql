@@ -0,0 +1,4 @@
+-- Modify "users" table
+ALTER TABLE "public"."users" ADD COLUMN "delete_after" timestamptz NULL;
+-- Create index "idx_users_delete_after" to table: "users"
+CREATE INDEX "idx_users_delete_after" ON "public"."users" ("delete_after") WHERE (delete_after IS NOT NULL);
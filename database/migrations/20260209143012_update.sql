@@ -0,0 +1,30 @@
+-- Create "data_exports" table
+CREATE TABLE "public"."data_exports" (
+  "id" uuid NOT NULL DEFAULT gen_random_uuid(),
+  "user_id" uuid NOT NULL,
+  "status" character varying(20) NOT NULL DEFAULT 'pending',
+  "blob_key" character varying(1024) NOT NULL,
+  "key_id" character varying(64) NULL,
+  "wrapped_key" text NULL,
+  "nonce_prefix" character varying(14) NULL,
+  "multipart_id" character varying(1024) NULL,
+  "entries" jsonb NULL,
+  "checkpoint" jsonb NULL,
+  "central" bytea NULL,
+  "buffer" bytea NULL,
+  "attempts" integer NOT NULL DEFAULT 0,
+  "error" text NULL,
+  "leased_until" timestamptz NULL,
+  "created_at" timestamptz NOT NULL DEFAULT now(),
+  "completed_at" timestamptz NULL,
+  "expires_at" timestamptz NULL,
+  PRIMARY KEY ("id"),
+  CONSTRAINT "data_exports_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "public"."users" ("id") ON UPDATE NO ACTION ON DELETE CASCADE,
+  CONSTRAINT "data_exports_status_check" CHECK (status IN ('pending', 'running', 'completed', 'failed'))
+);
+-- Create index "idx_data_exports_active" to table: "data_exports"
+CREATE UNIQUE INDEX "idx_data_exports_active" ON "public"."data_exports" ("user_id") WHERE (status IN ('pending', 'running'));
+-- Create index "idx_data_exports_status" to table: "data_exports"
+CREATE INDEX "idx_data_exports_status" ON "public"."data_exports" ("status") WHERE (status IN ('pending', 'running'));
+-- Create index "idx_data_exports_user" to table: "data_exports"
+CREATE INDEX "idx_data_exports_user" ON "public"."data_exports" ("user_id", "created_at");
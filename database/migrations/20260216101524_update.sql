@@ -0,0 +1,2 @@
+-- Modify "users" table
+ALTER TABLE "public"."users" ADD COLUMN "display_name" character varying(100) NULL, ADD COLUMN "avatar_object_id" integer NULL, ADD COLUMN "locale" character varying(35) NULL, ADD COLUMN "timezone" character varying(64) NULL, ADD COLUMN "app_metadata" jsonb NOT NULL DEFAULT '{}', ADD COLUMN "user_metadata" jsonb NOT NULL DEFAULT '{}', ADD CONSTRAINT "users_avatar_object_id_fkey" FOREIGN KEY ("avatar_object_id") REFERENCES "public"."objects" ("id") ON UPDATE NO ACTION ON DELETE SET NULL;
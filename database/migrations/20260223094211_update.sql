@@ -0,0 +1,4 @@
+-- Modify "object_versions" table
+ALTER TABLE "public"."object_versions" ADD COLUMN "scan_attempts" integer NOT NULL DEFAULT 0;
+-- Create index "idx_object_versions_scan" to table: "object_versions"
+CREATE INDEX "idx_object_versions_scan" ON "public"."object_versions" ("scan_status") WHERE scan_status IN ('pending', 'failed');
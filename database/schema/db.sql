@@ -5,9 +5,19 @@ CREATE TABLE IF NOT EXISTS users (
     last_name VARCHAR(50),
     is_verified BOOL NOT NULL,
     created_at TIMESTAMP DEFAULT NOW(),
-    updated_at TIMESTAMP DEFAULT NOW()
+    updated_at TIMESTAMP DEFAULT NOW(),
+    role VARCHAR(50) NOT NULL DEFAULT 'user',  -- 'user' | 'admin'
+    delete_after TIMESTAMPTZ,  -- Set while a deletion request waits out its grace period
+    display_name VARCHAR(100),
+    avatar_object_id INT,  -- One of the user's own objects, an image
+    locale VARCHAR(35),  -- BCP 47 language tag, e.g. 'en-CA'
+    timezone VARCHAR(64),  -- IANA time zone, e.g. 'America/Toronto'
+    app_metadata JSONB NOT NULL DEFAULT '{}',  -- Written by admins only
+    user_metadata JSONB NOT NULL DEFAULT '{}'  -- Written by the user
 );
 
+CREATE INDEX idx_users_delete_after ON users(delete_after) WHERE delete_after IS NOT NULL;
+
 CREATE TABLE IF NOT EXISTS auth_providers (
     id SERIAL PRIMARY KEY,
     user_id UUID REFERENCES users(id) ON DELETE CASCADE,
@@ -35,15 +45,97 @@ CREATE UNIQUE INDEX idx_linked_accounts ON linked_accounts(user_id, provider);
 CREATE TABLE IF NOT EXISTS objects (
     id SERIAL PRIMARY KEY,
     user_id UUID NOT NULL REFERENCES users(id) on DELETE CASCADE,
-    key VARCHAR(255) NOT NULL,  -- S3 key
-    filename VARCHAR(255) NOT NULL  -- Original filename
+    key VARCHAR(1024) NOT NULL,  -- Logical key, {user_id}/{folder/...}/{filename}
+    filename VARCHAR(255) NOT NULL,  -- Original filename
+    version INT NOT NULL DEFAULT 1,  -- Current version in object_versions
+    max_versions INT,  -- Versions kept for this object, NULL uses the configured default
     -- content_type VARCHAR(100),
     -- size_bytes BIGINT,
     -- visibility VARCHAR(20) DEFAULT 'private',  -- 'public' | 'private'
     -- created_at TIMESTAMP DEFAULT NOW()
+    expires_at TIMESTAMPTZ  -- Deleted by the lifecycle sweeper after this, overriding rules
+);
+
+CREATE UNIQUE INDEX idx_object_key ON objects(key);
+CREATE INDEX idx_objects_expires_at ON objects(expires_at) WHERE expires_at IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS object_versions (
+    id SERIAL PRIMARY KEY,
+    object_id INT NOT NULL REFERENCES objects(id) on DELETE CASCADE,
+    version INT NOT NULL,
+    blob_key VARCHAR(1024) NOT NULL,  -- S3 key of this version's immutable blob
+    content_type VARCHAR(255) NOT NULL DEFAULT 'application/octet-stream',
+    size_bytes BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    scan_status VARCHAR(20) NOT NULL DEFAULT 'pending',  -- 'pending' | 'clean' | 'infected' | 'failed'
+    key_id VARCHAR(64),  -- Master key wrapping the data key, NULL when stored in plaintext
+    wrapped_key TEXT,  -- Blob's data key sealed by the master key
+    storage_class VARCHAR(20) NOT NULL DEFAULT 'standard',  -- 'standard' | 'archive', the store holding the blob
+    scan_attempts INT NOT NULL DEFAULT 0  -- Scans tried, so failed ones are retried a few times
 );
 
-CREATE UNIQUE INDEX idx_user_files ON objects(user_id, filename);
+CREATE UNIQUE INDEX idx_object_versions ON object_versions(object_id, version);
+CREATE INDEX idx_object_versions_scan ON object_versions(scan_status) WHERE scan_status IN ('pending', 'failed');
+
+-- Content-addressed blobs shared by versions with identical bytes
+CREATE TABLE IF NOT EXISTS blobs (
+    blob_key VARCHAR(1024) PRIMARY KEY,
+    ref_count INT NOT NULL DEFAULT 0,  -- Versions pointing at the blob, deleted with the last
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS folders (
+    id SERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) on DELETE CASCADE,
+    path VARCHAR(1024) NOT NULL,  -- Folder path relative to the user root
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_user_folders ON folders(user_id, path);
+
+CREATE TABLE IF NOT EXISTS organizations (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS organization_members (
+    id SERIAL PRIMARY KEY,
+    organization_id UUID NOT NULL REFERENCES organizations(id) on DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) on DELETE CASCADE,
+    role VARCHAR(50) NOT NULL DEFAULT 'member',
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+-- A user belongs to at most one organization
+CREATE UNIQUE INDEX idx_organization_members ON organization_members(user_id);
+
+-- Overrides of the configured default quotas, for either a user or an organization
+CREATE TABLE IF NOT EXISTS storage_quotas (
+    id SERIAL PRIMARY KEY,
+    user_id UUID UNIQUE REFERENCES users(id) on DELETE CASCADE,
+    organization_id UUID UNIQUE REFERENCES organizations(id) on DELETE CASCADE,
+    max_bytes BIGINT NOT NULL,
+    CHECK ((user_id IS NULL) <> (organization_id IS NULL))
+);
+
+CREATE INDEX idx_objects_user ON objects(user_id);
+
+-- Added once objects exists; an avatar whose object is deleted is cleared
+ALTER TABLE users ADD CONSTRAINT users_avatar_object_id_fkey
+    FOREIGN KEY (avatar_object_id) REFERENCES objects(id) ON DELETE SET NULL;
+
+-- Per-prefix lifecycle of a user's objects, by the age of each version
+CREATE TABLE IF NOT EXISTS lifecycle_rules (
+    id SERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) on DELETE CASCADE,
+    prefix VARCHAR(1024) NOT NULL,  -- Key prefix including the user root
+    expire_after_days INT,  -- Objects whose current version is older are deleted
+    transition_after_days INT,  -- Versions older are moved to the archive store
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_lifecycle_rules ON lifecycle_rules(user_id, prefix);
 
 CREATE TABLE IF NOT EXISTS refresh_tokens(
   id SERIAL PRIMARY KEY,
@@ -61,3 +153,109 @@ CREATE TABLE IF NOT EXISTS refresh_tokens(
 -- );
 
 
+
+-- Role the data API switches to for each request, so row-level security policies apply
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = 'authenticated') THEN
+        CREATE ROLE authenticated NOLOGIN;
+    END IF;
+END
+$$;
+GRANT authenticated TO CURRENT_USER;
+
+-- Tables the data API exposes
+CREATE SCHEMA IF NOT EXISTS api;
+GRANT USAGE ON SCHEMA api TO authenticated;
+ALTER DEFAULT PRIVILEGES IN SCHEMA api GRANT SELECT, INSERT, UPDATE, DELETE ON TABLES TO authenticated;
+ALTER DEFAULT PRIVILEGES IN SCHEMA api GRANT USAGE, SELECT ON SEQUENCES TO authenticated;
+
+-- The calling user, for policies such as USING (owner_id = request_user_id())
+CREATE OR REPLACE FUNCTION request_user_id() RETURNS UUID
+LANGUAGE SQL STABLE
+AS $$
+    SELECT (NULLIF(current_setting('request.jwt.claims', true), '')::JSON ->> 'sub')::UUID
+$$;
+
+-- Announces a row change on the "table_changes" channel for the realtime feed, carrying
+-- only the primary key so payloads stay under the NOTIFY size limit. Attach it with:
+-- CREATE TRIGGER notes_realtime AFTER INSERT OR UPDATE OR DELETE ON api.notes
+--     FOR EACH ROW EXECUTE FUNCTION realtime_notify();
+CREATE OR REPLACE FUNCTION realtime_notify() RETURNS TRIGGER
+LANGUAGE plpgsql
+AS $$
+DECLARE
+    row JSONB := to_jsonb(CASE WHEN TG_OP = 'DELETE' THEN OLD ELSE NEW END);
+    keys JSONB;
+BEGIN
+    SELECT jsonb_object_agg(a.attname, row -> a.attname::TEXT)
+    INTO keys
+    FROM pg_index AS i
+    JOIN pg_attribute AS a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
+    WHERE i.indrelid = TG_RELID
+    AND i.indisprimary;
+
+    PERFORM pg_notify('table_changes', jsonb_build_object(
+        'schema', TG_TABLE_SCHEMA,
+        'table', TG_TABLE_NAME,
+        'type', TG_OP,
+        'keys', COALESCE(keys, '{}'::JSONB)
+    )::TEXT);
+    RETURN NULL;
+END
+$$;
+
+-- Security-relevant actions, kept when the actor's account is deleted
+CREATE TABLE IF NOT EXISTS audit_events (
+    id BIGSERIAL PRIMARY KEY,
+    actor_id UUID,  -- No foreign key, so events outlive the user
+    action VARCHAR(100) NOT NULL,  -- e.g. 'auth.login', 'object.delete'
+    target TEXT,
+    ip VARCHAR(45),
+    user_agent TEXT,
+    outcome VARCHAR(10) NOT NULL CHECK (outcome IN ('success', 'failure')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_audit_events_actor ON audit_events(actor_id, id);
+
+-- Keeps audit_events append-only, even for the application's own role
+CREATE OR REPLACE FUNCTION audit_events_append_only() RETURNS TRIGGER
+LANGUAGE plpgsql
+AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END
+$$;
+
+CREATE TRIGGER audit_events_append_only BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE FUNCTION audit_events_append_only();
+CREATE TRIGGER audit_events_no_truncate BEFORE TRUNCATE ON audit_events
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_events_append_only();
+
+-- Exports of everything held about a user, built in the background into a ZIP
+CREATE TABLE IF NOT EXISTS data_exports (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'running', 'completed', 'failed')),
+    blob_key VARCHAR(1024) NOT NULL,  -- Where the archive is written in the object store
+    key_id VARCHAR(64),  -- Master key wrapping the archive's data key, NULL when stored in plaintext
+    wrapped_key TEXT,
+    nonce_prefix VARCHAR(14),  -- Hex nonce prefix the archive's parts are sealed under
+    multipart_id VARCHAR(1024),
+    entries JSONB,  -- Files of the archive, fixed once collected
+    checkpoint JSONB,  -- Position in the archive after the last part written
+    central BYTEA,  -- Central directory of the entries written so far
+    buffer BYTEA,  -- Archive bytes short of a part, in plaintext
+    attempts INT NOT NULL DEFAULT 0,
+    error TEXT,
+    leased_until TIMESTAMPTZ,  -- A running export whose lease ran out is picked up again
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMPTZ,
+    expires_at TIMESTAMPTZ  -- The archive is deleted after this
+);
+
+CREATE INDEX idx_data_exports_user ON data_exports(user_id, created_at);
+CREATE INDEX idx_data_exports_status ON data_exports(status) WHERE status IN ('pending', 'running');
+-- One export at a time per user
+CREATE UNIQUE INDEX idx_data_exports_active ON data_exports(user_id) WHERE status IN ('pending', 'running');